@@ -0,0 +1,167 @@
+//! Version consistency between the application and its crates.
+//!
+//! The application version comes from `vsn` in `src/<app>.app.src`, or `version` in
+//! `mix.exs`.  `version-policy` in `erlangapp.toml` says how much of it each crate's version
+//! must share; a native library released out of step with its BEAM code is a common cause of
+//! confusing hot-upgrade failures.
+
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+/// How closely crate versions must follow the application version
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum VersionPolicy {
+    /// No check
+    Any,
+    /// Same major version
+    Major,
+    /// Same major and minor version
+    Minor,
+    /// Same version, ignoring build metadata
+    Exact,
+}
+
+impl FromStr for VersionPolicy {
+    type Err = ();
+    fn from_str(s: &str) -> Result<VersionPolicy, ()> {
+        match s {
+            "any" => Ok(VersionPolicy::Any),
+            "major" => Ok(VersionPolicy::Major),
+            "minor" => Ok(VersionPolicy::Minor),
+            "exact" => Ok(VersionPolicy::Exact),
+            _ => Err(()),
+        }
+    }
+}
+
+impl fmt::Display for VersionPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match *self {
+            VersionPolicy::Any => "any",
+            VersionPolicy::Major => "major",
+            VersionPolicy::Minor => "minor",
+            VersionPolicy::Exact => "exact",
+        })
+    }
+}
+
+impl VersionPolicy {
+    /// True if a crate version is out of step with the application version
+    pub fn diverges(self, app_version: &str, crate_version: &str) -> bool {
+        let strip = |v: &str| v.split('+').next().unwrap_or(v).trim().to_string();
+        let (app, krate) = (strip(app_version), strip(crate_version));
+        let parts = match self {
+            VersionPolicy::Any => return false,
+            VersionPolicy::Exact => return app != krate,
+            VersionPolicy::Major => 1,
+            VersionPolicy::Minor => 2,
+        };
+        let release = |v: &str| -> Vec<String> {
+            v.split('-').next().unwrap_or("").split('.').take(parts).map(String::from).collect()
+        };
+        release(&app) != release(&krate)
+    }
+}
+
+/// The application version from `src/*.app.src`, else `mix.exs`.  `None` if neither gives
+/// a literal version (`{vsn, git}`, for instance).
+pub fn app_version(appdir: &Path) -> Option<String> {
+    let path = version_file(appdir)?;
+    version_at(&fs::read_to_string(&path).ok()?, is_app_src(&path)).map(|(_, version)| version)
+}
+
+/// The literal `vsn` of an application resource file, such as the compiled `ebin/<app>.app`
+pub fn resource_version(path: &Path) -> Option<String> {
+    version_at(&fs::read_to_string(path).ok()?, true).map(|(_, version)| version)
+}
+
+/// Replace the literal application version.  Returns the file changed, or `None` if there
+/// is no literal version to replace.
+pub fn set_app_version(appdir: &Path, version: &str) -> io::Result<Option<PathBuf>> {
+    let path = match version_file(appdir) {
+        Some(path) => path,
+        None => return Ok(None),
+    };
+    let text = fs::read_to_string(&path)?;
+    match with_version(&text, is_app_src(&path), version) {
+        Some(text) => fs::write(&path, text).map(|_| Some(path)),
+        None => Ok(None),
+    }
+}
+
+/// `src/*.app.src`, else `mix.exs`, if present
+fn version_file(appdir: &Path) -> Option<PathBuf> {
+    appdir.join("src").read_dir().ok()
+        .and_then(|entries| entries
+            .filter_map(Result::ok)
+            .map(|e| e.path())
+            .find(|p| is_app_src(p)))
+        .or_else(|| Some(appdir.join("mix.exs")).filter(|p| p.is_file()))
+}
+
+fn is_app_src(path: &Path) -> bool {
+    path.to_string_lossy().ends_with(".app.src")
+}
+
+fn with_version(text: &str, app_src: bool, version: &str) -> Option<String> {
+    let (at, current) = version_at(text, app_src)?;
+    Some(format!("{}{}{}", &text[..at], version, &text[at + current.len()..]))
+}
+
+/// Offset and value of the literal version: `{vsn, "1.2.3"}` in an application resource
+/// file; `version: "1.2.3"` in a mix project, or a `@version "1.2.3"` attribute
+fn version_at(text: &str, app_src: bool) -> Option<(usize, String)> {
+    let quoted = |at: usize| {
+        let value = text[at..].trim_start().strip_prefix('"')?;
+        let start = text.len() - value.len();
+        Some((start, value[..value.find('"')?].to_string()))
+    };
+    if app_src {
+        let at = text.find("vsn")? + "vsn".len();
+        let rest = text[at..].trim_start().strip_prefix(',')?;
+        return quoted(text.len() - rest.len());
+    }
+    let line_value = |prefix: &str| {
+        let mut offset = 0;
+        for line in text.split_inclusive('\n') {
+            let trimmed = line.trim_start();
+            if let Some(rest) = trimmed.strip_prefix(prefix) {
+                if let Some(found) = quoted(offset + line.len() - rest.len()) {
+                    return Some(found);
+                }
+            }
+            offset += line.len();
+        }
+        None
+    };
+    line_value("version:").or_else(|| line_value("@version "))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_versions() {
+        let version = |text: &str, app_src: bool| version_at(text, app_src).map(|(_, v)| v);
+        let app_src = "{application, my_app,\n [{description, \"x\"},\n  {vsn , \"1.4.2\"},\n  {modules, []}]}.\n";
+        assert_eq!(Some("1.4.2".to_string()), version(app_src, true));
+        assert_eq!(None, version("{application, my_app, [{vsn, git}]}.\n", true));
+        assert_eq!(Some("0.3.0".to_string()), version("  def project do\n    [app: :my_app,\n     version: \"0.3.0\",\n", false));
+        let mix = "  @version \"0.3.1\"\n  def project do\n    [app: :my_app, version: @version]\n";
+        assert_eq!(Some("0.3.1".to_string()), version(mix, false));
+        assert_eq!(Some(app_src.replace("1.4.2", "1.5.0")), with_version(app_src, true, "1.5.0"));
+        assert_eq!(Some(mix.replace("0.3.1", "0.4.0")), with_version(mix, false, "0.4.0"));
+
+        assert!(!VersionPolicy::Any.diverges("1.0.0", "2.0.0"));
+        assert!(!VersionPolicy::Major.diverges("1.4.2", "1.0.0"));
+        assert!(VersionPolicy::Major.diverges("1.4.2", "2.4.2"));
+        assert!(!VersionPolicy::Minor.diverges("1.4.2", "1.4.0-rc.1"));
+        assert!(VersionPolicy::Minor.diverges("1.4.2", "1.5.0"));
+        assert!(!VersionPolicy::Exact.diverges("1.4.2", "1.4.2+build.7"));
+        assert!(VersionPolicy::Exact.diverges("1.4.2", "1.4.3"));
+    }
+}
@@ -0,0 +1,454 @@
+//! Installing artifacts, and retention of previous artifact generations.
+//!
+//! An artifact a running VM has loaded can't be overwritten on Windows (nor a running port
+//! program on Linux), but it can be renamed.  Such an artifact is moved aside to
+//! `<name>.old-N` and the new one copied in its place; leftovers are deleted once unlocked.
+//!
+//! Artifacts are never written in place: the copy goes to a hidden `.<name>.partial-<pid>`
+//! in the same directory, is flushed to disk, and is then renamed over `<name>`, so a VM
+//! loading the library mid-install sees either the old file or the new one, never a mix.
+//! Copying and renaming are retried with backoff on errors that usually pass: sharing
+//! violations, virus scanners holding the file, busy or stale NFS handles.
+//!
+//! With a retention policy, an installed artifact is never overwritten in place: the
+//! previous file is hard-linked as `<name>.1` (older ones renamed to `.2`, `.3`, ...) before
+//! the new one is renamed over it, which leaves a copy that a running VM has mapped
+//! untouched and `<name>` never missing.  With versioned artifact names
+//! every version is its own generation.  Either way only the newest `keep` are kept.
+//!
+//! With backups, the artifact a build replaces is kept as `<name>.prev`, the last build
+//! known to differ, which `rollback` swaps back in.
+
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process;
+use std::thread;
+use std::time::{Duration, SystemTime};
+
+use interrupt;
+use verify;
+
+const COPY_ATTEMPTS: u32 = 6;
+const MAX_BACKOFF: Duration = Duration::from_secs(2);
+
+/// True if `file_name` is a generation of the artifact installed as `name`: the artifact
+/// itself, a rotated `<name>.N`, or a versioned `<stem>-<version>.<ext>`
+fn is_generation(name: &str, file_name: &str) -> bool {
+    if file_name == name {
+        return true;
+    }
+    if let Some(n) = file_name.strip_prefix(name).and_then(|rest| rest.strip_prefix('.')) {
+        return !n.is_empty() && n.bytes().all(|b| b.is_ascii_digit());
+    }
+    let (stem, ext) = match name.rfind('.') {
+        Some(dot) => (&name[..dot], &name[dot..]),
+        None => (name, ""),
+    };
+    file_name.strip_prefix(stem)
+        .and_then(|rest| rest.strip_prefix('-'))
+        .and_then(|rest| rest.strip_suffix(ext))
+        .map(|version| version.starts_with(|c: char| c.is_ascii_digit()))
+        .unwrap_or(false)
+}
+
+/// Where `dst` is staged before being renamed into place: in the same directory, since a
+/// rename is only atomic within one filesystem, and named per process so concurrent builds
+/// don't write to the same file
+fn staging_path(dst: &Path) -> PathBuf {
+    let name = dst.file_name().map(|n| n.to_string_lossy().into_owned()).unwrap_or_default();
+    dst.with_file_name(format!(".{}.partial-{}", name, process::id()))
+}
+
+/// Copy `src` to `dst`, rotating an existing `dst` first if `keep_previous`, and moving it aside
+/// if it is locked.  The copy is made next to `dst` and renamed into place, so `dst` is
+/// never seen half-written; after an interrupt or a failed rename the copy is discarded and
+/// `dst` left as it was.  Returns the bytes copied.
+pub fn install(src: &Path, dst: &Path, keep_previous: bool) -> io::Result<u64> {
+    install_with(src, dst, keep_previous, |from, to| fs::rename(from, to))
+}
+
+/// `install`, renaming the staged copy into place with `rename`
+fn install_with<R>(src: &Path, dst: &Path, keep_previous: bool, rename: R) -> io::Result<u64>
+    where R: Fn(&Path, &Path) -> io::Result<()>
+{
+    remove_moved_aside(dst);
+    let partial = staging_path(dst);
+    let bytes = retry(|| fs::copy(src, &partial)
+            .and_then(|bytes| fs::File::open(&partial).and_then(|file| file.sync_all()).map(|_| bytes)))
+        .and_then(|bytes| match interrupt::interrupted() {
+            true => Err(io::Error::new(io::ErrorKind::Interrupted, "interrupted")),
+            false => Ok(bytes),
+        })
+        .and_then(|bytes| match keep_previous {
+            true => rotate(dst).map(|_| bytes),
+            false => Ok(bytes),
+        })
+        .inspect_err(|_| {
+            let _ = fs::remove_file(&partial);
+        })?;
+    retry(|| rename(&partial, dst).or_else(|err| {
+        // a locked artifact can still be renamed out of the way
+        if is_in_use(&err) && dst.exists() {
+            move_aside(dst)?;
+        }
+        Err(err)
+    }))
+    .inspect_err(|_| {
+        let _ = fs::remove_file(&partial);
+    })
+    .map(|_| bytes)
+}
+
+/// Run `f` until it succeeds, fails with a non-transient error, or has been tried
+/// `COPY_ATTEMPTS` times, backing off between attempts.  A final transient error says how
+/// often it was tried.
+fn retry<T, F>(mut f: F) -> io::Result<T>
+    where F: FnMut() -> io::Result<T>
+{
+    let mut attempt = 1;
+    loop {
+        match f() {
+            Err(ref err) if is_transient(err) && attempt < COPY_ATTEMPTS && !interrupt::interrupted() => {
+                thread::sleep(backoff(attempt));
+                attempt += 1;
+            },
+            Err(err) if attempt > 1 =>
+                return Err(io::Error::new(err.kind(), format!("{}, after {} attempts", err, attempt))),
+            result => return result,
+        }
+    }
+}
+
+/// Delay after the given failed attempt: doubling from 100ms, at most `MAX_BACKOFF`
+fn backoff(attempt: u32) -> Duration {
+    Duration::from_millis(100 << (attempt - 1).min(16)).min(MAX_BACKOFF)
+}
+
+/// Check an installed copy against the file it was copied from: present, not empty, with
+/// the same SHA-256, and executable if the source is.  Returns what is wrong.
+pub fn check_installed(src: &Path, dst: &Path) -> Result<(), String> {
+    let installed = fs::read(dst).map_err(|err| format!("missing after install ({})", err))?;
+    if installed.is_empty() {
+        return Err("empty after install".to_string());
+    }
+    let built = fs::read(src).map_err(|err| format!("cannot read {} ({})", src.display(), err))?;
+    if verify::sha256_hex(&installed) != verify::sha256_hex(&built) {
+        return Err("contents differ from the build".to_string());
+    }
+    if is_executable(src) && !is_executable(dst) {
+        return Err("not executable".to_string());
+    }
+    Ok(())
+}
+
+#[cfg(unix)]
+fn is_executable(path: &Path) -> bool {
+    use std::os::unix::fs::PermissionsExt;
+    fs::metadata(path).map(|metadata| metadata.permissions().mode() & 0o111 != 0).unwrap_or(false)
+}
+
+#[cfg(not(unix))]
+fn is_executable(_path: &Path) -> bool {
+    false
+}
+
+/// Errors worth retrying: the file is locked (a loaded library, a scanning virus checker)
+/// or the filesystem hiccuped (NFS)
+fn is_transient(err: &io::Error) -> bool {
+    is_in_use(err) || matches!(err.kind(), io::ErrorKind::ResourceBusy | io::ErrorKind::WouldBlock
+                                           | io::ErrorKind::TimedOut | io::ErrorKind::StaleNetworkFileHandle)
+}
+
+#[cfg(windows)]
+fn is_in_use(err: &io::Error) -> bool {
+    // ERROR_ACCESS_DENIED, ERROR_SHARING_VIOLATION, ERROR_LOCK_VIOLATION
+    matches!(err.raw_os_error(), Some(5) | Some(32) | Some(33))
+}
+
+#[cfg(not(windows))]
+fn is_in_use(err: &io::Error) -> bool {
+    err.raw_os_error() == Some(26) // ETXTBSY
+}
+
+fn move_aside(path: &Path) -> io::Result<()> {
+    let mut n = 1;
+    loop {
+        let aside = PathBuf::from(format!("{}.old-{}", path.display(), n));
+        if !aside.exists() {
+            return fs::rename(path, aside);
+        }
+        n += 1;
+    }
+}
+
+/// Delete artifacts moved aside by earlier installs, where no longer locked, and copies
+/// staged by installs that were killed before renaming them into place.  A copy another
+/// running process is staging is left to it.
+fn remove_moved_aside(path: &Path) {
+    let (aside, staged) = match path.file_name() {
+        Some(name) => (format!("{}.old-", name.to_string_lossy()), format!(".{}.partial-", name.to_string_lossy())),
+        None => return,
+    };
+    if let Some(entries) = path.parent().and_then(|dir| dir.read_dir().ok()) {
+        for entry in entries.filter_map(Result::ok) {
+            let file_name = entry.file_name().to_string_lossy().into_owned();
+            let abandoned = match file_name.strip_prefix(&staged).map(str::parse::<u32>) {
+                Some(Ok(pid)) => pid == process::id() || !process_running(pid),
+                Some(Err(_)) => false,
+                None => file_name.starts_with(&aside),
+            };
+            if abandoned {
+                let _ = fs::remove_file(entry.path());
+            }
+        }
+    }
+}
+
+/// True if a process with this id is running
+#[cfg(windows)]
+fn process_running(pid: u32) -> bool {
+    let filter = format!("PID eq {}", pid);
+    match process::Command::new("tasklist").args(["/fi", &filter, "/fo", "csv", "/nh"]).output() {
+        Ok(output) => String::from_utf8_lossy(&output.stdout).contains(&format!("\"{}\"", pid)),
+        // can't tell, so assume it is
+        Err(_) => true,
+    }
+}
+
+#[cfg(not(windows))]
+fn process_running(pid: u32) -> bool {
+    let proc_dir = Path::new("/proc");
+    if proc_dir.join("self").exists() {
+        return proc_dir.join(pid.to_string()).exists();
+    }
+    // `kill -0` fails for another user's process too, so this only errs towards keeping
+    match process::Command::new("kill").args(["-0", &pid.to_string()]).stderr(process::Stdio::null()).status() {
+        Ok(status) => status.success(),
+        Err(_) => true,
+    }
+}
+
+/// Processes that have `path` loaded, for error messages
+#[cfg(windows)]
+pub fn lock_holders(path: &Path) -> Option<String> {
+    let name = path.file_name()?.to_string_lossy().into_owned();
+    let output = process::Command::new("tasklist")
+        .args(["/m", &name, "/fo", "csv", "/nh"])
+        .output()
+        .ok()?;
+    let holders: Vec<String> = String::from_utf8_lossy(&output.stdout).lines()
+        .filter_map(|line| {
+            let fields: Vec<&str> = line.split("\",\"").map(|f| f.trim_matches('"')).collect();
+            match fields.len() {
+                3 => Some(format!("{} (pid {})", fields[0], fields[1])),
+                _ => None,
+            }
+        })
+        .collect();
+    match holders.is_empty() {
+        true => None,
+        false => Some(holders.join(", ")),
+    }
+}
+
+#[cfg(not(windows))]
+pub fn lock_holders(path: &Path) -> Option<String> {
+    let output = process::Command::new("fuser").arg(path).output().ok()?;
+    let pids = String::from_utf8_lossy(&output.stdout).split_whitespace()
+        .map(|pid| format!("pid {}", pid.trim_end_matches(|c: char| c.is_alphabetic())))
+        .collect::<Vec<_>>();
+    match pids.is_empty() {
+        true => None,
+        false => Some(pids.join(", ")),
+    }
+}
+
+/// Keep `path` as `<path>.1`, shifting older rotated generations up by one.  `path` is
+/// hard-linked (or copied) rather than renamed, so it stays in place until replaced.
+fn rotate(path: &Path) -> io::Result<()> {
+    if !path.exists() {
+        return Ok(());
+    }
+    let rotated = |n: usize| PathBuf::from(format!("{}.{}", path.display(), n));
+    let mut last = 1;
+    while rotated(last).exists() {
+        last += 1;
+    }
+    for n in (1..last).rev() {
+        fs::rename(rotated(n), rotated(n + 1))?;
+    }
+    fs::hard_link(path, rotated(1)).or_else(|_| fs::copy(path, rotated(1)).map(|_| ()))
+}
+
+/// Where the backup of the file at `path` is kept
+pub fn backup_path(path: &Path) -> PathBuf {
+    PathBuf::from(format!("{}.prev", path.display()))
+}
+
+/// True if the files at `a` and `b` can be read and hold the same bytes
+pub fn same_contents(a: &Path, b: &Path) -> bool {
+    match (fs::read(a), fs::read(b)) {
+        (Ok(a), Ok(b)) => a == b,
+        _ => false,
+    }
+}
+
+/// Keep the file at `path` as its backup, replacing an older one.  The backup is a hard
+/// link where possible, so the file stays in place and a VM that has it mapped keeps the
+/// same file.
+pub fn backup(path: &Path) -> io::Result<()> {
+    let backup = backup_path(path);
+    if backup.is_file() {
+        fs::remove_file(&backup)?;
+    }
+    fs::hard_link(path, &backup).or_else(|_| fs::copy(path, &backup).map(|_| ()))
+}
+
+/// Swap the file at `path` with its backup, so that swapping again undoes it.  Each is
+/// renamed into place, so `path` is never missing.  Returns false if there is no backup.
+pub fn swap_backup(path: &Path) -> io::Result<bool> {
+    let backup = backup_path(path);
+    if !backup.is_file() {
+        return Ok(false);
+    }
+    let (restored, replaced) = (staging_path(path), staging_path(&backup));
+    fs::hard_link(&backup, &restored).or_else(|_| fs::copy(&backup, &restored).map(|_| ()))?;
+    if path.is_file() {
+        fs::hard_link(path, &replaced).or_else(|_| fs::copy(path, &replaced).map(|_| ()))?;
+    }
+    retry(|| fs::rename(&restored, path))?;
+    match replaced.is_file() {
+        true => fs::rename(&replaced, &backup)?,
+        false => fs::remove_file(&backup)?,
+    }
+    Ok(true)
+}
+
+/// Delete all but the newest `keep` generations of the artifact installed as `dir/name`.
+/// Returns the number of files removed.
+pub fn prune(dir: &Path, name: &str, keep: usize) -> io::Result<usize> {
+    let entries = match dir.read_dir() {
+        Ok(entries) => entries,
+        Err(ref err) if err.kind() == io::ErrorKind::NotFound => return Ok(0),
+        Err(err) => return Err(err),
+    };
+    let mut generations: Vec<(SystemTime, PathBuf)> = Vec::new();
+    for entry in entries {
+        let entry = entry?;
+        if is_generation(name, &entry.file_name().to_string_lossy()) {
+            generations.push((entry.metadata()?.modified()?, entry.path()));
+        }
+    }
+    generations.sort_by(|a, b| b.cmp(a)); // newest first
+    let mut removed = 0;
+    for (_, path) in generations.into_iter().skip(keep.max(1)) {
+        fs::remove_file(path)?;
+        removed += 1;
+    }
+    Ok(removed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+
+    #[test]
+    fn test_is_generation() {
+        assert!(is_generation("libmynif.so", "libmynif.so"));
+        assert!(is_generation("libmynif.so", "libmynif.so.2"));
+        assert!(is_generation("libmynif.so", "libmynif-1.4.2.so"));
+        assert!(is_generation("myport", "myport-0.1.0"));
+        assert!(!is_generation("libmynif.so", "libmynif.so.link-args"));
+        assert!(!is_generation("libmynif.so", "libmynif-extra.so"));
+        assert!(!is_generation("libmynif.so", "libmynif_extra.so"));
+    }
+
+    #[test]
+    fn test_staging_path() {
+        let staged = staging_path(Path::new("priv/crates/mynif/libmynif.so"));
+        assert!(!is_generation("libmynif.so", &staged.file_name().unwrap().to_string_lossy()));
+        assert_eq!(Some(Path::new("priv/crates/mynif")), staged.parent());
+    }
+
+    #[test]
+    fn test_retry_backoff() {
+        assert_eq!(Duration::from_millis(100), backoff(1));
+        assert_eq!(Duration::from_millis(400), backoff(3));
+        assert_eq!(MAX_BACKOFF, backoff(COPY_ATTEMPTS));
+    }
+
+    #[test]
+    fn test_backup() {
+        let dir = env::temp_dir().join(format!("cargo-erlangapp-backup-{}", process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let (built, installed) = (dir.join("build.so"), dir.join("libmynif.so"));
+        fs::write(&installed, b"good").unwrap();
+        fs::write(&built, b"bad").unwrap();
+        assert!(!same_contents(&built, &installed));
+        assert!(!swap_backup(&installed).unwrap());
+        backup(&installed).unwrap();
+        install(&built, &installed, false).unwrap();
+        assert_eq!(b"good".to_vec(), fs::read(backup_path(&installed)).unwrap());
+        assert!(swap_backup(&installed).unwrap());
+        assert_eq!(b"good".to_vec(), fs::read(&installed).unwrap());
+        assert_eq!(b"bad".to_vec(), fs::read(backup_path(&installed)).unwrap());
+        assert!(!is_generation("libmynif.so", "libmynif.so.prev"));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_install_keep_previous() {
+        let dir = env::temp_dir().join(format!("cargo-erlangapp-keep-previous-{}", process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let (built, installed) = (dir.join("build.so"), dir.join("libmynif.so"));
+        fs::write(&installed, b"old").unwrap();
+        fs::write(&built, b"new").unwrap();
+        let failing = |_: &Path, _: &Path| Err(io::Error::new(io::ErrorKind::PermissionDenied, "denied"));
+        assert!(install_with(&built, &installed, true, failing).is_err());
+        assert_eq!(b"old".to_vec(), fs::read(&installed).unwrap());
+        assert!(!staging_path(&installed).exists());
+        install(&built, &installed, true).unwrap();
+        assert_eq!(b"new".to_vec(), fs::read(&installed).unwrap());
+        assert_eq!(b"old".to_vec(), fs::read(dir.join("libmynif.so.1")).unwrap());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_remove_moved_aside() {
+        let dir = env::temp_dir().join(format!("cargo-erlangapp-moved-aside-{}", process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let installed = dir.join("libmynif.so");
+        let mut running = process::Command::new("sleep").arg("10").spawn().unwrap();
+        let mut exited = process::Command::new("true").spawn().unwrap();
+        exited.wait().unwrap();
+        let staged = |pid: u32| dir.join(format!(".libmynif.so.partial-{}", pid));
+        for path in [staged(process::id()), staged(running.id()), staged(exited.id()), dir.join("libmynif.so.old-1")].iter() {
+            fs::write(path, b"").unwrap();
+        }
+        remove_moved_aside(&installed);
+        let left: Vec<PathBuf> = dir.read_dir().unwrap().map(|entry| entry.unwrap().path()).collect();
+        running.kill().unwrap();
+        running.wait().unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(vec![staged(running.id())], left);
+    }
+
+    #[test]
+    fn test_check_installed() {
+        let dir = env::temp_dir().join(format!("cargo-erlangapp-check-installed-{}", process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let (src, dst) = (dir.join("libmynif.so"), dir.join("installed.so"));
+        fs::write(&src, b"\x7fELF").unwrap();
+        assert!(check_installed(&src, &dst).unwrap_err().starts_with("missing"));
+        install(&src, &dst, false).unwrap();
+        assert_eq!(Ok(()), check_installed(&src, &dst));
+        fs::write(&dst, b"").unwrap();
+        assert_eq!(Err("empty after install".to_string()), check_installed(&src, &dst));
+        fs::write(&dst, b"\x7fELG").unwrap();
+        assert_eq!(Err("contents differ from the build".to_string()), check_installed(&src, &dst));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
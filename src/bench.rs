@@ -0,0 +1,246 @@
+//! `bench` command: run each crate's benchmarks and stage the results in the application.
+//!
+//! Each crate gets `bench_results/<crate>/` holding the Criterion reports its run produced
+//! (`criterion/`), libtest `#[bench]` output (`libtest.txt`) and `history.csv`, which every
+//! run appends to.  `bench_results/index.html` shows the latest and previous result of
+//! every benchmark, with links to the reports.
+
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use gc;
+use json;
+use MsgError;
+use MsgError::*;
+
+pub const HISTORY_FILE: &str = "history.csv";
+const HISTORY_HEADER: &str = "timestamp,benchmark,ns\n";
+
+/// One benchmark's result
+#[derive(Debug, PartialEq)]
+pub struct Measurement {
+    pub name: String,
+    /// Mean time per iteration
+    pub ns: f64,
+}
+
+/// Results from libtest's `test name ... bench: 1,234 ns/iter (+/- 56)` lines
+pub fn parse_libtest(stdout: &str) -> Vec<Measurement> {
+    stdout.lines()
+        .filter_map(|line| {
+            let rest = line.trim().strip_prefix("test ")?;
+            let (name, rest) = rest.split_once(" ... bench:")?;
+            let ns = rest.trim().split(" ns/iter").next()?.replace(',', "");
+            Some(Measurement { name: name.trim().to_string(), ns: ns.trim().parse().ok()? })
+        })
+        .collect()
+}
+
+/// Stage one crate's results: libtest output, Criterion reports modified since `started`
+/// (the Criterion directory may be shared with other crates), and new history entries.
+/// Returns the measurements found; without any, nothing is staged.
+pub fn stage(results_dir: &Path, crate_name: &str, stdout: &str, criterion_dir: &Path, started: SystemTime)
+    -> Result<Vec<Measurement>, MsgError>
+{
+    let crate_dir = results_dir.join(crate_name);
+    let mut measurements = parse_libtest(stdout);
+    let reports = modified_since(criterion_dir, started).map_err(|err| MsgIo("cannot read Criterion reports", err))?;
+    if measurements.is_empty() && reports.is_empty() {
+        return Ok(measurements);
+    }
+    fs::create_dir_all(&crate_dir).map_err(|err| MsgIo("cannot create bench results directory", err))?;
+    if !measurements.is_empty() {
+        let lines: String = stdout.lines().filter(|l| l.contains(" ... bench:")).map(|l| format!("{}\n", l)).collect();
+        fs::write(crate_dir.join("libtest.txt"), lines).map_err(|err| MsgIo("cannot write bench results", err))?;
+    }
+
+    let staged = crate_dir.join("criterion");
+    for dir in reports {
+        let dest = staged.join(dir.file_name().unwrap_or_default());
+        if dest.exists() {
+            fs::remove_dir_all(&dest).map_err(|err| MsgIo("cannot replace Criterion report", err))?;
+        }
+        copy_tree(&dir, &dest).map_err(|err| MsgIo("cannot copy Criterion report", err))?;
+        if dir.file_name().map(|n| n != "report").unwrap_or(false) {
+            criterion_measurements(&dest, &staged, &mut measurements);
+        }
+    }
+
+    append_history(&crate_dir, &measurements)?;
+    Ok(measurements)
+}
+
+/// Subdirectories of `dir` with anything modified since `since`
+fn modified_since(dir: &Path, since: SystemTime) -> io::Result<Vec<PathBuf>> {
+    let mut dirs = Vec::new();
+    let entries = match dir.read_dir() {
+        Ok(entries) => entries,
+        Err(ref err) if err.kind() == io::ErrorKind::NotFound => return Ok(dirs),
+        Err(err) => return Err(err),
+    };
+    for entry in entries {
+        let path = entry?.path();
+        if path.is_dir() && gc::usage(&path)?.1 >= since {
+            dirs.push(path);
+        }
+    }
+    dirs.sort();
+    Ok(dirs)
+}
+
+fn copy_tree(from: &Path, to: &Path) -> io::Result<()> {
+    fs::create_dir_all(to)?;
+    for entry in from.read_dir()? {
+        let entry = entry?;
+        let dest = to.join(entry.file_name());
+        if entry.file_type()?.is_dir() {
+            copy_tree(&entry.path(), &dest)?;
+        } else {
+            fs::copy(entry.path(), dest)?;
+        }
+    }
+    Ok(())
+}
+
+/// Mean estimates from `<benchmark>/new/estimates.json` files under `dir`, named by their
+/// path relative to `root`
+fn criterion_measurements(dir: &Path, root: &Path, measurements: &mut Vec<Measurement>) {
+    let estimates = dir.join("new").join("estimates.json");
+    let mean = fs::read_to_string(&estimates).ok()
+        .and_then(|text| json::from_str::<json::Value>(&text).ok())
+        .and_then(|value| value.get("mean")?.get("point_estimate")?.as_f64());
+    if let Some(ns) = mean {
+        let name = dir.strip_prefix(root).unwrap_or(dir).to_string_lossy().replace('\\', "/");
+        measurements.push(Measurement { name, ns });
+    }
+    let mut subdirs: Vec<PathBuf> = dir.read_dir().into_iter().flatten()
+        .filter_map(Result::ok)
+        .map(|e| e.path())
+        .filter(|p| p.is_dir() && p.file_name().map(|n| n != "new" && n != "base" && n != "report").unwrap_or(false))
+        .collect();
+    subdirs.sort();
+    for subdir in subdirs.iter() {
+        criterion_measurements(subdir, root, measurements);
+    }
+}
+
+fn append_history(crate_dir: &Path, measurements: &[Measurement]) -> Result<(), MsgError> {
+    if measurements.is_empty() {
+        return Ok(());
+    }
+    let path = crate_dir.join(HISTORY_FILE);
+    let mut text = fs::read_to_string(&path).unwrap_or_else(|_| HISTORY_HEADER.to_string());
+    let timestamp = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0);
+    for m in measurements.iter() {
+        text.push_str(&format!("{},{},{}\n", timestamp, csv_field(&m.name), m.ns));
+    }
+    fs::write(&path, text).map_err(|err| MsgIo("cannot write bench history", err))
+}
+
+fn csv_field(s: &str) -> String {
+    match s.contains([',', '"', '\n']) {
+        true => format!("\"{}\"", s.replace('"', "\"\"")),
+        false => s.to_string(),
+    }
+}
+
+/// Latest and previous result of each benchmark in a history file, in first-seen order
+fn latest(history: &str) -> Vec<(String, f64, Option<f64>)> {
+    let mut rows: Vec<(String, f64, Option<f64>)> = Vec::new();
+    for line in history.lines().skip(1) {
+        let (_, rest) = match line.split_once(',') {
+            Some(split) => split,
+            None => continue,
+        };
+        let (name, ns) = match rest.rsplit_once(',') {
+            Some((name, ns)) => (name.trim_matches('"').replace("\"\"", "\""), ns),
+            None => continue,
+        };
+        let ns: f64 = match ns.parse() {
+            Ok(ns) => ns,
+            Err(_) => continue,
+        };
+        match rows.iter_mut().find(|row| row.0 == name) {
+            Some(row) => *row = (name, ns, Some(row.1)),
+            None => rows.push((name, ns, None)),
+        }
+    }
+    rows
+}
+
+/// A time in the largest unit that keeps it at least 1
+pub fn format_ns(ns: f64) -> String {
+    match ns {
+        ns if ns >= 1e9 => format!("{:.2} s", ns / 1e9),
+        ns if ns >= 1e6 => format!("{:.2} ms", ns / 1e6),
+        ns if ns >= 1e3 => format!("{:.2} µs", ns / 1e3),
+        ns => format!("{:.1} ns", ns),
+    }
+}
+
+fn escape_html(s: &str) -> String {
+    s.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;")
+}
+
+/// Write `index.html` over every crate staged in `results_dir`
+pub fn write_index(results_dir: &Path) -> Result<(), MsgError> {
+    let mut crates: Vec<String> = results_dir.read_dir()
+        .map_err(|err| MsgIo("cannot read bench results directory", err))?
+        .filter_map(Result::ok)
+        .filter(|e| e.path().is_dir())
+        .map(|e| e.file_name().to_string_lossy().into_owned())
+        .collect();
+    crates.sort();
+
+    let mut html = String::from("<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<title>Benchmarks</title>\n\
+        <style>body { font-family: sans-serif; } td, th { padding: 0.2em 1em; text-align: left; }</style>\n\
+        </head>\n<body>\n<h1>Benchmarks</h1>\n");
+    for name in crates.iter() {
+        let dir = results_dir.join(name);
+        let href = escape_html(name);
+        html.push_str(&format!("<h2>{}</h2>\n<p>", href));
+        let mut links = Vec::new();
+        if dir.join("criterion").join("report").join("index.html").is_file() {
+            links.push(format!("<a href=\"{}/criterion/report/index.html\">Criterion report</a>", href));
+        }
+        if dir.join("libtest.txt").is_file() {
+            links.push(format!("<a href=\"{}/libtest.txt\">libtest output</a>", href));
+        }
+        links.push(format!("<a href=\"{}/{}\">history</a>", href, HISTORY_FILE));
+        html.push_str(&links.join(" | "));
+        html.push_str("</p>\n<table>\n<tr><th>benchmark</th><th>latest</th><th>previous</th><th>change</th></tr>\n");
+        for (bench, ns, previous) in latest(&fs::read_to_string(dir.join(HISTORY_FILE)).unwrap_or_default()) {
+            let (previous, change) = match previous {
+                Some(p) if p > 0.0 => (format_ns(p), format!("{:+.1}%", (ns - p) / p * 100.0)),
+                _ => (String::new(), String::new()),
+            };
+            html.push_str(&format!("<tr><td>{}</td><td>{}</td><td>{}</td><td>{}</td></tr>\n",
+                                   escape_html(&bench), format_ns(ns), previous, change));
+        }
+        html.push_str("</table>\n");
+    }
+    html.push_str("</body>\n</html>\n");
+    fs::write(results_dir.join("index.html"), html).map_err(|err| MsgIo("cannot write bench index", err))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_libtest() {
+        let stdout = "running 2 tests\n\
+                      test bench_add    ... bench:       1,234 ns/iter (+/- 56)\n\
+                      test bench_sort   ... bench:          12.50 ns/iter (+/- 0.30)\n\
+                      test plain ... ok\n";
+        assert_eq!(vec![Measurement { name: "bench_add".into(), ns: 1234.0 },
+                        Measurement { name: "bench_sort".into(), ns: 12.5 }],
+                   parse_libtest(stdout));
+
+        let history = "timestamp,benchmark,ns\n1,add,100\n1,\"sort, big\",5\n2,add,80\n";
+        assert_eq!(vec![("add".to_string(), 80.0, Some(100.0)), ("sort, big".to_string(), 5.0, None)], latest(history));
+        assert_eq!("1.50 µs", format_ns(1500.0));
+    }
+}
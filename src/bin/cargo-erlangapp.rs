@@ -3,7 +3,12 @@ extern crate cargo_erlangapp;
 fn main() {
     let appdir = std::env::current_dir().unwrap();
 
-    let args_string: Vec<String> = std::env::args().collect();
+    let mut args_string: Vec<String> = std::env::args().collect();
+
+    // When run as `cargo erlangapp ...`, cargo passes the subcommand name as the first argument
+    if args_string.get(1).map(|s| s == "erlangapp").unwrap_or(false) {
+        args_string.remove(1);
+    }
 
     cargo_erlangapp::invoke_with_args(&args_string, &appdir);
 }
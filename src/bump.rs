@@ -0,0 +1,130 @@
+//! `version-bump` command: raise crate versions in their `Cargo.toml`, and optionally the
+//! application's own version with them.
+//!
+//! Manifests are edited as text so comments and layout survive.  A crate inheriting its
+//! version from a workspace (`version.workspace = true`) has the workspace's
+//! `[workspace.package]` version bumped instead.
+
+use std::str::FromStr;
+
+/// Which version to move to
+#[derive(Debug, Clone, PartialEq)]
+pub enum Bump {
+    Patch,
+    Minor,
+    Major,
+    /// An explicit version
+    To(String),
+}
+
+impl FromStr for Bump {
+    type Err = ();
+    fn from_str(s: &str) -> Result<Bump, ()> {
+        match s {
+            "patch" => Ok(Bump::Patch),
+            "minor" => Ok(Bump::Minor),
+            "major" => Ok(Bump::Major),
+            _ if release(s).is_some() => Ok(Bump::To(s.to_string())),
+            _ => Err(()),
+        }
+    }
+}
+
+/// Major, minor and patch numbers of a version, and whether it is a pre-release
+fn release(version: &str) -> Option<(u64, u64, u64, bool)> {
+    let version = version.split('+').next().unwrap_or(version);
+    let (numbers, pre) = match version.split_once('-') {
+        Some((numbers, pre)) if !pre.is_empty() => (numbers, true),
+        Some(_) => return None,
+        None => (version, false),
+    };
+    let mut parts = numbers.split('.').map(|n| n.parse::<u64>());
+    match (parts.next(), parts.next(), parts.next(), parts.next()) {
+        (Some(Ok(major)), Some(Ok(minor)), Some(Ok(patch)), None) => Some((major, minor, patch, pre)),
+        _ => None,
+    }
+}
+
+impl Bump {
+    /// The version after `version`.  As in semver, bumping a pre-release first releases it:
+    /// `1.3.0-rc.1` bumped by minor is `1.3.0`.
+    pub fn apply(&self, version: &str) -> Option<String> {
+        let (major, minor, patch, pre) = release(version)?;
+        let (major, minor, patch) = match *self {
+            Bump::To(ref version) => return Some(version.clone()),
+            Bump::Patch if pre => (major, minor, patch),
+            Bump::Patch => (major, minor, patch + 1),
+            Bump::Minor if pre && patch == 0 => (major, minor, 0),
+            Bump::Minor => (major, minor + 1, 0),
+            Bump::Major if pre && minor == 0 && patch == 0 => (major, 0, 0),
+            Bump::Major => (major + 1, 0, 0),
+        };
+        Some(format!("{}.{}.{}", major, minor, patch))
+    }
+}
+
+/// Replace the literal `version = "..."` in a manifest's `[package]` section (or
+/// `[workspace.package]`, given that as `section`).  `None` if there is no literal version
+/// there, e.g. because it is inherited from the workspace.
+pub fn set_manifest_version(text: &str, section: &str, version: &str) -> Option<String> {
+    let header = format!("[{}]", section);
+    let mut in_section = false;
+    let mut out = String::with_capacity(text.len());
+    let mut replaced = false;
+    for line in text.split_inclusive('\n') {
+        let trimmed = line.trim();
+        if trimmed.starts_with('[') {
+            in_section = trimmed == header;
+        } else if in_section && !replaced && trimmed.strip_prefix("version").map(|r| r.trim_start().starts_with('=')).unwrap_or(false) {
+            let open = line.find('=').and_then(|eq| line[eq..].find('"').map(|q| eq + q));
+            let close = open.and_then(|open| line[open + 1..].find('"').map(|q| open + 1 + q));
+            if let (Some(open), Some(close)) = (open, close) {
+                out.push_str(&format!("{}\"{}\"{}", &line[..open], version, &line[close + 1..]));
+                replaced = true;
+                continue;
+            }
+        }
+        out.push_str(line);
+    }
+    match replaced {
+        true => Some(out),
+        false => None,
+    }
+}
+
+/// True if a manifest's package takes its version from the workspace
+pub fn inherits_version(text: &str) -> bool {
+    text.lines()
+        .map(|line| line.split('#').next().unwrap_or("").replace(' ', ""))
+        .any(|line| line == "version.workspace=true" || line == "version={workspace=true}")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_bump() {
+        assert_eq!(Some("1.4.3".to_string()), Bump::Patch.apply("1.4.2"));
+        assert_eq!(Some("1.5.0".to_string()), Bump::Minor.apply("1.4.2"));
+        assert_eq!(Some("2.0.0".to_string()), Bump::Major.apply("1.4.2+build.5"));
+        assert_eq!(Some("1.3.0".to_string()), Bump::Minor.apply("1.3.0-rc.1"));
+        assert_eq!(Some("1.3.1".to_string()), Bump::Patch.apply("1.3.1-rc.1"));
+        assert_eq!(None, Bump::Patch.apply("1.3"));
+        assert_eq!(Ok(Bump::To("2.0.0-rc.1".to_string())), "2.0.0-rc.1".parse());
+        assert!("v2".parse::<Bump>().is_err());
+
+        let manifest = "[package]\nname = \"mynif\"\nversion = \"0.1.0\"   # keep in step\n\n\
+                        [dependencies]\nrustler = { version = \"0.36\" }\nversion = \"9\"\n";
+        assert_eq!(Some("[package]\nname = \"mynif\"\nversion = \"0.2.0\"   # keep in step\n\n\
+                         [dependencies]\nrustler = { version = \"0.36\" }\nversion = \"9\"\n".to_string()),
+                   set_manifest_version(manifest, "package", "0.2.0"));
+
+        let member = "[package]\nname = \"mynif\"\nversion.workspace = true\n";
+        assert!(inherits_version(member));
+        assert_eq!(None, set_manifest_version(member, "package", "0.2.0"));
+        assert_eq!(Some("[workspace]\nmembers = [\"a\"]\n[workspace.package]\nversion = \"0.2.0\"\n".to_string()),
+                   set_manifest_version("[workspace]\nmembers = [\"a\"]\n[workspace.package]\nversion = \"0.1.0\"\n",
+                                        "workspace.package", "0.2.0"));
+    }
+}
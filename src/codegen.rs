@@ -0,0 +1,865 @@
+//! `codegen` command: Rust constants from the `-define` macros and structs from the
+//! `-record` definitions in an application's `include/*.hrl`, so protocol numbers, flags
+//! and messages are declared once, in Erlang.
+//!
+//! Macros without arguments whose bodies are integers (including `16#FF` and `$a`),
+//! integer expressions over `+ - * div rem band bor bxor bsl bsr bnot` and earlier
+//! macros, floats, strings and atoms become constants.  Records whose fields all have
+//! types we can map become structs, encoded either by rustler or, for port programs, in
+//! the external term format.  Anything else is skipped.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::convert::TryFrom;
+use std::fmt::{self, Display};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use native_hrl;
+use MsgError;
+use MsgError::*;
+
+/// A constant's Rust value
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    Int(i64),
+    Float(f64),
+    /// A string, or an atom's name
+    Str(String),
+}
+
+/// A `-define` turned into a constant
+#[derive(Debug, PartialEq)]
+pub struct Define {
+    /// Macro name as written in Erlang
+    pub name: String,
+    pub value: Value,
+}
+
+/// A record field's type, as far as it maps to Rust
+#[derive(Debug, Clone, PartialEq)]
+pub enum Type {
+    Int,
+    Float,
+    Bool,
+    /// `binary()`, as a UTF-8 string
+    Str,
+    Atom,
+    List(Box<Type>),
+    /// Another record, by Erlang name
+    Record(String),
+}
+
+/// A `-record` turned into a struct
+#[derive(Debug, PartialEq)]
+pub struct Record {
+    pub name: String,
+    pub fields: Vec<(String, Type)>,
+}
+
+/// How generated record structs are encoded
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RecordEncoding {
+    /// `#[derive(rustler::NifRecord)]`, for NIFs
+    Rustler,
+    /// External term format (`term_to_binary/1`), for port programs
+    Term,
+}
+
+impl Display for RecordEncoding {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            RecordEncoding::Rustler => write!(f, "rustler"),
+            RecordEncoding::Term => write!(f, "term"),
+        }
+    }
+}
+
+impl FromStr for RecordEncoding {
+    type Err = ();
+    fn from_str(s: &str) -> Result<RecordEncoding, ()> {
+        match s {
+            "rustler" => Ok(RecordEncoding::Rustler),
+            "term" => Ok(RecordEncoding::Term),
+            _ => Err(()),
+        }
+    }
+}
+
+/// What one header defines, and the macros and records (as `#name{}`) that could not be
+/// translated
+#[derive(Debug)]
+pub struct Header {
+    /// Path relative to the application, for the generated comments
+    pub path: String,
+    pub defines: Vec<Define>,
+    pub records: Vec<Record>,
+    pub skipped: Vec<String>,
+}
+
+/// Macros and records defined so far, which later definitions may refer to
+#[derive(Default)]
+struct Scope {
+    macros: BTreeMap<String, Value>,
+    records: BTreeSet<String>,
+}
+
+/// The application's `include/*.hrl`, in name order
+pub fn headers(appdir: &Path) -> Result<Vec<PathBuf>, MsgError> {
+    let mut paths: Vec<PathBuf> = match appdir.join("include").read_dir() {
+        Ok(entries) => entries.filter_map(Result::ok)
+            .map(|e| e.path())
+            .filter(|p| p.extension().map(|ext| ext == "hrl").unwrap_or(false))
+            .collect(),
+        Err(_) => return Err(Msg("codegen: no include directory in the application")),
+    };
+    paths.sort();
+    Ok(paths)
+}
+
+/// Read the constants and records of each header.  Definitions may refer to any made
+/// earlier, in the same header or a previous one.  Headers generated from Rust constants
+/// are left out, so their constants don't come back.
+pub fn read_headers(appdir: &Path, paths: &[PathBuf]) -> Result<Vec<Header>, MsgError> {
+    let mut known = Scope::default();
+    let mut headers = Vec::new();
+    for path in paths.iter() {
+        let text = fs::read_to_string(path).map_err(|err| MsgIo("cannot read header", err))?;
+        if text.starts_with(native_hrl::GENERATED_MARKER) {
+            continue;
+        }
+        let relative = path.strip_prefix(appdir).unwrap_or(path).to_string_lossy().replace('\\', "/");
+        headers.push(parse(&relative, &text, &mut known));
+    }
+    Ok(headers)
+}
+
+/// Parse the `-define`s and `-record`s of a header, resolving references against `known`
+/// and adding the new definitions to it
+fn parse(path: &str, text: &str, known: &mut Scope) -> Header {
+    let mut header = Header { path: path.to_string(), defines: Vec::new(), records: Vec::new(), skipped: Vec::new() };
+    let tokens = tokenize(&strip_comments(text));
+    let mut i = 0;
+    while i < tokens.len() {
+        let start = i;
+        i += 1;
+        let attribute = match (tokens[start].as_str(), tokens.get(start + 1), tokens.get(start + 2)) {
+            ("-", Some(attribute), Some(paren)) if (attribute == "define" || attribute == "record") && paren == "(" =>
+                attribute.clone(),
+            _ => continue,
+        };
+        // the body runs to the `)` closing `-define(` or `-record(`
+        let mut depth = 0;
+        let mut end = start + 2;
+        while end < tokens.len() {
+            match tokens[end].as_str() {
+                "(" => depth += 1,
+                ")" => {
+                    depth -= 1;
+                    if depth == 0 {
+                        break;
+                    }
+                },
+                _ => (),
+            }
+            end += 1;
+        }
+        i = end + 1;
+        let name = match tokens.get(start + 3) {
+            Some(name) if is_name(name) => name.clone(),
+            _ => continue,
+        };
+        let body = &tokens[(start + 5).min(end)..end.min(tokens.len())];
+        if attribute == "record" {
+            match record(&name, body, known) {
+                Some(record) => {
+                    known.records.insert(name);
+                    header.records.push(record);
+                },
+                None => header.skipped.push(format!("#{}{{}}", name)),
+            }
+            continue;
+        }
+        if tokens.get(start + 4).map(String::as_str) != Some(",") || known.macros.contains_key(&name) {
+            header.skipped.push(name); // takes arguments, or already defined
+            continue;
+        }
+        match evaluate(body, &known.macros) {
+            Some(value) => {
+                known.macros.insert(name.clone(), value.clone());
+                header.defines.push(Define { name, value });
+            },
+            None => header.skipped.push(name),
+        }
+    }
+    header
+}
+
+/// A record from the tokens of `{field = default :: type(), ...}`, if every field is typed
+/// with a type we can map
+fn record(name: &str, body: &[String], known: &Scope) -> Option<Record> {
+    let body = body.strip_prefix(&["{".to_string()][..])?.strip_suffix(&["}".to_string()][..])?;
+    let mut fields = Vec::new();
+    for field in split_top_level(body).into_iter().filter(|f| !f.is_empty()) {
+        let field_name = field[0].clone();
+        if !field_name.starts_with(|c: char| c.is_ascii_lowercase()) || RUST_KEYWORDS.contains(&field_name.as_str()) {
+            return None;
+        }
+        let ty = field.iter().position(|t| t == "::")
+            .and_then(|colons| field_type(&field[colons + 1..], known))?;
+        fields.push((field_name, ty));
+    }
+    match fields.is_empty() || fields.len() > 254 {
+        true => None,
+        false => Some(Record { name: name.to_string(), fields }),
+    }
+}
+
+static RUST_KEYWORDS: &[&str] = &[
+    "as", "async", "await", "break", "const", "continue", "crate", "dyn", "else", "enum", "extern",
+    "false", "fn", "for", "if", "impl", "in", "let", "loop", "match", "mod", "move", "mut", "pub",
+    "ref", "return", "self", "static", "struct", "super", "trait", "true", "type", "unsafe", "use",
+    "where", "while",
+];
+
+/// Split tokens at commas outside brackets
+fn split_top_level(tokens: &[String]) -> Vec<&[String]> {
+    let mut parts = Vec::new();
+    let (mut depth, mut start) = (0i32, 0);
+    for (i, token) in tokens.iter().enumerate() {
+        match token.as_str() {
+            "(" | "[" | "{" => depth += 1,
+            ")" | "]" | "}" => depth -= 1,
+            "," if depth == 0 => {
+                parts.push(&tokens[start..i]);
+                start = i + 1;
+            },
+            _ => (),
+        }
+    }
+    parts.push(&tokens[start..]);
+    parts
+}
+
+/// The Rust mapping of an Erlang type
+fn field_type(tokens: &[String], known: &Scope) -> Option<Type> {
+    let strs: Vec<&str> = tokens.iter().map(String::as_str).collect();
+    match strs.as_slice() {
+        ["integer", "(", ")"] | ["non_neg_integer", "(", ")"] | ["pos_integer", "(", ")"]
+        | ["neg_integer", "(", ")"] | ["byte", "(", ")"] | ["char", "(", ")"] => Some(Type::Int),
+        [low, ".", ".", high] if integer(low, &known.macros).is_some() && integer(high, &known.macros).is_some() =>
+            Some(Type::Int),
+        ["float", "(", ")"] => Some(Type::Float),
+        ["boolean", "(", ")"] => Some(Type::Bool),
+        ["binary", "(", ")"] | ["unicode", ":", "unicode_binary", "(", ")"] => Some(Type::Str),
+        ["atom", "(", ")"] => Some(Type::Atom),
+        ["list", "(", .., ")"] | ["[", .., "]"] if tokens.len() > 3 || strs[0] == "[" =>
+            field_type(&tokens[if strs[0] == "[" { 1 } else { 2 }..tokens.len() - 1], known)
+                .map(|t| Type::List(Box::new(t))),
+        ["#", name, "{", "}"] if known.records.contains(*name) => Some(Type::Record(name.to_string())),
+        _ => None,
+    }
+}
+
+fn strip_comments(text: &str) -> String {
+    let mut out = String::new();
+    for line in text.lines() {
+        let (mut quoted, mut escaped, mut char_literal) = (false, false, false);
+        for c in line.chars() {
+            if char_literal {
+                char_literal = c == '\\';
+            } else if quoted {
+                match c {
+                    _ if escaped => escaped = false,
+                    '\\' => escaped = true,
+                    '"' => quoted = false,
+                    _ => (),
+                }
+            } else if c == '%' {
+                break;
+            } else if c == '"' {
+                quoted = true;
+            } else if c == '$' {
+                char_literal = true;
+            }
+            out.push(c);
+        }
+        out.push('\n');
+    }
+    out
+}
+
+/// Split Erlang source into tokens: names, numbers, strings, `$` characters, `?` macro
+/// references, quoted atoms and punctuation
+fn tokenize(text: &str) -> Vec<String> {
+    let chars: Vec<char> = text.chars().collect();
+    let mut tokens = Vec::new();
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        let start = i;
+        if c.is_whitespace() {
+            i += 1;
+            continue;
+        } else if c == '"' || c == '\'' {
+            i += 1;
+            while i < chars.len() && chars[i] != c {
+                i += if chars[i] == '\\' { 2 } else { 1 };
+            }
+            i += 1;
+        } else if c == ':' && chars.get(i + 1) == Some(&':') {
+            i += 2;
+        } else if c == '$' {
+            i += if chars.get(i + 1) == Some(&'\\') { 3 } else { 2 };
+        } else if c.is_ascii_digit() {
+            while i < chars.len() && (chars[i].is_ascii_alphanumeric() || chars[i] == '#' || chars[i] == '_'
+                                      || (chars[i] == '.' && chars.get(i + 1).map(|d| d.is_ascii_digit()).unwrap_or(false))) {
+                i += 1;
+            }
+        } else if c.is_alphabetic() || c == '_' || c == '?' {
+            i += 1;
+            while i < chars.len() && (chars[i].is_alphanumeric() || chars[i] == '_' || chars[i] == '@') {
+                i += 1;
+            }
+        } else {
+            i += 1;
+        }
+        tokens.push(chars[start..i.min(chars.len())].iter().collect());
+    }
+    tokens
+}
+
+fn is_name(token: &str) -> bool {
+    token.chars().next().map(|c| c.is_alphabetic() || c == '_').unwrap_or(false)
+}
+
+/// The value of a macro body, if it is a constant
+fn evaluate(tokens: &[String], known: &BTreeMap<String, Value>) -> Option<Value> {
+    match tokens {
+        [] => None,
+        [token] if token.starts_with('"') || token.starts_with('\'') => Some(Value::Str(unquote(token)?)),
+        [token] if token.chars().next()?.is_lowercase() => Some(Value::Str(token.clone())),
+        [token] if token.contains('.') => token.replace('_', "").parse().ok().map(Value::Float),
+        [minus, token] if minus == "-" && token.contains('.') => token.replace('_', "").parse::<f64>().ok().map(|f| Value::Float(-f)),
+        [token] if token.starts_with('?') => known.get(&token[1..]).cloned(),
+        _ if tokens.iter().all(|t| t.starts_with('"')) =>
+            tokens.iter().map(|t| unquote(t)).collect::<Option<String>>().map(Value::Str),
+        _ => {
+            let mut expr = Expr { tokens, pos: 0, known };
+            let value = expr.binary(0)?;
+            match expr.pos == tokens.len() {
+                true => Some(Value::Int(value)),
+                false => None,
+            }
+        },
+    }
+}
+
+/// Contents of a string or quoted atom token
+fn unquote(token: &str) -> Option<String> {
+    let quote = token.chars().next()?;
+    let s = token.strip_prefix(quote)?.strip_suffix(quote).filter(|_| token.len() >= 2)?;
+    unescape(s)
+}
+
+fn unescape(s: &str) -> Option<String> {
+    let mut out = String::new();
+    let mut chars = s.chars();
+    while let Some(c) = chars.next() {
+        out.push(match c {
+            '\\' => escape(chars.next()?)?,
+            c => c,
+        });
+    }
+    Some(out)
+}
+
+fn escape(c: char) -> Option<char> {
+    match c {
+        'n' => Some('\n'),
+        't' => Some('\t'),
+        'r' => Some('\r'),
+        's' => Some(' '),
+        '0' => Some('\0'),
+        'e' => Some('\x1b'),
+        '\\' | '"' | '\'' => Some(c),
+        _ => None,
+    }
+}
+
+/// Integer expression evaluator, by precedence climbing
+struct Expr<'a> {
+    tokens: &'a [String],
+    pos: usize,
+    known: &'a BTreeMap<String, Value>,
+}
+
+impl<'a> Expr<'a> {
+    /// Binding strength of a binary operator, as in Erlang
+    fn precedence(op: &str) -> Option<u8> {
+        match op {
+            "+" | "-" | "bor" | "bxor" | "bsl" | "bsr" => Some(1),
+            "*" | "div" | "rem" | "band" => Some(2),
+            _ => None,
+        }
+    }
+
+    fn binary(&mut self, min: u8) -> Option<i64> {
+        let mut left = self.unary()?;
+        while let Some(prec) = self.tokens.get(self.pos).and_then(|op| Expr::precedence(op)) {
+            if prec <= min {
+                break;
+            }
+            let op = self.tokens[self.pos].clone();
+            self.pos += 1;
+            let right = self.binary(prec)?;
+            left = match op.as_str() {
+                "+" => left.checked_add(right)?,
+                "-" => left.checked_sub(right)?,
+                "*" => left.checked_mul(right)?,
+                "div" => left.checked_div(right)?,
+                "rem" => left.checked_rem(right)?,
+                "band" => left & right,
+                "bor" => left | right,
+                "bxor" => left ^ right,
+                "bsl" => left.checked_shl(u32::try_from(right).ok()?).filter(|v| v >> right == left)?,
+                "bsr" => left.checked_shr(u32::try_from(right).ok()?)?,
+                _ => return None,
+            };
+        }
+        Some(left)
+    }
+
+    fn unary(&mut self) -> Option<i64> {
+        let token = self.tokens.get(self.pos)?.clone();
+        self.pos += 1;
+        match token.as_str() {
+            "-" => self.unary()?.checked_neg(),
+            "+" => self.unary(),
+            "bnot" => self.unary().map(|v| !v),
+            "(" => {
+                let value = self.binary(0)?;
+                match self.tokens.get(self.pos).map(String::as_str) {
+                    Some(")") => {
+                        self.pos += 1;
+                        Some(value)
+                    },
+                    _ => None,
+                }
+            },
+            _ => integer(&token, self.known),
+        }
+    }
+}
+
+/// An integer literal, character or integer macro reference
+fn integer(token: &str, known: &BTreeMap<String, Value>) -> Option<i64> {
+    if let Some(name) = token.strip_prefix('?') {
+        return match known.get(name) {
+            Some(&Value::Int(n)) => Some(n),
+            _ => None,
+        };
+    }
+    if let Some(c) = token.strip_prefix('$') {
+        let mut chars = c.chars();
+        let c = match chars.next()? {
+            '\\' => escape(chars.next()?)?,
+            c => c,
+        };
+        return Some(c as i64);
+    }
+    let token = token.replace('_', "");
+    match token.split_once('#') {
+        Some((radix, digits)) => i64::from_str_radix(digits, radix.parse().ok().filter(|r| (2..=36).contains(r))?).ok(),
+        None => token.parse().ok(),
+    }
+}
+
+/// Rust constant name for an Erlang macro name: upper snake case
+pub fn const_name(name: &str) -> String {
+    let mut out = String::new();
+    let mut prev_lower = false;
+    for c in name.chars() {
+        if c.is_uppercase() && prev_lower {
+            out.push('_');
+        }
+        prev_lower = c.is_lowercase() || c.is_ascii_digit();
+        out.extend(c.to_uppercase());
+    }
+    out
+}
+
+/// Rust struct name for an Erlang record name: upper camel case
+pub fn struct_name(name: &str) -> String {
+    name.split('_').filter(|w| !w.is_empty())
+        .map(|word| {
+            let mut chars = word.chars();
+            chars.next().map(|c| c.to_uppercase().chain(chars).collect::<String>()).unwrap_or_default()
+        })
+        .collect()
+}
+
+/// Write the module, leaving it untouched if unchanged so the crate isn't rebuilt.
+/// Returns whether it was written.
+pub fn generate(path: &Path, headers: &[Header], encoding: RecordEncoding) -> Result<bool, MsgError> {
+    let text = render(headers, encoding);
+    if fs::read_to_string(path).map(|old| old == text).unwrap_or(false) {
+        return Ok(false);
+    }
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir).map_err(|err| MsgIo("cannot create codegen module directory", err))?;
+    }
+    fs::write(path, text).map_err(|err| MsgIo("cannot write codegen module", err))?;
+    Ok(true)
+}
+
+fn render(headers: &[Header], encoding: RecordEncoding) -> String {
+    let mut text = String::from("\
+// Generated by cargo-erlangapp from the application's include/*.hrl; do not edit.
+#![allow(dead_code)]
+");
+    for header in headers.iter().filter(|h| !h.defines.is_empty() || !h.records.is_empty()) {
+        text.push_str(&format!("\n// {}\n", header.path));
+        for define in header.defines.iter() {
+            let (ty, value) = match define.value {
+                Value::Int(n) => ("i64", n.to_string()),
+                Value::Float(f) => ("f64", format!("{:?}", f)),
+                Value::Str(ref s) => ("&str", format!("{:?}", s)),
+            };
+            text.push_str(&format!("pub const {}: {} = {};\n", const_name(&define.name), ty, value));
+        }
+        for record in header.records.iter() {
+            text.push_str(&render_record(record, encoding));
+        }
+    }
+    if encoding == RecordEncoding::Term && headers.iter().any(|h| !h.records.is_empty()) {
+        text.push_str(TERM_CODEC);
+    }
+    text
+}
+
+fn rust_type(ty: &Type, encoding: RecordEncoding) -> String {
+    match *ty {
+        Type::Int => "i64".to_string(),
+        Type::Float => "f64".to_string(),
+        Type::Bool => "bool".to_string(),
+        Type::Str => "String".to_string(),
+        Type::Atom if encoding == RecordEncoding::Rustler => "rustler::Atom".to_string(),
+        Type::Atom => "Atom".to_string(),
+        Type::List(ref t) => format!("Vec<{}>", rust_type(t, encoding)),
+        Type::Record(ref name) => struct_name(name),
+    }
+}
+
+fn render_record(record: &Record, encoding: RecordEncoding) -> String {
+    let name = struct_name(&record.name);
+    let mut text = format!("\n/// `#{}{{}}`\n", record.name);
+    match encoding {
+        RecordEncoding::Rustler => text.push_str(&format!(
+            "#[derive(Debug, Clone, PartialEq, rustler::NifRecord)]\n#[tag = \"{}\"]\n", record.name)),
+        RecordEncoding::Term => text.push_str("#[derive(Debug, Clone, PartialEq)]\n"),
+    }
+    text.push_str(&format!("pub struct {} {{\n", name));
+    for (field, ty) in record.fields.iter() {
+        text.push_str(&format!("    pub {}: {},\n", field, rust_type(ty, encoding)));
+    }
+    text.push_str("}\n");
+    if encoding == RecordEncoding::Term {
+        let encode: String = record.fields.iter().map(|(field, _)| format!("        self.{}.encode(out);\n", field)).collect();
+        let decode: String = record.fields.iter().map(|(field, _)| format!("            {}: Term::decode(input)?,\n", field)).collect();
+        text.push_str(&format!("
+impl Term for {name} {{
+    fn encode(&self, out: &mut Vec<u8>) {{
+        out.extend([104, {arity}]);
+        encode_atom({tag:?}, out);
+{encode}    }}
+
+    fn decode(input: &mut &[u8]) -> Option<{name}> {{
+        if take(input, 2)? != [104, {arity}] || decode_atom(input)? != {tag:?} {{
+            return None;
+        }}
+        Some({name} {{
+{decode}        }})
+    }}
+}}
+", name = name, arity = record.fields.len() + 1, tag = record.name, encode = encode, decode = decode));
+    }
+    text
+}
+
+/// Term codec for record structs, as `term_to_binary/1` and `binary_to_term/1` see them
+static TERM_CODEC: &str = "
+/// An atom in a record field
+#[derive(Debug, Clone, PartialEq)]
+pub struct Atom(pub String);
+
+/// External term format encoding
+pub trait Term: Sized {
+    fn encode(&self, out: &mut Vec<u8>);
+    fn decode(input: &mut &[u8]) -> Option<Self>;
+}
+
+/// Encode as `term_to_binary/1` does
+pub fn to_binary<T: Term>(value: &T) -> Vec<u8> {
+    let mut out = vec![131];
+    value.encode(&mut out);
+    out
+}
+
+/// Decode the output of `term_to_binary/1`
+pub fn from_binary<T: Term>(mut input: &[u8]) -> Option<T> {
+    if take(&mut input, 1)? != [131] {
+        return None;
+    }
+    let value = T::decode(&mut input)?;
+    match input.is_empty() {
+        true => Some(value),
+        false => None,
+    }
+}
+
+fn take<'a>(input: &mut &'a [u8], n: usize) -> Option<&'a [u8]> {
+    if input.len() < n {
+        return None;
+    }
+    let (head, rest) = input.split_at(n);
+    *input = rest;
+    Some(head)
+}
+
+fn take_u16(input: &mut &[u8]) -> Option<usize> {
+    let b = take(input, 2)?;
+    Some(u16::from_be_bytes([b[0], b[1]]) as usize)
+}
+
+fn take_u32(input: &mut &[u8]) -> Option<usize> {
+    let b = take(input, 4)?;
+    Some(u32::from_be_bytes([b[0], b[1], b[2], b[3]]) as usize)
+}
+
+fn encode_atom(name: &str, out: &mut Vec<u8>) {
+    match name.len() {
+        n if n < 256 => out.extend([119, n as u8]),
+        n => {
+            out.push(118);
+            out.extend((n as u16).to_be_bytes());
+        },
+    }
+    out.extend(name.as_bytes());
+}
+
+fn decode_atom(input: &mut &[u8]) -> Option<String> {
+    let len = match take(input, 1)?[0] {
+        119 | 115 => take(input, 1)?[0] as usize,
+        118 | 100 => take_u16(input)?,
+        _ => return None,
+    };
+    String::from_utf8(take(input, len)?.to_vec()).ok()
+}
+
+impl Term for i64 {
+    fn encode(&self, out: &mut Vec<u8>) {
+        match *self {
+            0..=255 => out.extend([97, *self as u8]),
+            n if n == n as i32 as i64 => {
+                out.push(98);
+                out.extend((n as i32).to_be_bytes());
+            },
+            n => {
+                let magnitude = n.unsigned_abs();
+                let len = 8 - magnitude.leading_zeros() as usize / 8;
+                out.extend([110, len as u8, (n < 0) as u8]);
+                out.extend(&magnitude.to_le_bytes()[..len]);
+            },
+        }
+    }
+
+    fn decode(input: &mut &[u8]) -> Option<i64> {
+        match take(input, 1)?[0] {
+            97 => Some(take(input, 1)?[0] as i64),
+            98 => {
+                let b = take(input, 4)?;
+                Some(i32::from_be_bytes([b[0], b[1], b[2], b[3]]) as i64)
+            },
+            110 => {
+                let header = take(input, 2)?;
+                let digits = take(input, header[0] as usize)?;
+                if digits.len() > 8 {
+                    return None;
+                }
+                let mut bytes = [0; 8];
+                bytes[..digits.len()].copy_from_slice(digits);
+                let magnitude = u64::from_le_bytes(bytes);
+                match header[1] {
+                    0 if magnitude <= i64::MAX as u64 => Some(magnitude as i64),
+                    1 if magnitude <= 1 << 63 => Some((magnitude as i64).wrapping_neg()),
+                    _ => None,
+                }
+            },
+            _ => None,
+        }
+    }
+}
+
+impl Term for f64 {
+    fn encode(&self, out: &mut Vec<u8>) {
+        out.push(70);
+        out.extend(self.to_be_bytes());
+    }
+
+    fn decode(input: &mut &[u8]) -> Option<f64> {
+        if take(input, 1)? != [70] {
+            return None;
+        }
+        let mut bytes = [0; 8];
+        bytes.copy_from_slice(take(input, 8)?);
+        Some(f64::from_be_bytes(bytes))
+    }
+}
+
+impl Term for bool {
+    fn encode(&self, out: &mut Vec<u8>) {
+        encode_atom(if *self { \"true\" } else { \"false\" }, out);
+    }
+
+    fn decode(input: &mut &[u8]) -> Option<bool> {
+        match decode_atom(input)?.as_str() {
+            \"true\" => Some(true),
+            \"false\" => Some(false),
+            _ => None,
+        }
+    }
+}
+
+impl Term for String {
+    fn encode(&self, out: &mut Vec<u8>) {
+        out.push(109);
+        out.extend((self.len() as u32).to_be_bytes());
+        out.extend(self.as_bytes());
+    }
+
+    fn decode(input: &mut &[u8]) -> Option<String> {
+        if take(input, 1)? != [109] {
+            return None;
+        }
+        let len = take_u32(input)?;
+        String::from_utf8(take(input, len)?.to_vec()).ok()
+    }
+}
+
+impl Term for Atom {
+    fn encode(&self, out: &mut Vec<u8>) {
+        encode_atom(&self.0, out);
+    }
+
+    fn decode(input: &mut &[u8]) -> Option<Atom> {
+        decode_atom(input).map(Atom)
+    }
+}
+
+impl<T: Term> Term for Vec<T> {
+    fn encode(&self, out: &mut Vec<u8>) {
+        if !self.is_empty() {
+            out.push(108);
+            out.extend((self.len() as u32).to_be_bytes());
+            for element in self.iter() {
+                element.encode(out);
+            }
+        }
+        out.push(106);
+    }
+
+    fn decode(input: &mut &[u8]) -> Option<Vec<T>> {
+        match take(input, 1)?[0] {
+            106 => Some(Vec::new()),
+            // a list of small integers, sent as a string
+            107 => {
+                let len = take_u16(input)?;
+                take(input, len)?.iter().map(|b| T::decode(&mut &[97, *b][..])).collect()
+            },
+            108 => {
+                let len = take_u32(input)?;
+                let elements = (0..len).map(|_| T::decode(input)).collect::<Option<Vec<T>>>()?;
+                match take(input, 1)? == [106] {
+                    true => Some(elements),
+                    false => None,
+                }
+            },
+            _ => None,
+        }
+    }
+}
+";
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        let mut known = Scope::default();
+        let header = parse("include/proto.hrl", r#"
+            %% Protocol constants
+            -define(VERSION, 3).          % bumped for framing
+            -define(MAGIC, 16#CAFE).
+            -define(SEP, $,).
+            -define(FLAG_ACK, (1 bsl 2)).
+            -define(FLAGS, ?FLAG_ACK bor 1).
+            -define(NEG, -(2 + 3) * 4).
+            -define(RATIO, 0.75).
+            -define(NAME, "pro" "to\n").
+            -define(defaultMode, passive).
+            -define(square(X), X * X).
+            -define(NOW, erlang:monotonic_time()).
+            -define(VERSION, 4).
+        "#, &mut known);
+        let value = |name: &str| header.defines.iter().find(|d| d.name == name).map(|d| d.value.clone());
+        assert_eq!(Some(Value::Int(3)), value("VERSION"));
+        assert_eq!(Some(Value::Int(0xcafe)), value("MAGIC"));
+        assert_eq!(Some(Value::Int(',' as i64)), value("SEP"));
+        assert_eq!(Some(Value::Int(4)), value("FLAG_ACK"));
+        assert_eq!(Some(Value::Int(5)), value("FLAGS"));
+        assert_eq!(Some(Value::Int(-20)), value("NEG"));
+        assert_eq!(Some(Value::Float(0.75)), value("RATIO"));
+        assert_eq!(Some(Value::Str("proto\n".into())), value("NAME"));
+        assert_eq!(Some(Value::Str("passive".into())), value("defaultMode"));
+        assert_eq!(vec!["square", "NOW", "VERSION"], header.skipped);
+
+        let text = render(&[header], RecordEncoding::Rustler);
+        assert!(text.contains("// include/proto.hrl\npub const VERSION: i64 = 3;\n"));
+        assert!(text.contains("pub const NAME: &str = \"proto\\n\";\n"));
+        assert!(text.contains("pub const DEFAULT_MODE: &str = \"passive\";\n"));
+        assert!(text.contains("pub const RATIO: f64 = 0.75;\n"));
+    }
+
+    #[test]
+    fn test_records() {
+        let mut known = Scope::default();
+        let header = parse("include/msgs.hrl", r#"
+            -define(MAX, 100).
+            -record(point, {x :: integer(), y = 0 :: 0..?MAX}).
+            -record(shape_msg, {id :: pos_integer(), label = <<>> :: binary(),
+                                points = [] :: [#point{}], kind :: atom(), tags :: list(binary())}).
+            -record(state, {socket, pending = []}).
+            -record(bad, {type :: integer()}).
+        "#, &mut known);
+        assert_eq!(vec!["#state{}", "#bad{}"], header.skipped);
+        assert_eq!(Record { name: "point".into(), fields: vec![("x".into(), Type::Int), ("y".into(), Type::Int)] },
+                   header.records[0]);
+        assert_eq!(vec![("id".to_string(), Type::Int), ("label".to_string(), Type::Str),
+                        ("points".to_string(), Type::List(Box::new(Type::Record("point".into())))),
+                        ("kind".to_string(), Type::Atom), ("tags".to_string(), Type::List(Box::new(Type::Str)))],
+                   header.records[1].fields);
+        assert_eq!("ShapeMsg", struct_name("shape_msg"));
+
+        let text = render(&[header], RecordEncoding::Rustler);
+        assert!(text.contains("#[derive(Debug, Clone, PartialEq, rustler::NifRecord)]\n#[tag = \"shape_msg\"]\npub struct ShapeMsg {\n"));
+        assert!(text.contains("    pub points: Vec<Point>,\n    pub kind: rustler::Atom,\n"));
+        assert!(!text.contains("impl Term"));
+
+        let header = parse("include/msgs.hrl", "-record(ping, {seq :: integer()}).", &mut Scope::default());
+        let text = render(&[header], RecordEncoding::Term);
+        assert!(text.contains("        out.extend([104, 2]);\n        encode_atom(\"ping\", out);\n        self.seq.encode(out);\n"));
+        assert!(text.contains("pub fn from_binary<T: Term>"));
+    }
+}
@@ -0,0 +1,1105 @@
+//! Application configuration, read from `erlangapp.toml` in the application directory.
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use toml;
+
+use app_version::VersionPolicy;
+use codegen::RecordEncoding;
+use link::{self, Cfg, Kind};
+use dist::Compression;
+use env_policy;
+use otp;
+use pkg_config;
+use policy::Policy;
+use qemu;
+use MsgError;
+use MsgError::*;
+
+pub const CONFIG_FILE: &str = "erlangapp.toml";
+
+/// Contents of `erlangapp.toml`.  A missing file yields the defaults.
+#[derive(Debug)]
+pub struct Config {
+    /// `[rebar-profiles]`: rebar3 profile name -> cargo profile name
+    pub rebar_profiles: BTreeMap<String, String>,
+    /// `umbrella-dirs`: directories holding the applications of an umbrella project
+    pub umbrella_dirs: Vec<String>,
+    /// `crates.include`: the only directories under `crates/` to process, when present
+    pub crate_include: Option<Vec<String>>,
+    /// `crates.exclude`: directories under `crates/` to ignore
+    pub crate_exclude: Vec<String>,
+    /// `[external-crates]`: crates outside the application to build and install
+    pub external_crates: Vec<ExternalCrate>,
+    /// `[targets]`: crate name -> the only targets of that crate to build and install.  When
+    /// present, crates not listed install nothing.
+    pub target_set: Option<BTreeMap<String, Vec<String>>>,
+    /// `[elixir-modules]`: crate name -> Elixir module its dylib is named after
+    pub elixir_modules: BTreeMap<String, String>,
+    /// `driver.crates`: crates whose dynamic library is a port driver rather than a NIF
+    pub driver_crates: Vec<String>,
+    /// `staticlib.crates`: crates whose `staticlib` targets are built and installed
+    pub staticlib_crates: Vec<String>,
+    /// `staticlib.dir`: where staticlibs go instead of `priv/crates`, relative to the application
+    pub staticlib_dir: Option<PathBuf>,
+    /// `static-crt.crates`: crates whose port programs link a static C runtime on Windows
+    pub static_crt_crates: Vec<String>,
+    /// `keep-artifacts`: generations of each installed artifact to retain
+    pub keep_artifacts: Option<usize>,
+    /// `backup`: keep the artifact a build replaces as `<name>.prev`, for `rollback`
+    pub backup: bool,
+    /// `lock-timeout`: seconds to wait for another invocation on the same application
+    pub lock_timeout: Option<Duration>,
+    /// `versioned-artifacts`: always install dylibs under versioned names
+    pub versioned_artifacts: bool,
+    /// `platform-dirs`: install into `priv/crates/<crate>/<os>-<arch>/` and generate
+    /// `src/<app>_nif_platform.erl`
+    pub platform_dirs: bool,
+    /// `profile-dirs`: also keep each build in `priv/crates/<crate>/<profile>/`
+    pub profile_dirs: bool,
+    /// `priv-dirs`: where artifacts are installed, relative to the application or absolute.
+    /// The first is built into, the others receive copies.
+    pub priv_dirs: Vec<PathBuf>,
+    /// `rebar-lib-dirs`: also install into rebar3's `_build/<profile>/lib/<app>/priv`
+    pub rebar_lib_dirs: bool,
+    /// `strip`: strip debug information and local symbols from installed NIFs, drivers and
+    /// port programs
+    pub strip: bool,
+    /// `strip-keep`: symbols stripping must keep, `*` matching anything
+    pub strip_keep: Vec<String>,
+    /// `strict-targets`: fail on crates with no installable targets instead of skipping them
+    pub strict_targets: bool,
+    /// `deny-warnings`: make compiler warnings errors in every crate
+    pub deny_warnings: bool,
+    /// `host-tools = "skip"`: when cross-compiling, leave out crates built for the host
+    /// instead of installing them into `priv/host-tools/`
+    pub skip_host_tools: bool,
+    /// `nif-reload-module`: generate `src/<app>_nif_reload.erl` when building
+    pub nif_reload_module: bool,
+    /// `nif-stubs`: generate each rustler NIF's Erlang module, with specs, when building
+    pub nif_stubs: bool,
+    /// `version-policy`: how closely crate versions must follow the application version
+    pub version_policy: VersionPolicy,
+    /// `enable-required-features`: build targets with their `required-features` enabled
+    /// instead of skipping them
+    pub enable_required_features: bool,
+    /// `min-otp`: oldest OTP major release the NIFs must load into
+    pub min_otp: Option<u32>,
+    /// `erl`: Erlang for the build scripts of NIF sys crates, instead of `$ERL` or `PATH`
+    pub erl: Option<PathBuf>,
+    /// `[port-smoke-test]`: handshake with port programs in `smoke-test`
+    pub port_probe: Option<PortProbe>,
+    /// `dist.compression`: archive compression for `dist`
+    pub dist_compression: Compression,
+    /// `dist.dir`: where `dist` writes archives, relative to the application
+    pub dist_dir: PathBuf,
+    /// `codegen.crate`: crate receiving constants generated from `include/*.hrl`
+    pub codegen_crate: Option<String>,
+    /// `codegen.module`: the generated module, relative to the crate
+    pub codegen_module: PathBuf,
+    /// `codegen.records`: how record structs are encoded; by default rustler if the crate
+    /// depends on it, else the external term format
+    pub codegen_records: Option<RecordEncoding>,
+    /// `[link]`: extra rustc arguments per target kind and platform, after the built-in ones
+    pub link: Vec<link::Entry>,
+    /// `[env]`: variables removed from and set in the environment of cargo processes
+    pub env: env_policy::Policy,
+    /// `[policy]`: licenses, banned crates and sources allowed in dependency graphs
+    pub policy: Option<Policy>,
+    /// `[patch.<registry>]`: dependency overrides applied to every crate
+    pub patches: Vec<Patch>,
+    /// `[shared-dependencies]`: package -> the version requirement every crate must declare
+    pub shared_dependencies: BTreeMap<String, String>,
+    /// `[lints.rust]` and `[lints.clippy]`: lint -> level for every crate, clippy's lints
+    /// prefixed `clippy::`, lowest priority first
+    pub lints: Vec<(String, String)>,
+    /// `[target.<triple>.env]`: triple -> variables set for cargo when building for it
+    pub target_env: BTreeMap<String, BTreeMap<String, String>>,
+    /// `[target.<triple>.pkg-config]`: triple -> where its `.pc` files are
+    pub target_pkg_config: BTreeMap<String, pkg_config::Settings>,
+    /// `runner` in `[target.<triple>]`: triple -> the command cargo runs its binaries with
+    pub target_runner: BTreeMap<String, String>,
+    /// `[target.'cfg(...)']`: settings for build targets matching a cfg, other than `link`
+    pub conditional: Vec<Conditional>,
+}
+
+/// A `[target.'cfg(...)']` section, applied when the build target matches.  Its `link`
+/// entries go to `Config::link` with the cfg attached.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Conditional {
+    pub cfg: Cfg,
+    /// `env`: variables set for cargo, as in `[env.set]`
+    pub env: BTreeMap<String, String>,
+    /// `features`: cargo features enabled for every crate
+    pub features: Vec<String>,
+    pub platform_dirs: Option<bool>,
+    pub versioned_artifacts: Option<bool>,
+}
+
+/// A cargo `[patch]` entry, e.g. `[patch.crates-io] openssl-src = { git = "..." }`
+#[derive(Debug, PartialEq)]
+pub struct Patch {
+    /// `crates-io`, a registry name or a source URL
+    pub registry: String,
+    pub package: String,
+    /// Dependency fields (`path`, `git`, `branch`, ...); a `path` is relative to the application
+    pub fields: Vec<(String, String)>,
+}
+
+/// Fields of a `[patch]` entry, all of them strings
+const PATCH_FIELDS: &[&str] = &["path", "git", "branch", "tag", "rev", "version", "package", "registry"];
+
+/// A crate outside `crates/`, e.g. a NIF shared from a sibling repository
+#[derive(Debug, PartialEq)]
+pub struct ExternalCrate {
+    /// Installed into `priv/crates/<name>/`
+    pub name: String,
+    /// Crate directory, relative to the application
+    pub path: PathBuf,
+    /// Base name for installed artifacts, if the application expects something other
+    /// than the crate's target names
+    pub artifact: Option<String>,
+}
+
+/// Probe message exchanged with a port program over `{packet, N}` framing
+#[derive(Debug, PartialEq)]
+pub struct PortProbe {
+    /// Length header size: 1, 2 or 4 bytes
+    pub packet: usize,
+    pub probe: String,
+    /// Expected response; any response passes when absent
+    pub expect: Option<String>,
+    pub timeout: Duration,
+    /// Crates to probe; all bin targets when empty
+    pub crates: Vec<String>,
+}
+
+impl Default for Config {
+    fn default() -> Config {
+        Config {
+            rebar_profiles: BTreeMap::new(),
+            umbrella_dirs: vec!["apps".to_string()],
+            crate_include: None,
+            crate_exclude: Vec::new(),
+            external_crates: Vec::new(),
+            target_set: None,
+            elixir_modules: BTreeMap::new(),
+            driver_crates: Vec::new(),
+            staticlib_crates: Vec::new(),
+            staticlib_dir: None,
+            static_crt_crates: Vec::new(),
+            keep_artifacts: None,
+            backup: false,
+            lock_timeout: None,
+            versioned_artifacts: false,
+            platform_dirs: false,
+            profile_dirs: false,
+            priv_dirs: vec![PathBuf::from("priv")],
+            rebar_lib_dirs: false,
+            strip: false,
+            strip_keep: Vec::new(),
+            strict_targets: false,
+            deny_warnings: false,
+            skip_host_tools: false,
+            nif_reload_module: false,
+            nif_stubs: false,
+            version_policy: VersionPolicy::Any,
+            enable_required_features: false,
+            min_otp: None,
+            erl: None,
+            port_probe: None,
+            dist_compression: Compression::Gzip,
+            dist_dir: PathBuf::from("dist"),
+            codegen_crate: None,
+            codegen_module: PathBuf::from("src/erl_defines.rs"),
+            codegen_records: None,
+            link: link::defaults(),
+            env: env_policy::Policy::default(),
+            patches: Vec::new(),
+            shared_dependencies: BTreeMap::new(),
+            lints: Vec::new(),
+            target_env: BTreeMap::new(),
+            target_pkg_config: BTreeMap::new(),
+            target_runner: BTreeMap::new(),
+            conditional: Vec::new(),
+            policy: None,
+        }
+    }
+}
+
+impl Config {
+    pub fn load(appdir: &Path) -> Result<Config, MsgError> {
+        match fs::read_to_string(appdir.join(CONFIG_FILE)) {
+            Ok(text) => Config::parse(&text),
+            Err(ref err) if err.kind() == io::ErrorKind::NotFound => Ok(Config::default()),
+            Err(err) => Err(MsgIo("cannot read erlangapp.toml", err)),
+        }
+    }
+
+    pub fn parse(text: &str) -> Result<Config, MsgError> {
+        let value = text.parse::<toml::Value>()
+            .map_err(|err| MsgDetail("cannot parse erlangapp.toml", err.to_string()))?;
+        let mut config = Config::default();
+        if let Some(v) = value.get("rebar-profiles") {
+            config.rebar_profiles = string_map(v, "rebar-profiles")?;
+        }
+        if let Some(v) = value.get("umbrella-dirs") {
+            config.umbrella_dirs = string_list(v, "umbrella-dirs")?;
+        }
+        if let Some(v) = value.get("external-crates") {
+            config.external_crates = external_crates(v)?;
+        }
+        if let Some(v) = value.get("targets") {
+            let table = v.as_table()
+                .ok_or_else(|| MsgDetail("erlangapp.toml: expected a table", "targets".to_string()))?;
+            config.target_set = Some(table.iter()
+                .map(|(name, v)| string_list(v, &format!("targets.{}", name)).map(|targets| (name.clone(), targets)))
+                .collect::<Result<_, _>>()?);
+        }
+        if let Some(v) = value.get("crates") {
+            if let Some(include) = v.get("include") {
+                config.crate_include = Some(string_list(include, "crates.include")?);
+            }
+            if let Some(exclude) = v.get("exclude") {
+                config.crate_exclude = string_list(exclude, "crates.exclude")?;
+            }
+        }
+        if let Some(v) = value.get("elixir-modules") {
+            config.elixir_modules = string_map(v, "elixir-modules")?;
+        }
+        if let Some(v) = value.get("driver") {
+            if let Some(crates) = v.get("crates") {
+                config.driver_crates = string_list(crates, "driver.crates")?;
+            }
+        }
+        if let Some(v) = value.get("staticlib") {
+            if let Some(crates) = v.get("crates") {
+                config.staticlib_crates = string_list(crates, "staticlib.crates")?;
+            }
+            if let Some(dir) = v.get("dir") {
+                config.staticlib_dir = Some(PathBuf::from(dir.as_str()
+                    .ok_or_else(|| MsgDetail("erlangapp.toml: expected a string", "staticlib.dir".to_string()))?));
+            }
+        }
+        if let Some(v) = value.get("static-crt") {
+            if let Some(crates) = v.get("crates") {
+                config.static_crt_crates = string_list(crates, "static-crt.crates")?;
+            }
+        }
+        if let Some(v) = value.get("keep-artifacts") {
+            config.keep_artifacts = Some(v.as_integer()
+                .filter(|n| *n >= 1)
+                .ok_or_else(|| MsgDetail("erlangapp.toml: expected a positive integer", "keep-artifacts".to_string()))?
+                as usize);
+        }
+        if let Some(v) = value.get("backup") {
+            config.backup = v.as_bool()
+                .ok_or_else(|| MsgDetail("erlangapp.toml: expected a boolean", "backup".to_string()))?;
+        }
+        if let Some(v) = value.get("lock-timeout") {
+            config.lock_timeout = Some(Duration::from_secs(v.as_integer()
+                .filter(|n| *n >= 0)
+                .ok_or_else(|| MsgDetail("erlangapp.toml: expected a number of seconds", "lock-timeout".to_string()))?
+                as u64));
+        }
+        if let Some(v) = value.get("versioned-artifacts") {
+            config.versioned_artifacts = v.as_bool()
+                .ok_or_else(|| MsgDetail("erlangapp.toml: expected a boolean", "versioned-artifacts".to_string()))?;
+        }
+        if let Some(v) = value.get("profile-dirs") {
+            config.profile_dirs = v.as_bool()
+                .ok_or_else(|| MsgDetail("erlangapp.toml: expected a boolean", "profile-dirs".to_string()))?;
+        }
+        if let Some(v) = value.get("platform-dirs") {
+            config.platform_dirs = v.as_bool()
+                .ok_or_else(|| MsgDetail("erlangapp.toml: expected a boolean", "platform-dirs".to_string()))?;
+        }
+        if let Some(v) = value.get("priv-dirs") {
+            config.priv_dirs = string_list(v, "priv-dirs")?.into_iter().map(PathBuf::from).collect();
+            if config.priv_dirs.is_empty() {
+                return Err(MsgDetail("erlangapp.toml: expected at least one directory", "priv-dirs".to_string()));
+            }
+        }
+        if let Some(v) = value.get("rebar-lib-dirs") {
+            config.rebar_lib_dirs = v.as_bool()
+                .ok_or_else(|| MsgDetail("erlangapp.toml: expected a boolean", "rebar-lib-dirs".to_string()))?;
+        }
+        if let Some(v) = value.get("strip") {
+            config.strip = v.as_bool()
+                .ok_or_else(|| MsgDetail("erlangapp.toml: expected a boolean", "strip".to_string()))?;
+        }
+        if let Some(v) = value.get("strip-keep") {
+            config.strip_keep = string_list(v, "strip-keep")?;
+        }
+        if let Some(v) = value.get("host-tools") {
+            config.skip_host_tools = match v.as_str() {
+                Some("install") => false,
+                Some("skip") => true,
+                _ => return Err(MsgDetail("erlangapp.toml: expected install or skip", "host-tools".to_string())),
+            };
+        }
+        if let Some(v) = value.get("strict-targets") {
+            config.strict_targets = v.as_bool()
+                .ok_or_else(|| MsgDetail("erlangapp.toml: expected a boolean", "strict-targets".to_string()))?;
+        }
+        if let Some(v) = value.get("deny-warnings") {
+            config.deny_warnings = v.as_bool()
+                .ok_or_else(|| MsgDetail("erlangapp.toml: expected a boolean", "deny-warnings".to_string()))?;
+        }
+        if let Some(v) = value.get("nif-reload-module") {
+            config.nif_reload_module = v.as_bool()
+                .ok_or_else(|| MsgDetail("erlangapp.toml: expected a boolean", "nif-reload-module".to_string()))?;
+        }
+        if let Some(v) = value.get("nif-stubs") {
+            config.nif_stubs = v.as_bool()
+                .ok_or_else(|| MsgDetail("erlangapp.toml: expected a boolean", "nif-stubs".to_string()))?;
+        }
+        if let Some(v) = value.get("version-policy") {
+            config.version_policy = v.as_str().and_then(|p| p.parse().ok())
+                .ok_or_else(|| MsgDetail("erlangapp.toml: expected any, major, minor or exact", "version-policy".to_string()))?;
+        }
+        if let Some(v) = value.get("enable-required-features") {
+            config.enable_required_features = v.as_bool()
+                .ok_or_else(|| MsgDetail("erlangapp.toml: expected a boolean", "enable-required-features".to_string()))?;
+        }
+        if let Some(v) = value.get("erl") {
+            config.erl = Some(PathBuf::from(v.as_str()
+                .ok_or_else(|| MsgDetail("erlangapp.toml: expected a string", "erl".to_string()))?));
+        }
+        if let Some(v) = value.get("min-otp") {
+            let release = v.as_integer().map(|n| n.to_string()).or_else(|| v.as_str().map(String::from)).unwrap_or_default();
+            config.min_otp = Some(release.split('.').next().and_then(|major| major.parse().ok())
+                .filter(|major| otp::release_nif_version(*major).is_some())
+                .ok_or_else(|| MsgDetail("erlangapp.toml: expected a known OTP release", "min-otp".to_string()))?);
+        }
+        if let Some(v) = value.get("port-smoke-test") {
+            config.port_probe = Some(port_probe(v)?);
+        }
+        if let Some(v) = value.get("link") {
+            if let Some(defaults) = v.get("defaults") {
+                if !defaults.as_bool()
+                    .ok_or_else(|| MsgDetail("erlangapp.toml: expected a boolean", "link.defaults".to_string()))? {
+                    config.link.clear();
+                }
+            }
+            config.link.extend(link_entries(v)?);
+        }
+        if let Some(v) = value.get("env") {
+            if let Some(remove) = v.get("remove") {
+                config.env.remove = string_list(remove, "env.remove")?;
+            }
+            if let Some(keep) = v.get("keep") {
+                config.env.keep = string_list(keep, "env.keep")?;
+            }
+            if let Some(set) = v.get("set") {
+                config.env.set = string_map(set, "env.set")?;
+            }
+        }
+        if let Some(v) = value.get("policy") {
+            let list = |key: &str| v.get(key).map(|list| string_list(list, &format!("policy.{}", key))).transpose();
+            config.policy = Some(Policy {
+                licenses: list("licenses")?,
+                banned: list("banned")?.unwrap_or_default(),
+                sources: list("sources")?,
+            });
+        }
+        if let Some(v) = value.get("patch") {
+            config.patches = patches(v)?;
+        }
+        if let Some(v) = value.get("shared-dependencies") {
+            config.shared_dependencies = string_map(v, "shared-dependencies")?;
+        }
+        if let Some(v) = value.get("lints") {
+            config.lints = lints(v)?;
+        }
+        if let Some(v) = value.get("target") {
+            let triples = v.as_table()
+                .ok_or_else(|| MsgDetail("erlangapp.toml: expected a table", "target".to_string()))?;
+            for (triple, t) in triples.iter() {
+                if let Some(cfg) = Cfg::parse(triple) {
+                    let (conditional, entries) = conditional(cfg, t, triple)?;
+                    config.conditional.push(conditional);
+                    config.link.extend(entries);
+                    continue;
+                }
+                if let Some(env) = t.get("env") {
+                    config.target_env.insert(triple.clone(), string_map(env, &format!("target.{}.env", triple))?);
+                }
+                if let Some(pc) = t.get("pkg-config") {
+                    config.target_pkg_config.insert(triple.clone(), pkg_config_settings(pc, triple)?);
+                }
+                if let Some(runner) = t.get("runner") {
+                    let key = format!("target.{}.runner", triple);
+                    let runner = runner.as_str().ok_or_else(|| MsgDetail("erlangapp.toml: expected a string", key.clone()))?;
+                    let sysroot = config.target_pkg_config.get(triple).and_then(|pc| pc.sysroot.as_deref());
+                    config.target_runner.insert(triple.clone(), qemu::runner(runner, triple, sysroot)
+                        .ok_or(MsgDetail("erlangapp.toml: no qemu-user emulator for this target; give the runner command", key))?);
+                }
+            }
+        }
+        if let Some(v) = value.get("dist") {
+            if let Some(compression) = v.get("compression") {
+                config.dist_compression = compression.as_str().and_then(|c| c.parse().ok())
+                    .ok_or_else(|| MsgDetail("erlangapp.toml: expected none, gzip, zstd or xz", "dist.compression".to_string()))?;
+            }
+            if let Some(dir) = v.get("dir") {
+                config.dist_dir = PathBuf::from(dir.as_str()
+                    .ok_or_else(|| MsgDetail("erlangapp.toml: expected a string", "dist.dir".to_string()))?);
+            }
+        }
+        if let Some(v) = value.get("codegen") {
+            if let Some(krate) = v.get("crate") {
+                config.codegen_crate = Some(krate.as_str()
+                    .ok_or_else(|| MsgDetail("erlangapp.toml: expected a string", "codegen.crate".to_string()))?
+                    .to_string());
+            }
+            if let Some(module) = v.get("module") {
+                config.codegen_module = PathBuf::from(module.as_str()
+                    .ok_or_else(|| MsgDetail("erlangapp.toml: expected a string", "codegen.module".to_string()))?);
+            }
+            if let Some(records) = v.get("records") {
+                config.codegen_records = Some(records.as_str().and_then(|r| r.parse().ok())
+                    .ok_or_else(|| MsgDetail("erlangapp.toml: expected rustler or term", "codegen.records".to_string()))?);
+            }
+        }
+        Ok(config)
+    }
+
+    /// True if the directory `name` under `crates/` is processed: listed in `crates.include`
+    /// if that is present, and not in `crates.exclude`
+    pub fn selects_crate(&self, name: &str) -> bool {
+        let name = name.to_string();
+        self.crate_include.as_ref().map(|include| include.contains(&name)).unwrap_or(true)
+            && !self.crate_exclude.contains(&name)
+    }
+
+    /// `--config` values applying `[patch]` to cargo run for the application in `appdir`
+    pub fn patch_config(&self, appdir: &Path) -> Vec<String> {
+        let mut values = Vec::new();
+        for patch in self.patches.iter() {
+            for (field, value) in patch.fields.iter() {
+                let value = match field.as_str() {
+                    "path" => appdir.join(value).to_string_lossy().into_owned(),
+                    _ => value.clone(),
+                };
+                // cargo refuses inline tables here, so each field is a value of its own
+                values.push(format!("patch.{}.{}.{}={}", toml_key(&patch.registry), toml_key(&patch.package), field,
+                                    toml::Value::String(value)));
+            }
+        }
+        values
+    }
+
+    /// Variables to set for cargo building for `triple`, from `[target.<triple>.pkg-config]`,
+    /// its `runner` and `[target.<triple>.env]`.  `linker` stands for cargo's
+    /// `CARGO_TARGET_<TRIPLE>_LINKER`.
+    pub fn target_env(&self, triple: &str) -> Vec<(String, String)> {
+        let cargo_var = |name: &str| cargo_target_var(triple, name);
+        let mut vars = self.target_pkg_config.get(triple).map(|pc| pc.env(triple)).unwrap_or_default();
+        if let Some(runner) = self.target_runner.get(triple) {
+            vars.push((cargo_var("RUNNER"), runner.clone()));
+        }
+        if let Some(env) = self.target_env.get(triple) {
+            vars.extend(env.iter().map(|(name, value)| match name.as_str() {
+                "linker" => (cargo_var("LINKER"), value.clone()),
+                _ => (name.clone(), value.clone()),
+            }));
+        }
+        vars
+    }
+
+    /// rustc flags setting the `[lints]` levels, later ones taking precedence
+    pub fn lint_flags(&self) -> Vec<String> {
+        self.lints.iter()
+            .map(|(lint, level)| {
+                let flag = match level.as_str() {
+                    "allow" => "-A",
+                    "warn" => "-W",
+                    "deny" => "-D",
+                    _ => "-F",
+                };
+                format!("{}{}", flag, lint)
+            })
+            .collect()
+    }
+
+    /// Cargo profile to use for a rebar3 profile.  `prod` maps to `release` unless configured otherwise.
+    pub fn cargo_profile_for_rebar(&self, rebar_profile: &str) -> Option<&str> {
+        match self.rebar_profiles.get(rebar_profile) {
+            Some(p) => Some(p.as_str()),
+            None if rebar_profile == "prod" => Some("release"),
+            None => None,
+        }
+    }
+}
+
+/// `[lints]`: a table per tool of lint levels, as in `Cargo.toml`: `level` or
+/// `{ level = "level", priority = n }`
+fn lints(value: &toml::Value) -> Result<Vec<(String, String)>, MsgError> {
+    const LEVELS: &[&str] = &["allow", "warn", "deny", "forbid"];
+    let tools = value.as_table()
+        .ok_or_else(|| MsgDetail("erlangapp.toml: expected a table", "lints".to_string()))?;
+    let mut lints = Vec::new();
+    for (tool, table) in tools.iter() {
+        let prefix = match tool.as_str() {
+            "rust" => "",
+            "clippy" => "clippy::",
+            _ => return Err(MsgDetail("erlangapp.toml: expected rust or clippy", format!("lints.{}", tool))),
+        };
+        let table = table.as_table()
+            .ok_or_else(|| MsgDetail("erlangapp.toml: expected a table", format!("lints.{}", tool)))?;
+        for (name, v) in table.iter() {
+            let key = format!("lints.{}.{}", tool, name);
+            if name.is_empty() || !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-') {
+                return Err(MsgDetail("erlangapp.toml: expected a lint name", key));
+            }
+            let level = v.as_str().or_else(|| v.get("level").and_then(|l| l.as_str()));
+            let priority = v.get("priority").map(|p| p.as_integer()).unwrap_or(Some(0));
+            match (level, priority) {
+                (Some(level), Some(priority)) if LEVELS.contains(&level) =>
+                    lints.push((priority, format!("{}{}", prefix, name), level.to_string())),
+                _ => return Err(MsgDetail("erlangapp.toml: expected allow, warn, deny or forbid", key)),
+            }
+        }
+    }
+    lints.sort();
+    Ok(lints.into_iter().map(|(_, lint, level)| (lint, level)).collect())
+}
+
+/// Interpret a table of string values
+fn string_map(value: &toml::Value, section: &str) -> Result<BTreeMap<String, String>, MsgError> {
+    let table = value.as_table()
+        .ok_or_else(|| MsgDetail("erlangapp.toml: expected a table", section.to_string()))?;
+    table.iter()
+        .map(|(k, v)| v.as_str()
+            .map(|s| (k.clone(), s.to_string()))
+            .ok_or_else(|| MsgDetail("erlangapp.toml: expected a string", format!("{}.{}", section, k))))
+        .collect()
+}
+
+/// Interpret `[patch]`: a table per registry of `package = { path = "..." }` or
+/// `package = { git = "...", branch = "..." }` entries
+fn patches(value: &toml::Value) -> Result<Vec<Patch>, MsgError> {
+    let registries = value.as_table()
+        .ok_or_else(|| MsgDetail("erlangapp.toml: expected a table", "patch".to_string()))?;
+    let mut patches = Vec::new();
+    for (registry, packages) in registries.iter() {
+        let packages = packages.as_table()
+            .ok_or_else(|| MsgDetail("erlangapp.toml: expected a table", format!("patch.{}", registry)))?;
+        for (package, entry) in packages.iter() {
+            let key = format!("patch.{}.{}", registry, package);
+            let entry = entry.as_table()
+                .ok_or_else(|| MsgDetail("erlangapp.toml: expected a table", key.clone()))?;
+            let fields = entry.iter()
+                .map(|(field, v)| match (PATCH_FIELDS.contains(&field.as_str()), v.as_str()) {
+                    (true, Some(s)) => Ok((field.clone(), s.to_string())),
+                    (true, None) => Err(MsgDetail("erlangapp.toml: expected a string", format!("{}.{}", key, field))),
+                    (false, _) => Err(MsgDetail("erlangapp.toml: unknown patch field", format!("{}.{}", key, field))),
+                })
+                .collect::<Result<Vec<_>, _>>()?;
+            if !fields.iter().any(|(field, _)| field == "path" || field == "git") {
+                return Err(MsgDetail("erlangapp.toml: expected a path or git source", key));
+            }
+            patches.push(Patch { registry: registry.clone(), package: package.clone(), fields });
+        }
+    }
+    Ok(patches)
+}
+
+/// `key` as a TOML key, quoted unless it is bare
+fn toml_key(key: &str) -> String {
+    match !key.is_empty() && key.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_') {
+        true => key.to_string(),
+        false => toml::Value::String(key.to_string()).to_string(),
+    }
+}
+
+/// Interpret `[external-crates]`: `name = "path"` or `name = { path = "...", artifact = "..." }`
+fn external_crates(value: &toml::Value) -> Result<Vec<ExternalCrate>, MsgError> {
+    let table = value.as_table()
+        .ok_or_else(|| MsgDetail("erlangapp.toml: expected a table", "external-crates".to_string()))?;
+    table.iter()
+        .map(|(name, v)| {
+            let key = format!("external-crates.{}", name);
+            let (path, artifact) = match *v {
+                toml::Value::String(ref path) => (path.clone(), None),
+                toml::Value::Table(ref t) => {
+                    let path = t.get("path").and_then(|p| p.as_str())
+                        .ok_or_else(|| MsgDetail("erlangapp.toml: expected a string", format!("{}.path", key)))?;
+                    let artifact = match t.get("artifact") {
+                        Some(a) => Some(a.as_str()
+                            .ok_or_else(|| MsgDetail("erlangapp.toml: expected a string", format!("{}.artifact", key)))?
+                            .to_string()),
+                        None => None,
+                    };
+                    (path.to_string(), artifact)
+                },
+                _ => return Err(MsgDetail("erlangapp.toml: expected a path or a table", key)),
+            };
+            Ok(ExternalCrate { name: name.clone(), path: PathBuf::from(path), artifact })
+        })
+        .collect()
+}
+
+/// Interpret `[port-smoke-test]`
+fn port_probe(value: &toml::Value) -> Result<PortProbe, MsgError> {
+    let string = |key: &str| match value.get(key) {
+        Some(v) => v.as_str().map(|s| Some(s.to_string()))
+            .ok_or_else(|| MsgDetail("erlangapp.toml: expected a string", format!("port-smoke-test.{}", key))),
+        None => Ok(None),
+    };
+    let integer = |key: &str, default: i64| match value.get(key) {
+        Some(v) => v.as_integer().filter(|n| *n > 0)
+            .ok_or_else(|| MsgDetail("erlangapp.toml: expected a positive integer", format!("port-smoke-test.{}", key))),
+        None => Ok(default),
+    };
+    let packet = integer("packet", 4)? as usize;
+    if ![1, 2, 4].contains(&packet) {
+        return Err(MsgDetail("erlangapp.toml: packet must be 1, 2 or 4", "port-smoke-test.packet".to_string()));
+    }
+    Ok(PortProbe {
+        packet,
+        probe: string("probe")?.unwrap_or_default(),
+        expect: string("expect")?,
+        timeout: Duration::from_millis(integer("timeout-ms", 5000)? as u64),
+        crates: match value.get("crates") {
+            Some(v) => string_list(v, "port-smoke-test.crates")?,
+            None => Vec::new(),
+        },
+    })
+}
+
+/// Cargo's `CARGO_TARGET_<TRIPLE>_<NAME>` variable for a target triple
+pub fn cargo_target_var(triple: &str, name: &str) -> String {
+    format!("CARGO_TARGET_{}_{}", triple.to_uppercase().replace(['-', '.'], "_"), name)
+}
+
+/// `[target.'cfg(...)']`: `env`, `features`, `platform-dirs`, `versioned-artifacts`, and
+/// `link` kind keys
+fn conditional(cfg: Cfg, value: &toml::Value, key: &str) -> Result<(Conditional, Vec<link::Entry>), MsgError> {
+    let path = |name: &str| format!("target.'{}'.{}", key, name);
+    let boolean = |name: &str| value.get(name)
+        .map(|v| v.as_bool().ok_or_else(|| MsgDetail("erlangapp.toml: expected a boolean", path(name))))
+        .transpose();
+    let mut entries = Vec::new();
+    if let Some(link) = value.get("link") {
+        let kinds = link.as_table().ok_or_else(|| MsgDetail("erlangapp.toml: expected a table", path("link")))?;
+        for (kind, args) in kinds.iter() {
+            let path = path(&format!("link.{}", kind));
+            entries.push(link::Entry {
+                cfg: Some(cfg.clone()),
+                kind: Kind::from_key(kind).ok_or_else(|| MsgDetail("erlangapp.toml: expected nif, driver, bin or staticlib", path.clone()))?,
+                args: string_list(args, &path)?,
+            });
+        }
+    }
+    let conditional = Conditional {
+        env: value.get("env").map(|env| string_map(env, &path("env"))).transpose()?.unwrap_or_default(),
+        features: value.get("features").map(|f| string_list(f, &path("features"))).transpose()?.unwrap_or_default(),
+        platform_dirs: boolean("platform-dirs")?,
+        versioned_artifacts: boolean("versioned-artifacts")?,
+        cfg,
+    };
+    Ok((conditional, entries))
+}
+
+/// `[target.<triple>.pkg-config]`: `sysroot`, `path` and `packages`
+fn pkg_config_settings(value: &toml::Value, triple: &str) -> Result<pkg_config::Settings, MsgError> {
+    let key = |name: &str| format!("target.{}.pkg-config.{}", triple, name);
+    let list = |name: &str| value.get(name).map(|v| string_list(v, &key(name))).transpose();
+    Ok(pkg_config::Settings {
+        sysroot: match value.get("sysroot") {
+            Some(v) => Some(PathBuf::from(v.as_str()
+                .ok_or_else(|| MsgDetail("erlangapp.toml: expected a string", key("sysroot")))?)),
+            None => None,
+        },
+        path: list("path")?.unwrap_or_default().into_iter().map(PathBuf::from).collect(),
+        packages: list("packages")?.unwrap_or_default(),
+    })
+}
+
+/// `[link]`: kind keys apply everywhere, then `cfg(...)` tables of kind keys
+fn link_entries(value: &toml::Value) -> Result<Vec<link::Entry>, MsgError> {
+    let table = value.as_table()
+        .ok_or_else(|| MsgDetail("erlangapp.toml: expected a table", "link".to_string()))?;
+    let mut entries = Vec::new();
+    for (key, v) in table.iter().filter(|(key, _)| Kind::from_key(key).is_some()) {
+        entries.push(link::Entry { cfg: None, kind: Kind::from_key(key).unwrap(), args: string_list(v, &format!("link.{}", key))? });
+    }
+    for (key, v) in table.iter().filter(|(key, _)| Kind::from_key(key).is_none() && *key != "defaults") {
+        let cfg = Cfg::parse(key)
+            .ok_or_else(|| MsgDetail("erlangapp.toml: expected nif, driver, bin, staticlib or a cfg(...) table", format!("link.{}", key)))?;
+        let kinds = v.as_table()
+            .ok_or_else(|| MsgDetail("erlangapp.toml: expected a table", format!("link.'{}'", key)))?;
+        for (kind, args) in kinds.iter() {
+            let path = format!("link.'{}'.{}", key, kind);
+            entries.push(link::Entry {
+                cfg: Some(cfg.clone()),
+                kind: Kind::from_key(kind).ok_or_else(|| MsgDetail("erlangapp.toml: expected nif, driver, bin or staticlib", path.clone()))?,
+                args: string_list(args, &path)?,
+            });
+        }
+    }
+    Ok(entries)
+}
+
+/// Interpret an array of strings
+fn string_list(value: &toml::Value, key: &str) -> Result<Vec<String>, MsgError> {
+    value.as_array()
+        .and_then(|arr| arr.iter().map(|v| v.as_str().map(String::from)).collect())
+        .ok_or_else(|| MsgDetail("erlangapp.toml: expected an array of strings", key.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rebar_profiles() {
+        let config = Config::parse("[rebar-profiles]\ntest = \"debug\"\nbench = \"release\"\n").unwrap();
+        assert_eq!(Some("debug"), config.cargo_profile_for_rebar("test"));
+        assert_eq!(Some("release"), config.cargo_profile_for_rebar("bench"));
+        assert_eq!(Some("release"), config.cargo_profile_for_rebar("prod"));
+        assert_eq!(None, config.cargo_profile_for_rebar("default"));
+        assert!(Config::parse("[rebar-profiles]\ntest = 1\n").is_err());
+    }
+
+    #[test]
+    fn test_umbrella_dirs() {
+        assert_eq!(vec!["apps".to_string()], Config::parse("").unwrap().umbrella_dirs);
+        assert_eq!(vec!["apps".to_string(), "libs".to_string()],
+                   Config::parse("umbrella-dirs = [\"apps\", \"libs\"]").unwrap().umbrella_dirs);
+        assert!(Config::parse("umbrella-dirs = \"apps\"").is_err());
+    }
+
+    #[test]
+    fn test_external_crates() {
+        let config = Config::parse("[external-crates]\n\
+                                    a = \"../shared/a\"\n\
+                                    b = { path = \"../shared/b\", artifact = \"b_nif\" }\n").unwrap();
+        assert_eq!(vec![
+            ExternalCrate { name: "a".into(), path: PathBuf::from("../shared/a"), artifact: None },
+            ExternalCrate { name: "b".into(), path: PathBuf::from("../shared/b"), artifact: Some("b_nif".into()) },
+        ], config.external_crates);
+        assert!(Config::parse("[external-crates]\nc = { artifact = \"x\" }\n").is_err());
+    }
+
+    #[test]
+    fn test_keep_artifacts() {
+        assert_eq!(None, Config::parse("").unwrap().keep_artifacts);
+        assert_eq!(Some(3), Config::parse("keep-artifacts = 3").unwrap().keep_artifacts);
+        assert!(Config::parse("keep-artifacts = 0").is_err());
+    }
+
+    #[test]
+    fn test_backup() {
+        assert!(Config::parse("backup = true").unwrap().backup);
+        assert!(!Config::parse("").unwrap().backup);
+        assert!(Config::parse("backup = \"prev\"").is_err());
+    }
+
+    #[test]
+    fn test_lock_timeout() {
+        assert_eq!(None, Config::parse("").unwrap().lock_timeout);
+        assert_eq!(Some(Duration::from_secs(0)), Config::parse("lock-timeout = 0").unwrap().lock_timeout);
+        assert!(Config::parse("lock-timeout = -1").is_err());
+    }
+
+    #[test]
+    fn test_target_set() {
+        let config = Config::parse("[targets]\nmynif = [\"mynif\"]\nmyport = []\n").unwrap();
+        let target_set = config.target_set.unwrap();
+        assert_eq!(vec!["mynif".to_string()], target_set["mynif"]);
+        assert!(target_set["myport"].is_empty());
+        assert_eq!(None, Config::parse("").unwrap().target_set);
+        assert!(Config::parse("[targets]\nmynif = \"mynif\"\n").is_err());
+    }
+
+    #[test]
+    fn test_elixir_modules() {
+        let config = Config::parse("[elixir-modules]\nmy_nif = \"MyApp.Native\"\n").unwrap();
+        assert_eq!(Some(&"MyApp.Native".to_string()), config.elixir_modules.get("my_nif"));
+        assert!(Config::parse("[elixir-modules]\nmy_nif = 1\n").is_err());
+    }
+
+    #[test]
+    fn test_versioned_artifacts() {
+        assert!(!Config::parse("").unwrap().versioned_artifacts);
+        assert!(Config::parse("versioned-artifacts = true").unwrap().versioned_artifacts);
+    }
+
+    #[test]
+    fn test_link() {
+        let defaults = Config::parse("").unwrap().link.len();
+        let config = Config::parse("[link]\nbin = [\"-C\", \"target-feature=+crt-static\"]\n\
+                                    [link.'cfg(target_os = \"linux\")']\nnif = [\"-C\", \"link-arg=-Wl,-z,noexecstack\"]\n").unwrap();
+        assert_eq!(defaults + 2, config.link.len());
+        assert_eq!(link::Entry { cfg: None, kind: Kind::Bin, args: vec!["-C".into(), "target-feature=+crt-static".into()] },
+                   config.link[defaults]);
+        assert_eq!(Some(Kind::Nif), config.link.last().map(|e| e.kind));
+        assert!(Config::parse("[link]\nexe = []\n").is_err());
+        assert!(Config::parse("[link.'cfg(unix)']\nnifs = []\n").is_err());
+        assert!(Config::parse("[link]\nnif = \"-C\"\n").is_err());
+        assert_eq!(1, Config::parse("[link]\ndefaults = false\nnif = [\"-v\"]\n").unwrap().link.len());
+        assert!(Config::parse("[link]\ndefaults = \"no\"\n").is_err());
+    }
+
+    #[test]
+    fn test_env() {
+        assert_eq!(env_policy::Policy::default(), Config::parse("").unwrap().env);
+        let env = Config::parse("[env]\nremove = [\"CARGO_TARGET_DIR\"]\nkeep = [\"REBAR_CACHE_DIR\"]\n\
+                                 [env.set]\nRUSTUP_TOOLCHAIN = \"stable\"\n").unwrap().env;
+        assert_eq!(vec!["CARGO_TARGET_DIR"], env.remove);
+        assert_eq!(vec!["REBAR_CACHE_DIR"], env.keep);
+        assert_eq!(Some(&"stable".to_string()), env.set.get("RUSTUP_TOOLCHAIN"));
+        assert!(Config::parse("[env]\nremove = \"ERL_LIBS\"\n").is_err());
+        assert!(Config::parse("[env.set]\nRUSTUP_TOOLCHAIN = 1\n").is_err());
+    }
+
+    #[test]
+    fn test_crate_selection() {
+        let config = Config::parse("[crates]\ninclude = [\"a_nif\", \"b_port\"]\nexclude = [\"b_port\"]\n").unwrap();
+        assert_eq!(Some(vec!["a_nif".to_string(), "b_port".to_string()]), config.crate_include);
+        assert_eq!(vec!["b_port"], config.crate_exclude);
+        assert!(config.selects_crate("a_nif"));
+        assert!(!config.selects_crate("b_port"));
+        assert!(!config.selects_crate("scratch"));
+        assert!(Config::default().selects_crate("scratch"));
+        assert!(Config::parse("[crates]\nexclude = \"scratch\"\n").is_err());
+    }
+
+    #[test]
+    fn test_min_otp() {
+        assert_eq!(None, Config::parse("").unwrap().min_otp);
+        assert_eq!(Some(26), Config::parse("min-otp = \"26\"").unwrap().min_otp);
+        assert_eq!(Some(25), Config::parse("min-otp = \"25.3\"").unwrap().min_otp);
+        assert_eq!(Some(27), Config::parse("min-otp = 27").unwrap().min_otp);
+        assert!(Config::parse("min-otp = \"12\"").is_err());
+        assert!(Config::parse("min-otp = true").is_err());
+    }
+
+    #[test]
+    fn test_policy() {
+        assert_eq!(None, Config::parse("").unwrap().policy);
+        let policy = Config::parse("[policy]\nlicenses = [\"MIT\"]\nbanned = [\"openssl-sys\"]\n").unwrap().policy.unwrap();
+        assert_eq!(Some(vec!["MIT".to_string()]), policy.licenses);
+        assert_eq!(vec!["openssl-sys"], policy.banned);
+        assert_eq!(None, policy.sources);
+        assert!(Config::parse("[policy]\nsources = \"crates.io\"\n").is_err());
+    }
+
+    #[test]
+    fn test_platform_dirs() {
+        assert!(!Config::parse("").unwrap().platform_dirs);
+        assert!(Config::parse("platform-dirs = true").unwrap().platform_dirs);
+        assert!(Config::parse("platform-dirs = \"yes\"").is_err());
+    }
+
+    #[test]
+    fn test_profile_dirs() {
+        assert!(!Config::parse("").unwrap().profile_dirs);
+        assert!(Config::parse("profile-dirs = true").unwrap().profile_dirs);
+        assert!(Config::parse("profile-dirs = 1").is_err());
+    }
+
+    #[test]
+    fn test_priv_dirs() {
+        assert_eq!(vec![PathBuf::from("priv")], Config::parse("").unwrap().priv_dirs);
+        assert_eq!(vec![PathBuf::from("priv"), PathBuf::from("/mnt/nifs/my_app")],
+                   Config::parse("priv-dirs = [\"priv\", \"/mnt/nifs/my_app\"]").unwrap().priv_dirs);
+        assert!(Config::parse("priv-dirs = []").is_err());
+    }
+
+    #[test]
+    fn test_strip() {
+        let config = Config::parse("strip = true\nstrip-keep = [\"my_*\"]\n").unwrap();
+        assert!(config.strip);
+        assert_eq!(vec!["my_*".to_string()], config.strip_keep);
+        assert!(!Config::parse("").unwrap().strip);
+        assert!(Config::parse("strip-keep = \"my_*\"").is_err());
+    }
+
+    #[test]
+    fn test_target_env() {
+        let config = Config::parse("[target.aarch64-unknown-linux-gnu.env]\n\
+                                    CC = \"aarch64-linux-gnu-gcc\"\n\
+                                    linker = \"aarch64-linux-gnu-gcc\"\n\
+                                    PKG_CONFIG_SYSROOT_DIR = \"/sysroots/aarch64\"\n").unwrap();
+        assert_eq!(vec![("CC".to_string(), "aarch64-linux-gnu-gcc".to_string()),
+                        ("PKG_CONFIG_SYSROOT_DIR".to_string(), "/sysroots/aarch64".to_string()),
+                        ("CARGO_TARGET_AARCH64_UNKNOWN_LINUX_GNU_LINKER".to_string(), "aarch64-linux-gnu-gcc".to_string())],
+                   config.target_env("aarch64-unknown-linux-gnu"));
+        assert!(config.target_env("x86_64-unknown-linux-gnu").is_empty());
+        let config = Config::parse("[target.aarch64-unknown-linux-gnu.pkg-config]\n\
+                                    sysroot = \"/sysroots/aarch64\"\npackages = [\"openssl\"]\n").unwrap();
+        let pc = &config.target_pkg_config["aarch64-unknown-linux-gnu"];
+        assert_eq!(vec!["openssl"], pc.packages);
+        assert_eq!(Some(PathBuf::from("/sysroots/aarch64")), pc.sysroot);
+        assert_eq!(3, config.target_env("aarch64-unknown-linux-gnu").len());
+        assert!(Config::parse("[target.aarch64-unknown-linux-gnu.pkg-config]\npackages = \"openssl\"\n").is_err());
+        let config = Config::parse("[target.aarch64-unknown-linux-gnu]\nrunner = \"qemu\"\n").unwrap();
+        assert_eq!(vec![("CARGO_TARGET_AARCH64_UNKNOWN_LINUX_GNU_RUNNER".to_string(), "qemu-aarch64".to_string())],
+                   config.target_env("aarch64-unknown-linux-gnu"));
+        assert!(Config::parse("[target.wasm32-wasip1]\nrunner = \"qemu\"\n").is_err());
+        assert!(Config::parse("[target.x86_64-pc-windows-msvc.env]\nCC = 1\n").is_err());
+    }
+
+    #[test]
+    fn test_conditional() {
+        let config = Config::parse("[target.'cfg(unix)']\nfeatures = [\"epoll\"]\nplatform-dirs = true\n\
+                                    [target.'cfg(unix)'.env]\nCFLAGS = \"-fPIC\"\n\
+                                    [target.'cfg(windows)'.link]\nbin = [\"-C\", \"target-feature=+crt-static\"]\n").unwrap();
+        assert_eq!(2, config.conditional.len());
+        let unix = config.conditional.iter().find(|c| c.cfg == Cfg::Name("unix".into())).unwrap();
+        assert_eq!(vec!["epoll"], unix.features);
+        assert_eq!(Some(true), unix.platform_dirs);
+        assert_eq!(None, unix.versioned_artifacts);
+        assert_eq!(Some("-fPIC"), unix.env.get("CFLAGS").map(String::as_str));
+        assert!(config.link.contains(&link::static_crt()));
+        assert!(Config::parse("[target.'cfg(unix)'.link]\nlib = []\n").is_err());
+        assert!(Config::parse("[target.'cfg(unix)']\nplatform-dirs = \"yes\"\n").is_err());
+    }
+
+    #[test]
+    fn test_patches() {
+        let config = Config::parse("[patch.crates-io]\nopenssl-src = { git = \"https://example.com/openssl-src\", branch = \"cve\" }\n\
+                                    [patch.\"https://github.com/org/repo\"]\nshared = { path = \"../shared\" }\n").unwrap();
+        assert_eq!(vec!["patch.crates-io.openssl-src.branch=\"cve\"",
+                        "patch.crates-io.openssl-src.git=\"https://example.com/openssl-src\"",
+                        "patch.\"https://github.com/org/repo\".shared.path=\"/app/../shared\""],
+                   config.patch_config(Path::new("/app")));
+        assert!(Config::parse("[patch.crates-io]\nfoo = { branch = \"main\" }").is_err());
+        assert!(Config::parse("[patch.crates-io]\nfoo = { path = \"x\", features = [\"a\"] }").is_err());
+        assert!(Config::parse("[patch.crates-io]\nfoo = \"1.0\"").is_err());
+    }
+
+    #[test]
+    fn test_shared_dependencies() {
+        let config = Config::parse("[shared-dependencies]\nrustler = \"0.36\"\nserde = \"1.0.200\"\n").unwrap();
+        assert_eq!(Some(&"0.36".to_string()), config.shared_dependencies.get("rustler"));
+        assert_eq!(2, config.shared_dependencies.len());
+        assert!(Config::parse("[shared-dependencies]\nrustler = { version = \"0.36\" }").is_err());
+    }
+
+    #[test]
+    fn test_host_tools() {
+        assert!(Config::parse("host-tools = \"skip\"").unwrap().skip_host_tools);
+        assert!(!Config::parse("host-tools = \"install\"").unwrap().skip_host_tools);
+        assert!(!Config::parse("").unwrap().skip_host_tools);
+        assert!(Config::parse("host-tools = true").is_err());
+    }
+
+    #[test]
+    fn test_strict_targets() {
+        assert!(Config::parse("strict-targets = true").unwrap().strict_targets);
+        assert!(!Config::parse("").unwrap().strict_targets);
+        assert!(Config::parse("strict-targets = \"yes\"").is_err());
+    }
+
+    #[test]
+    fn test_lints() {
+        let config = Config::parse("[lints.rust]\nunsafe_code = \"forbid\"\n\
+                                    [lints.clippy]\nunwrap_used = \"deny\"\n\
+                                    all = { level = \"warn\", priority = -1 }\n").unwrap();
+        assert_eq!(vec!["-Wclippy::all", "-Dclippy::unwrap_used", "-Funsafe_code"], config.lint_flags());
+        assert!(Config::parse("").unwrap().lints.is_empty());
+        assert!(Config::parse("[lints.rust]\nunsafe_code = \"never\"\n").is_err());
+        assert!(Config::parse("[lints.rustdoc]\nbroken_intra_doc_links = \"deny\"\n").is_err());
+        assert!(Config::parse("[lints.rust]\n\"x y\" = \"deny\"\n").is_err());
+    }
+
+    #[test]
+    fn test_deny_warnings() {
+        assert!(Config::parse("deny-warnings = true").unwrap().deny_warnings);
+        assert!(!Config::parse("").unwrap().deny_warnings);
+        assert!(Config::parse("deny-warnings = 1").is_err());
+    }
+
+    #[test]
+    fn test_rebar_lib_dirs() {
+        assert!(!Config::parse("").unwrap().rebar_lib_dirs);
+        assert!(Config::parse("rebar-lib-dirs = true").unwrap().rebar_lib_dirs);
+        assert!(Config::parse("rebar-lib-dirs = \"yes\"").is_err());
+    }
+
+    #[test]
+    fn test_nif_stubs() {
+        assert!(!Config::parse("").unwrap().nif_stubs);
+        assert!(Config::parse("nif-stubs = true").unwrap().nif_stubs);
+        assert!(Config::parse("nif-stubs = 1").is_err());
+    }
+
+    #[test]
+    fn test_version_policy() {
+        assert_eq!(VersionPolicy::Any, Config::parse("").unwrap().version_policy);
+        assert_eq!(VersionPolicy::Minor, Config::parse("version-policy = \"minor\"").unwrap().version_policy);
+        assert!(Config::parse("version-policy = \"patch\"").is_err());
+    }
+
+    #[test]
+    fn test_driver_crates() {
+        assert_eq!(vec!["my_drv".to_string()], Config::parse("[driver]\ncrates = [\"my_drv\"]\n").unwrap().driver_crates);
+        assert!(Config::parse("[driver]\ncrates = \"my_drv\"\n").is_err());
+        assert_eq!(vec!["my_port".to_string()],
+                   Config::parse("[static-crt]\ncrates = [\"my_port\"]\n").unwrap().static_crt_crates);
+    }
+
+    #[test]
+    fn test_enable_required_features() {
+        assert!(!Config::parse("").unwrap().enable_required_features);
+        assert!(Config::parse("enable-required-features = true").unwrap().enable_required_features);
+        assert!(Config::parse("enable-required-features = \"yes\"").is_err());
+    }
+
+    #[test]
+    fn test_erl() {
+        assert_eq!(Some(PathBuf::from("/opt/otp/bin/erl")), Config::parse("erl = \"/opt/otp/bin/erl\"").unwrap().erl);
+        assert!(Config::parse("erl = 26").is_err());
+    }
+
+    #[test]
+    fn test_port_probe() {
+        let config = Config::parse("[port-smoke-test]\nprobe = \"ping\"\nexpect = \"pong\"\ntimeout-ms = 100\n").unwrap();
+        assert_eq!(Some(PortProbe {
+            packet: 4,
+            probe: "ping".into(),
+            expect: Some("pong".into()),
+            timeout: Duration::from_millis(100),
+            crates: Vec::new(),
+        }), config.port_probe);
+        assert_eq!(None, Config::parse("").unwrap().port_probe);
+        assert!(Config::parse("[port-smoke-test]\npacket = 3\n").is_err());
+    }
+
+    #[test]
+    fn test_dist() {
+        let config = Config::parse("[dist]\ncompression = \"zstd\"\n").unwrap();
+        assert_eq!(Compression::Zstd, config.dist_compression);
+        assert_eq!(PathBuf::from("dist"), config.dist_dir);
+        assert!(Config::parse("[dist]\ncompression = \"lz4\"\n").is_err());
+    }
+
+    #[test]
+    fn test_staticlib() {
+        let config = Config::parse("[staticlib]\ncrates = [\"embedded\"]\ndir = \"c_src/static\"\n").unwrap();
+        assert_eq!(vec!["embedded".to_string()], config.staticlib_crates);
+        assert_eq!(Some(PathBuf::from("c_src/static")), config.staticlib_dir);
+        assert_eq!(None, Config::parse("").unwrap().staticlib_dir);
+        assert!(Config::parse("[staticlib]\ndir = 1\n").is_err());
+    }
+
+    #[test]
+    fn test_codegen() {
+        let config = Config::parse("[codegen]\ncrate = \"mynif\"\n").unwrap();
+        assert_eq!(Some("mynif".to_string()), config.codegen_crate);
+        assert_eq!(PathBuf::from("src/erl_defines.rs"), config.codegen_module);
+        assert_eq!(None, Config::parse("").unwrap().codegen_crate);
+        assert!(Config::parse("[codegen]\ncrate = \"mynif\"\nmodule = 1\n").is_err());
+        assert_eq!(Some(RecordEncoding::Term), Config::parse("[codegen]\nrecords = \"term\"\n").unwrap().codegen_records);
+        assert!(Config::parse("[codegen]\nrecords = \"bert\"\n").is_err());
+    }
+}
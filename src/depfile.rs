@@ -0,0 +1,132 @@
+//! Make-style dependency file of what each crate's build consumed, so a build tool can tell
+//! whether running the build hook would change anything without invoking cargo.
+//!
+//! `build` keeps `_build/cargo-erlangapp.d` with one rule per crate: its build manifest
+//! depends on the sources listed by cargo's dep-info files for the targets built (those of
+//! path dependencies included; registry sources never change), on the `Cargo.toml` of the
+//! crate and of its path dependencies, the lock file, `erlangapp.toml`, and the `crates`
+//! directory, which changes when a crate is added or removed.  The build is up to date
+//! when every manifest exists and is newer than all of its prerequisites.
+
+use std::collections::BTreeSet;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+pub const DEPFILE: &str = "cargo-erlangapp.d";
+
+/// A target and its prerequisites
+pub type Rule = (PathBuf, BTreeSet<PathBuf>);
+
+/// The dependency file of the application in `appdir`
+pub fn depfile_path(appdir: &Path) -> PathBuf {
+    appdir.join("_build").join(DEPFILE)
+}
+
+/// Prerequisites of the rules in a dep-info file, such as the `.d` cargo writes next to
+/// each artifact; empty if it can't be read
+pub fn dep_info(path: &Path) -> BTreeSet<PathBuf> {
+    fs::read_to_string(path).map(|text| parse(&text).into_iter().flat_map(|(_, prereqs)| prereqs).collect())
+        .unwrap_or_default()
+}
+
+/// Prerequisites of `target` in the application's dependency file
+pub fn prerequisites(appdir: &Path, target: &Path) -> BTreeSet<PathBuf> {
+    let text = fs::read_to_string(depfile_path(appdir)).unwrap_or_default();
+    parse(&text).into_iter().find(|(t, _)| t == target).map(|(_, prereqs)| prereqs).unwrap_or_default()
+}
+
+/// Replace the rule for `target` in the application's dependency file, keeping the others
+pub fn update(appdir: &Path, target: &Path, prereqs: BTreeSet<PathBuf>) -> io::Result<()> {
+    let path = depfile_path(appdir);
+    let mut rules: Vec<Rule> = parse(&fs::read_to_string(&path).unwrap_or_default()).into_iter()
+        .filter(|(t, _)| t != target)
+        .collect();
+    rules.push((target.to_path_buf(), prereqs));
+    rules.sort();
+    fs::create_dir_all(path.parent().unwrap())?;
+    fs::write(&path, render(&rules))
+}
+
+/// Parse `target: prereq prereq` lines, where `\ ` is a space within a path.  Comments and
+/// lines without a rule are skipped.
+pub fn parse(text: &str) -> Vec<Rule> {
+    text.lines()
+        .filter(|line| !line.starts_with('#'))
+        .filter_map(|line| {
+            let (target, prereqs) = match line.strip_suffix(':') {
+                Some(target) => (target, ""),
+                None => line.split_once(": ")?,
+            };
+            Some((PathBuf::from(unescape(target)), words(prereqs).into_iter().map(PathBuf::from).collect()))
+        })
+        .collect()
+}
+
+pub fn render(rules: &[Rule]) -> String {
+    rules.iter()
+        .map(|(target, prereqs)| {
+            let mut line = format!("{}:", escape(target));
+            for prereq in prereqs.iter() {
+                line.push(' ');
+                line.push_str(&escape(prereq));
+            }
+            line + "\n"
+        })
+        .collect()
+}
+
+fn escape(path: &Path) -> String {
+    path.to_string_lossy().replace(' ', "\\ ")
+}
+
+fn unescape(word: &str) -> String {
+    word.replace("\\ ", " ")
+}
+
+/// Split on spaces that aren't escaped
+fn words(text: &str) -> Vec<String> {
+    let mut words = Vec::new();
+    let mut word = String::new();
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' if chars.peek() == Some(&' ') => {
+                word.push(' ');
+                chars.next();
+            },
+            ' ' | '\t' => {
+                if !word.is_empty() {
+                    words.push(word.clone());
+                    word.clear();
+                }
+            },
+            _ => word.push(c),
+        }
+    }
+    if !word.is_empty() {
+        words.push(word);
+    }
+    words
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        let cargo = "/app/crates/my\\ nif/target/debug/libmy_nif.so: /app/crates/my\\ nif/src/lib.rs /app/crates/my\\ nif/src/atoms.rs\n";
+        let rules = parse(cargo);
+        assert_eq!(1, rules.len());
+        assert_eq!(PathBuf::from("/app/crates/my nif/target/debug/libmy_nif.so"), rules[0].0);
+        assert_eq!(vec![PathBuf::from("/app/crates/my nif/src/atoms.rs"), PathBuf::from("/app/crates/my nif/src/lib.rs")],
+                   rules[0].1.iter().cloned().collect::<Vec<_>>());
+        assert_eq!(rules, parse(&render(&rules)));
+
+        let rules = parse("# env-dep:ERL\nC:\\app\\priv\\crates\\a\\artifacts.json: C:\\app\\crates\\a\\src\\lib.rs\n/b:\n");
+        assert_eq!(vec![PathBuf::from("C:\\app\\priv\\crates\\a\\artifacts.json"), PathBuf::from("/b")],
+                   rules.iter().map(|(target, _)| target.clone()).collect::<Vec<_>>());
+        assert!(rules[1].1.is_empty());
+    }
+}
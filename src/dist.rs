@@ -0,0 +1,159 @@
+//! `dist` command: compressed per-artifact archives and a manifest, for precompiled NIF downloads.
+
+use std::fmt::{self, Display};
+use std::fs;
+use std::path::Path;
+use std::process;
+use std::str::FromStr;
+
+use json;
+
+use MsgError;
+use MsgError::*;
+
+pub const MANIFEST_FILE: &str = "manifest.json";
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Compression {
+    None,
+    Gzip,
+    Zstd,
+    Xz,
+}
+
+impl Compression {
+    /// Archive file extension
+    fn extension(&self) -> &'static str {
+        match *self {
+            Compression::None => "tar",
+            Compression::Gzip => "tar.gz",
+            Compression::Zstd => "tar.zst",
+            Compression::Xz => "tar.xz",
+        }
+    }
+
+    fn tar_flags(&self) -> &'static [&'static str] {
+        match *self {
+            Compression::None => &[],
+            Compression::Gzip => &["--gzip"],
+            Compression::Zstd => &["--zstd"],
+            Compression::Xz => &["--xz"],
+        }
+    }
+
+    /// How to unpack an archive, for the manifest
+    fn decompress_command(&self, archive: &str) -> String {
+        match *self {
+            Compression::None => format!("tar -xf {}", archive),
+            Compression::Gzip => format!("tar -xzf {}", archive),
+            Compression::Zstd => format!("zstd -dc {} | tar -xf -", archive),
+            Compression::Xz => format!("tar -xJf {}", archive),
+        }
+    }
+}
+
+impl Display for Compression {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Compression::None => write!(f, "none"),
+            Compression::Gzip => write!(f, "gzip"),
+            Compression::Zstd => write!(f, "zstd"),
+            Compression::Xz => write!(f, "xz"),
+        }
+    }
+}
+
+impl FromStr for Compression {
+    type Err = ();
+    fn from_str(s: &str) -> Result<Compression, ()> {
+        match s {
+            "none" => Ok(Compression::None),
+            "gzip" | "gz" => Ok(Compression::Gzip),
+            "zstd" | "zst" => Ok(Compression::Zstd),
+            "xz" => Ok(Compression::Xz),
+            _ => Err(()),
+        }
+    }
+}
+
+/// One archived artifact
+#[derive(Debug)]
+pub struct Entry {
+    pub crate_name: String,
+    pub target: String,
+    /// Artifact file name inside the archive
+    pub file: String,
+    pub version: String,
+    pub triple: String,
+}
+
+impl Entry {
+    fn archive_name(&self, compression: Compression) -> String {
+        let stem = self.file.rsplitn(2, '.').last().unwrap_or(&self.file);
+        let stem = stem.strip_suffix(&format!("-{}", self.version)).unwrap_or(stem); // versioned artifact
+        format!("{}-{}-{}.{}", stem, self.version, self.triple, compression.extension())
+    }
+}
+
+/// Archive `artifact` into `dist_dir`, returning the archive's file name
+pub fn archive(entry: &Entry, artifact: &Path, dist_dir: &Path, compression: Compression) -> Result<String, MsgError> {
+    let name = entry.archive_name(compression);
+    let dir = artifact.parent().ok_or(Msg("artifact has no parent directory"))?;
+    fs::create_dir_all(dist_dir).map_err(|err| MsgIo("cannot create dist directory", err))?;
+    let status = process::Command::new("tar")
+        .arg("-c")
+        .args(compression.tar_flags())
+        .arg("-f").arg(dist_dir.join(&name))
+        .arg("-C").arg(dir)
+        .arg(&entry.file)
+        .status()
+        .map_err(|err| MsgIo("cannot start tar", err))?;
+    match status.success() {
+        true => Ok(name),
+        false => Err(MsgDetail("cannot create archive", name)),
+    }
+}
+
+/// Write `manifest.json` describing the archives and how to unpack them
+pub fn write_manifest(dist_dir: &Path, entries: &[(Entry, String)], compression: Compression) -> Result<(), MsgError> {
+    let artifacts: Vec<json::Value> = entries.iter()
+        .map(|(entry, archive)| {
+            let mut obj = json::Map::new();
+            let mut put = |key: &str, value: &str| obj.insert(key.to_string(), json::Value::String(value.to_string()));
+            put("crate", &entry.crate_name);
+            put("target", &entry.target);
+            put("file", &entry.file);
+            put("version", &entry.version);
+            put("triple", &entry.triple);
+            put("archive", archive);
+            put("decompress", &compression.decompress_command(archive));
+            json::Value::Object(obj)
+        })
+        .collect();
+    let mut manifest = json::Map::new();
+    manifest.insert("compression".to_string(), json::Value::String(compression.to_string()));
+    manifest.insert("artifacts".to_string(), json::Value::Array(artifacts));
+    let text = json::to_string_pretty(&json::Value::Object(manifest))
+        .map_err(|err| MsgDetail("cannot serialize dist manifest", err.to_string()))?;
+    fs::write(dist_dir.join(MANIFEST_FILE), text + "\n")
+        .map_err(|err| MsgIo("cannot write dist manifest", err))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_archive_name() {
+        let entry = Entry {
+            crate_name: "mynif".into(),
+            target: "mynif (dylib)".into(),
+            file: "libmynif.so".into(),
+            version: "1.4.2".into(),
+            triple: "x86_64-unknown-linux-gnu".into(),
+        };
+        assert_eq!("libmynif-1.4.2-x86_64-unknown-linux-gnu.tar.zst", entry.archive_name(Compression::Zstd));
+        assert_eq!(Ok(Compression::Gzip), "gzip".parse());
+        assert_eq!("zstd -dc a.tar.zst | tar -xf -", Compression::Zstd.decompress_command("a.tar.zst"));
+    }
+}
@@ -0,0 +1,128 @@
+//! Environment of spawned cargo processes, from `[env]` in `erlangapp.toml`.
+//!
+//! Run from a rebar3 or mix hook, cargo inherits the build tool's variables: `ERL_LIBS`
+//! changes what the `erl` run by NIF build scripts loads, and `REBAR_*` and `MIX_*` are the
+//! outer build's business, not the crates'.  These are removed by default.  A
+//! `CARGO_TARGET_DIR` or `RUSTUP_TOOLCHAIN` exported by an outer build can be removed or
+//! pinned the same way.
+
+use std::collections::BTreeMap;
+use std::env;
+use std::process;
+
+/// Removed unless `remove` is configured
+pub const DEFAULT_REMOVE: &[&str] = &["ERL_LIBS", "REBAR_*", "MIX_*"];
+
+/// Inherited variables shown by `-v`, as they reach cargo
+const SHOWN: &[&str] = &["CARGO_*", "RUSTUP_*", "RUSTC*", "RUSTFLAGS", "RUSTDOCFLAGS"];
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Policy {
+    /// `env.remove`: names, or prefixes ending in `*`, removed from cargo's environment
+    pub remove: Vec<String>,
+    /// `env.keep`: exceptions to `remove`
+    pub keep: Vec<String>,
+    /// `[env.set]`: set for cargo, whatever was inherited
+    pub set: BTreeMap<String, String>,
+}
+
+impl Default for Policy {
+    fn default() -> Policy {
+        Policy {
+            remove: DEFAULT_REMOVE.iter().map(|s| s.to_string()).collect(),
+            keep: Vec::new(),
+            set: BTreeMap::new(),
+        }
+    }
+}
+
+/// One difference from the inherited environment: `None` removes the variable
+pub type Change = (String, Option<String>);
+
+impl Policy {
+    /// What to change in an environment holding `inherited`
+    pub fn changes<I: IntoIterator<Item = String>>(&self, inherited: I) -> Vec<Change> {
+        let mut changes: Vec<Change> = inherited.into_iter()
+            .filter(|name| self.removes(name) && !self.set.contains_key(name))
+            .map(|name| (name, None))
+            .collect();
+        changes.sort();
+        changes.extend(self.set.iter().map(|(k, v)| (k.clone(), Some(v.clone()))));
+        changes
+    }
+
+    /// Apply the policy to `command`, against this process's environment
+    pub fn apply(&self, command: &mut process::Command) {
+        for (name, value) in self.changes(inherited()) {
+            match value {
+                Some(value) => command.env(name, value),
+                None => command.env_remove(name),
+            };
+        }
+    }
+
+    /// The value of `name` that cargo will see
+    pub fn var(&self, name: &str) -> Option<String> {
+        match self.set.get(name) {
+            Some(value) => Some(value.clone()),
+            None if self.removes(name) => None,
+            None => env::var(name).ok(),
+        }
+    }
+
+    fn removes(&self, name: &str) -> bool {
+        self.remove.iter().any(|p| matches(p, name)) && !self.keep.iter().any(|p| matches(p, name))
+    }
+
+    /// Lines describing cargo's environment, for `-v`: the changes, then the inherited
+    /// variables that affect cargo
+    pub fn describe(&self) -> Vec<String> {
+        let changes = self.changes(inherited());
+        let mut lines: Vec<String> = changes.iter()
+            .map(|(name, value)| match *value {
+                Some(ref value) => format!("set {}={}", name, value),
+                None => format!("unset {}", name),
+            })
+            .collect();
+        let mut shown: Vec<(String, String)> = env::vars()
+            .filter(|(name, _)| SHOWN.iter().any(|p| matches(p, name)) && !changes.iter().any(|(n, _)| n == name))
+            .collect();
+        shown.sort();
+        lines.extend(shown.into_iter().map(|(name, value)| format!("{}={}", name, value)));
+        lines
+    }
+}
+
+fn inherited() -> Vec<String> {
+    env::vars_os().filter_map(|(name, _)| name.into_string().ok()).collect()
+}
+
+/// `NAME`, or `PREFIX*`
+fn matches(pattern: &str, name: &str) -> bool {
+    match pattern.strip_suffix('*') {
+        Some(prefix) => name.starts_with(prefix),
+        None => name == pattern,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_changes() {
+        let inherited = || ["PATH", "ERL_LIBS", "REBAR_PROFILE", "REBAR_CACHE_DIR", "MIX_ENV", "RUSTUP_TOOLCHAIN"]
+            .iter().map(|s| s.to_string()).collect::<Vec<_>>();
+        let unset = |name: &str| (name.to_string(), None);
+        assert_eq!(vec![unset("ERL_LIBS"), unset("MIX_ENV"), unset("REBAR_CACHE_DIR"), unset("REBAR_PROFILE")],
+                   Policy::default().changes(inherited()));
+
+        let mut policy = Policy::default();
+        policy.remove.push("RUSTUP_TOOLCHAIN".to_string());
+        policy.keep.push("REBAR_CACHE_DIR".to_string());
+        policy.set.insert("MIX_ENV".to_string(), "prod".to_string());
+        assert_eq!(vec![unset("ERL_LIBS"), unset("REBAR_PROFILE"), unset("RUSTUP_TOOLCHAIN"),
+                        ("MIX_ENV".to_string(), Some("prod".to_string()))],
+                   policy.changes(inherited()));
+    }
+}
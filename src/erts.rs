@@ -0,0 +1,90 @@
+//! Erlang runtime wiring for crates with NIF sys crates (`erlang_nif-sys`, `rustler_sys`)
+//! among their dependencies.
+//!
+//! Their build scripts run `erl` and look for ERTS headers.  We locate Erlang once, then
+//! put its `bin` directory first on `PATH` and export `ERL` and `ERTS_INCLUDE_DIR` for
+//! those crates' builds, so a missing or unexpected Erlang is reported up front.
+
+use std::env;
+use std::ffi::OsString;
+use std::path::{Path, PathBuf};
+use std::process;
+
+use metadata::Metadata;
+use MsgError;
+use MsgError::*;
+
+/// Dependencies whose build scripts need an Erlang installation
+static NIF_SYS_CRATES: &[&str] = &["erlang_nif-sys", "rustler_sys"];
+
+/// An Erlang installation
+#[derive(Debug)]
+pub struct Erts {
+    pub erl: PathBuf,
+    /// `<root>/erts-<version>/include`, holding `erl_nif.h`
+    pub include_dir: PathBuf,
+}
+
+impl Erts {
+    /// Locate Erlang: `erl` from `erlangapp.toml`, else `$ERL`, else `erl` on `PATH`
+    pub fn locate(configured: Option<&Path>) -> Result<Erts, MsgError> {
+        let erl = configured.map(PathBuf::from)
+            .or_else(|| env::var_os("ERL").map(PathBuf::from))
+            .or_else(|| find_on_path(if cfg!(windows) { "erl.exe" } else { "erl" }))
+            .ok_or(Msg("no Erlang found for NIF build scripts; put erl on PATH, set ERL, or set erl in erlangapp.toml"))?;
+        let output = process::Command::new(&erl)
+            .args(["-noshell", "-eval",
+                   "io:format(\"~s\", [filename:join([code:root_dir(), \"erts-\" ++ erlang:system_info(version), \"include\"])]), halt()."])
+            .output()
+            .map_err(|err| MsgIo("cannot run erl", err))?;
+        let include_dir = PathBuf::from(String::from_utf8_lossy(&output.stdout).trim());
+        if !output.status.success() || !include_dir.join("erl_nif.h").is_file() {
+            return Err(MsgDetail("cannot find erl_nif.h for this Erlang", erl.to_string_lossy().into_owned()));
+        }
+        Ok(Erts { erl, include_dir })
+    }
+
+    /// Environment for a NIF sys crate's build script
+    pub fn env(&self) -> Vec<(&'static str, OsString)> {
+        let mut vars = vec![("ERL", self.erl.clone().into_os_string()),
+                            ("ERTS_INCLUDE_DIR", self.include_dir.clone().into_os_string())];
+        if let Some(bin_dir) = self.erl.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+            let path = env::var_os("PATH").unwrap_or_default();
+            let dirs = Some(bin_dir.to_path_buf()).into_iter().chain(env::split_paths(&path));
+            if let Ok(path) = env::join_paths(dirs) {
+                vars.push(("PATH", path));
+            }
+        }
+        vars
+    }
+}
+
+/// True if a crate's resolved dependencies include a NIF sys crate
+pub fn needs_erts(metadata: &Metadata) -> bool {
+    metadata.packages.iter().any(|p| NIF_SYS_CRATES.contains(&p.name.as_str()))
+}
+
+pub fn find_on_path(program: &str) -> Option<PathBuf> {
+    env::split_paths(&env::var_os("PATH")?)
+        .map(|dir| dir.join(program))
+        .find(|path| path.is_file())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_needs_erts() {
+        let metadata = |name: &str| Metadata::from_slice(format!(
+            r#"{{"packages": [{{"name": "mynif", "id": "mynif 0.1.0"}}, {{"name": "{}", "id": "x 1.0.0"}}]}}"#, name).as_bytes()).unwrap();
+        assert!(needs_erts(&metadata("rustler_sys")));
+        assert!(needs_erts(&metadata("erlang_nif-sys")));
+        assert!(!needs_erts(&metadata("serde")));
+
+        let erts = Erts { erl: PathBuf::from("/opt/otp/bin/erl"), include_dir: PathBuf::from("/opt/otp/erts-14.2/include") };
+        let env = erts.env();
+        assert_eq!(("ERTS_INCLUDE_DIR", OsString::from("/opt/otp/erts-14.2/include")), env[1]);
+        assert!(env[2].1.to_string_lossy().starts_with("/opt/otp/bin"));
+    }
+}
@@ -0,0 +1,227 @@
+//! `ez` command: the application's `ebin/` and built `priv/` in an Erlang code archive.
+//!
+//! An `.ez` is a zip of `<app>-<vsn>/`.  The code server loads beams straight from it, but
+//! `erlang:load_nif/2` and `open_port/2` need real files, so deployments that ship archives
+//! unpack the `priv/` part (or all of it) before starting the node.  Entries are stored
+//! uncompressed with a fixed timestamp, so the same build gives the same archive.
+
+use std::convert::TryFrom;
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+/// 1980-01-01 00:00, the earliest DOS date
+const DOS_DATE: u16 = (1 << 5) | 1;
+const DOS_TIME: u16 = 0;
+
+/// "Made by" Unix, zip 2.0, so the mode in the external attributes is honoured
+const VERSION_MADE_BY: u16 = (3 << 8) | 20;
+const VERSION_NEEDED: u16 = 20;
+
+struct Entry {
+    name: String,
+    name_len: u16,
+    crc: u32,
+    size: u32,
+    mode: u32,
+    offset: u32,
+}
+
+/// Write a stored zip of `files`, each an archive name and the file to read it from, with
+/// an entry for every directory above them.  Returns the archive size.
+pub fn write(path: &Path, files: &[(String, PathBuf)]) -> io::Result<u64> {
+    let mut out = Vec::new();
+    let mut entries: Vec<Entry> = Vec::new();
+    let mut files: Vec<&(String, PathBuf)> = files.iter().collect();
+    files.sort_by(|a, b| a.0.cmp(&b.0));
+
+    for (name, src) in files {
+        let mut at = 0;
+        while let Some(slash) = name[at..].find('/') {
+            at += slash + 1;
+            let dir = &name[..at];
+            if !entries.iter().any(|entry| entry.name == dir) {
+                let offset = offset(&out)?;
+                entries.push(local_entry(&mut out, dir, &[], 0o40755, offset)?);
+            }
+        }
+        let data = fs::read(src)?;
+        let offset = offset(&out)?;
+        entries.push(local_entry(&mut out, name, &data, file_mode(src)?, offset)?);
+    }
+
+    let directory_offset = offset(&out)?;
+    for entry in entries.iter() {
+        put32(&mut out, 0x0201_4b50);
+        put16(&mut out, VERSION_MADE_BY);
+        header(&mut out, entry);
+        put16(&mut out, 0); // comment length
+        put16(&mut out, 0); // disk number
+        put16(&mut out, 0); // internal attributes
+        put32(&mut out, entry.mode << 16);
+        put32(&mut out, entry.offset);
+        out.extend_from_slice(entry.name.as_bytes());
+    }
+    let directory_size = offset(&out)? - directory_offset;
+    let count = u16::try_from(entries.len()).map_err(|_| too_large())?;
+
+    put32(&mut out, 0x0605_4b50);
+    put16(&mut out, 0); // this disk
+    put16(&mut out, 0); // disk with the central directory
+    put16(&mut out, count);
+    put16(&mut out, count);
+    put32(&mut out, directory_size);
+    put32(&mut out, directory_offset);
+    put16(&mut out, 0); // comment length
+
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    let mut file = fs::File::create(path)?;
+    file.write_all(&out)?;
+    Ok(out.len() as u64)
+}
+
+/// Add the files under `dir` as `<prefix>/<relative path>`, except hidden ones and the
+/// top-level names in `skip`
+pub fn add_dir(files: &mut Vec<(String, PathBuf)>, prefix: &str, dir: &Path, skip: &[&str]) -> io::Result<()> {
+    let mut entries = fs::read_dir(dir)?.collect::<io::Result<Vec<_>>>()?;
+    entries.sort_by_key(|entry| entry.file_name());
+    for entry in entries {
+        let name = entry.file_name().to_string_lossy().into_owned();
+        if name.starts_with('.') || skip.contains(&name.as_str()) {
+            continue;
+        }
+        let archived = format!("{}/{}", prefix, name);
+        match entry.path().is_dir() {
+            true => add_dir(files, &archived, &entry.path(), &[])?,
+            false => files.push((archived, entry.path())),
+        }
+    }
+    Ok(())
+}
+
+fn local_entry(out: &mut Vec<u8>, name: &str, data: &[u8], mode: u32, offset: u32) -> io::Result<Entry> {
+    let entry = Entry {
+        name: name.to_string(),
+        name_len: u16::try_from(name.len()).map_err(|_| too_large())?,
+        crc: crc32(data),
+        size: u32::try_from(data.len()).map_err(|_| too_large())?,
+        mode,
+        offset,
+    };
+    put32(out, 0x0403_4b50);
+    header(out, &entry);
+    out.extend_from_slice(name.as_bytes());
+    out.extend_from_slice(data);
+    Ok(entry)
+}
+
+/// The fields local and central headers share, from "version needed" to "extra length"
+fn header(out: &mut Vec<u8>, entry: &Entry) {
+    put16(out, VERSION_NEEDED);
+    put16(out, 0); // flags
+    put16(out, 0); // stored
+    put16(out, DOS_TIME);
+    put16(out, DOS_DATE);
+    put32(out, entry.crc);
+    put32(out, entry.size);
+    put32(out, entry.size);
+    put16(out, entry.name_len);
+    put16(out, 0); // extra length
+}
+
+/// Regular file mode, keeping whether it is executable: port programs must stay runnable
+#[cfg(unix)]
+fn file_mode(path: &Path) -> io::Result<u32> {
+    use std::os::unix::fs::PermissionsExt;
+    let executable = fs::metadata(path)?.permissions().mode() & 0o111 != 0;
+    Ok(if executable { 0o100755 } else { 0o100644 })
+}
+
+#[cfg(not(unix))]
+fn file_mode(path: &Path) -> io::Result<u32> {
+    let executable = path.extension().map_or(false, |ext| ext == "exe");
+    Ok(if executable { 0o100755 } else { 0o100644 })
+}
+
+fn offset(out: &[u8]) -> io::Result<u32> {
+    u32::try_from(out.len()).map_err(|_| too_large())
+}
+
+fn too_large() -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, "archive too large (over 4 GiB, 65535 entries or a 65535-byte name)")
+}
+
+fn put16(out: &mut Vec<u8>, value: u16) {
+    out.extend_from_slice(&value.to_le_bytes());
+}
+
+fn put32(out: &mut Vec<u8>, value: u32) {
+    out.extend_from_slice(&value.to_le_bytes());
+}
+
+/// CRC-32 (IEEE), as zip uses
+fn crc32(data: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for byte in data {
+        crc ^= u32::from(*byte);
+        for _ in 0..8 {
+            crc = (crc >> 1) ^ (0xEDB8_8320 & (!(crc & 1)).wrapping_add(1));
+        }
+    }
+    !crc
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use std::process;
+
+    #[test]
+    fn test_write() {
+        assert_eq!(0xCBF4_3926, crc32(b"123456789"));
+
+        let dir = env::temp_dir().join(format!("cargo-erlangapp-ez-{}", process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let (beam, nif) = (dir.join("my_app.beam"), dir.join("libmy_nif.so"));
+        fs::write(&beam, b"FOR1").unwrap();
+        fs::write(&nif, b"\x7fELF").unwrap();
+        fs::write(dir.join(".hidden"), b"").unwrap();
+        let mut files = Vec::new();
+        add_dir(&mut files, "my_app-1.0.0/ebin", &dir, &["libmy_nif.so"]).unwrap();
+        assert_eq!(vec![("my_app-1.0.0/ebin/my_app.beam".to_string(), beam)], files);
+        files.push(("my_app-1.0.0/priv/crates/my_nif/libmy_nif.so".to_string(), nif));
+        files.reverse();
+        let archive = dir.join("out").join("my_app-1.0.0.ez");
+        let size = write(&archive, &files).unwrap();
+
+        let data = fs::read(&archive).unwrap();
+        assert_eq!(size, data.len() as u64);
+        let u16_at = |at: usize| u16::from_le_bytes([data[at], data[at + 1]]) as usize;
+        let u32_at = |at: usize| u32::from_le_bytes([data[at], data[at + 1], data[at + 2], data[at + 3]]) as usize;
+        let end = data.len() - 22;
+        assert_eq!(0x0605_4b50, u32_at(end));
+        let mut at = u32_at(end + 16);
+        let mut names = Vec::new();
+        for _ in 0..u16_at(end + 10) {
+            assert_eq!(0x0201_4b50, u32_at(at));
+            let len = u16_at(at + 28);
+            let name = String::from_utf8(data[at + 46..at + 46 + len].to_vec()).unwrap();
+            let local = u32_at(at + 42);
+            assert_eq!(0x0403_4b50, u32_at(local));
+            if name == "my_app-1.0.0/ebin/my_app.beam" {
+                assert_eq!(b"FOR1", &data[local + 30 + len..local + 34 + len]);
+                assert_eq!(crc32(b"FOR1") as usize, u32_at(at + 16));
+            }
+            names.push(name);
+            at += 46 + len;
+        }
+        assert_eq!(vec!["my_app-1.0.0/", "my_app-1.0.0/ebin/", "my_app-1.0.0/ebin/my_app.beam",
+                        "my_app-1.0.0/priv/", "my_app-1.0.0/priv/crates/", "my_app-1.0.0/priv/crates/my_nif/",
+                        "my_app-1.0.0/priv/crates/my_nif/libmy_nif.so"], names);
+        assert!(local_entry(&mut Vec::new(), &"x".repeat(65536), &[], 0o100644, 0).is_err());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
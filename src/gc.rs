@@ -0,0 +1,143 @@
+//! `gc` command: prune stale build outputs from cargo target directories.
+//!
+//! Entries are the per-package items cargo keeps under each profile directory (`deps/*`,
+//! `build/*`, `.fingerprint/*`, `incremental/*`); cargo rebuilds whatever is missing.
+//! Installed artifacts in `priv/` are never touched.
+
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+static ENTRY_DIRS: &[&str] = &["deps", "build", ".fingerprint", "incremental"];
+
+/// What to keep: entries modified within `max_age`, and at most `max_size` bytes in total,
+/// evicting least recently modified first
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Policy {
+    pub max_age: Option<Duration>,
+    pub max_size: Option<u64>,
+}
+
+/// Parse an age such as `30d`, `12h` or `90m`
+pub fn parse_age(s: &str) -> Option<Duration> {
+    let (n, unit) = s.split_at(s.find(|c: char| !c.is_ascii_digit())?);
+    let n: u64 = n.parse().ok()?;
+    let secs = match unit {
+        "d" => 86400,
+        "h" => 3600,
+        "m" => 60,
+        _ => return None,
+    };
+    Some(Duration::from_secs(n * secs))
+}
+
+/// Parse a size such as `10G`, `500M` or `1024` (bytes)
+pub fn parse_size(s: &str) -> Option<u64> {
+    let end = s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len());
+    let n: u64 = s[..end].parse().ok()?;
+    let unit = match s[end..].trim_end_matches(['i', 'B']) {
+        "" => 1,
+        "K" | "k" => 1 << 10,
+        "M" => 1 << 20,
+        "G" => 1 << 30,
+        "T" => 1 << 40,
+        _ => return None,
+    };
+    Some(n * unit)
+}
+
+struct Entry {
+    path: PathBuf,
+    bytes: u64,
+    modified: SystemTime,
+}
+
+/// Apply `policy` across `target_dirs`, returning the entries removed and bytes freed
+pub fn collect(target_dirs: &[PathBuf], policy: &Policy) -> io::Result<(usize, u64)> {
+    let mut entries = Vec::new();
+    for target_dir in target_dirs.iter() {
+        for profile_dir in profile_dirs(target_dir) {
+            for name in ENTRY_DIRS.iter() {
+                if let Ok(children) = profile_dir.join(name).read_dir() {
+                    for child in children {
+                        let path = child?.path();
+                        let (bytes, modified) = usage(&path)?;
+                        entries.push(Entry { path, bytes, modified });
+                    }
+                }
+            }
+        }
+    }
+    entries.sort_by_key(|e| e.modified); // oldest first
+
+    let now = SystemTime::now();
+    let mut total: u64 = entries.iter().map(|e| e.bytes).sum();
+    let (mut removed, mut freed) = (0, 0);
+    for entry in entries.iter() {
+        let expired = policy.max_age
+            .map(|age| now.duration_since(entry.modified).map(|d| d > age).unwrap_or(false))
+            .unwrap_or(false);
+        let over_budget = policy.max_size.map(|size| total > size).unwrap_or(false);
+        if !expired && !over_budget {
+            continue;
+        }
+        match fs::metadata(&entry.path)?.is_dir() {
+            true => fs::remove_dir_all(&entry.path)?,
+            false => fs::remove_file(&entry.path)?,
+        }
+        total -= entry.bytes;
+        removed += 1;
+        freed += entry.bytes;
+    }
+    Ok((removed, freed))
+}
+
+/// Profile directories in a target dir: `target/<profile>` and `target/<triple>/<profile>`
+fn profile_dirs(target_dir: &Path) -> Vec<PathBuf> {
+    let subdirs = |dir: &Path| dir.read_dir()
+        .map(|entries| entries.filter_map(Result::ok).map(|e| e.path()).filter(|p| p.is_dir()).collect())
+        .unwrap_or_else(|_| Vec::new());
+    let is_profile = |dir: &Path| ENTRY_DIRS.iter().any(|name| dir.join(name).is_dir());
+    let mut dirs = Vec::new();
+    for dir in subdirs(target_dir) {
+        if is_profile(&dir) {
+            dirs.push(dir);
+        } else {
+            dirs.extend(subdirs(&dir).into_iter().filter(|d| is_profile(d)));
+        }
+    }
+    dirs
+}
+
+/// Total size and latest modification time of a file or directory tree
+pub fn usage(path: &Path) -> io::Result<(u64, SystemTime)> {
+    let metadata = fs::symlink_metadata(path)?;
+    let mut bytes = metadata.len();
+    let mut modified = metadata.modified()?;
+    if metadata.is_dir() {
+        bytes = 0;
+        for entry in path.read_dir()? {
+            let (b, m) = usage(&entry?.path())?;
+            bytes += b;
+            modified = modified.max(m);
+        }
+    }
+    Ok((bytes, modified))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(Some(Duration::from_secs(30 * 86400)), parse_age("30d"));
+        assert_eq!(Some(Duration::from_secs(2 * 3600)), parse_age("2h"));
+        assert_eq!(None, parse_age("30"));
+        assert_eq!(Some(10 << 30), parse_size("10G"));
+        assert_eq!(Some(500 << 20), parse_size("500MiB"));
+        assert_eq!(Some(1024), parse_size("1024"));
+        assert_eq!(None, parse_size("10X"));
+    }
+}
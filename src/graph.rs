@@ -0,0 +1,219 @@
+//! `graph` command: the application's crates and their path dependencies on each other as
+//! a Graphviz DOT or Mermaid graph, optionally with their heavy external dependencies.
+//!
+//! Crates under `crates/` are boxes, path dependencies elsewhere dashed boxes, and external
+//! (registry or git) dependencies ellipses labeled with the number of packages each pulls
+//! in.  Dev-dependencies are left out.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use metadata::{Metadata, Package};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Format {
+    Dot,
+    Mermaid,
+}
+
+impl FromStr for Format {
+    type Err = ();
+    fn from_str(s: &str) -> Result<Format, ()> {
+        match s {
+            "dot" => Ok(Format::Dot),
+            "mermaid" => Ok(Format::Mermaid),
+            _ => Err(()),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Kind {
+    /// Under `crates/`
+    Crate,
+    /// A path dependency elsewhere
+    Path,
+    /// From a registry or git, with the number of packages it brings in
+    External(usize),
+}
+
+#[derive(Debug, Default)]
+pub struct Graph {
+    nodes: Vec<(String, Kind)>,
+    edges: BTreeSet<(usize, usize)>,
+}
+
+impl Graph {
+    fn node(&mut self, label: &str, kind: Kind) -> usize {
+        match self.nodes.iter().position(|(l, _)| l == label) {
+            Some(i) => {
+                if kind == Kind::Crate {
+                    self.nodes[i].1 = kind;
+                }
+                i
+            },
+            None => {
+                self.nodes.push((label.to_string(), kind));
+                self.nodes.len() - 1
+            },
+        }
+    }
+
+    /// Add the crate in `crate_dir` from its resolved `metadata`, following path dependencies.
+    /// Those in `crate_dirs` are the application's crates.  External dependencies of the
+    /// local packages are added when they pull in at least `min_external` packages.
+    pub fn add_crate(&mut self, metadata: &Metadata, crate_dir: &Path, crate_dirs: &[PathBuf], min_external: Option<usize>) {
+        let resolve = match metadata.resolve {
+            Some(ref resolve) => resolve,
+            None => return,
+        };
+        let packages: BTreeMap<&str, &Package> = metadata.packages.iter().map(|p| (p.id.as_str(), p)).collect();
+        let deps: BTreeMap<&str, Vec<&str>> = resolve.nodes.iter()
+            .map(|node| (node.id.as_str(), node.deps.iter()
+                .filter(|dep| dep.dep_kinds.is_empty() || dep.dep_kinds.iter().any(|k| k.kind.as_deref() != Some("dev")))
+                .map(|dep| dep.pkg.as_str())
+                .collect()))
+            .collect();
+        let canonical = |dir: &Path| fs::canonicalize(dir).unwrap_or_else(|_| dir.to_path_buf());
+        let crate_dirs: Vec<PathBuf> = crate_dirs.iter().map(|dir| canonical(dir)).collect();
+        let kind = |package: &Package| match package.manifest_path.parent() {
+            Some(dir) if crate_dirs.contains(&canonical(dir)) => Kind::Crate,
+            _ => Kind::Path,
+        };
+
+        let root = canonical(crate_dir);
+        let root = match metadata.packages.iter().find(|p| p.manifest_path.parent().map(|dir| canonical(dir) == root).unwrap_or(false)) {
+            Some(package) => package,
+            None => return,
+        };
+        let mut pending = vec![root];
+        let mut seen = BTreeSet::new();
+        while let Some(package) = pending.pop() {
+            if !seen.insert(package.id.as_str()) {
+                continue;
+            }
+            let from = self.node(&package.name, kind(package));
+            for dep in deps.get(package.id.as_str()).into_iter().flatten() {
+                let dep_package = match packages.get(dep) {
+                    Some(p) => *p,
+                    None => continue,
+                };
+                if dep_package.source.is_none() {
+                    let to = self.node(&dep_package.name, kind(dep_package));
+                    self.edges.insert((from, to));
+                    pending.push(dep_package);
+                } else if let Some(min) = min_external {
+                    let pulled = reachable(&deps, dep);
+                    if pulled >= min {
+                        let to = self.node(&format!("{} {}", dep_package.name, dep_package.version), Kind::External(pulled));
+                        self.edges.insert((from, to));
+                    }
+                }
+            }
+        }
+    }
+
+    pub fn render(&self, format: Format, name: &str) -> String {
+        let label = |i: usize| match self.nodes[i] {
+            (ref label, Kind::External(n)) => format!("{} ({} package{})", label, n, if n == 1 { "" } else { "s" }),
+            (ref label, _) => label.clone(),
+        };
+        let mut out = String::new();
+        match format {
+            Format::Dot => {
+                out.push_str(&format!("digraph \"{}\" {{\n    rankdir=LR;\n    node [shape=box];\n", name));
+                for (i, (_, kind)) in self.nodes.iter().enumerate() {
+                    let style = match *kind {
+                        Kind::Crate => "",
+                        Kind::Path => " [style=dashed]",
+                        Kind::External(_) => " [shape=ellipse, color=gray50]",
+                    };
+                    out.push_str(&format!("    n{} [label=\"{}\"]{};\n", i, label(i).replace('"', "\\\""), style));
+                }
+                for (from, to) in self.edges.iter() {
+                    out.push_str(&format!("    n{} -> n{};\n", from, to));
+                }
+                out.push_str("}\n");
+            },
+            Format::Mermaid => {
+                out.push_str("graph LR\n");
+                for (i, (_, kind)) in self.nodes.iter().enumerate() {
+                    let text = label(i).replace('"', "#quot;");
+                    out.push_str(&match *kind {
+                        Kind::Crate => format!("    n{}[\"{}\"]\n", i, text),
+                        Kind::Path => format!("    n{}[/\"{}\"/]\n", i, text),
+                        Kind::External(_) => format!("    n{}([\"{}\"])\n", i, text),
+                    });
+                }
+                for (from, to) in self.edges.iter() {
+                    out.push_str(&format!("    n{} --> n{}\n", from, to));
+                }
+            },
+        }
+        out
+    }
+}
+
+/// Number of packages `id` brings in, itself included
+fn reachable(deps: &BTreeMap<&str, Vec<&str>>, id: &str) -> usize {
+    let mut seen = BTreeSet::new();
+    let mut pending = vec![id];
+    while let Some(id) = pending.pop() {
+        if seen.insert(id) {
+            pending.extend(deps.get(id).into_iter().flatten().cloned());
+        }
+    }
+    seen.len()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_graph() {
+        let metadata = Metadata::from_slice(br#"{
+            "packages": [
+                {"name": "mynif", "id": "mynif", "version": "0.1.0", "manifest_path": "/app/crates/mynif/Cargo.toml"},
+                {"name": "shared", "id": "shared", "version": "0.1.0", "manifest_path": "/libs/shared/Cargo.toml"},
+                {"name": "util", "id": "util", "version": "0.2.0", "manifest_path": "/app/crates/util/Cargo.toml"},
+                {"name": "rustler", "id": "rustler", "version": "0.36.2", "manifest_path": "/r/Cargo.toml", "source": "registry+x"},
+                {"name": "inventory", "id": "inventory", "version": "0.3.0", "manifest_path": "/i/Cargo.toml", "source": "registry+x"},
+                {"name": "tempfile", "id": "tempfile", "version": "3.0.0", "manifest_path": "/t/Cargo.toml", "source": "registry+x"}
+            ],
+            "resolve": {"nodes": [
+                {"id": "mynif", "deps": [
+                    {"name": "shared", "pkg": "shared", "dep_kinds": [{"kind": null}]},
+                    {"name": "rustler", "pkg": "rustler", "dep_kinds": [{"kind": null}]},
+                    {"name": "tempfile", "pkg": "tempfile", "dep_kinds": [{"kind": "dev"}]}
+                ]},
+                {"id": "shared", "deps": [{"name": "util", "pkg": "util", "dep_kinds": [{"kind": null}]}]},
+                {"id": "util", "deps": []},
+                {"id": "rustler", "deps": [{"name": "inventory", "pkg": "inventory", "dep_kinds": [{"kind": null}]}]},
+                {"id": "inventory", "deps": []}
+            ]}
+        }"#).unwrap();
+        let crate_dirs = vec![PathBuf::from("/app/crates/mynif"), PathBuf::from("/app/crates/util")];
+        let mut graph = Graph::default();
+        graph.add_crate(&metadata, Path::new("/app/crates/mynif"), &crate_dirs, Some(2));
+        assert_eq!("digraph \"my_app\" {
+    rankdir=LR;
+    node [shape=box];
+    n0 [label=\"mynif\"];
+    n1 [label=\"shared\"] [style=dashed];
+    n2 [label=\"rustler 0.36.2 (2 packages)\"] [shape=ellipse, color=gray50];
+    n3 [label=\"util\"];
+    n0 -> n1;
+    n0 -> n2;
+    n1 -> n3;
+}
+", graph.render(Format::Dot, "my_app"));
+
+        let mut graph = Graph::default();
+        graph.add_crate(&metadata, Path::new("/app/crates/mynif"), &crate_dirs, Some(3));
+        assert_eq!("graph LR\n    n0[\"mynif\"]\n    n1[/\"shared\"/]\n    n2[\"util\"]\n    n0 --> n1\n    n1 --> n2\n",
+                   graph.render(Format::Mermaid, "my_app"));
+    }
+}
@@ -0,0 +1,66 @@
+//! Ctrl-C and termination handling.
+//!
+//! An interrupt no longer kills us outright: running cargo processes get it too and are
+//! waited for, and the command then stops with `Failure::Interrupted` at the next check,
+//! never between copying an artifact and moving it into place.  Children started from a
+//! terminal already receive its Ctrl-C; on Unix the signal is also forwarded, for when it
+//! was sent to us alone (by make or rebar3, say).
+
+use std::process;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Mutex, Once};
+
+use ctrlc;
+
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+static CHILDREN: Mutex<Vec<u32>> = Mutex::new(Vec::new());
+static INSTALL: Once = Once::new();
+
+/// Handle Ctrl-C, SIGTERM and SIGHUP from now on.  Only the command line tool does this;
+/// programs using the library keep their own signal handling.
+pub fn install() {
+    INSTALL.call_once(|| {
+        // failure only means another handler is installed, which then stays in charge
+        let _ = ctrlc::set_handler(|| {
+            INTERRUPTED.store(true, Ordering::SeqCst);
+            if let Ok(children) = CHILDREN.lock() {
+                children.iter().for_each(|pid| forward(*pid));
+            }
+        });
+    });
+}
+
+/// True once an interrupt has been received
+pub fn interrupted() -> bool {
+    INTERRUPTED.load(Ordering::SeqCst)
+}
+
+#[cfg(unix)]
+fn forward(pid: u32) {
+    let _ = process::Command::new("kill").args(["-INT", &pid.to_string()]).status();
+}
+
+#[cfg(not(unix))]
+fn forward(_pid: u32) {
+    // console control events already reach every process attached to the console
+}
+
+/// A running child process that interrupts are forwarded to, until dropped
+pub struct Child(u32);
+
+impl Child {
+    pub fn register(child: &process::Child) -> Child {
+        if let Ok(mut children) = CHILDREN.lock() {
+            children.push(child.id());
+        }
+        Child(child.id())
+    }
+}
+
+impl Drop for Child {
+    fn drop(&mut self) {
+        if let Ok(mut children) = CHILDREN.lock() {
+            children.retain(|pid| *pid != self.0);
+        }
+    }
+}
@@ -0,0 +1,116 @@
+//! GNU make jobserver pass-through.
+//!
+//! When make (or rebar3 driving make) exports a jobserver in `MAKEFLAGS`, cargo is pointed
+//! at it so the whole build shares one pool of job slots.  Crates are built one at a time,
+//! so our own parallelism never adds to what the jobserver hands out.
+
+use std::env;
+use std::path::{Path, PathBuf};
+use std::process;
+
+/// Jobserver advertised in `MAKEFLAGS`
+#[derive(Debug, PartialEq)]
+enum Jobserver {
+    /// `--jobserver-auth=R,W` (or the older `--jobserver-fds=R,W`)
+    Fds(u32, u32),
+    /// `--jobserver-auth=fifo:PATH` (make 4.4+)
+    Fifo(PathBuf),
+    /// Windows named semaphore
+    Named(String),
+}
+
+impl Jobserver {
+    fn from_makeflags(makeflags: &str) -> Option<Jobserver> {
+        // the last occurrence wins, as in make itself
+        let auth = makeflags.split_whitespace()
+            .rev()
+            .find_map(|flag| {
+                let mut kv = flag.splitn(2, '=');
+                match (kv.next(), kv.next()) {
+                    (Some("--jobserver-auth"), Some(v)) | (Some("--jobserver-fds"), Some(v)) => Some(v),
+                    _ => None,
+                }
+            })?;
+
+        if let Some(path) = auth.strip_prefix("fifo:") {
+            return Some(Jobserver::Fifo(PathBuf::from(path)));
+        }
+        let mut fds = auth.splitn(2, ',').map(|fd| fd.parse::<u32>());
+        match (fds.next(), fds.next()) {
+            (Some(Ok(r)), Some(Ok(w))) => Some(Jobserver::Fds(r, w)),
+            _ => Some(Jobserver::Named(auth.to_string())),
+        }
+    }
+
+    /// False when make advertised a jobserver but did not hand us its file descriptors
+    /// (the recipe wasn't marked `+` or didn't reference `$(MAKE)`)
+    fn is_usable(&self) -> bool {
+        match *self {
+            Jobserver::Fds(r, w) => fd_is_open(r) && fd_is_open(w),
+            Jobserver::Fifo(ref path) => path.exists(),
+            Jobserver::Named(_) => true,
+        }
+    }
+}
+
+#[cfg(unix)]
+fn fd_is_open(fd: u32) -> bool {
+    Path::new(&format!("/dev/fd/{}", fd)).exists()
+}
+
+#[cfg(not(unix))]
+fn fd_is_open(_fd: u32) -> bool {
+    true
+}
+
+/// Drop jobserver flags from `MAKEFLAGS`
+fn strip_jobserver(makeflags: &str) -> String {
+    makeflags.split_whitespace()
+        .filter(|flag| !flag.starts_with("--jobserver-auth=") && !flag.starts_with("--jobserver-fds="))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+fn current() -> Option<(String, Jobserver)> {
+    let makeflags = env::var("MAKEFLAGS").ok()?;
+    Jobserver::from_makeflags(&makeflags).map(|js| (makeflags, js))
+}
+
+/// Warning to show when a jobserver is advertised but unusable
+pub fn check() -> Option<&'static str> {
+    match current() {
+        Some((_, ref js)) if !js.is_usable() =>
+            Some("warning: make jobserver advertised in MAKEFLAGS is not available (mark the recipe with '+'); building without it"),
+        _ => None,
+    }
+}
+
+/// Point a cargo invocation at make's jobserver, or hide a broken one from it
+pub fn configure(cmd: &mut process::Command) {
+    match current() {
+        Some((makeflags, ref js)) if js.is_usable() => {
+            cmd.env("CARGO_MAKEFLAGS", makeflags);
+        },
+        Some((makeflags, _)) => {
+            cmd.env("MAKEFLAGS", strip_jobserver(&makeflags))
+                .env_remove("MFLAGS")
+                .env_remove("CARGO_MAKEFLAGS");
+        },
+        None => (),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_from_makeflags() {
+        assert_eq!(None, Jobserver::from_makeflags("-j4 --no-print-directory"));
+        assert_eq!(Some(Jobserver::Fds(3, 4)), Jobserver::from_makeflags(" -j4 --jobserver-auth=3,4"));
+        assert_eq!(Some(Jobserver::Fds(5, 6)), Jobserver::from_makeflags("--jobserver-fds=5,6 -j"));
+        assert_eq!(Some(Jobserver::Fifo(PathBuf::from("/tmp/GMfifo1"))),
+                   Jobserver::from_makeflags("-j8 --jobserver-auth=fifo:/tmp/GMfifo1"));
+        assert_eq!("-j4 --no-print-directory", strip_jobserver("-j4 --jobserver-auth=3,4 --no-print-directory"));
+    }
+}
@@ -0,0 +1,210 @@
+//! JUnit XML report of `test --junit`, combining the test results of every crate.
+//!
+//! Results are read from what the test runner printed: libtest's human output (`test a::b
+//! ... ok`) or its JSON events (`--format json`), or cargo-nextest's status lines (`PASS
+//! [ 0.004s] mynif a::b`).  Each crate is a test suite.  A failed libtest test carries its
+//! captured output; nextest retries leave the last attempt's result.
+
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::time::Duration;
+
+use json;
+use strip_ansi;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Outcome {
+    Passed,
+    /// With the test's output, if known
+    Failed(String),
+    Skipped,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Case {
+    pub name: String,
+    pub outcome: Outcome,
+    pub time: Option<f64>,
+}
+
+/// One crate's results
+#[derive(Debug)]
+pub struct Suite {
+    pub name: String,
+    pub cases: Vec<Case>,
+    pub duration: Duration,
+}
+
+/// Test cases in a runner's output
+pub fn parse(text: &str) -> Vec<Case> {
+    let mut cases: Vec<Case> = Vec::new();
+    let mut outputs: Vec<(String, String)> = Vec::new();
+    let mut collecting: Option<(String, String)> = None;
+    for line in text.lines().map(strip_ansi) {
+        let line = line.as_str();
+        // libtest's captured output of failures: `---- a::b stdout ----` up to the next header
+        if let Some(name) = line.strip_prefix("---- ").and_then(|rest| rest.strip_suffix(" stdout ----")) {
+            outputs.extend(collecting.take());
+            collecting = Some((name.to_string(), String::new()));
+            continue;
+        }
+        if let Some((_, ref mut output)) = collecting {
+            if line == "failures:" {
+                outputs.extend(collecting.take());
+            } else {
+                output.push_str(line);
+                output.push('\n');
+            }
+            continue;
+        }
+        if let Some(case) = libtest_line(line).or_else(|| libtest_json(line)) {
+            cases.push(case);
+        } else if let Some((case, retry)) = nextest_line(line) {
+            match cases.iter_mut().rev().find(|c| retry && c.name == case.name) {
+                Some(earlier) => *earlier = case,
+                None => cases.push(case),
+            }
+        }
+    }
+    outputs.extend(collecting);
+    for (name, output) in outputs {
+        if let Some(case) = cases.iter_mut().find(|c| c.name == name) {
+            if let Outcome::Failed(ref mut text) = case.outcome {
+                if text.is_empty() {
+                    *text = output.trim().to_string();
+                }
+            }
+        }
+    }
+    cases
+}
+
+/// `test a::b ... ok`
+fn libtest_line(line: &str) -> Option<Case> {
+    let (name, result) = line.strip_prefix("test ")?.split_once(" ... ")?;
+    let outcome = match result {
+        "ok" => Outcome::Passed,
+        "FAILED" => Outcome::Failed(String::new()),
+        _ if result.starts_with("ignored") => Outcome::Skipped,
+        _ => return None,
+    };
+    Some(Case { name: name.to_string(), outcome, time: None })
+}
+
+/// `{ "type": "test", "event": "ok", "name": "a::b", "exec_time": 0.001 }`
+fn libtest_json(line: &str) -> Option<Case> {
+    let value: json::Value = json::from_str(line.trim()).ok()?;
+    if value.get("type")?.as_str()? != "test" {
+        return None;
+    }
+    let outcome = match value.get("event")?.as_str()? {
+        "ok" => Outcome::Passed,
+        "failed" => Outcome::Failed(value.get("stdout").and_then(|s| s.as_str()).unwrap_or_default().trim_end().to_string()),
+        "ignored" => Outcome::Skipped,
+        _ => return None,
+    };
+    Some(Case {
+        name: value.get("name")?.as_str()?.to_string(),
+        outcome,
+        time: value.get("exec_time").and_then(|t| t.as_f64()),
+    })
+}
+
+/// `PASS [   0.004s] mynif a::b`, or a retry's `TRY 2 PASS ...`, which is also returned
+fn nextest_line(line: &str) -> Option<(Case, bool)> {
+    let mut line = line.trim_start();
+    let retry = line.starts_with("TRY ");
+    if retry {
+        line = line["TRY ".len()..].trim_start().split_once(' ')?.1.trim_start();
+    }
+    let (status, rest) = line.split_once(' ')?;
+    let outcome = match status {
+        "PASS" | "FLAKY" => Outcome::Passed,
+        "FAIL" | "TIMEOUT" | "LEAK-FAIL" | "ABORT" => Outcome::Failed(String::new()),
+        _ if status.starts_with("SIG") => Outcome::Failed(String::new()),
+        "SKIP" => Outcome::Skipped,
+        _ => return None,
+    };
+    let (time, rest) = rest.trim_start().strip_prefix('[')?.split_once(']')?;
+    let (_binary, name) = rest.trim().split_once(' ')?;
+    Some((Case {
+        name: name.trim().to_string(),
+        outcome,
+        time: time.trim().strip_suffix('s').and_then(|t| t.parse().ok()),
+    }, retry))
+}
+
+pub fn render(suites: &[Suite]) -> String {
+    let count = |suite: &Suite, f: fn(&Outcome) -> bool| suite.cases.iter().filter(|c| f(&c.outcome)).count();
+    let failed = |o: &Outcome| matches!(*o, Outcome::Failed(_));
+    let skipped = |o: &Outcome| *o == Outcome::Skipped;
+    let total: usize = suites.iter().map(|s| s.cases.len()).sum();
+    let failures: usize = suites.iter().map(|s| count(s, failed)).sum();
+    let time: f64 = suites.iter().map(|s| s.duration.as_secs_f64()).sum();
+
+    let mut out = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+    out.push_str(&format!("<testsuites name=\"cargo-erlangapp\" tests=\"{}\" failures=\"{}\" time=\"{:.3}\">\n", total, failures, time));
+    for suite in suites.iter() {
+        out.push_str(&format!("  <testsuite name=\"{}\" tests=\"{}\" failures=\"{}\" skipped=\"{}\" time=\"{:.3}\">\n",
+                              escape(&suite.name), suite.cases.len(), count(suite, failed), count(suite, skipped),
+                              suite.duration.as_secs_f64()));
+        for case in suite.cases.iter() {
+            let time = case.time.map(|t| format!(" time=\"{:.3}\"", t)).unwrap_or_default();
+            let open = format!("    <testcase classname=\"{}\" name=\"{}\"{}", escape(&suite.name), escape(&case.name), time);
+            match case.outcome {
+                Outcome::Passed => out.push_str(&format!("{}/>\n", open)),
+                Outcome::Skipped => out.push_str(&format!("{}>\n      <skipped/>\n    </testcase>\n", open)),
+                Outcome::Failed(ref output) => out.push_str(&format!(
+                    "{}>\n      <failure message=\"failed\">{}</failure>\n    </testcase>\n", open, escape(output))),
+            }
+        }
+        out.push_str("  </testsuite>\n");
+    }
+    out.push_str("</testsuites>\n");
+    out
+}
+
+pub fn write(path: &Path, suites: &[Suite]) -> io::Result<()> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    fs::write(path, render(suites))
+}
+
+fn escape(s: &str) -> String {
+    s.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        let libtest = "running 3 tests\ntest tests::a ... ok\ntest tests::b ... FAILED\ntest tests::c ... ignored, slow\n\n\
+                       failures:\n\n---- tests::b stdout ----\nthread 'tests::b' panicked at src/lib.rs:9:5:\n1 < 0\n\n\
+                       failures:\n    tests::b\n\ntest result: FAILED. 1 passed; 1 failed; 1 ignored\n";
+        assert_eq!(vec![Case { name: "tests::a".into(), outcome: Outcome::Passed, time: None },
+                        Case { name: "tests::b".into(), outcome: Outcome::Failed("thread 'tests::b' panicked at src/lib.rs:9:5:\n1 < 0".into()), time: None },
+                        Case { name: "tests::c".into(), outcome: Outcome::Skipped, time: None }],
+                   parse(libtest));
+
+        let nextest = "    Starting 2 tests across 1 binary\n        PASS [   0.004s] mynif tests::a\n   TRY 1 FAIL [   0.010s] mynif tests::b\n  \
+                       TRY 2 PASS [   0.011s] mynif tests::b\n        SKIP [         ] mynif tests::c\n";
+        let cases = parse(nextest);
+        assert_eq!(3, cases.len());
+        assert_eq!(Case { name: "tests::b".into(), outcome: Outcome::Passed, time: Some(0.011) }, cases[1]);
+        assert_eq!(Outcome::Skipped, cases[2].outcome);
+
+        let json = "{ \"type\": \"test\", \"event\": \"failed\", \"name\": \"a\", \"stdout\": \"boom\\n\" }\n";
+        assert_eq!(vec![Case { name: "a".into(), outcome: Outcome::Failed("boom".into()), time: None }], parse(json));
+
+        let suites = [Suite { name: "mynif".into(), cases: parse(json), duration: Duration::from_millis(1500) }];
+        assert_eq!("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+                    <testsuites name=\"cargo-erlangapp\" tests=\"1\" failures=\"1\" time=\"1.500\">\n  \
+                    <testsuite name=\"mynif\" tests=\"1\" failures=\"1\" skipped=\"0\" time=\"1.500\">\n    \
+                    <testcase classname=\"mynif\" name=\"a\">\n      <failure message=\"failed\">boom</failure>\n    </testcase>\n  \
+                    </testsuite>\n</testsuites>\n", render(&suites));
+    }
+}
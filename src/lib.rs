@@ -1,335 +1,2995 @@
 
+extern crate ctrlc;
+#[macro_use]
+extern crate serde;
 extern crate serde_json as json;
+extern crate sha2;
+extern crate toml;
 
+mod app_version;
+mod artifacts;
+mod bench;
+mod bump;
+mod codegen;
+mod config;
+mod depfile;
+mod dist;
+mod env_policy;
+mod erts;
+mod ez;
+mod gc;
+mod graph;
+mod interrupt;
+mod jobserver;
+mod junit;
+mod link;
+mod lock;
+mod metadata;
+mod mix;
+mod msrv;
+mod native_hrl;
+mod nif_stubs;
+mod options;
+mod otp;
+mod outdated;
+mod output;
+mod pkg_config;
+mod platform;
+mod policy;
+mod preflight;
+mod progress;
+mod provenance;
+mod qemu;
+mod rebar_lib;
+mod reload;
+mod scaffold;
+mod shared_deps;
+mod smoke;
+mod strip;
+mod suggest;
+mod summary;
+mod symbols;
+mod verify;
+mod windows;
+mod workspace;
+
+use std::collections::{BTreeMap, BTreeSet};
 use std::fs;
 use std::path::{Path, PathBuf};
 use std::fs::DirEntry;
 use std::process;
 use std::error::Error;
-use std::io::{self, stderr, Write};
+use std::io;
 use std::convert::From;
 use std::result;
 use std::fmt::{self, Display};
+use std::cell::{OnceCell, RefCell};
+use std::ffi::OsString;
+use std::str::FromStr;
+use std::time::{Duration, Instant, SystemTime};
 
-/// `try!` for `Option`
-macro_rules! otry(
-    ($e:expr) => (match $e { Some(e) => e, None => return None })
-);
-
-// Special OSX link args
-// Without them linker throws a fit about NIF API calls.
-#[cfg(target_os="macos")]
-static DYLIB_LINKER_ARGS: &'static[&'static str] = &["--", "--codegen", "link-args=-flat_namespace -undefined suppress"];
+use config::Config;
+use output::Output;
+use summary::CrateReport;
 
-#[cfg(not(target_os="macos"))]
-static DYLIB_LINKER_ARGS: &'static[&'static str] = &[];
+pub use dist::Compression;
+pub use gc::Policy as GcPolicy;
+pub use graph::Format as GraphFormat;
+pub use options::Options;
+pub use windows::Toolchain as WindowsToolchain;
 
+// Have rustc report the native libraries a staticlib must be linked with
+static STATICLIB_RUSTC_ARGS: &[&str] = &["--print=native-static-libs"];
 
-static BIN_LINKER_ARGS: &'static[&'static str] = &[];
 
 
+/// Failure categories.  The discriminant is the process exit code; these values are
+/// stable so wrapping scripts can branch on them.  Uncategorized errors exit with 1.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Failure {
+    Usage = 2,
+    Enumeration = 3,
+    Compile = 4,
+    Test = 5,
+    Install = 6,
+    /// Another invocation held the application's lock past the lock timeout
+    Locked = 7,
+    /// A dependency broke the `[policy]` in `erlangapp.toml`
+    Policy = 8,
+    /// Ctrl-C or a termination signal, as shells report death by SIGINT
+    Interrupted = 130,
+}
 
+/// Errors from commands and from the public enumeration API
 #[derive(Debug)]
-enum MsgError {
+pub enum MsgError {
     Msg(&'static str),
     MsgIo(&'static str, io::Error),
+    MsgDetail(&'static str, String),
+    MsgCommand(&'static str, CommandFailure),
+    Failed(Failure, Box<MsgError>),
+}
+
+/// Lines of a failed subprocess's output to show in its error message
+const OUTPUT_EXCERPT_LINES: usize = 20;
+
+/// Where `bench` stages benchmark results, relative to the application
+const BENCH_RESULTS_DIR: &str = "bench_results";
+
+/// A subprocess that failed: what was run, where, and what it printed
+#[derive(Debug)]
+pub struct CommandFailure {
+    command: String,
+    dir: PathBuf,
+    output: String,
+    /// How it ended, if it ran to the end
+    status: Option<process::ExitStatus>,
+}
+
+impl CommandFailure {
+    fn new(command: &process::Command, output: String) -> CommandFailure {
+        CommandFailure {
+            command: command_line(command),
+            dir: command.get_current_dir().map(PathBuf::from).unwrap_or_else(|| PathBuf::from(".")),
+            output,
+            status: None,
+        }
+    }
+
+    /// Record the exit status the command failed with
+    fn exited(mut self, status: process::ExitStatus) -> CommandFailure {
+        self.status = Some(status);
+        self
+    }
+
+    /// True if cargo failed building, rather than in the tests or benchmarks it ran
+    fn build_failed(&self) -> bool {
+        self.output.contains("error: could not compile")
+    }
+}
+
+impl Display for CommandFailure {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "    command: {}\n    directory: {}", self.command, self.dir.display())?;
+        if let Some(status) = self.status {
+            write!(f, "\n    {}", status)?;
+        }
+        for line in output_excerpt(&self.output, OUTPUT_EXCERPT_LINES) {
+            write!(f, "\n    | {}", line)?;
+        }
+        Ok(())
+    }
+}
+
+/// The command line of `command`, quoted where needed so it can be pasted into a shell
+fn command_line(command: &process::Command) -> String {
+    let quote = |s: &std::ffi::OsStr| {
+        let s = s.to_string_lossy();
+        match s.is_empty() || s.contains(|c: char| c.is_whitespace() || "\"'$`\\|&;<>()*?".contains(c)) {
+            true => format!("'{}'", s.replace('\'', "'\\''")),
+            false => s.into_owned(),
+        }
+    };
+    Some(command.get_program()).into_iter().chain(command.get_args())
+        .map(quote)
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// The last `max` lines of subprocess output, without color codes, JSON messages or blank
+/// lines, and noting how many were left out
+fn output_excerpt(output: &str, max: usize) -> Vec<String> {
+    let lines: Vec<String> = output.lines()
+        .filter(|line| !line.starts_with('{'))
+        .map(strip_ansi)
+        .filter(|line| !line.trim().is_empty())
+        .collect();
+    let skip = lines.len().saturating_sub(max);
+    let mut excerpt = Vec::new();
+    if skip > 0 {
+        excerpt.push(format!("... ({} earlier lines)", skip));
+    }
+    excerpt.extend(lines.into_iter().skip(skip));
+    excerpt
+}
+
+fn strip_ansi(line: &str) -> String {
+    let mut text = String::new();
+    let mut chars = line.chars();
+    while let Some(c) = chars.next() {
+        if c == '\x1b' {
+            // CSI sequence: ESC [ parameters final-byte
+            if chars.next() == Some('[') {
+                for c in chars.by_ref() {
+                    if ('@'..='~').contains(&c) {
+                        break;
+                    }
+                }
+            }
+        } else {
+            text.push(c);
+        }
+    }
+    text
 }
 
 use MsgError::*;
 
-impl Error for MsgError {
-    fn description(&self) -> &str {
+impl MsgError {
+    /// Tag an error with its failure category.  The innermost category wins.
+    fn category(self, failure: Failure) -> MsgError {
         match self {
-            &Msg(s) => s,
-            &MsgIo(s, ref _err) => s,
+            Failed(..) => self,
+            _ => Failed(failure, Box::new(self)),
+        }
+    }
+
+    fn message(&self) -> &'static str {
+        match *self {
+            Msg(s) => s,
+            MsgIo(s, ref _err) => s,
+            MsgDetail(s, ref _detail) => s,
+            MsgCommand(s, ref _failure) => s,
+            Failed(_, ref err) => err.message(),
+        }
+    }
+
+    /// Process exit code for this error: 128 + the signal that killed a subprocess, as
+    /// shells report it, else its failure category, else 1
+    pub fn exit_code(&self) -> i32 {
+        if let Some(signal) = self.exit_status().and_then(|status| exit_signal(&status)) {
+            return 128 + signal;
+        }
+        match *self {
+            Failed(failure, _) => failure as i32,
+            _ => 1,
         }
     }
+
+    /// Exit status of the subprocess whose failure this is
+    pub fn exit_status(&self) -> Option<process::ExitStatus> {
+        match *self {
+            MsgCommand(_, ref failure) => failure.status,
+            Failed(_, ref err) => err.exit_status(),
+            _ => None,
+        }
+    }
+
+    /// Categorize a failed `cargo test` or `cargo bench`: a build failure is a compile error
+    fn test_category(self) -> MsgError {
+        let compile = match self {
+            MsgCommand(_, ref failure) => failure.build_failed(),
+            _ => false,
+        };
+        self.category(if compile { Failure::Compile } else { Failure::Test })
+    }
+}
+
+#[cfg(unix)]
+fn exit_signal(status: &process::ExitStatus) -> Option<i32> {
+    use std::os::unix::process::ExitStatusExt;
+    status.signal()
+}
+
+#[cfg(not(unix))]
+fn exit_signal(_status: &process::ExitStatus) -> Option<i32> {
+    None
+}
+
+impl Error for MsgError {
+    fn description(&self) -> &str {
+        self.message()
+    }
 }
 
 impl Display for MsgError {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        match self {
-            &Msg(s) =>
+        match *self {
+            Msg(s) =>
                 write!(f, "{}", s),
-            &MsgIo(s, ref err) =>
+            MsgIo(s, ref err) =>
                 write!(f, "{} ({})", s, err),
+            MsgDetail(s, ref detail) =>
+                write!(f, "{} ({})", s, detail),
+            MsgCommand(s, ref failure) =>
+                write!(f, "{}\n{}", s, failure),
+            Failed(_, ref err) =>
+                write!(f, "{}", err),
         }
     }
 }
 
 /// Main entry point into this application.  Invoked by main() and integration tests
 pub fn invoke_with_args_str(args: &[&str], appdir: &Path) {
-    let args_string: Vec<String> = args.into_iter().cloned().map(From::from).collect();
+    let args_string: Vec<String> = args.iter().cloned().map(From::from).collect();
     invoke_with_args(&args_string, appdir)
 }
 
-pub fn invoke_with_args(args: &[String], appdir: &Path)
-{
-    match ArgsInfo::from_args(&args) {
-        Some(ref ai) => invoke(ai, appdir),
-        None => usage(),
+pub fn invoke_with_args(args: &[String], appdir: &Path)
+{
+    match Options::from_args(args) {
+        Some(options) => invoke(options, appdir),
+        None => match args.get(1).filter(|cmd| parse_cmd_name(cmd).is_none()).and_then(|cmd| suggest::closest(cmd, COMMAND_NAMES)) {
+            Some(meant) => {
+                eprintln!("Error: no such command {} (did you mean {}?)", args[1], meant);
+                process::exit(Failure::Usage as i32);
+            },
+            None => usage(),
+        },
+    }
+}
+
+/// Run a command on the application in `appdir`.  Unlike `invoke_with_args`, errors are
+/// returned rather than reported and exited on (output captured in hook mode is written
+/// out first).
+pub fn run(options: Options, appdir: &Path) -> Result<(), MsgError> {
+    check_passed_options(&options)?;
+    // an explicit --target wins over --windows-toolchain
+    if options.both_windows_toolchains && options.target.is_none() {
+        match options.command {
+            CargoCommand::Build | CargoCommand::Clean | CargoCommand::Dist | CargoCommand::Verify => (),
+            _ => return Err(Msg("--windows-toolchain=both applies to build, clean, dist and verify").category(Failure::Usage)),
+        }
+        for toolchain in [WindowsToolchain::Msvc, WindowsToolchain::Gnu] {
+            let mut options = options.clone();
+            options.windows_toolchain = Some(toolchain);
+            run_once(options, appdir)?;
+        }
+        return Ok(());
+    }
+    run_once(options, appdir)
+}
+
+/// Refuse an option the tool would pass on to cargo when it looks like a typo of a known
+/// one, showing what cargo would be given
+fn check_passed_options(options: &Options) -> Result<(), MsgError> {
+    let end = options.cargo_args.iter().position(|arg| arg == "--").unwrap_or(options.cargo_args.len());
+    let known: Vec<&str> = options::TOOL_OPTIONS.iter().chain(suggest::CARGO_OPTIONS).cloned().collect();
+    for arg in options.cargo_args[..end].iter().filter(|arg| arg.starts_with("--")) {
+        let name = arg.split('=').next().unwrap_or(arg);
+        if known.contains(&name) {
+            continue;
+        }
+        if let Some(meant) = suggest::closest(name, &known) {
+            return Err(MsgDetail("unknown option", format!("{}, did you mean {}? Passed on to cargo: {}",
+                                                           name, meant, options.cargo_args.join(" ")))
+                .category(Failure::Usage));
+        }
+    }
+    Ok(())
+}
+
+/// True for commands that give the arguments they don't know to cargo
+fn passes_cargo_args(command: &CargoCommand) -> bool {
+    matches!(*command, CargoCommand::Build | CargoCommand::Test | CargoCommand::Bench | CargoCommand::Clippy
+             | CargoCommand::Clean | CargoCommand::SmokeTest | CargoCommand::Dist | CargoCommand::Ez)
+}
+
+fn run_once(options: Options, appdir: &Path) -> Result<(), MsgError> {
+    let mut argsinfo = ArgsInfo::new(options);
+    let result = argsinfo.resolve(appdir)
+        .and_then(|_| {
+            // as resolved: without app paths, with arguments from the profile mapping,
+            // the Windows toolchain and `[target.'cfg(...)']`
+            if passes_cargo_args(&argsinfo.options.command) && !argsinfo.options.cargo_args.is_empty() {
+                argsinfo.output.status(&format!("Passing on to cargo: {}", argsinfo.options.cargo_args.join(" ")));
+            }
+            do_command(&argsinfo, appdir)
+        });
+    if result.is_err() {
+        argsinfo.output.dump_captured();
+    }
+    result
+}
+
+
+fn usage() {
+    eprintln!("Usage:");
+    eprintln!("\tcargo-erlangapp build [options] [app dirs] [cargo rustc args]");
+    eprintln!("\tcargo-erlangapp clean [options] [app dirs] [cargo clean args]");
+    eprintln!("\tcargo-erlangapp test [--runner=cargo|nextest] [options] [app dirs] [cargo test args]");
+    eprintln!("\tcargo-erlangapp bench [options] [app dirs] [cargo bench args]");
+    eprintln!("\tcargo-erlangapp clippy [options] [app dirs] [cargo clippy args]");
+    eprintln!("\tcargo-erlangapp smoke-test [options] [app dirs] [cargo rustc args]");
+    eprintln!("\tcargo-erlangapp prune-artifacts [options] [app dirs]");
+    eprintln!("\tcargo-erlangapp rollback [options] [app dirs]");
+    eprintln!("\tcargo-erlangapp switch-profile <profile> [options] [app dirs]");
+    eprintln!("\tcargo-erlangapp check-otp <otp-path-or-version> [options] [app dirs]");
+    eprintln!("\tcargo-erlangapp check-symbols [options] [app dirs]");
+    eprintln!("\tcargo-erlangapp dist [options] [app dirs] [cargo rustc args]");
+    eprintln!("\tcargo-erlangapp ez [options] [app dirs] [cargo rustc args]");
+    eprintln!("\tcargo-erlangapp verify [options] [app dirs]");
+    eprintln!("\tcargo-erlangapp status [options] [app dirs]");
+    eprintln!("\tcargo-erlangapp gc [--max-age=<age>] [--max-size=<size>] [options] [app dirs]");
+    eprintln!("\tcargo-erlangapp du [options] [app dirs]");
+    eprintln!("\tcargo-erlangapp outdated [options] [app dirs]");
+    eprintln!("\tcargo-erlangapp policy [options] [app dirs]");
+    eprintln!("\tcargo-erlangapp graph [--format=dot|mermaid] [--external[=<n>]] [options] [app dirs]");
+    eprintln!("\tcargo-erlangapp sync-deps [--dry-run] [options] [app dirs]");
+    eprintln!("\tcargo-erlangapp codegen [options] [app dirs]");
+    eprintln!("\tcargo-erlangapp version-bump <patch|minor|major|version> [--sync-app] [options] [app dirs]");
+    eprintln!("\tcargo-erlangapp init [--nif=<name>] [--template=<git-url-or-path>]");
+    eprintln!("\tcargo-erlangapp new <name> [--template=<git-url-or-path>]");
+    eprintln!("\tcargo-erlangapp add-nif <name> [--template=<git-url-or-path>]");
+    eprintln!("\tcargo-erlangapp add-port <name> [--template=<git-url-or-path>]");
+    eprintln!("\tcargo-erlangapp workspace-ify");
+    eprintln!("Options:");
+    eprintln!("\t--all                          process every application with crates/ found under the app dirs");
+    eprintln!("\t--app=<name>[,<name>...]       only process the named umbrella applications");
+    eprintln!("\t--backup                       keep the artifact a build replaces as <name>.prev, for rollback");
+    eprintln!("\t--bin=<name>, --lib            build only these targets of the crates (build, smoke-test, dist)");
+    eprintln!("\t--compression=<method>         dist archive compression: none, gzip, zstd or xz");
+    eprintln!("\t--config=<key>=<value>         override a cargo config value in every cargo invocation");
+    eprintln!("\t--deny-warnings                make compiler warnings errors in every crate");
+    eprintln!("\t--hook-mode                    print nothing unless something fails");
+    eprintln!("\t--junit=<file>                 test: write a JUnit XML report of all crates' tests, relative to the app");
+    eprintln!("\t--keep-artifacts=<n>           keep the last n generations of each installed artifact");
+    eprintln!("\t--lock-timeout=<secs>          wait this long for another invocation on the same app (default 300)");
+    eprintln!("\t--manifest-path=<Cargo.toml>   only process this crate, wherever it is");
+    eprintln!("\t--max-age=<age>                gc: remove entries older than this, e.g. 30d, 12h");
+    eprintln!("\t--max-size=<size>              gc: evict oldest entries beyond this size, e.g. 10G");
+    eprintln!("\t--message-format=json          pass cargo's JSON messages through, tagged with the crate");
+    eprintln!("\t--mix                          build: write a Mix compiler manifest for a Mix compiler task");
+    eprintln!("\t--only-nifs, --only-bins       build only dylib (NIF) or only bin (port program) targets");
+    eprintln!("\t--platform-dirs                install into priv/crates/<crate>/<os>-<arch>/ for multi-platform releases");
+    eprintln!("\t--priv-dir=<dir>               install artifacts into <dir>/crates instead of priv/crates");
+    eprintln!("\t--profile-dirs                 also keep each build in priv/crates/<crate>/<profile>/ for switch-profile");
+    eprintln!("\t--prune                        build, prune-artifacts: remove the priv/crates directories of crates that no longer exist");
+    eprintln!("\t--quiet-cargo                  hide cargo's output unless it fails, keeping progress lines");
+    eprintln!("\t--rebar-lib-dirs               also install into rebar3's _build/<profile>/lib/<app>/priv copies");
+    eprintln!("\t--rebar-profile=<profile>      select the cargo profile from a rebar3 profile");
+    eprintln!("\t--rustc-args=\"<args>\"        pass these arguments to rustc (build, smoke-test, dist)");
+    eprintln!("\t--static-crt                   link port programs with a static C runtime on Windows");
+    eprintln!("\t--strict-targets               fail on crates with no installable targets instead of skipping them");
+    eprintln!("\t--strip                        strip debug info and local symbols, keeping NIF and driver entry points");
+    eprintln!("\t--sync-app                     version-bump: bump the app version too and give crates that version");
+    eprintln!("\t--versioned-artifacts          install dylibs as lib<name>-<version>.so for hot upgrades");
+    eprintln!("\t--windows-toolchain=msvc|gnu   build for the Windows target of this toolchain (=both: for each)");
+    process::exit(Failure::Usage as i32);
+}
+
+
+
+fn invoke(options: Options, appdir: &Path) {
+    interrupt::install();
+    if let Err(err) = run(options, appdir) {
+        eprintln!("Error: {}", err);
+        process::exit(err.exit_code());
+    }
+}
+
+fn do_command(argsinfo: &ArgsInfo, appdir: &Path) -> Result<(), MsgError> {
+    match argsinfo.options.command {
+        CargoCommand::Build =>
+            for_each_app(argsinfo, appdir, build_crates),
+        CargoCommand::Test =>
+            for_each_app(argsinfo, appdir, test_crates),
+        CargoCommand::Bench =>
+            for_each_app(argsinfo, appdir, bench_crates),
+        CargoCommand::Clippy =>
+            for_each_app(argsinfo, appdir, clippy_crates),
+        CargoCommand::Clean =>
+            for_each_app(argsinfo, appdir, clean_crates),
+        CargoCommand::SmokeTest =>
+            for_each_app(argsinfo, appdir, smoke_test),
+        CargoCommand::PruneArtifacts =>
+            for_each_app(argsinfo, appdir, prune_artifacts),
+        CargoCommand::Rollback =>
+            for_each_app(argsinfo, appdir, rollback_crates),
+        CargoCommand::SwitchProfile =>
+            for_each_app(argsinfo, appdir, switch_profile),
+        CargoCommand::CheckOtp =>
+            for_each_app(argsinfo, appdir, check_otp),
+        CargoCommand::CheckSymbols =>
+            for_each_app(argsinfo, appdir, check_symbols),
+        CargoCommand::Dist =>
+            for_each_app(argsinfo, appdir, dist_crates),
+        CargoCommand::Ez =>
+            for_each_app(argsinfo, appdir, ez_crates),
+        CargoCommand::Verify =>
+            for_each_app(argsinfo, appdir, verify_crates),
+        CargoCommand::Status =>
+            for_each_app(argsinfo, appdir, status_crates),
+        CargoCommand::Gc =>
+            for_each_app(argsinfo, appdir, gc_crates),
+        CargoCommand::Du =>
+            for_each_app(argsinfo, appdir, du_crates),
+        CargoCommand::Outdated =>
+            for_each_app(argsinfo, appdir, outdated_crates),
+        CargoCommand::Policy =>
+            for_each_app(argsinfo, appdir, policy_crates),
+        CargoCommand::Graph =>
+            for_each_app(argsinfo, appdir, graph_crates),
+        CargoCommand::SyncDeps =>
+            for_each_app(argsinfo, appdir, sync_deps),
+        CargoCommand::Codegen =>
+            for_each_app(argsinfo, appdir, codegen_crates),
+        CargoCommand::VersionBump =>
+            for_each_app(argsinfo, appdir, version_bump),
+        CargoCommand::Init => {
+            let nif_name = find_option_value(&argsinfo.options.cargo_args, "--nif");
+            let template = find_option_value(&argsinfo.options.cargo_args, "--template");
+            scaffold::init(appdir, nif_name.as_deref(), template.as_deref())
+        },
+        CargoCommand::New =>
+            new_crate(argsinfo, appdir, scaffold::Skeleton::Lib),
+        CargoCommand::AddNif =>
+            new_crate(argsinfo, appdir, scaffold::Skeleton::Nif),
+        CargoCommand::AddPort =>
+            new_crate(argsinfo, appdir, scaffold::Skeleton::Port),
+        CargoCommand::WorkspaceIfy =>
+            workspace::workspaceify(appdir),
+    }
+}
+
+/// Run a command against each resolved application, then print a combined summary
+fn for_each_app<F>(argsinfo: &ArgsInfo, appdir: &Path, f: F) -> Result<(), MsgError>
+    where F: Fn(&ArgsInfo, &Path, &mut Vec<CrateReport>) -> Result<(), MsgError>
+{
+    let several = argsinfo.app_dirs.len() != 1 || argsinfo.app_dirs[0] != appdir;
+    let mut reports = Vec::new();
+    for app_dir in argsinfo.app_dirs.iter() {
+        // scanned applications are named by path, since names may repeat across a tree
+        let app_name = match argsinfo.options.scan_all {
+            true => app_dir.strip_prefix(appdir).unwrap_or(app_dir).to_string_lossy(),
+            false => app_dir.file_name().unwrap_or(app_dir.as_os_str()).to_string_lossy(),
+        };
+        if several {
+            argsinfo.output.status(&format!("==> {}", app_name));
+        }
+        let _lock = lock_app(argsinfo, app_dir, &app_name)?;
+        let first = reports.len();
+        let result = f(argsinfo, app_dir, &mut reports);
+        if several {
+            for report in reports[first..].iter_mut() {
+                report.name = format!("{}/{}", app_name, report.name);
+            }
+        }
+        if result.is_err() {
+            print_summary(argsinfo, &reports);
+            return result;
+        }
+    }
+    print_summary(argsinfo, &reports);
+    Ok(())
+}
+
+/// Take the application's lock for commands that write to it; read-only commands don't wait
+fn lock_app(argsinfo: &ArgsInfo, app_dir: &Path, app_name: &str) -> Result<Option<lock::AppLock>, MsgError> {
+    match argsinfo.options.command {
+        CargoCommand::Verify | CargoCommand::Status | CargoCommand::Du | CargoCommand::Outdated
+        | CargoCommand::Policy | CargoCommand::Graph | CargoCommand::CheckOtp | CargoCommand::CheckSymbols
+        | CargoCommand::Clippy => return Ok(None),
+        _ => (),
+    }
+    let timeout = argsinfo.lock_timeout();
+    let waiting = |holder: Option<u32>| {
+        let holder = holder.map(|pid| format!(" (pid {})", pid)).unwrap_or_default();
+        argsinfo.output.status(&format!("Waiting for another cargo-erlangapp{} to finish with {}", holder, app_name));
+    };
+    match lock::acquire(app_dir, timeout, waiting) {
+        Ok(lock) => Ok(Some(lock)),
+        Err(lock::LockError::TimedOut(holder)) => {
+            let holder = holder.map(|pid| format!("pid {}, ", pid)).unwrap_or_default();
+            Err(MsgDetail("application locked by another cargo-erlangapp",
+                          format!("{}{}; waited {}s", holder, lock::lock_path(app_dir).display(), timeout.as_secs()))
+                .category(Failure::Locked))
+        },
+        Err(lock::LockError::Interrupted) => Err(Msg("interrupted").category(Failure::Interrupted)),
+        Err(lock::LockError::Io(err)) => Err(MsgIo("cannot lock application", err)),
+    }
+}
+
+fn print_summary(argsinfo: &ArgsInfo, reports: &[CrateReport]) {
+    if !reports.is_empty() {
+        argsinfo.output.status(&summary::render(reports));
+    }
+    if !argsinfo.deny_warnings() {
+        if let Some(warnings) = summary::render_warnings(reports) {
+            argsinfo.output.status(&warnings);
+        }
+    }
+}
+
+/// Scaffold a crate named by the first positional argument
+fn new_crate(argsinfo: &ArgsInfo, appdir: &Path, skeleton: scaffold::Skeleton) -> Result<(), MsgError> {
+    let mut args = argsinfo.options.cargo_args.clone();
+    let template = match take_option_value(&mut args, "--template") {
+        // "--template= path"
+        Some(ref value) if value.is_empty() => args.iter().position(|arg| !arg.starts_with('-'))
+            .map(|i| args.remove(i)),
+        template => template,
+    };
+    let name = args.iter()
+        .find(|arg| !arg.starts_with('-'))
+        .ok_or(Msg("crate name required"))?;
+    scaffold::new_crate(appdir, name, skeleton, template.as_deref())
+}
+
+fn build_crates(argsinfo: &ArgsInfo, appdir: &Path, reports: &mut Vec<CrateReport>) -> Result<(), MsgError> {
+    if let Some(warning) = jobserver::check() {
+        argsinfo.output.status(warning);
+    }
+    if cfg!(windows) && !argsinfo.options.both_windows_toolchains {
+        if let Some(warning) = windows::check(argsinfo.options.target.as_deref()) {
+            argsinfo.output.status(&warning);
+        }
+    }
+
+    check_rust_versions(argsinfo, appdir)?;
+    check_cross_toolchains(argsinfo, appdir)?;
+    check_shared_dependencies(argsinfo, appdir)?;
+    check_pkg_config(argsinfo, appdir)?;
+    if argsinfo.config.codegen_crate.is_some() {
+        write_codegen_module(argsinfo, appdir, false)?;
+    }
+
+    // build(rustc) each crate
+    let first = reports.len();
+    let crates = crates(argsinfo, appdir)?;
+    argsinfo.output.begin_crates(&crates.iter().map(|krate| krate.name.clone()).collect::<Vec<_>>());
+    let built = build_each_crate(argsinfo, appdir, &crates, reports);
+    argsinfo.output.end_crates();
+    built?;
+    for name in argsinfo.options.bins.iter() {
+        let built = Target::Bin(name.clone()).to_string();
+        if !reports[first..].iter().any(|report| report.targets.contains(&built)) {
+            match argsinfo.app_dirs.len() {
+                1 => return Err(MsgDetail("--bin: no crate has a bin target of this name", name.clone()).category(Failure::Usage)),
+                _ => argsinfo.output.status(&format!("No bin target named {} in this application", name)),
+            }
+        }
+    }
+
+    check_versions(argsinfo, appdir)?;
+    if argsinfo.options.manifest_path.is_none() {
+        prune_orphans(argsinfo, appdir, argsinfo.options.prune)?;
+    }
+    if argsinfo.platform.is_some() && argsinfo.options.manifest_path.is_none() && platform::generate(appdir)? {
+        argsinfo.output.status(&format!("Generated src/{}_nif_platform.erl", reload::app_name(appdir)));
+    }
+    if argsinfo.config.nif_reload_module && argsinfo.options.manifest_path.is_none() {
+        write_reload_module(argsinfo, appdir)?;
+    }
+    if argsinfo.config.nif_stubs && argsinfo.options.manifest_path.is_none() {
+        write_nif_stubs(argsinfo, appdir)?;
+    }
+    if argsinfo.options.mix {
+        write_mix_manifest(argsinfo, appdir)?;
+    }
+    let mut installed: Vec<PathBuf> = reports[first..].iter().flat_map(|report| report.installed.iter().cloned()).collect();
+    installed.extend(install_copies(argsinfo, appdir)?);
+    if !installed.is_empty() {
+        let root = appdir.canonicalize().unwrap_or_else(|_| appdir.to_path_buf());
+        let lines: Vec<String> = installed.iter()
+            .map(|path| path.strip_prefix(appdir).or_else(|_| path.strip_prefix(&root)).unwrap_or(path))
+            .map(|path| format!("  {}", path.display()))
+            .collect();
+        argsinfo.output.status(&format!("Installed:\n{}", lines.join("\n")));
+    }
+    Ok(())
+}
+
+fn build_each_crate(argsinfo: &ArgsInfo, appdir: &Path, crates: &[Crate], reports: &mut Vec<CrateReport>) -> Result<(), MsgError> {
+    for krate in crates.iter() {
+        write_native_header(argsinfo, appdir, krate, false)?;
+        let start = Instant::now();
+        let mut report = CrateReport::new(&krate.name);
+        let result = build_crate(argsinfo, appdir, krate, &mut report);
+        report.duration = start.elapsed();
+        report.ok = result.is_ok();
+        argsinfo.output.crate_state(&krate.name, if report.ok { progress::State::Done } else { progress::State::Failed });
+        reports.push(report);
+        result?;
+    }
+    Ok(())
+}
+
+/// Warn about crates whose versions diverge from the application's beyond `version-policy`
+fn check_versions(argsinfo: &ArgsInfo, appdir: &Path) -> Result<(), MsgError> {
+    let policy = argsinfo.config.version_policy;
+    if policy == app_version::VersionPolicy::Any {
+        return Ok(());
+    }
+    let app_version = match app_version::app_version(appdir) {
+        Some(version) => version,
+        None => {
+            argsinfo.output.status("Warning: no application version in src/*.app.src or mix.exs to check crate versions against");
+            return Ok(());
+        },
+    };
+    for krate in crates(argsinfo, appdir)?.iter() {
+        let version = crate_package(argsinfo, &krate.dir)?.version;
+        if policy.diverges(&app_version, &version) {
+            argsinfo.output.status(&format!("Warning: crate {} is version {} but the application is {} (version-policy = {})",
+                                            krate.name, version, app_version, policy));
+        }
+    }
+    Ok(())
+}
+
+fn codegen_crates(argsinfo: &ArgsInfo, appdir: &Path, _reports: &mut Vec<CrateReport>) -> Result<(), MsgError> {
+    for krate in crates(argsinfo, appdir)?.iter() {
+        write_native_header(argsinfo, appdir, krate, true)?;
+    }
+    if argsinfo.config.codegen_crate.is_some() {
+        write_codegen_module(argsinfo, appdir, true)?;
+    }
+    if argsinfo.config.nif_stubs {
+        write_nif_stubs(argsinfo, appdir)?;
+    }
+    Ok(())
+}
+
+/// Generate the Erlang module of each rustler NIF crate, with a spec per NIF
+fn write_nif_stubs(argsinfo: &ArgsInfo, appdir: &Path) -> Result<(), MsgError> {
+    let app = reload::app_name(appdir);
+    for krate in crates(argsinfo, appdir)?.iter() {
+        let module = match reload::nif_module(&krate.dir) {
+            Some(ref module) if !module.starts_with("Elixir.") => module.clone(),
+            _ => continue,
+        };
+        let manifest = read_manifest(argsinfo, krate)?;
+        let target = match manifest.targets.iter().find(|t| is_native_nif(argsinfo, krate, t)) {
+            Some(target) => target,
+            None => continue,
+        };
+        if nif_stubs::is_hand_written(appdir, &module) {
+            argsinfo.output.status(&format!("Not replacing hand-written src/{}.erl with NIF stubs", module));
+            continue;
+        }
+        let fns = nif_stubs::scan(&krate.dir)?;
+        if fns.is_empty() {
+            continue;
+        }
+        let load_path = match argsinfo.config.nif_reload_module {
+            true => format!("{}_nif_reload:path(\"{}\")", app, krate.name),
+            false => {
+                let name = match is_versioned(argsinfo, target) {
+                    true => versioned_filename(&install_name(argsinfo, krate, target), &manifest.version),
+                    false => install_name(argsinfo, krate, target),
+                };
+                let library = name.rsplitn(2, '.').last().unwrap_or(&name).to_string();
+                match argsinfo.platform.is_some() {
+                    true => format!("{}_nif_platform:path(\"{}\", \"{}\")", app, krate.name, library),
+                    false => format!("filename:join([code:priv_dir({}), \"crates\", \"{}\", \"{}\"])", app, krate.name, library),
+                }
+            },
+        };
+        if nif_stubs::generate(appdir, &module, &krate.name, &load_path, &fns)? {
+            argsinfo.output.status(&format!("Generated src/{}.erl from crate {}", module, krate.name));
+        }
+    }
+    Ok(())
+}
+
+/// Generate `include/<crate>_native.hrl` from a crate's annotated constants.  Without
+/// `verbose`, only report when the header changed.
+fn write_native_header(argsinfo: &ArgsInfo, appdir: &Path, krate: &Crate, verbose: bool) -> Result<(), MsgError> {
+    let (defines, skipped) = native_hrl::scan(&krate.dir)
+        .map_err(|err| MsgIo("cannot read crate sources", err))?;
+    let path = native_hrl::header_path(appdir, &krate.name);
+    let changed = native_hrl::generate(&path, &krate.name, &defines)?;
+    let shown = path.strip_prefix(appdir).unwrap_or(&path).display().to_string();
+    if (changed || verbose) && !defines.is_empty() {
+        argsinfo.output.status(&format!("Generated {} ({} defines)", shown, defines.len()));
+    } else if changed {
+        argsinfo.output.status(&format!("Removed {} (no annotated constants)", shown));
+    }
+    if verbose && !skipped.is_empty() {
+        argsinfo.output.status(&format!("Skipped in {} (not a one-line literal constant): {}", krate.name, skipped.join(", ")));
+    }
+    Ok(())
+}
+
+/// Generate the `[codegen]` crate's constants module from `include/*.hrl`.  Without
+/// `verbose`, only report when the module changed.
+fn write_codegen_module(argsinfo: &ArgsInfo, appdir: &Path, verbose: bool) -> Result<(), MsgError> {
+    let name = argsinfo.config.codegen_crate.as_ref()
+        .ok_or(Msg("codegen: set crate in [codegen] in erlangapp.toml"))?;
+    let krate = crates(argsinfo, appdir)?.into_iter()
+        .find(|krate| krate.name == *name)
+        .ok_or_else(|| MsgDetail("codegen: no such crate", name.clone()))?;
+    let headers = codegen::read_headers(appdir, &codegen::headers(appdir)?)?;
+    let encoding = match argsinfo.config.codegen_records {
+        Some(encoding) => encoding,
+        None => match crate_package(argsinfo, &krate.dir)?.dependencies.iter().any(|d| d.name == "rustler") {
+            true => codegen::RecordEncoding::Rustler,
+            false => codegen::RecordEncoding::Term,
+        },
+    };
+    let path = krate.dir.join(&argsinfo.config.codegen_module);
+    let written = codegen::generate(&path, &headers, encoding)?;
+    if written || verbose {
+        let count: usize = headers.iter().map(|h| h.defines.len()).sum();
+        let records: usize = headers.iter().map(|h| h.records.len()).sum();
+        let shown = path.strip_prefix(appdir).unwrap_or(&path).display().to_string();
+        argsinfo.output.status(&format!("Generated {} ({} constants, {} records, {} encoding)", shown, count, records, encoding));
+    }
+    if verbose {
+        for header in headers.iter().filter(|h| !h.skipped.is_empty()) {
+            argsinfo.output.status(&format!("Skipped in {} (unsupported): {}", header.path, header.skipped.join(", ")));
+        }
+    }
+    Ok(())
+}
+
+/// Generate the application's NIF reload helper module from its dylib targets
+fn write_reload_module(argsinfo: &ArgsInfo, appdir: &Path) -> Result<(), MsgError> {
+    let mut nifs = Vec::new();
+    for krate in crates(argsinfo, appdir)?.iter() {
+        let manifest = read_manifest(argsinfo, krate)?;
+        for target in manifest.targets.iter().filter(|t| is_native_nif(argsinfo, krate, t) && argsinfo.options.selects(t)) {
+            let name = match is_versioned(argsinfo, target) {
+                true => versioned_filename(&install_name(argsinfo, krate, target), &manifest.version),
+                false => install_name(argsinfo, krate, target),
+            };
+            nifs.push(reload::Nif {
+                crate_name: krate.name.clone(),
+                module: reload::nif_module(&krate.dir),
+                library: name.rsplitn(2, '.').last().unwrap_or(&name).to_string(),
+                version: manifest.version.clone(),
+            });
+        }
+    }
+    reload::generate(appdir, &nifs, argsinfo.platform.is_some())
+}
+
+/// Before building anything, fail if the toolchain is older than any crate's `rust-version`,
+/// listing those crates and the toolchain that satisfies every crate.  Cargo's
+/// `--ignore-rust-version` skips the check.
+fn check_rust_versions(argsinfo: &ArgsInfo, appdir: &Path) -> Result<(), MsgError> {
+    if find_option(&argsinfo.options.cargo_args, "--ignore-rust-version") {
+        return Ok(());
+    }
+    let mut rows = vec![["crate", "rust-version", "toolchain"].map(String::from).to_vec()];
+    let mut needed = None;
+    for krate in crates(argsinfo, appdir)?.iter() {
+        let required = match crate_package(argsinfo, &krate.dir)?.rust_version.as_deref().and_then(msrv::RustVersion::parse) {
+            Some(required) => required,
+            None => continue,
+        };
+        needed = needed.max(Some(required));
+        match msrv::toolchain_version(&krate.dir, &argsinfo.config.env) {
+            Some(toolchain) if toolchain < required =>
+                rows.push(vec![krate.name.clone(), required.to_string(), toolchain.to_string()]),
+            _ => (),  // satisfied, or no rustc to ask, which the build reports better
+        }
+    }
+    match needed {
+        Some(needed) if rows.len() > 1 => {
+            argsinfo.output.status(&summary::table(&rows));
+            Err(MsgDetail("Rust toolchain older than the crates' rust-version",
+                          format!("{} or newer builds every crate", needed))
+                .category(Failure::Compile))
+        },
+        _ => Ok(()),
+    }
+}
+
+/// Before cross-compiling, check that each crate's toolchain has the standard library for
+/// its target and that the target's configured linker exists, reporting all that is missing
+fn check_cross_toolchains(argsinfo: &ArgsInfo, appdir: &Path) -> Result<(), MsgError> {
+    // custom target specs and -Zbuild-std bring their own standard library
+    if argsinfo.options.cargo_args.iter().any(|arg| arg.contains("build-std")) {
+        return Ok(());
+    }
+    let mut checked = BTreeSet::new();
+    let mut problems = Vec::new();
+    for krate in crates(argsinfo, appdir)?.iter() {
+        let triple = match crate_target(argsinfo, krate) {
+            Some(triple) if !triple.ends_with(".json") => triple,
+            _ => continue,
+        };
+        let sysroot = match preflight::sysroot(&krate.dir, &argsinfo.config.env) {
+            Some(sysroot) => sysroot,
+            None => continue,  // no rustc to ask, which the build reports better
+        };
+        if !checked.insert((sysroot.clone(), triple.to_string())) {
+            continue;
+        }
+        if !preflight::target_installed(&sysroot, triple) {
+            problems.push(format!("{} is not installed for the toolchain in {} (run rustup target add {} in {})",
+                                  triple, sysroot.display(), triple, krate.dir.display()));
+        }
+        let var = config::cargo_target_var(triple, "LINKER");
+        let linker = argsinfo.config.target_env(triple).into_iter().find(|(name, _)| *name == var).map(|(_, linker)| linker)
+            .or_else(|| argsinfo.config.env.var(&var));
+        if let Some(linker) = linker.filter(|linker| !preflight::linker_exists(linker)) {
+            problems.push(format!("linker {} for {} not found (install the cross toolchain, or set linker in [target.{}.env])",
+                                  linker, triple, triple));
+        }
+    }
+    problems.sort();
+    problems.dedup();
+    match problems.is_empty() {
+        true => Ok(()),
+        false => Err(MsgDetail("cross toolchain not ready", problems.join("; ")).category(Failure::Compile)),
+    }
+}
+
+/// Fail when a crate declares a dependency of `[shared-dependencies]` with another version
+/// requirement, showing the change that brings each crate in line
+fn check_shared_dependencies(argsinfo: &ArgsInfo, appdir: &Path) -> Result<(), MsgError> {
+    if argsinfo.config.shared_dependencies.is_empty() {
+        return Ok(());
+    }
+    let mut drifting = Vec::new();
+    for krate in crates(argsinfo, appdir)?.iter() {
+        let drifts = shared_deps::check(&argsinfo.config.shared_dependencies, &crate_package(argsinfo, &krate.dir)?);
+        if !drifts.is_empty() {
+            let manifest = krate.dir.join("Cargo.toml");
+            let path = manifest.strip_prefix(appdir).unwrap_or(&manifest);
+            argsinfo.output.status(&format!("--- {}\n+++ [shared-dependencies]\n{}", path.display(), shared_deps::diff(&drifts).join("\n")));
+            drifting.push(krate.name.clone());
+        }
+    }
+    match drifting.is_empty() {
+        true => Ok(()),
+        false => Err(MsgDetail("crates drift from [shared-dependencies] in erlangapp.toml", drifting.join(", "))
+            .category(Failure::Policy)),
+    }
+}
+
+/// Before building, check that the packages in `[target.<triple>.pkg-config]` have `.pc`
+/// files for each triple a crate is cross-compiled for
+fn check_pkg_config(argsinfo: &ArgsInfo, appdir: &Path) -> Result<(), MsgError> {
+    if argsinfo.config.target_pkg_config.is_empty() {
+        return Ok(());
+    }
+    let crates = crates(argsinfo, appdir)?;
+    let triples: BTreeSet<&str> = crates.iter().filter_map(|krate| crate_target(argsinfo, krate)).collect();
+    let mut missing = Vec::new();
+    for triple in triples {
+        if let Some(settings) = argsinfo.config.target_pkg_config.get(triple) {
+            let packages = settings.missing();
+            if !packages.is_empty() {
+                let searched: Vec<String> = settings.search_path().iter().map(|dir| dir.display().to_string()).collect();
+                missing.push(format!("{}: {} (searched {})", triple, packages.join(", "), searched.join(", ")));
+            }
+        }
+    }
+    match missing.is_empty() {
+        true => Ok(()),
+        false => Err(MsgDetail("pkg-config: packages without a .pc file for the target",
+                               missing.join("; ")).category(Failure::Compile)),
+    }
+}
+
+/// Before testing, check that the runners configured for the triples crates are
+/// cross-compiled for are installed
+fn check_runners(argsinfo: &ArgsInfo, appdir: &Path) -> Result<(), MsgError> {
+    if argsinfo.config.target_runner.is_empty() {
+        return Ok(());
+    }
+    let crates = crates(argsinfo, appdir)?;
+    let triples: BTreeSet<&str> = crates.iter().filter_map(|krate| crate_target(argsinfo, krate)).collect();
+    for triple in triples {
+        let program = match argsinfo.config.target_runner.get(triple).and_then(|runner| runner.split_whitespace().next()) {
+            Some(program) => program,
+            None => continue,
+        };
+        let found = match program.contains(std::path::MAIN_SEPARATOR) {
+            true => Path::new(program).is_file(),
+            false => erts::find_on_path(program).is_some(),
+        };
+        if !found {
+            return Err(MsgDetail("test runner not found; install it (qemu-user for qemu-*) or change the runner in erlangapp.toml",
+                                 format!("{} for {}", program, triple)).category(Failure::Test));
+        }
+    }
+    Ok(())
+}
+
+/// Build every target of one crate and copy the artifacts to `priv/crates/<cratename>`
+fn build_crate(argsinfo: &ArgsInfo, appdir: &Path, krate: &Crate, report: &mut CrateReport) -> Result<(), MsgError> {
+    let crate_dir = krate.dir.as_path();
+    let manifest = read_manifest(argsinfo, krate)?;
+    for (name, missing) in manifest.skipped.iter() {
+        argsinfo.output.status(&format!("Skipping {} (requires features: {}; enable them or set enable-required-features)",
+                                        name, missing.join(", ")));
+    }
+    if is_host_tool(argsinfo, krate) && argsinfo.config.skip_host_tools {
+        argsinfo.output.status(&format!("Skipping host tool {} (host-tools = \"skip\")", krate.name));
+        return Ok(());
+    }
+    if let Some(kinds) = manifest.uninstallable(argsinfo.config.staticlib_crates.contains(&krate.name)) {
+        if argsinfo.options.strict_targets || argsinfo.config.strict_targets {
+            return Err(MsgDetail("no installable targets", format!("{}, kinds: {}", krate.name, kinds.join(", ")))
+                .category(Failure::Enumeration));
+        }
+        argsinfo.output.status(&format!("Skipping {}: no installable targets (kinds: {})", krate.name, kinds.join(", ")));
+        return Ok(());
+    }
+    let triple = crate_triple(argsinfo, krate)?;
+    let metadata = crate_metadata(argsinfo, crate_dir, true);
+    if let (Some(ref policy), Ok(ref metadata)) = (&argsinfo.config.policy, &metadata) {
+        let violations = policy::check(policy, metadata);
+        if !violations.is_empty() {
+            let list: Vec<String> = violations.iter().map(|v| format!("{} ({})", v.package, v.problem)).collect();
+            return Err(MsgDetail("dependency policy violated", list.join(", ")).category(Failure::Policy));
+        }
+    }
+    if let (Some(min_otp), Ok(ref metadata)) = (argsinfo.config.min_otp, &metadata) {
+        if manifest.targets.iter().any(|t| is_native_nif(argsinfo, krate, t)) {
+            check_min_otp(argsinfo, min_otp, metadata)?;
+        }
+    }
+    let build_env = match metadata {
+        Ok(ref metadata) if erts::needs_erts(metadata) => erts(argsinfo)?.env(),
+        _ => Vec::new(),  // a failed resolve shows up in the build itself
+    };
+    let mut fingerprint = verify::source_fingerprint(crate_dir)
+        .map_err(|err| MsgIo("cannot fingerprint crate sources", err).category(Failure::Enumeration))?;
+    let mut records = Vec::new();
+    let mut sources = BTreeSet::new();
+    let build = build_provenance(argsinfo, crate_dir, &triple);
+    let mut provenance = Vec::new();
+    let mut warnings = BTreeMap::new();
+    for target in manifest.targets.into_iter() {
+        // staticlibs are opt-in per crate
+        if let Target::Staticlib(_) = target {
+            if !argsinfo.config.staticlib_crates.contains(&krate.name) {
+                continue;
+            }
+        }
+        if !argsinfo.options.selects(&target) {
+            continue;
+        }
+
+        argsinfo.output.crate_state(&krate.name, progress::State::Building);
+        argsinfo.output.step(&format!("Building {}", crate_dir.to_string_lossy()));
+
+        // args for build target
+        let mut rustc_args: Vec<String> = match target {
+            Target::Bin(ref s) => vec!("--bin".to_string(), s.to_string()),
+            Target::Example(ref s) => vec!("--example".to_string(), s.to_string()),
+            _ => vec!("--lib".to_string()),  // only 1 lib permitted per crate, name is implicit
+        };
+
+        // args from commandline
+        if argsinfo.output.json() {
+            rustc_args.push("--message-format=json".to_string());
+        }
+        if let Some((_, features)) = manifest.enabled.iter().find(|(name, _)| name == target.as_ref()) {
+            rustc_args.push("--features".to_string());
+            rustc_args.push(features.join(","));
+        }
+
+        // cargo args, then linker args and the user's rustc args after a single `--`
+        rustc_args.extend(merge_rustc_args(&crate_cargo_args(argsinfo, krate), &linker_args(argsinfo, krate, &target)?, &argsinfo.options.rustc_args));
+
+        // build it!
+        let compiler_output = cargo_command_output(argsinfo, "rustc", rustc_args.as_slice(), crate_dir, &build_env)
+            .map_err(|err| err.category(Failure::Compile))?;
+        warnings.extend(warning_counts(&compiler_output));
+        report.warnings = warnings.values().sum();
+
+        // copy artifacts to priv/crates/<cratename>
+        let (_, src_name) = target_filenames_for(&target, &triple);
+        let installed_name = install_name(argsinfo, krate, &target);
+        let versioned = is_versioned(argsinfo, &target);
+
+        // build src path
+        let mut src_path = crate_target_dir(argsinfo, appdir, crate_dir);
+        if let Some(target_arch) = crate_target(argsinfo, krate) {
+            src_path.push(target_arch);
+        }
+        src_path.push(argsinfo.options.build_type.dir_name());
+        src_path.push(src_name);
+        sources.extend(depfile::dep_info(&src_path.with_extension("d")));
+
+        // build dst path
+        let dst_dir = install_dir(argsinfo, appdir, krate, &target);
+        fs::create_dir_all(&dst_dir)
+                 .map_err(|err| MsgIo("cannot create dest directories in priv/", err).category(Failure::Install))?;
+        let dst_path = installed_path(argsinfo, appdir, krate, &target, &manifest.version);
+
+        // record the NIF API version a dylib was compiled against
+        if is_native_nif(argsinfo, krate, &target) {
+            let path = dst_dir.join(otp::NIF_VERSION_FILE);
+            match crate_metadata(argsinfo, crate_dir, true).ok().and_then(|metadata| otp::nif_version_from_metadata(&metadata)) {
+                Some(version) => fs::write(&path, format!("{}\n", version)),
+                None => remove_file_force(&path),
+            }.map_err(|err| MsgIo("cannot write NIF version file", err).category(Failure::Install))?;
+        }
+
+        // record what a staticlib must be linked with next to it
+        if let Target::Staticlib(_) = target {
+            let link_args = native_static_libs(&compiler_output).unwrap_or_default();
+            fs::write(dst_path.with_extension("link-args"), link_args + "\n")
+                .map_err(|err| MsgIo("cannot write linker args file", err).category(Failure::Install))?;
+        }
+
+        // strip a copy next to the build output; that copy is what gets installed
+        if (argsinfo.options.strip || argsinfo.config.strip) && !is_wasm(&triple) {
+            let entry = match target {
+                Target::Dylib(_) | Target::Cdylib(_) => Some("nif_init"),
+                Target::Driver(_) => Some("driver_init"),
+                _ => None,
+            };
+            if entry.is_some() || matches!(target, Target::Bin(_)) {
+                let stripped = src_path.parent().unwrap().join("stripped").join(src_path.file_name().unwrap());
+                fs::create_dir_all(stripped.parent().unwrap())
+                    .map_err(|err| MsgIo("cannot create stripped artifact directory", err).category(Failure::Install))?;
+                strip::strip(&src_path, &stripped, &triple, entry, &argsinfo.config.strip_keep)
+                    .map_err(|err| err.category(Failure::Install))?;
+                src_path = stripped;
+            }
+        }
+
+        // keep what is installed for `rollback`, unless it is this very build again, which
+        // would replace the last different one
+        if argsinfo.backup() && !versioned && dst_path.is_file() && !artifacts::same_contents(&src_path, &dst_path) {
+            for path in [dst_path.clone(), provenance::build_info_path(&dst_path)].iter().filter(|path| path.is_file()) {
+                artifacts::backup(path)
+                    .map_err(|err| MsgIo("cannot back up the installed artifact", err).category(Failure::Install))?;
+            }
+        }
+
+        // finally, copy the artifact with its new name, keeping the previous generation
+        // aside rather than overwriting it when there is a retention policy
+        argsinfo.output.crate_state(&krate.name, progress::State::Copying);
+        let keep = argsinfo.keep_artifacts();
+        report.artifact_bytes += artifacts::install(&src_path, &dst_path, keep.is_some() && !versioned)
+            .map_err(|err| match artifacts::lock_holders(&dst_path) {
+                _ if err.kind() == io::ErrorKind::Interrupted => Msg("interrupted").category(Failure::Interrupted),
+                Some(holders) => MsgDetail("artifact is in use", format!("{} held by {}", dst_path.display(), holders)),
+                None => MsgDetail("cannot install artifact",
+                                  format!("{} to {}: {}", src_path.display(), dst_path.display(), err)),
+            }.category(Failure::Install))?;
+        check_installed(&src_path, &dst_path)?;
+        report.installed.push(dst_path.clone());
+        report.targets.push(target.to_string());
+        let record = verify::Record::of(appdir, &dst_path, &target.to_string(), &triple)
+            .map_err(|err| err.category(Failure::Install))?;
+        let target_features = manifest.enabled.iter().find(|(name, _)| name == target.as_ref())
+            .map(|(_, features)| features.clone()).unwrap_or_default();
+        let mut features = manifest.features.clone();
+        features.extend(target_features.iter().cloned());
+        provenance::write_build_info(&dst_path, &provenance::BuildInfo {
+            crate_name: krate.name.clone(),
+            version: manifest.version.clone(),
+            target: record.target.clone(),
+            triple: triple.clone(),
+            profile: argsinfo.options.build_type.profile_name().to_string(),
+            features: features.into_iter().collect(),
+        }).map_err(|err| err.category(Failure::Install))?;
+        provenance.push(provenance::Artifact {
+            target: record.target.clone(),
+            path: record.path.clone(),
+            sha256: record.sha256.clone(),
+            cargo_args: rustc_args.clone(),
+            features: target_features,
+        });
+        records.push(record);
+
+        if let Some(keep) = keep {
+            artifacts::prune(&dst_dir, &installed_name, keep)
+                .map_err(|err| MsgIo("cannot prune old artifacts", err).category(Failure::Install))?;
+        }
+    }
+
+    // record what was installed, for `verify`.  Targets filtered out keep their earlier
+    // records, and the earlier fingerprint since they weren't rebuilt from these sources.
+    let manifest_dir = crate_priv_dir(argsinfo, appdir, krate);
+    fs::create_dir_all(&manifest_dir)
+        .map_err(|err| MsgIo("cannot create dest directories in priv/", err).category(Failure::Install))?;
+    if argsinfo.options.filters_targets() {
+        if let Ok(Some(previous)) = verify::read_manifest(&manifest_dir) {
+            let kept: Vec<verify::Record> = previous.records.into_iter()
+                .filter(|old| !records.iter().any(|new: &verify::Record| new.target == old.target))
+                .collect();
+            if !kept.is_empty() {
+                fingerprint = previous.fingerprint;
+            }
+            records.extend(kept);
+        }
+    }
+    let build_manifest = verify::BuildManifest {
+        profile: argsinfo.options.build_type.profile_name().to_string(),
+        fingerprint,
+        min_otp: argsinfo.config.min_otp,
+        records,
+    };
+    verify::write_manifest(&manifest_dir, &build_manifest).map_err(|err| err.category(Failure::Install))?;
+    provenance::write(&manifest_dir, &build, &provenance, argsinfo.options.filters_targets())
+        .map_err(|err| err.category(Failure::Install))?;
+    write_depfile(argsinfo, appdir, &manifest_dir, sources, metadata.as_ref().ok())?;
+    if argsinfo.profile_dirs() {
+        keep_profile_build(argsinfo, appdir, krate)?;
+    }
+    Ok(())
+}
+
+/// Keep a copy of what a crate installed in `<crate>/<profile>/` next to its build manifest,
+/// replacing the profile's previous build, for `switch-profile`
+fn keep_profile_build(argsinfo: &ArgsInfo, appdir: &Path, krate: &Crate) -> Result<(), MsgError> {
+    let outputs = match crate_outputs(argsinfo, appdir, krate)? {
+        Some((_, outputs)) => outputs,
+        None => return Ok(()),
+    };
+    let store = crate_priv_dir(argsinfo, appdir, krate).join(argsinfo.options.build_type.profile_name());
+    // a bin target named after the profile is installed at the same path
+    if store.is_file() {
+        return Err(MsgDetail("a target is installed where the profile's build would be kept",
+                             store.display().to_string()).category(Failure::Install));
+    }
+    remove_dir_all_force(&store)
+        .and_then(|_| fs::create_dir_all(&store))
+        .map_err(|err| MsgIo("cannot create profile directory", err).category(Failure::Install))?;
+    for output in outputs.iter() {
+        if let Some(name) = output.file_name() {
+            artifacts::install(output, &store.join(name), false)
+                .map_err(|err| MsgIo("cannot keep build in profile directory", err).category(Failure::Install))?;
+        }
+    }
+    Ok(())
+}
+
+/// Write the Mix compiler manifest: each crate's outputs, with the inputs its dependency
+/// file lists
+fn write_mix_manifest(argsinfo: &ArgsInfo, appdir: &Path) -> Result<(), MsgError> {
+    let mut entries = Vec::new();
+    for krate in crates(argsinfo, appdir)?.iter() {
+        if let Some((manifest_file, outputs)) = crate_outputs(argsinfo, appdir, krate)? {
+            entries.push(mix::Entry {
+                krate: krate.name.clone(),
+                outputs,
+                inputs: depfile::prerequisites(appdir, &manifest_file).into_iter().collect(),
+            });
+        }
+    }
+    mix::write_manifest(&mix::manifest_path(appdir), &entries)
+        .map_err(|err| MsgIo("cannot write Mix compiler manifest", err).category(Failure::Install))
+}
+
+/// Install what the crates installed into priv/ into the other destinations as well: the
+/// rest of `priv-dirs` and, with `rebar-lib-dirs`, rebar3's copies of priv/.  Returns the
+/// paths installed.
+fn install_copies(argsinfo: &ArgsInfo, appdir: &Path) -> Result<Vec<PathBuf>, MsgError> {
+    let mut installed = Vec::new();
+    let own_priv = match priv_dir(argsinfo, appdir).canonicalize() {
+        Ok(dir) => dir,
+        Err(_) => return Ok(installed),  // nothing installed
+    };
+    let mut destinations = copy_priv_dirs(argsinfo, appdir);
+    if argsinfo.options.rebar_lib_dirs || argsinfo.config.rebar_lib_dirs {
+        destinations.extend(rebar_lib::priv_dirs(appdir, &reload::app_name(appdir)));
+    }
+    destinations.retain(|dir| dir.canonicalize().map(|dir| dir != own_priv).unwrap_or(true));
+    destinations.dedup();
+    if destinations.is_empty() {
+        return Ok(installed);
+    }
+    let mut outputs = Vec::new();
+    for krate in crates(argsinfo, appdir)?.iter() {
+        if let Some((_, crate_outputs)) = crate_outputs(argsinfo, appdir, krate)? {
+            outputs.extend(crate_outputs);
+        }
+    }
+    for destination in destinations.iter() {
+        for output in outputs.iter() {
+            let relative = match output.strip_prefix(&own_priv) {
+                Ok(relative) => relative,
+                Err(_) => continue,  // a staticlib installed outside priv/
+            };
+            let dst = destination.join(relative);
+            fs::create_dir_all(dst.parent().unwrap())
+                .and_then(|_| artifacts::install(output, &dst, false))
+                .map_err(|err| MsgDetail("cannot install artifact",
+                                         format!("{} to {}: {}", output.display(), dst.display(), err)).category(Failure::Install))?;
+            check_installed(output, &dst)?;
+            installed.push(dst);
+        }
+    }
+    Ok(installed)
+}
+
+/// Fail unless `dst` is an intact copy of `src`
+fn check_installed(src: &Path, dst: &Path) -> Result<(), MsgError> {
+    artifacts::check_installed(src, dst)
+        .map_err(|problem| MsgDetail("installed artifact is broken", format!("{}: {}", dst.display(), problem))
+                 .category(Failure::Install))
+}
+
+/// A crate's build manifest and the files its last build left: installed artifacts (with
+/// their build info and a staticlib's linker args), the build manifest, NIF version and
+/// provenance files.  `None` if it hasn't been built.
+fn crate_outputs(argsinfo: &ArgsInfo, appdir: &Path, krate: &Crate) -> Result<Option<(PathBuf, Vec<PathBuf>)>, MsgError> {
+    let absolute = |path: PathBuf| path.canonicalize().unwrap_or(path);
+    let manifest_dir = crate_priv_dir(argsinfo, appdir, krate);
+    let build_manifest = match verify::read_manifest(&manifest_dir)? {
+        Some(build_manifest) => build_manifest,
+        None => return Ok(None),
+    };
+    let manifest_file = absolute(manifest_dir.join(verify::MANIFEST_FILE));
+    let mut outputs = Vec::new();
+    for record in build_manifest.records.iter() {
+        let path = absolute(appdir.join(&record.path));
+        let link_args = path.with_extension("link-args");
+        let build_info = provenance::build_info_path(&path);
+        outputs.push(path);
+        for file in [link_args, build_info].iter().filter(|file| file.is_file()) {
+            outputs.push(file.clone());
+        }
+    }
+    outputs.push(manifest_file.clone());
+    for file in [otp::NIF_VERSION_FILE, provenance::PROVENANCE_FILE].iter() {
+        let path = manifest_dir.join(file);
+        if path.is_file() {
+            outputs.push(absolute(path));
+        }
+    }
+    Ok(Some((manifest_file, outputs)))
+}
+
+/// Record what the crate's build manifest depends on in the application's dependency file.
+/// Targets filtered out keep their earlier sources.
+fn write_depfile(argsinfo: &ArgsInfo, appdir: &Path, manifest_dir: &Path, mut sources: BTreeSet<PathBuf>,
+                 metadata: Option<&metadata::Metadata>) -> Result<(), MsgError> {
+    let absolute = |path: PathBuf| path.canonicalize().unwrap_or(path);
+    let target = absolute(manifest_dir.join(verify::MANIFEST_FILE));
+    if argsinfo.options.filters_targets() {
+        sources.extend(depfile::prerequisites(appdir, &target));
+    }
+    if let Some(metadata) = metadata {
+        sources.extend(metadata.packages.iter()
+                       .filter(|package| package.source.is_none())
+                       .map(|package| package.manifest_path.clone()));
+        sources.insert(metadata.workspace_root.join("Cargo.lock"));
+    }
+    sources.insert(appdir.join(config::CONFIG_FILE));
+    sources.insert(appdir.join("crates"));
+    let sources = sources.into_iter().filter(|path| path.exists()).map(absolute).collect();
+    depfile::update(appdir, &target, sources)
+        .map_err(|err| MsgIo("cannot write dependency file", err).category(Failure::Install))
+}
+
+/// How a crate is about to be built, for its provenance file
+fn build_provenance(argsinfo: &ArgsInfo, crate_dir: &Path, triple: &str) -> provenance::Build {
+    let env = &argsinfo.config.env;
+    let mut rustc = process::Command::new(env.var("RUSTC").unwrap_or_else(|| "rustc".to_string()));
+    rustc.current_dir(crate_dir);
+    env.apply(&mut rustc);
+    let mut cargo = cargo(argsinfo);
+    cargo.current_dir(crate_dir);
+    provenance::Build {
+        rustc: provenance::tool_version(rustc),
+        cargo: provenance::tool_version(cargo),
+        profile: argsinfo.options.build_type.profile_name().to_string(),
+        triple: triple.to_string(),
+        cargo_config: argsinfo.options.cargo_config.clone(),
+        rustflags: env.var("RUSTFLAGS"),
+        git: provenance::git(crate_dir),
+        built_at: provenance::timestamp(),
+    }
+}
+
+/// Fail if a crate's NIFs need a newer NIF API than `min-otp` provides.  The API is that of
+/// its rustler `nif_version_*` features or, for NIF sys crates without them, of the Erlang
+/// whose headers they build against.
+fn check_min_otp(argsinfo: &ArgsInfo, min_otp: u32, metadata: &metadata::Metadata) -> Result<(), MsgError> {
+    let provided = otp::release_nif_version(min_otp).ok_or(Msg("unknown min-otp release"))?;
+    let built = match otp::nif_version_from_metadata(metadata) {
+        Some(version) => version,
+        None if erts::needs_erts(metadata) =>
+            otp::runtime_nif_version(&erts(argsinfo)?.include_dir.join("erl_nif.h").to_string_lossy())?,
+        None => return Ok(()),
+    };
+    match built.loads_into(&provided) {
+        true => Ok(()),
+        false => Err(MsgDetail("NIF API newer than min-otp provides",
+                               format!("built for {}, OTP {} provides {}", built, min_otp, provided))
+                     .category(Failure::Compile)),
+    }
+}
+
+/// The Erlang installation NIF sys crates build against, located on first use
+fn erts(argsinfo: &ArgsInfo) -> Result<&erts::Erts, MsgError> {
+    if argsinfo.erts.get().is_none() {
+        let erts = erts::Erts::locate(argsinfo.config.erl.as_deref()).map_err(|err| err.category(Failure::Compile))?;
+        let _ = argsinfo.erts.set(erts);
+    }
+    Ok(argsinfo.erts.get().unwrap())
+}
+
+/// Target triple being built for: `--target`, else the Rust host
+fn target_triple(argsinfo: &ArgsInfo) -> Result<String, MsgError> {
+    match argsinfo.options.target {
+        Some(ref target) => Ok(target.clone()),
+        None => windows::rust_host().ok_or(Msg("cannot determine the Rust host triple")),
+    }
+}
+
+/// The `--target` a crate is built with: its own override, else the command line's
+fn crate_target<'a>(argsinfo: &'a ArgsInfo, krate: &'a Crate) -> Option<&'a str> {
+    match krate.target {
+        None => argsinfo.options.target.as_deref(),
+        Some(TargetOverride::Host) => None,
+        Some(TargetOverride::Triple(ref triple)) => Some(triple),
+    }
+}
+
+/// The target triple a crate is built for
+fn crate_triple(argsinfo: &ArgsInfo, krate: &Crate) -> Result<String, MsgError> {
+    match crate_target(argsinfo, krate) {
+        Some(target) => Ok(target.to_string()),
+        None => windows::rust_host().ok_or(Msg("cannot determine the Rust host triple")),
+    }
+}
+
+/// Cargo arguments for one crate: the command line's, with `--target` following the
+/// crate's override
+fn crate_cargo_args(argsinfo: &ArgsInfo, krate: &Crate) -> Vec<String> {
+    let mut args = argsinfo.options.cargo_args.clone();
+    if krate.target.is_some() {
+        while take_option_value(&mut args, "--target").is_some() {}
+        if let Some(target) = crate_target(argsinfo, krate) {
+            args.insert(0, format!("--target={}", target));
+        }
+    }
+    args
+}
+
+/// Directory a crate's artifact is installed into: `priv/crates/<cratename>`, or the
+/// configured staticlib directory, and the platform's subdirectory of it with per-platform
+/// directories
+fn install_dir(argsinfo: &ArgsInfo, appdir: &Path, krate: &Crate, target: &Target) -> PathBuf {
+    match (target, argsinfo.config.staticlib_dir.as_ref()) {
+        (&Target::Staticlib(_), Some(dir)) => with_platform(argsinfo, appdir.join(dir).join(&krate.name)),
+        _ => crate_priv_dir(argsinfo, appdir, krate),
+    }
+}
+
+/// `priv/crates/<cratename>`, or its platform subdirectory: where the crate's artifacts,
+/// build manifest and NIF version are.  Host tools go to `priv/host-tools/<cratename>`.
+fn crate_priv_dir(argsinfo: &ArgsInfo, appdir: &Path, krate: &Crate) -> PathBuf {
+    match is_host_tool(argsinfo, krate) {
+        true => priv_crates_dir(argsinfo, appdir, krate),
+        false => with_platform(argsinfo, priv_crates_dir(argsinfo, appdir, krate)),
+    }
+}
+
+/// `priv/crates/<cratename>`, or `priv/host-tools/<cratename>` for a host tool
+fn priv_crates_dir(argsinfo: &ArgsInfo, appdir: &Path, krate: &Crate) -> PathBuf {
+    let area = if is_host_tool(argsinfo, krate) { HOST_TOOLS_DIR } else { "crates" };
+    priv_dir(argsinfo, appdir).join(area).join(&krate.name)
+}
+
+/// Where host tools are installed under `priv/` when cross-compiling
+const HOST_TOOLS_DIR: &str = "host-tools";
+
+/// True for a crate built for the host (`target = "host"`) while `--target` cross-compiles
+/// the others
+fn is_host_tool(argsinfo: &ArgsInfo, krate: &Crate) -> bool {
+    krate.target == Some(TargetOverride::Host) && argsinfo.options.target.is_some()
+}
+
+fn with_platform(argsinfo: &ArgsInfo, dir: PathBuf) -> PathBuf {
+    match argsinfo.platform {
+        Some(ref platform) => dir.join(platform),
+        None => dir,
+    }
+}
+
+/// True if the target is installed under a versioned name
+fn is_versioned(argsinfo: &ArgsInfo, target: &Target) -> bool {
+    (argsinfo.options.versioned_artifacts || argsinfo.config.versioned_artifacts) && target.is_nif()
+}
+
+/// Full path a target is installed at
+fn installed_path(argsinfo: &ArgsInfo, appdir: &Path, krate: &Crate, target: &Target, version: &str) -> PathBuf {
+    let name = install_name(argsinfo, krate, target);
+    install_dir(argsinfo, appdir, krate, target).join(match is_versioned(argsinfo, target) {
+        true => versioned_filename(&name, version),
+        false => name,
+    })
+}
+
+/// True for WebAssembly targets, whose cdylibs are modules for a wasm runtime rather than NIFs
+fn is_wasm(triple: &str) -> bool {
+    triple.starts_with("wasm32-") || triple.starts_with("wasm64-")
+}
+
+/// True for a dylib target the VM loads as a NIF: not one built as a wasm module
+fn is_native_nif(argsinfo: &ArgsInfo, krate: &Crate, target: &Target) -> bool {
+    target.is_nif() && !crate_target(argsinfo, krate).is_some_and(is_wasm)
+}
+
+/// File name a target is installed as, before any version suffix, following the naming of
+/// the platform built for
+fn install_name(argsinfo: &ArgsInfo, krate: &Crate, target: &Target) -> String {
+    let naming = Naming::of(crate_target(argsinfo, krate));
+    match (target, krate.elixir_module.as_ref(), krate.artifact_name.as_ref()) {
+        (target, Some(module), _) if target.is_nif() && naming != Naming::Wasm => elixir_filename(module, naming),
+        (_, _, Some(name)) => filenames(&target.with_name(name), naming).0,
+        _ => filenames(target, naming).0,
+    }
+}
+
+/// Dylib file name after an Elixir module, e.g. `Elixir.MyApp.Native.so`
+fn elixir_filename(module: &str, naming: Naming) -> String {
+    let module = module.strip_prefix("Elixir.").unwrap_or(module);
+    format!("Elixir.{}.{}", module, if naming == Naming::Windows { "dll" } else { "so" })
+}
+
+/// Cargo's target directory for a crate.  Workspace members share `crates/target`, or the
+/// `target/` of the virtual workspace below `crates/` they belong to.
+fn crate_target_dir(argsinfo: &ArgsInfo, appdir: &Path, crate_dir: &Path) -> PathBuf {
+    if let Some(ref target_dir) = argsinfo.target_dir {
+        target_dir.clone()
+    } else if let Some(root) = workspace::virtual_root(appdir, crate_dir) {
+        root.join("target")
+    } else if workspace::is_workspace(appdir) && crate_dir.starts_with(appdir.join("crates")) {
+        appdir.join("crates").join("target")
+    } else {
+        crate_dir.join("target")
+    }
+}
+
+/// The tool's rustc arguments for a target: built-in ones, then `[link]` entries for its kind
+/// that match the build target, then the static C runtime for bins with `--static-crt`
+fn linker_args(argsinfo: &ArgsInfo, krate: &Crate, target: &Target) -> Result<Vec<String>, MsgError> {
+    if crate_target(argsinfo, krate).is_some_and(is_wasm) {
+        // wasm modules are linked by rust-lld for the wasm runtime, not loaded by the VM
+        return Ok(Vec::new());
+    }
+    let (kind, mut args) = match *target {
+        Target::Dylib(_) | Target::Cdylib(_) => (link::Kind::Nif, Vec::new()),
+        Target::Driver(_) => (link::Kind::Driver, Vec::new()),
+        Target::Bin(_) | Target::Example(_) => (link::Kind::Bin, Vec::new()),
+        Target::Staticlib(_) => (link::Kind::Staticlib, STATICLIB_RUSTC_ARGS.iter().map(|x| x.to_string()).collect()),
+    };
+    let mut entries = argsinfo.config.link.clone();
+    if kind == link::Kind::Bin && (argsinfo.options.static_crt || argsinfo.config.static_crt_crates.contains(&krate.name)) {
+        entries.push(link::static_crt());
+    }
+    let read_cfg = |target: Option<&str>| link::target_cfg(target)
+        .ok_or(Msg("cannot read the target's cfg values from rustc --print cfg").category(Failure::Compile));
+    if !entries.iter().any(|e| e.kind == kind && e.cfg.is_some()) {
+        args.extend(link::args(&entries, kind, &[]));
+    } else if krate.target.is_some() {
+        // a crate with its own target doesn't share the cached cfg values
+        args.extend(link::args(&entries, kind, &read_cfg(crate_target(argsinfo, krate))?));
+    } else {
+        if argsinfo.target_cfg.get().is_none() {
+            let _ = argsinfo.target_cfg.set(read_cfg(argsinfo.options.target.as_deref())?);
+        }
+        args.extend(link::args(&entries, kind, argsinfo.target_cfg.get().map(Vec::as_slice).unwrap_or(&[])));
+    }
+    Ok(args)
+}
+
+/// Cargo arguments followed by one `--` and everything for rustc: the tool's own `tool_args`
+/// first, then rustc arguments given after `--` on the command line, then `--rustc-args`,
+/// so the user's come last and win
+fn merge_rustc_args(cargo_args: &[String], tool_args: &[String], rustc_args: &[String]) -> Vec<String> {
+    let (cargo_args, user_args) = match cargo_args.iter().position(|arg| arg == "--") {
+        Some(i) => (&cargo_args[..i], &cargo_args[i + 1..]),
+        None => (cargo_args, &[][..]),
+    };
+    let mut args = cargo_args.to_vec();
+    if !tool_args.is_empty() || !user_args.is_empty() || !rustc_args.is_empty() {
+        args.push("--".to_string());
+        args.extend(tool_args.iter().cloned());
+        args.extend(user_args.iter().cloned());
+        args.extend(rustc_args.iter().cloned());
+    }
+    args
+}
+
+/// Insert a version before the extension: `libmynif.so` -> `libmynif-1.4.2.so`
+fn versioned_filename(filename: &str, version: &str) -> String {
+    match filename.rfind('.') {
+        Some(dot) => format!("{}-{}{}", &filename[..dot], version, &filename[dot..]),
+        None => format!("{}-{}", filename, version),
+    }
+}
+
+/// Find rustc's `native-static-libs: ...` note in compiler output
+fn native_static_libs(compiler_output: &str) -> Option<String> {
+    const NOTE: &str = "native-static-libs:";
+    let start = compiler_output.rfind(NOTE)? + NOTE.len();
+    // the note may sit inside a JSON string, so stop at an escape or quote too
+    let rest = &compiler_output[start..];
+    let end = rest.find(['\n', '\\', '"']).unwrap_or(rest.len());
+    Some(rest[..end].trim().to_string())
+}
+
+/// Warnings in compiler output, by the unit that produced them: from the JSON messages if
+/// any, else from cargo's `warning: `mynif` (lib) generated 2 warnings` lines.  Warnings
+/// cargo replays for units that were already built count once per unit.
+fn warning_counts(compiler_output: &str) -> BTreeMap<String, usize> {
+    let mut counts = BTreeMap::new();
+    for line in compiler_output.lines().filter(|line| line.starts_with('{')) {
+        let message = match json::from_str::<json::Value>(line) {
+            Ok(message) => message,
+            Err(_) => continue,
+        };
+        if message["reason"] == "compiler-message" && message["message"]["level"] == "warning" {
+            let unit = format!("{} {}", message["package_id"].as_str().unwrap_or(""), message["target"]["name"].as_str().unwrap_or(""));
+            *counts.entry(unit).or_insert(0) += 1;
+        }
+    }
+    if !counts.is_empty() {
+        return counts;
+    }
+    for line in compiler_output.lines().map(strip_ansi) {
+        let summary = line.strip_prefix("warning: ").and_then(|rest| rest.split_once(" generated "));
+        if let Some((unit, rest)) = summary {
+            let digits: String = rest.chars().take_while(|c| c.is_ascii_digit()).collect();
+            if let Ok(count) = digits.parse() {
+                counts.insert(unit.to_string(), count);
+            }
+        }
+    }
+    counts
+}
+
+
+/// Artifact naming conventions of a target platform
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Naming { Windows, Apple, Unix, Wasm }
+
+impl Naming {
+    /// Naming for a target triple, or for the host without one
+    fn of(triple: Option<&str>) -> Naming {
+        match triple {
+            Some(triple) if triple.contains("-windows") => Naming::Windows,
+            Some(triple) if triple.contains("-apple-") => Naming::Apple,
+            Some(triple) if is_wasm(triple) => Naming::Wasm,
+            Some(_) => Naming::Unix,
+            None if cfg!(windows) => Naming::Windows,
+            None if cfg!(target_os = "macos") => Naming::Apple,
+            None => Naming::Unix,
+        }
+    }
+}
+
+/// (installed, built) file names of a target when building for the host
+pub fn target_filenames(target: &Target) -> (String, String) {
+    filenames(target, Naming::of(None))
+}
+
+/// (installed, built) file names of a target when building for `triple`
+pub fn target_filenames_for(target: &Target, triple: &str) -> (String, String) {
+    filenames(target, Naming::of(Some(triple)))
+}
+
+/// Windows: bins have `.exe` suffix, dylibs have `.dll` suffix.  Apple: dylibs have `lib`
+/// prefix, and `.dylib` suffix gets changed to `.so`.  Elsewhere dylibs have `lib` prefix
+/// and `.so` suffix.  Drivers are installed without the `lib` prefix.  Wasm: bins and dylibs
+/// are `.wasm` modules.
+fn filenames(target: &Target, naming: Naming) -> (String, String) {
+    match (naming, target) {
+        (Naming::Wasm, Target::Staticlib(s)) => ("lib".to_string() + s + ".a", "lib".to_string() + s + ".a"),
+        (Naming::Wasm, target) => (target.as_ref().to_string() + ".wasm", target.as_ref().to_string() + ".wasm"),
+        (Naming::Windows, Target::Bin(s)) | (Naming::Windows, Target::Example(s)) =>
+            (s.to_string() + ".exe", s.to_string() + ".exe"),
+        (Naming::Windows, Target::Dylib(s)) | (Naming::Windows, Target::Cdylib(s))
+        | (Naming::Windows, Target::Driver(s)) => (s.to_string() + ".dll", s.to_string() + ".dll"),
+        (Naming::Windows, Target::Staticlib(s)) => (s.to_string() + ".lib", s.to_string() + ".lib"),
+        (_, Target::Bin(s)) | (_, Target::Example(s)) => (s.to_string(), s.to_string()),
+        (_, Target::Staticlib(s)) => ("lib".to_string() + s + ".a", "lib".to_string() + s + ".a"),
+        (Naming::Apple, Target::Dylib(s)) | (Naming::Apple, Target::Cdylib(s)) =>
+            ("lib".to_string() + s + ".so", "lib".to_string() + s + ".dylib"),
+        (Naming::Apple, Target::Driver(s)) => (s.to_string() + ".so", "lib".to_string() + s + ".dylib"),
+        (_, Target::Dylib(s)) | (_, Target::Cdylib(s)) => ("lib".to_string() + s + ".so", "lib".to_string() + s + ".so"),
+        (_, Target::Driver(s)) => (s.to_string() + ".so", "lib".to_string() + s + ".so"),
+    }
+}
+
+
+/// Build artifact types.  More kinds may be added, so matches need a wildcard arm.
+#[derive(Debug, Clone, PartialEq)]
+#[non_exhaustive]
+pub enum Target {
+    Bin(String),
+    /// Rust `dylib` loaded as a NIF
+    Dylib(String),
+    /// C-ABI `cdylib` loaded as a NIF
+    Cdylib(String),
+    Staticlib(String),
+    /// Example program; enumerated, but not built or installed
+    Example(String),
+    /// Dynamic library loaded as a port driver with `erl_ddll`, from a crate listed in
+    /// `driver.crates`
+    Driver(String),
+}
+
+impl AsRef<String> for Target {
+    fn as_ref(&self) -> &String {
+        match *self {
+            Target::Bin(ref s) | Target::Dylib(ref s) | Target::Cdylib(ref s) |
+            Target::Staticlib(ref s) | Target::Example(ref s) | Target::Driver(ref s) => s,
+        }
+    }
+}
+
+impl Display for Target {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Target::Bin(ref s) => write!(f, "{} (bin)", s),
+            Target::Dylib(ref s) => write!(f, "{} (dylib)", s),
+            Target::Cdylib(ref s) => write!(f, "{} (cdylib)", s),
+            Target::Staticlib(ref s) => write!(f, "{} (staticlib)", s),
+            Target::Example(ref s) => write!(f, "{} (example)", s),
+            Target::Driver(ref s) => write!(f, "{} (driver)", s),
+        }
+    }
+}
+
+impl Target {
+    /// Target name
+    pub fn name(&self) -> &str {
+        self.as_ref()
+    }
+
+    /// True for a library loaded as a NIF
+    pub fn is_nif(&self) -> bool {
+        matches!(*self, Target::Dylib(_) | Target::Cdylib(_))
+    }
+
+    /// True for a target built from the crate's `lib`
+    pub fn is_lib(&self) -> bool {
+        matches!(*self, Target::Dylib(_) | Target::Cdylib(_) | Target::Staticlib(_) | Target::Driver(_))
+    }
+
+    /// Same kind of target with another name
+    fn with_name(&self, name: &str) -> Target {
+        let name = name.to_string();
+        match *self {
+            Target::Bin(_) => Target::Bin(name),
+            Target::Dylib(_) => Target::Dylib(name),
+            Target::Cdylib(_) => Target::Cdylib(name),
+            Target::Staticlib(_) => Target::Staticlib(name),
+            Target::Example(_) => Target::Example(name),
+            Target::Driver(_) => Target::Driver(name),
+        }
+    }
+
+    /// Create targets from a `cargo metadata` target.  A lib may be both a dynamic library
+    /// (a NIF, or a port driver when `driver` is set) and a staticlib.
+    fn from_metadata(target: &metadata::Target, driver: bool) -> Vec<Target> {
+        let name = target.name.clone();
+        let has = |kind: &str| target.kind.iter().any(|k| k == kind);
+        let mut targets = Vec::new();
+        if has("bin") {
+            targets.push(Target::Bin(name));
+        } else if has("example") {
+            targets.push(Target::Example(name));
+        } else {
+            if driver && (has("cdylib") || has("dylib")) {
+                targets.push(Target::Driver(name.clone()));
+            } else if has("cdylib") {
+                targets.push(Target::Cdylib(name.clone()));
+            } else if has("dylib") {
+                targets.push(Target::Dylib(name.clone()));
+            }
+            if has("staticlib") {
+                targets.push(Target::Staticlib(name));
+            }
+        }
+        targets
+    }
+}
+
+/// A target of a crate, with what a tool needs to know about it
+#[derive(Debug, Clone, PartialEq)]
+pub struct TargetInfo {
+    pub target: Target,
+    pub crate_name: String,
+    pub version: String,
+    /// Cargo features that must be enabled to build the target
+    pub required_features: Vec<String>,
+}
+
+/// The parts of a crate's `cargo metadata` we use
+struct Manifest {
+    version: String,
+    targets: Vec<Target>,
+    /// Targets left out because their `required-features` aren't enabled, with the missing features
+    skipped: Vec<(String, Vec<String>)>,
+    /// With `enable-required-features`, targets built with features enabled for them
+    enabled: Vec<(String, Vec<String>)>,
+    /// Cargo kinds of the targets considered, such as `cdylib`, `lib` or `proc-macro`
+    kinds: Vec<String>,
+    /// Features the cargo arguments enable, default ones included
+    features: BTreeSet<String>,
+}
+
+impl Manifest {
+    /// The kinds of the crate's targets if none of them gives an artifact to install.
+    /// Staticlibs only count when the crate's staticlibs are installed.
+    fn uninstallable(&self, staticlibs: bool) -> Option<&[String]> {
+        let installable = self.targets.iter().any(|t| staticlibs || !matches!(*t, Target::Staticlib(_)));
+        match installable || !self.skipped.is_empty() || self.kinds.is_empty() {
+            true => None,  // skipped targets are reported, and no targets selected is deliberate
+            false => Some(&self.kinds),
+        }
+    }
+}
+
+/// A crate found in an application, for tools built on this library
+#[derive(Debug, Clone, PartialEq)]
+pub struct CrateInfo {
+    pub name: String,
+    /// Directory holding `Cargo.toml`
+    pub dir: PathBuf,
+    pub version: String,
+    /// Every target of the crate, including examples and those needing features enabled
+    pub targets: Vec<TargetInfo>,
+}
+
+/// Enumerate the crates of the application in `appdir` (those in `crates/`) and their
+/// targets, as configured by its `erlangapp.toml`
+pub fn enumerate_crates(appdir: &Path) -> Result<Vec<CrateInfo>, MsgError> {
+    let config = Config::load(appdir)?;
+    let mut crate_dirs = selected_crate_dirs(appdir, &config)?;
+    crate_dirs.sort();
+    crate_dirs.into_iter()
+        .map(|dir| {
+            let package = package_in(metadata::read(cargo_with(&config.env, &[]), &dir, false)?, &dir)?;
+            let dir_name = dir.file_name().map(|n| n.to_string_lossy().into_owned()).unwrap_or_default();
+            let driver = config.driver_crates.contains(&dir_name);
+            let mut targets = Vec::new();
+            for t in package.targets.iter() {
+                for target in Target::from_metadata(t, driver) {
+                    targets.push(TargetInfo {
+                        target,
+                        crate_name: package.name.clone(),
+                        version: package.version.clone(),
+                        required_features: t.required_features.clone(),
+                    });
+                }
+            }
+            Ok(CrateInfo { name: package.name, dir, version: package.version, targets })
+        })
+        .collect()
+}
+
+/// `cargo metadata` for the crate in `crate_dir`, read once per command.  Metadata with
+/// dependencies also serves requests without.
+fn crate_metadata(argsinfo: &ArgsInfo, crate_dir: &Path, deps: bool) -> Result<metadata::Metadata, MsgError> {
+    let key = |deps: bool| (crate_dir.to_path_buf(), deps);
+    let cached = {
+        let cache = argsinfo.metadata.borrow();
+        cache.get(&key(true)).or_else(|| cache.get(&key(deps))).cloned()
+    };
+    if let Some(metadata) = cached {
+        return Ok(metadata);
+    }
+    let metadata = metadata::read(cargo(argsinfo), crate_dir, deps)?;
+    argsinfo.metadata.borrow_mut().insert(key(deps), metadata.clone());
+    Ok(metadata)
+}
+
+/// The cargo package whose manifest is in `crate_dir`
+fn crate_package(argsinfo: &ArgsInfo, crate_dir: &Path) -> Result<metadata::Package, MsgError> {
+    package_in(crate_metadata(argsinfo, crate_dir, false)?, crate_dir)
+}
+
+fn package_in(metadata: metadata::Metadata, crate_dir: &Path) -> Result<metadata::Package, MsgError> {
+    metadata::package(metadata, crate_dir)
+        .ok_or_else(|| MsgDetail("no package in crate directory", crate_dir.to_string_lossy().into_owned())
+                       .category(Failure::Enumeration))
+}
+
+/// Read metadata for given crate and enumerate targets, limited to the configured target set
+fn read_manifest(argsinfo: &ArgsInfo, krate: &Crate) -> Result<Manifest, MsgError> {
+    let crate_dir = krate.dir.as_path();
+    let package = crate_package(argsinfo, crate_dir)?;
+    let selected = match argsinfo.config.target_set {
+        Some(ref target_set) => {
+            let names = target_set.get(&krate.name).map(Vec::as_slice).unwrap_or(&[]);
+            if let Some(name) = names.iter().find(|name| !package.targets.iter().any(|t| &t.name == *name)) {
+                return Err(MsgDetail("erlangapp.toml: no such target", format!("targets.{}: {}", krate.name, name))
+                           .category(Failure::Enumeration));
+            }
+            Some(names)
+        },
+        None => None,
+    };
+    let enabled = package.enabled_features(&argsinfo.options.cargo_args);
+    let driver = argsinfo.config.driver_crates.contains(&krate.name);
+    let mut targets = Vec::new();
+    let mut skipped = Vec::new();
+    let mut enabled_for = Vec::new();
+    let mut kinds: Vec<String> = Vec::new();
+    // examples are never built
+    for target in package.targets.iter().filter(|t| !t.kind.iter().any(|k| k == "example")) {
+        if let Some(names) = selected {
+            if !names.contains(&target.name) {
+                continue;
+            }
+        }
+        for kind in target.kind.iter().filter(|k| !["test", "bench", "custom-build"].contains(&k.as_str())) {
+            if !kinds.contains(kind) {
+                kinds.push(kind.clone());
+            }
+        }
+        let missing = target.missing_features(&enabled);
+        if missing.is_empty() {
+            targets.extend(Target::from_metadata(target, driver));
+        } else if argsinfo.config.enable_required_features {
+            targets.extend(Target::from_metadata(target, driver));
+            enabled_for.push((target.name.clone(), missing));
+        } else {
+            skipped.push((target.name.clone(), missing));
+        }
+    }
+    Ok(Manifest { version: package.version, targets, skipped, enabled: enabled_for, kinds, features: enabled })
+}
+
+/// Test all crates
+fn test_crates(argsinfo: &ArgsInfo, appdir: &Path, reports: &mut Vec<CrateReport>) -> Result<(), MsgError> {
+    check_rust_versions(argsinfo, appdir)?;
+    check_cross_toolchains(argsinfo, appdir)?;
+    check_runners(argsinfo, appdir)?;
+    let nextest = argsinfo.options.runner == TestRunner::Nextest && {
+        let installed = cargo(argsinfo).args(["nextest", "--version"]).output().map(|o| o.status.success()).unwrap_or(false);
+        if !installed {
+            argsinfo.output.status("cargo-nextest is not installed, falling back to cargo test");
+        }
+        installed
+    };
+    let mut suites = argsinfo.options.junit.as_ref().map(|_| Vec::new());
+    // test each create, short circuit fail
+    for krate in crates(argsinfo, appdir)?.iter() {
+        let runner = if nextest { "cargo nextest" } else { "cargo test" };
+        argsinfo.output.status(&format!("Testing {} with {}", krate.dir.to_string_lossy(), runner));
+        let start = Instant::now();
+        let mut report = CrateReport::new(&krate.name);
+        let mut test_args = Vec::new();
+        if nextest {
+            test_args.push("run".to_string());
+        }
+        if argsinfo.output.json() {
+            test_args.push(format!("--{}message-format=json", if nextest { "cargo-" } else { "" }));
+        }
+        test_args.extend(crate_cargo_args(argsinfo, krate));
+        let cmd = if nextest { "nextest" } else { "test" };
+        let result = match suites {
+            // the runner's stdout is needed for the report
+            Some(ref mut suites) => {
+                let mut command = cargo_process(argsinfo, cmd, &test_args, &krate.dir);
+                argsinfo.output.run_with_stdout(&mut command, &krate.name)
+                    .map_err(|err| MsgIo("cannot start cargo", err))
+                    .and_then(|(status, text)| {
+                        suites.push(junit::Suite { name: krate.name.clone(), cases: junit::parse(&text), duration: start.elapsed() });
+                        check_interrupted()?;
+                        match status.success() {
+                            true => Ok(text),
+                            false => Err(MsgCommand("cargo command failed", CommandFailure::new(&command, text).exited(status))),
+                        }
+                    })
+            },
+            None => cargo_command_output(argsinfo, cmd, &test_args, &krate.dir, &[]),
+        }.map_err(MsgError::test_category);
+        report.duration = start.elapsed();
+        report.ok = result.is_ok();
+        report.warnings = result.as_ref().map(|text| warning_counts(text).values().sum()).unwrap_or(0);
+        reports.push(report);
+        if result.is_err() {
+            write_junit(argsinfo, appdir, suites.as_deref())?;
+        }
+        result?;
+    };
+    write_junit(argsinfo, appdir, suites.as_deref())
+}
+
+/// Write the JUnit report of `test --junit`, relative to the application
+fn write_junit(argsinfo: &ArgsInfo, appdir: &Path, suites: Option<&[junit::Suite]>) -> Result<(), MsgError> {
+    match (&argsinfo.options.junit, suites) {
+        (Some(path), Some(suites)) => junit::write(&appdir.join(path), suites)
+            .map_err(|err| MsgIo("cannot write JUnit report", err)),
+        _ => Ok(()),
+    }
+}
+
+/// Benchmark all crates, staging their results in `bench_results/`
+fn bench_crates(argsinfo: &ArgsInfo, appdir: &Path, reports: &mut Vec<CrateReport>) -> Result<(), MsgError> {
+    check_rust_versions(argsinfo, appdir)?;
+    check_cross_toolchains(argsinfo, appdir)?;
+    check_runners(argsinfo, appdir)?;
+    let results_dir = appdir.join(BENCH_RESULTS_DIR);
+    for krate in crates(argsinfo, appdir)?.iter() {
+        argsinfo.output.status(&format!("Benchmarking {}", krate.dir.to_string_lossy()));
+        let started = SystemTime::now();
+        let start = Instant::now();
+        let mut report = CrateReport::new(&krate.name);
+        let mut bench_args = Vec::new();
+        if argsinfo.output.json() {
+            bench_args.push("--message-format=json".to_string());
+        }
+        bench_args.extend(crate_cargo_args(argsinfo, krate));
+        let mut command = cargo_process(argsinfo, "bench", &bench_args, &krate.dir);
+        let result = argsinfo.output.run_with_stdout(&mut command, &krate.name)
+            .map_err(|err| MsgIo("cannot start cargo", err))
+            .and_then(|output| check_interrupted().map(|_| output))
+            .and_then(|(status, text)| match status.success() {
+                true => Ok(text),
+                false => Err(MsgCommand("cargo command failed", CommandFailure::new(&command, text).exited(status))),
+            })
+            .map_err(MsgError::test_category);
+        report.duration = start.elapsed();
+        report.ok = result.is_ok();
+        report.warnings = result.as_ref().map(|text| warning_counts(text).values().sum()).unwrap_or(0);
+        reports.push(report);
+
+        let criterion_dir = crate_target_dir(argsinfo, appdir, &krate.dir).join("criterion");
+        let measurements = bench::stage(&results_dir, &krate.name, &result?, &criterion_dir, started)?;
+        if !measurements.is_empty() {
+            argsinfo.output.status(&format!("Staged {} benchmark results in {}", measurements.len(),
+                                            results_dir.join(&krate.name).to_string_lossy()));
+        }
+    }
+    if results_dir.is_dir() {
+        bench::write_index(&results_dir)?;
+    }
+    Ok(())
+}
+
+/// Lint all crates with clippy
+fn clippy_crates(argsinfo: &ArgsInfo, appdir: &Path, reports: &mut Vec<CrateReport>) -> Result<(), MsgError> {
+    check_rust_versions(argsinfo, appdir)?;
+    check_cross_toolchains(argsinfo, appdir)?;
+    for krate in crates(argsinfo, appdir)?.iter() {
+        argsinfo.output.status(&format!("Linting {}", krate.dir.to_string_lossy()));
+        let start = Instant::now();
+        let mut report = CrateReport::new(&krate.name);
+        let mut clippy_args = Vec::new();
+        if argsinfo.output.json() {
+            clippy_args.push("--message-format=json".to_string());
+        }
+        clippy_args.extend(crate_cargo_args(argsinfo, krate));
+        let result = cargo_command_output(argsinfo, "clippy", &clippy_args, &krate.dir, &[])
+            .map_err(|err| err.category(Failure::Compile));
+        report.duration = start.elapsed();
+        report.ok = result.is_ok();
+        report.warnings = result.as_ref().map(|text| warning_counts(text).values().sum()).unwrap_or(0);
+        reports.push(report);
+        result?;
+    }
+    Ok(())
+}
+
+/// Clean all crates, remote artifacts in `priv/`
+fn clean_crates(argsinfo: &ArgsInfo, appdir: &Path, _reports: &mut Vec<CrateReport>) -> Result<(), MsgError> {
+    // clean all crate dirs
+    let crates = crates(argsinfo, appdir)?;
+    for krate in crates.iter() {
+        argsinfo.output.status(&format!("Cleaning {}", krate.dir.to_string_lossy()));
+        cargo_command(argsinfo, "clean", &crate_cargo_args(argsinfo, krate), &krate.dir)?;
+    };
+
+    // clean priv/crates, priv/host-tools and their copies, or just the one crate's artifacts
+    // in single-crate mode
+    for dir in Some(priv_dir(argsinfo, appdir)).into_iter().chain(copy_priv_dirs(argsinfo, appdir)) {
+        for area in ["crates", HOST_TOOLS_DIR].iter() {
+            let mut output_dir = dir.join(area);
+            if argsinfo.options.manifest_path.is_some() {
+                output_dir.push(&crates[0].name);
+            }
+            remove_dir_all_force(output_dir).map_err(|err| MsgIo("can't delete output dir", err))?;
+        }
+    }
+    Ok(())
+}
+
+/// Build, then load each NIF into a throwaway Erlang VM and probe each port program
+fn smoke_test(argsinfo: &ArgsInfo, appdir: &Path, reports: &mut Vec<CrateReport>) -> Result<(), MsgError> {
+    build_crates(argsinfo, appdir, reports)?;
+
+    let mut failed = Vec::new();
+    for krate in crates(argsinfo, appdir)?.iter() {
+        let manifest = read_manifest(argsinfo, krate)?;
+        for target in manifest.targets.iter().filter(|t| is_native_nif(argsinfo, krate, t) && argsinfo.options.selects(t)) {
+            let path = installed_path(argsinfo, appdir, krate, target, &manifest.version);
+            let module = reload::nif_module(&krate.dir).unwrap_or_else(|| target.as_ref().clone());
+            match smoke::probe(&module, &path.with_extension(""))? {
+                Ok(()) => argsinfo.output.status(&format!("Loaded {}", path.to_string_lossy())),
+                Err(err) => {
+                    argsinfo.output.status(&format!("Cannot load {}: {}", path.to_string_lossy(), err));
+                    failed.push(target.to_string());
+                },
+            }
+        }
+
+        let probe = match argsinfo.config.port_probe {
+            Some(ref probe) if probe.crates.is_empty() || probe.crates.contains(&krate.name) => probe,
+            _ => continue,
+        };
+        for target in manifest.targets.iter().filter(|t| matches!(t, Target::Bin(_)) && argsinfo.options.selects(t)) {
+            let path = installed_path(argsinfo, appdir, krate, target, &manifest.version);
+            match smoke::probe_port(&path, probe)? {
+                Ok(()) => argsinfo.output.status(&format!("Port handshake ok {}", path.to_string_lossy())),
+                Err(err) => {
+                    argsinfo.output.status(&format!("Port handshake failed {}: {}", path.to_string_lossy(), err));
+                    failed.push(target.to_string());
+                },
+            }
+        }
+    }
+    match failed.is_empty() {
+        true => Ok(()),
+        false => Err(MsgDetail("smoke test failed", failed.join(", ")).category(Failure::Test)),
+    }
+}
+
+/// Build, then archive each installed dylib and bin into the dist directory with a manifest
+fn dist_crates(argsinfo: &ArgsInfo, appdir: &Path, reports: &mut Vec<CrateReport>) -> Result<(), MsgError> {
+    build_crates(argsinfo, appdir, reports)?;
+
+    let compression = argsinfo.options.compression.unwrap_or(argsinfo.config.dist_compression);
+    let dist_dir = appdir.join(&argsinfo.config.dist_dir);
+    let mut entries = Vec::new();
+    for krate in crates(argsinfo, appdir)?.iter() {
+        let manifest = read_manifest(argsinfo, krate)?;
+        let triple = crate_triple(argsinfo, krate)?;
+        for target in manifest.targets.iter().filter(|t| !matches!(t, Target::Staticlib(_)) && argsinfo.options.selects(t)) {
+            let path = installed_path(argsinfo, appdir, krate, target, &manifest.version);
+            let entry = dist::Entry {
+                crate_name: krate.name.clone(),
+                target: target.to_string(),
+                file: path.file_name().map(|n| n.to_string_lossy().into_owned()).unwrap_or_default(),
+                version: manifest.version.clone(),
+                triple: triple.clone(),
+            };
+            let archive = dist::archive(&entry, &path, &dist_dir, compression)
+                .map_err(|err| err.category(Failure::Install))?;
+            argsinfo.output.status(&format!("Packaged {}", archive));
+            entries.push((entry, archive));
+        }
+    }
+    dist::write_manifest(&dist_dir, &entries, compression).map_err(|err| err.category(Failure::Install))
+}
+
+/// Build, then package the application's `ebin/` and `priv/`, with each crate's installed
+/// artifacts and build records but nothing else of `priv/crates`, into `<app>-<vsn>.ez` in
+/// the dist directory
+fn ez_crates(argsinfo: &ArgsInfo, appdir: &Path, reports: &mut Vec<CrateReport>) -> Result<(), MsgError> {
+    build_crates(argsinfo, appdir, reports)?;
+
+    let app = reload::app_name(appdir);
+    let rebar_profile = argsinfo.options.rebar_profile.as_deref().unwrap_or("default");
+    let ebin = Some(appdir.join("ebin")).filter(|ebin| ebin.is_dir())
+        .or_else(|| rebar_lib::ebin_dir(appdir, &app, rebar_profile))
+        .ok_or(MsgDetail("no ebin directory (compile the application first)", app.clone()).category(Failure::Install))?;
+    let version = app_version::resource_version(&ebin.join(format!("{}.app", app)))
+        .or_else(|| app_version::app_version(appdir))
+        .ok_or(MsgDetail("no literal application version for the archive name", app.clone()).category(Failure::Install))?;
+    let root = format!("{}-{}", app, version);
+
+    let cannot_read = |err| MsgIo("cannot read files to archive", err).category(Failure::Install);
+    let mut files = Vec::new();
+    ez::add_dir(&mut files, &format!("{}/ebin", root), &ebin, &[]).map_err(cannot_read)?;
+    let priv_dir = priv_dir(argsinfo, appdir);
+    if priv_dir.is_dir() {
+        ez::add_dir(&mut files, &format!("{}/priv", root), &priv_dir, &["crates", HOST_TOOLS_DIR]).map_err(cannot_read)?;
+    }
+    // host tools ran during the build and have no place in a deployment
+    let priv_dir = priv_dir.canonicalize().unwrap_or(priv_dir);
+    for krate in crates(argsinfo, appdir)?.iter().filter(|krate| !is_host_tool(argsinfo, krate)) {
+        let (_, outputs) = crate_outputs(argsinfo, appdir, krate)?
+            .ok_or(MsgDetail("crate not built", krate.name.clone()).category(Failure::Install))?;
+        // the archive holds priv/ only: outputs installed outside it, such as staticlibs
+        // copied next to C sources, are left out
+        for output in outputs {
+            let relative = match output.strip_prefix(&priv_dir) {
+                Ok(relative) => relative,
+                Err(_) => continue,
+            };
+            let archived = relative.components().map(|c| c.as_os_str().to_string_lossy()).collect::<Vec<_>>().join("/");
+            files.push((format!("{}/priv/{}", root, archived), output.clone()));
+        }
+    }
+
+    let archive = appdir.join(&argsinfo.config.dist_dir).join(format!("{}.ez", root));
+    ez::write(&archive, &files).map_err(|err| MsgIo("cannot write archive", err).category(Failure::Install))?;
+    argsinfo.output.status(&format!("Packaged {}", archive.strip_prefix(appdir).unwrap_or(&archive).display()));
+    Ok(())
+}
+
+/// Bump each crate's version; with `--sync-app`, bump the application version and give
+/// every crate that version
+fn version_bump(argsinfo: &ArgsInfo, appdir: &Path, _reports: &mut Vec<CrateReport>) -> Result<(), MsgError> {
+    let spec = argsinfo.options.bump.as_ref()
+        .ok_or(Msg("patch, minor, major or a version required").category(Failure::Usage))?;
+    let bump: bump::Bump = spec.parse()
+        .map_err(|_| MsgDetail("expected patch, minor, major or a version", spec.clone()).category(Failure::Usage))?;
+    let synced = match argsinfo.options.sync_app {
+        true => {
+            let current = app_version::app_version(appdir)
+                .ok_or(Msg("--sync-app: no literal version in src/*.app.src or mix.exs"))?;
+            let version = bump.apply(&current).ok_or_else(|| MsgDetail("cannot bump version", current.clone()))?;
+            app_version::set_app_version(appdir, &version).map_err(|err| MsgIo("cannot write application version", err))?;
+            argsinfo.output.status(&format!("Bumped application {} -> {}", current, version));
+            Some(version)
+        },
+        false => None,
+    };
+
+    let mut workspaces: Vec<PathBuf> = Vec::new();
+    for krate in crates(argsinfo, appdir)?.iter() {
+        if !krate.dir.starts_with(appdir) {
+            argsinfo.output.status(&format!("Not bumping external crate {}", krate.name));
+            continue;
+        }
+        let manifest_path = krate.dir.join("Cargo.toml");
+        let text = fs::read_to_string(&manifest_path).map_err(|err| MsgIo("cannot read Cargo.toml", err))?;
+        let (path, section, text) = match bump::inherits_version(&text) {
+            true => {
+                let root = crate_metadata(argsinfo, &krate.dir, false)?.workspace_root;
+                if workspaces.contains(&root) {
+                    continue;
+                }
+                workspaces.push(root.clone());
+                let path = root.join("Cargo.toml");
+                let text = fs::read_to_string(&path).map_err(|err| MsgIo("cannot read Cargo.toml", err))?;
+                (path, "workspace.package", text)
+            },
+            false => (manifest_path, "package", text),
+        };
+        let current = crate_package(argsinfo, &krate.dir)?.version;
+        let version = match synced {
+            Some(ref version) => version.clone(),
+            None => bump.apply(&current).ok_or_else(|| MsgDetail("cannot bump version", current.clone()))?,
+        };
+        let text = bump::set_manifest_version(&text, section, &version)
+            .ok_or_else(|| MsgDetail("no version to bump", format!("[{}] in {}", section, path.to_string_lossy())))?;
+        fs::write(&path, text).map_err(|err| MsgIo("cannot write Cargo.toml", err))?;
+        let name = match section {
+            "package" => krate.name.clone(),
+            _ => format!("workspace {}", path.to_string_lossy()),
+        };
+        argsinfo.output.status(&format!("Bumped {} {} -> {}", name, current, version));
+
+        // resolving rewrites the crate's entry in Cargo.lock
+        if let Err(err) = metadata::read(cargo(argsinfo), &krate.dir, true) {
+            argsinfo.output.status(&format!("Cargo.lock not updated ({}); the next build will update it", err));
+        }
+    }
+    Ok(())
+}
+
+/// Check installed artifacts against the build manifests written when they were built
+fn verify_crates(argsinfo: &ArgsInfo, appdir: &Path, _reports: &mut Vec<CrateReport>) -> Result<(), MsgError> {
+    let mut drifted = Vec::new();
+    for krate in crates(argsinfo, appdir)?.iter() {
+        let dir = crate_priv_dir(argsinfo, appdir, krate);
+        let records = match verify::read_manifest(&dir)? {
+            Some(manifest) => manifest.records,
+            None => {
+                argsinfo.output.status(&format!("{}: no build manifest (not built?)", krate.name));
+                drifted.push(krate.name.clone());
+                continue;
+            },
+        };
+        for record in records.iter() {
+            match record.check(appdir) {
+                Ok(()) => argsinfo.output.status(&format!("{}: {} ok", krate.name, record.path)),
+                Err(drift) => {
+                    argsinfo.output.status(&format!("{}: {} {}", krate.name, record.path, drift));
+                    drifted.push(record.target.clone());
+                },
+            }
+        }
+    }
+    match drifted.is_empty() {
+        true => Ok(()),
+        false => Err(MsgDetail("installed artifacts differ from the build", drifted.join(", ")).category(Failure::Test)),
+    }
+}
+
+/// List each crate's freshness, installed profile and target, and artifact age
+fn status_crates(argsinfo: &ArgsInfo, appdir: &Path, _reports: &mut Vec<CrateReport>) -> Result<(), MsgError> {
+    let mut rows = vec![["crate", "state", "profile", "target", "installed"].map(String::from).to_vec()];
+    for krate in crates(argsinfo, appdir)?.iter() {
+        let dir = crate_priv_dir(argsinfo, appdir, krate);
+        let row = match verify::read_manifest(&dir)? {
+            None => vec![krate.name.clone(), "not built".to_string()],
+            Some(manifest) => {
+                let fresh = verify::source_fingerprint(&krate.dir).map(|f| f == manifest.fingerprint).unwrap_or(false);
+                let installed = manifest.records.iter()
+                    .filter_map(|r| fs::metadata(appdir.join(&r.path)).and_then(|m| m.modified()).ok())
+                    .max()
+                    .and_then(|t| t.elapsed().ok())
+                    .map(summary::format_age)
+                    .unwrap_or_else(|| "missing".to_string());
+                let triples: Vec<&str> = manifest.records.iter().map(|r| r.triple.as_str()).collect();
+                vec![
+                    krate.name.clone(),
+                    (if fresh { "up to date" } else { "stale" }).to_string(),
+                    manifest.profile,
+                    triples.first().map(|t| t.to_string()).unwrap_or_else(|| "-".to_string()),
+                    installed,
+                ]
+            },
+        };
+        rows.push(row);
+    }
+    argsinfo.output.status(&summary::table(&rows));
+    Ok(())
+}
+
+/// Prune stale build outputs from the crates' target directories
+fn gc_crates(argsinfo: &ArgsInfo, appdir: &Path, _reports: &mut Vec<CrateReport>) -> Result<(), MsgError> {
+    let mut target_dirs: Vec<PathBuf> = crates(argsinfo, appdir)?.iter()
+        .map(|krate| crate_target_dir(argsinfo, appdir, &krate.dir))
+        .collect();
+    target_dirs.sort();
+    target_dirs.dedup();
+
+    let default_policy = gc::Policy { max_age: Some(Duration::from_secs(30 * 86400)), max_size: None };
+    let policy = match argsinfo.options.gc_policy {
+        gc::Policy { max_age: None, max_size: None } => &default_policy,
+        ref policy => policy,
+    };
+    let (removed, freed) = gc::collect(&target_dirs, policy)
+        .map_err(|err| MsgIo("cannot prune target directory", err))?;
+    argsinfo.output.status(&format!("Removed {} stale entries, freed {}", removed, summary::format_size(freed)));
+    Ok(())
+}
+
+/// Report disk usage of target directories and installed artifacts
+fn du_crates(argsinfo: &ArgsInfo, appdir: &Path, _reports: &mut Vec<CrateReport>) -> Result<(), MsgError> {
+    let size_of = |path: &Path| match gc::usage(path) {
+        Ok((bytes, _)) => summary::format_size(bytes),
+        Err(_) => "-".to_string(),
+    };
+    let mut rows = vec![["crate", "target", "priv"].map(String::from).to_vec()];
+    let mut shared = Vec::new();
+    for krate in crates(argsinfo, appdir)?.iter() {
+        let target_dir = crate_target_dir(argsinfo, appdir, &krate.dir);
+        let target = match target_dir.starts_with(&krate.dir) {
+            true => size_of(&target_dir),
+            false => {
+                if !shared.contains(&target_dir) {
+                    shared.push(target_dir);
+                }
+                "(shared)".to_string()
+            },
+        };
+        let priv_crate_dir = priv_crates_dir(argsinfo, appdir, krate);
+        rows.push(vec![krate.name.clone(), target, size_of(&priv_crate_dir)]);
+    }
+    for dir in shared.iter() {
+        let name = dir.strip_prefix(appdir).unwrap_or(dir).to_string_lossy().into_owned();
+        rows.push(vec![format!("shared {}", name), size_of(dir), String::new()]);
+    }
+    argsinfo.output.status(&summary::table(&rows));
+    Ok(())
+}
+
+/// List the crates' direct dependencies that have newer versions, compatible with their
+/// requirement or not
+fn outdated_crates(argsinfo: &ArgsInfo, appdir: &Path, _reports: &mut Vec<CrateReport>) -> Result<(), MsgError> {
+    let mut rows = vec![["crate", "dependency", "locked", "compatible", "latest"].map(String::from).to_vec()];
+    for krate in crates(argsinfo, appdir)?.iter() {
+        let direct: Vec<String> = crate_package(argsinfo, &krate.dir)?.dependencies.into_iter()
+            .map(|d| d.name)
+            .collect();
+        argsinfo.output.status(&format!("Checking {}", krate.dir.to_string_lossy()));
+        let mut command = cargo_process(argsinfo, "update", &["--dry-run", "--verbose"].map(String::from), &krate.dir);
+        let output = command.output().map_err(|err| MsgIo("cannot start cargo", err))?;
+        check_interrupted()?;
+        let text = String::from_utf8_lossy(&output.stderr).into_owned();
+        if !output.status.success() {
+            return Err(MsgCommand("cargo command failed", CommandFailure::new(&command, text).exited(output.status)));
+        }
+        for dep in outdated::parse(&text).into_iter().filter(|dep| direct.contains(&dep.name)) {
+            let or_dash = |v: Option<String>| v.unwrap_or_else(|| "-".to_string());
+            rows.push(vec![krate.name.clone(), dep.name, dep.locked, or_dash(dep.compatible), or_dash(dep.latest)]);
+        }
+    }
+    match rows.len() {
+        1 => argsinfo.output.status("All dependencies are up to date"),
+        _ => argsinfo.output.status(&summary::table(&rows)),
+    }
+    Ok(())
+}
+
+/// Bring the crates' requirements for `[shared-dependencies]` in line, editing their
+/// `Cargo.toml` and updating their lock files; with `--dry-run`, only show the changes
+fn sync_deps(argsinfo: &ArgsInfo, appdir: &Path, _reports: &mut Vec<CrateReport>) -> Result<(), MsgError> {
+    if argsinfo.config.shared_dependencies.is_empty() {
+        return Err(Msg("no [shared-dependencies] in erlangapp.toml").category(Failure::Usage));
+    }
+    let mut synced = 0;
+    for krate in crates(argsinfo, appdir)?.iter() {
+        let metadata = crate_metadata(argsinfo, &krate.dir, false)?;
+        let workspace_root = metadata.workspace_root.clone();
+        let package = metadata::package(metadata, &krate.dir)
+            .ok_or_else(|| MsgDetail("no package in crate directory", krate.dir.to_string_lossy().into_owned())
+                           .category(Failure::Enumeration))?;
+        let drifts = shared_deps::check(&argsinfo.config.shared_dependencies, &package);
+        if drifts.is_empty() {
+            continue;
+        }
+        if !krate.dir.starts_with(appdir) {
+            argsinfo.output.status(&format!("Not syncing external crate {}", krate.name));
+            continue;
+        }
+        let requirements = drifts.iter().map(|d| (d.dependency.clone(), d.expected.clone())).collect();
+        let manifest = krate.dir.join("Cargo.toml");
+        let text = fs::read_to_string(&manifest).map_err(|err| MsgIo("cannot read Cargo.toml", err))?;
+        let new_text = shared_deps::set_requirements(&text, &requirements);
+        let path = manifest.strip_prefix(appdir).unwrap_or(&manifest);
+        if new_text == text {
+            let names: Vec<&str> = drifts.iter().map(|d| d.dependency.as_str()).collect();
+            argsinfo.output.status(&format!("Cannot sync {}: {} not declared with a literal version in {}",
+                                            krate.name, names.join(", "), path.display()));
+            continue;
+        }
+        argsinfo.output.status(&format!("--- {}\n+++ {}\n{}", path.display(), path.display(),
+                                        shared_deps::line_diff(&text, &new_text).join("\n")));
+        synced += 1;
+        if argsinfo.options.dry_run {
+            continue;
+        }
+        fs::write(&manifest, new_text).map_err(|err| MsgIo("cannot write Cargo.toml", err))?;
+        // move locked versions only where a lock file exists, as cargo would create one
+        if workspace_root.join("Cargo.lock").is_file() {
+            let mut args = vec!["update".to_string()];
+            for drift in drifts.iter() {
+                args.push("-p".to_string());
+                args.push(drift.dependency.clone());
+            }
+            let mut command = cargo_process(argsinfo, &args[0], &args[1..], &krate.dir);
+            let output = command.output().map_err(|err| MsgIo("cannot start cargo", err))?;
+            check_interrupted()?;
+            if !output.status.success() {
+                let stderr = String::from_utf8_lossy(&output.stderr).into_owned();
+                return Err(MsgCommand("cargo update failed", CommandFailure::new(&command, stderr).exited(output.status)));
+            }
+        }
+    }
+    match (synced, argsinfo.options.dry_run) {
+        (0, _) => argsinfo.output.status("All crates follow [shared-dependencies]"),
+        (n, true) => argsinfo.output.status(&format!("{} crate{} would be changed (dry run)", n, if n == 1 { "" } else { "s" })),
+        (n, false) => argsinfo.output.status(&format!("Synced {} crate{}", n, if n == 1 { "" } else { "s" })),
+    }
+    Ok(())
+}
+
+/// Check every crate's dependency graph against `[policy]`, listing all violations
+fn policy_crates(argsinfo: &ArgsInfo, appdir: &Path, _reports: &mut Vec<CrateReport>) -> Result<(), MsgError> {
+    let policy = argsinfo.config.policy.as_ref()
+        .ok_or(Msg("no [policy] in erlangapp.toml").category(Failure::Usage))?;
+    let mut rows = vec![["crate", "package", "problem"].map(String::from).to_vec()];
+    for krate in crates(argsinfo, appdir)?.iter() {
+        let metadata = crate_metadata(argsinfo, &krate.dir, true)?;
+        for violation in policy::check(policy, &metadata) {
+            rows.push(vec![krate.name.clone(), violation.package, violation.problem]);
+        }
+    }
+    if rows.len() == 1 {
+        argsinfo.output.status("No dependency policy violations");
+        return Ok(());
+    }
+    argsinfo.output.status(&summary::table(&rows));
+    Err(MsgDetail("dependency policy violated", format!("{} violations", rows.len() - 1)).category(Failure::Policy))
+}
+
+/// Print the crates and the path dependencies between them as a graph, with the external
+/// dependencies of each if asked for
+fn graph_crates(argsinfo: &ArgsInfo, appdir: &Path, _reports: &mut Vec<CrateReport>) -> Result<(), MsgError> {
+    let crates = crates(argsinfo, appdir)?;
+    let crate_dirs: Vec<PathBuf> = crates.iter().map(|krate| krate.dir.clone()).collect();
+    let mut graph = graph::Graph::default();
+    for krate in crates.iter() {
+        let metadata = crate_metadata(argsinfo, &krate.dir, true)?;
+        graph.add_crate(&metadata, &krate.dir, &crate_dirs, argsinfo.options.graph_external);
+    }
+    argsinfo.output.status(graph.render(argsinfo.options.graph_format, &reload::app_name(appdir)).trim_end());
+    Ok(())
+}
+
+/// Check the recorded NIF API versions of installed artifacts against an OTP release
+fn check_otp(argsinfo: &ArgsInfo, appdir: &Path, _reports: &mut Vec<CrateReport>) -> Result<(), MsgError> {
+    let spec = argsinfo.options.otp.as_ref().ok_or(Msg("OTP path or version required").category(Failure::Usage))?;
+    let runtime = otp::runtime_nif_version(spec).map_err(|err| err.category(Failure::Usage))?;
+
+    let mut incompatible = Vec::new();
+    for krate in crates(argsinfo, appdir)?.iter() {
+        let path = crate_priv_dir(argsinfo, appdir, krate).join(otp::NIF_VERSION_FILE);
+        let recorded = match fs::read_to_string(&path) {
+            Ok(text) => text,
+            Err(_) => continue, // no NIF, or not built yet
+        };
+        match recorded.parse::<otp::NifVersion>() {
+            Ok(version) if version.loads_into(&runtime) =>
+                argsinfo.output.status(&format!("{}: NIF API {} loads into NIF API {}", krate.name, version, runtime)),
+            Ok(version) => {
+                argsinfo.output.status(&format!("{}: NIF API {} does not load into NIF API {}", krate.name, version, runtime));
+                incompatible.push(krate.name.clone());
+            },
+            Err(_) => return Err(MsgDetail("cannot parse NIF version file", path.to_string_lossy().into_owned())),
+        }
+    }
+    match incompatible.is_empty() {
+        true => Ok(()),
+        false => Err(MsgDetail("artifacts incompatible with target OTP", incompatible.join(", ")).category(Failure::Test)),
+    }
+}
+
+/// Report native symbols defined by more than one installed NIF, failing if any are exported
+/// by several
+fn check_symbols(argsinfo: &ArgsInfo, appdir: &Path, _reports: &mut Vec<CrateReport>) -> Result<(), MsgError> {
+    let mut libraries = Vec::new();
+    for krate in crates(argsinfo, appdir)?.iter() {
+        let dir = crate_priv_dir(argsinfo, appdir, krate);
+        let records = verify::read_manifest(&dir)?.map(|manifest| manifest.records).unwrap_or_default();
+        for record in records.iter().filter(|r| [".so", ".dylib", ".dll"].iter().any(|ext| r.path.ends_with(ext))) {
+            let symbols = symbols::read(&appdir.join(&record.path), record.triple.contains("apple"))?;
+            libraries.push((record.path.clone(), symbols));
+        }
+    }
+    let overlaps = symbols::overlaps(&libraries);
+    if overlaps.is_empty() {
+        argsinfo.output.status(&format!("No symbols shared between {} NIF libraries", libraries.len()));
+        return Ok(());
+    }
+    let mut rows = vec![["libraries", "exported", "hidden", "e.g."].map(String::from).to_vec()];
+    for overlap in overlaps.iter() {
+        let examples: Vec<&str> = overlap.clashes.iter().chain(overlap.duplicates.iter()).take(4).map(String::as_str).collect();
+        rows.push(vec![overlap.libraries.join(", "), overlap.clashes.len().to_string(), overlap.duplicates.len().to_string(),
+                       examples.join(" ")]);
+    }
+    argsinfo.output.status(&summary::table(&rows));
+    let clashes: usize = overlaps.iter().map(|overlap| overlap.clashes.len()).sum();
+    match clashes {
+        0 => Ok(()),
+        n => Err(MsgDetail("NIF libraries export the same symbols", format!("{} symbols", n)).category(Failure::Test)),
+    }
+}
+
+/// Apply the artifact retention policy without building
+fn prune_artifacts(argsinfo: &ArgsInfo, appdir: &Path, _reports: &mut Vec<CrateReport>) -> Result<(), MsgError> {
+    let keep = argsinfo.keep_artifacts().unwrap_or(1);
+    for krate in crates(argsinfo, appdir)?.iter() {
+        for target in read_manifest(argsinfo, krate)?.targets.iter() {
+            let dir = install_dir(argsinfo, appdir, krate, target);
+            let removed = artifacts::prune(&dir, &install_name(argsinfo, krate, target), keep)
+                .map_err(|err| MsgIo("cannot prune old artifacts", err).category(Failure::Install))?;
+            if removed > 0 {
+                argsinfo.output.status(&format!("Pruned {} old generation(s) of {} in {}",
+                                                removed, target, dir.to_string_lossy()));
+            }
+        }
+    }
+    match argsinfo.options.manifest_path {
+        Some(_) => Ok(()),
+        None => prune_orphans(argsinfo, appdir, argsinfo.options.prune),
+    }
+}
+
+/// Install the builds `--profile-dirs` kept for a profile in place of the active ones, with
+/// their build manifests.  Every crate must have one, so the application isn't left with a
+/// mix of profiles.
+fn switch_profile(argsinfo: &ArgsInfo, appdir: &Path, _reports: &mut Vec<CrateReport>) -> Result<(), MsgError> {
+    let profile = argsinfo.options.switch_to.as_ref()
+        .ok_or(Msg("profile required, such as debug or release").category(Failure::Usage))?;
+    if !is_profile_name(profile) {
+        return Err(MsgDetail("not a cargo profile name", profile.clone()).category(Failure::Usage));
+    }
+    let mut kept = Vec::new();
+    let mut missing = Vec::new();
+    for krate in crates(argsinfo, appdir)?.iter() {
+        let manifest_dir = crate_priv_dir(argsinfo, appdir, krate);
+        let store = manifest_dir.join(profile);
+        match verify::read_manifest(&store)? {
+            Some(build_manifest) => kept.push((krate.name.clone(), manifest_dir, store, build_manifest)),
+            None => missing.push(krate.name.clone()),
+        }
+    }
+    if !missing.is_empty() {
+        return Err(MsgDetail("no build kept for this profile (build it with --profile-dirs)",
+                             format!("{}: {}", profile, missing.join(", "))).category(Failure::Install));
+    }
+    for (name, manifest_dir, store, build_manifest) in kept.iter() {
+        let mut files = Vec::new();
+        for record in build_manifest.records.iter() {
+            let path = appdir.join(&record.path);
+            files.extend([provenance::build_info_path(&path), path.with_extension("link-args"), path]);
+        }
+        files.extend([otp::NIF_VERSION_FILE, provenance::PROVENANCE_FILE, verify::MANIFEST_FILE].iter()
+            .map(|file| manifest_dir.join(file)));
+        for dst in files.iter() {
+            let src = store.join(dst.file_name().unwrap_or_default());
+            if src.is_file() {
+                artifacts::install(&src, dst, false)
+                    .map_err(|err| MsgIo("cannot install kept build", err).category(Failure::Install))?;
+            }
+        }
+        argsinfo.output.status(&format!("Switched {} to its {} build", name, profile));
+    }
+    install_copies(argsinfo, appdir).map(|_| ())
+}
+
+/// True if `name` can be a cargo profile, and so names a directory `--profile-dirs` keeps:
+/// letters, digits, `-` and `_`
+fn is_profile_name(name: &str) -> bool {
+    !name.is_empty() && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+}
+
+/// Swap the artifacts `--backup` kept back in, with their build info, and record them for
+/// `verify`.  Rolling back again undoes it.
+fn rollback_crates(argsinfo: &ArgsInfo, appdir: &Path, _reports: &mut Vec<CrateReport>) -> Result<(), MsgError> {
+    let mut rolled_back = 0;
+    for krate in crates(argsinfo, appdir)?.iter() {
+        let manifest_dir = crate_priv_dir(argsinfo, appdir, krate);
+        let mut build_manifest = match verify::read_manifest(&manifest_dir)? {
+            Some(build_manifest) => build_manifest,
+            None => continue,
+        };
+        let mut swapped = false;
+        for record in build_manifest.records.iter_mut() {
+            let path = appdir.join(&record.path);
+            let swap = |path: &Path| artifacts::swap_backup(path)
+                .map_err(|err| MsgIo("cannot roll back artifact", err).category(Failure::Install));
+            if !swap(&path)? {
+                continue;
+            }
+            swap(&provenance::build_info_path(&path))?;
+            *record = verify::Record::of(appdir, &path, &record.target, &record.triple)
+                .map_err(|err| err.category(Failure::Install))?;
+            argsinfo.output.status(&format!("Rolled back {}", record.path));
+            swapped = true;
+            rolled_back += 1;
+        }
+        if swapped {
+            // no longer built from the current sources, so `status` calls it stale
+            build_manifest.fingerprint = String::new();
+            verify::write_manifest(&manifest_dir, &build_manifest).map_err(|err| err.category(Failure::Install))?;
+        }
+    }
+    if rolled_back == 0 {
+        return Err(Msg("no backed-up artifacts to roll back to (build with --backup)").category(Failure::Install));
+    }
+    install_copies(argsinfo, appdir).map(|_| ())
+}
+
+/// Directories in `priv/crates` and `priv/host-tools`, and their copies, of crates that no
+/// longer exist: a deleted or renamed crate's artifacts would otherwise still be loaded.
+/// Only directories this tool installed into count, and crates left out by `crates.exclude`
+/// or `crates.include` still exist.
+fn orphaned_crate_dirs(argsinfo: &ArgsInfo, appdir: &Path) -> Result<Vec<PathBuf>, MsgError> {
+    let mut names: BTreeSet<String> = crates(argsinfo, appdir)?.into_iter().map(|krate| krate.name).collect();
+    names.extend(enumerate_crate_dirs(appdir)?.iter()
+        .filter_map(|dir| dir.file_name().map(|name| name.to_string_lossy().into_owned())));
+    let mut orphans = Vec::new();
+    for dir in Some(priv_dir(argsinfo, appdir)).into_iter().chain(copy_priv_dirs(argsinfo, appdir)) {
+        for area in ["crates", HOST_TOOLS_DIR].iter() {
+            let entries = match fs::read_dir(dir.join(area)) {
+                Ok(entries) => entries,
+                Err(_) => continue,
+            };
+            orphans.extend(entries.filter_map(Result::ok)
+                .filter(|entry| !names.contains(&*entry.file_name().to_string_lossy()) && installed_into(&entry.path()))
+                .map(|entry| entry.path()));
+        }
     }
+    orphans.sort();
+    Ok(orphans)
 }
 
-
-fn usage() {
-    writeln!(stderr(), "Usage:").unwrap();
-    writeln!(stderr(), "\tcargo-erlangapp build [cargo rustc args]").unwrap();
-    writeln!(stderr(), "\tcargo-erlangapp clean [cargo clean args]").unwrap();
-    writeln!(stderr(), "\tcargo-erlangapp test [cargo test args]").unwrap();
-    process::exit(1);
+/// True if `dir` holds a build manifest, directly or in a `--platform-dirs` subdirectory
+fn installed_into(dir: &Path) -> bool {
+    dir.join(verify::MANIFEST_FILE).is_file()
+        || fs::read_dir(dir).map(|entries| entries.filter_map(Result::ok)
+            .any(|entry| entry.path().join(verify::MANIFEST_FILE).is_file())).unwrap_or(false)
 }
 
-
-
-fn invoke(argsinfo: &ArgsInfo, appdir: &Path) {
-    match do_command(argsinfo, appdir) {
-        Ok(_) => (),
-        Err(err) => {
-            writeln!(stderr(), "Error: {}", err).unwrap();
-            process::exit(1);
+/// Warn about the directories of crates that no longer exist, or with `remove` delete them
+fn prune_orphans(argsinfo: &ArgsInfo, appdir: &Path, remove: bool) -> Result<(), MsgError> {
+    for dir in orphaned_crate_dirs(argsinfo, appdir)? {
+        let shown = dir.strip_prefix(appdir).unwrap_or(&dir).display().to_string();
+        match remove {
+            true => {
+                remove_dir_all_force(&dir)
+                    .map_err(|err| MsgIo("cannot remove artifacts of a removed crate", err).category(Failure::Install))?;
+                argsinfo.output.status(&format!("Removed {}: no crate of that name", shown));
+            },
+            false => argsinfo.output.status(&format!(
+                "Warning: {} belongs to no crate but can still be loaded (--prune removes it)", shown)),
         }
     }
+    Ok(())
 }
 
-fn do_command(argsinfo: &ArgsInfo, appdir: &Path) -> Result<(), MsgError> {
-    match argsinfo.command {
-        CargoCommand::Build =>
-            build_crates(argsinfo, appdir),
-        CargoCommand::Test =>
-            test_crates(argsinfo, appdir),
-        CargoCommand::Clean =>
-            clean_crates(argsinfo, appdir),
+// Remove file.  The file being absent is not an error.
+fn remove_file_force(path: &Path) -> io::Result<()> {
+    match fs::remove_file(path) {
+        Err(ref err) if err.kind() == io::ErrorKind::NotFound => Ok(()),
+        result => result,
     }
 }
 
-fn build_crates(argsinfo: &ArgsInfo, appdir: &Path) -> Result<(), MsgError> {
-    // build(rustc) each crate
-    for crate_dir in try!(enumerate_crate_dirs(appdir)).iter() {
-        for target in try!(enumerate_targets(crate_dir)).into_iter() {
-            println!("Building {}", crate_dir.to_string_lossy());
-
-            // args for build target
-            let mut rustc_args: Vec<String> = match target {
-                Target::Bin(ref s) => vec!("--bin".to_string(), s.to_string()),
-                Target::Dylib(_) => vec!("--lib".to_string()),  // only 1 lib permitted per crate, name is implicit
-            };
-
-            // args from commandline
-            rustc_args.extend(argsinfo.cargo_args.iter().cloned());
-
-            // linker args
-            rustc_args.extend(linker_args(&target).iter().map(|x|x.to_string()));
-
-            // build it!
-            try!(cargo_command("rustc", rustc_args.as_slice(), crate_dir));
-
-            // copy artifacts to priv/crates/<cratename>
-            let (dst_name, src_name) = target_filenames(&target);
+// Remove dir.  The dir being absent is not an error.
+fn remove_dir_all_force<P: AsRef<Path>>(path: P) -> io::Result<()> {
 
-            // build src path
-            let mut src_path = crate_dir.join("target");
-            if let Some(ref target_arch) = argsinfo.target {
-                src_path.push(target_arch);
+    match fs::metadata(path.as_ref()) {
+        Err(err) => {
+            match err.kind() {
+                io::ErrorKind::NotFound => Ok(()),  // not finding is okay (already cleaned)
+                _ => Err(err),   // permission error on parent dir?
             }
-            src_path.push( match argsinfo.build_type {
-                BuildType::Release => "release",
-                _ => "debug",
-            });
-            src_path.push(src_name);
-
-            // build dst path
-            let mut dst_path = appdir.join("priv");
-            dst_path.push("crates");
-            dst_path.push(crate_dir.file_name().unwrap()); // filename will be valid if rustc worked
-            try!(fs::create_dir_all(&dst_path)
-                     .map_err(|err| MsgIo("cannot create dest directories in priv/", err)));
-            dst_path.push(dst_name);
-
-            // finally, copy the artifact with its new name.
-            try!(fs::copy(src_path, dst_path)
-                .map_err(|err| MsgIo("cannot copy artifact", err)));
-        }
-    };
+        },
+        Ok(m) => {
+            match m.is_dir() {
+                true => fs::remove_dir_all(path),
+                false => Ok(()),
+            }
+        },
+    }
+}
 
-    Ok(())
+fn cargo_command(argsinfo: &ArgsInfo, cmd: &str, args: &[String], dir: &Path) -> Result<(), MsgError> {
+    cargo_command_output(argsinfo, cmd, args, dir, &[]).map(|_| ())
 }
 
-fn linker_args(target: &Target) -> &'static [&'static str] {
-    match *target {
-        Target::Dylib(_) => DYLIB_LINKER_ARGS,
-        Target::Bin(_) => BIN_LINKER_ARGS,
+/// Run cargo with extra environment variables, also returning its compiler output (stderr, or
+/// stdout's JSON messages and stderr).  On failure the error carries the command line and the
+/// tail of that output.
+fn cargo_command_output(argsinfo: &ArgsInfo, cmd: &str, args: &[String], dir: &Path, vars: &[(&str, OsString)])
+    -> Result<String, MsgError>
+{
+    let crate_name = dir.file_name().map(|s| s.to_string_lossy().into_owned()).unwrap_or_default();
+    let mut command = cargo_process(argsinfo, cmd, args, dir);
+    command.envs(vars.iter().map(|(k, v)| (k, v)));
+    let (status, text) = argsinfo.output.run(&mut command, &crate_name)
+        .map_err(|err| MsgIo("cannot start cargo", err))?;
+    check_interrupted()?;
+    match status.success() {
+        true => Ok(text),
+        false => Err(MsgCommand("cargo command failed", CommandFailure::new(&command, text).exited(status))),
     }
 }
 
-
-/// OS X naming
-///
-/// Dylibs have `lib` prefix, and `.dylib` suffix gets changed to `.so`.
-#[cfg(target_os="macos")]
-pub fn target_filenames(target: &Target) -> (String, String) {
-    match *target {
-        Target::Bin(ref s) => (s.to_string(), s.to_string()),
-        Target::Dylib(ref s) => ("lib".to_string() + s + ".so", "lib".to_string() + s + ".dylib"),
+/// Stop if Ctrl-C was pressed, once whatever was running has finished
+fn check_interrupted() -> Result<(), MsgError> {
+    match interrupt::interrupted() {
+        true => Err(Msg("interrupted").category(Failure::Interrupted)),
+        false => Ok(()),
     }
 }
-/// Windows naming
-///
-/// Bins have `.exe` suffix, dylibs have `.dll` suffix.
-#[cfg(windows)]
-pub fn target_filenames(target: &Target) -> (String, String) {
-    match *target {
-        Target::Bin(ref s) => (s.to_string() + ".exe", s.to_string() + ".exe"),
-        Target::Dylib(ref s) => (s.to_string() + ".dll", s.to_string() + ".dll"),
+
+/// `cargo` with the `[env]` policy applied and the run's `--config` overrides, for a
+/// subcommand to be added
+fn cargo(argsinfo: &ArgsInfo) -> process::Command {
+    cargo_with(&argsinfo.config.env, &argsinfo.options.cargo_config)
+}
+
+fn cargo_with(env: &env_policy::Policy, cargo_config: &[String]) -> process::Command {
+    let mut command = process::Command::new("cargo");
+    env.apply(&mut command);
+    for value in cargo_config.iter() {
+        command.args(["--config", value]);
     }
+    command
 }
 
-/// Non-windows, non-OSX nameing
-///
-/// Dylibs have `lib` prefix and `.so` suffix.
-#[cfg(all(unix, not(target_os="macos")))]
-pub fn target_filenames(target: &Target) -> (String, String) {
-    match *target {
-        Target::Bin(ref s) => (s.to_string(), s.to_string()),
-        Target::Dylib(ref s) => ("lib".to_string() + s + ".so", "lib".to_string() + s + ".so"),
+/// `cargo <cmd> <args>` in `dir`, with the `[target.<triple>.env]` variables of the
+/// `--target` in `args`
+fn cargo_process(argsinfo: &ArgsInfo, cmd: &str, args: &[String], dir: &Path) -> process::Command {
+    let mut command = cargo(argsinfo);
+    command.arg(cmd)
+        .args(args)
+        .current_dir(dir);
+    if let Some(triple) = take_option_value(&mut args.to_vec(), "--target") {
+        command.envs(argsinfo.config.target_env(&triple));
+    }
+    if let Some(ref target_dir) = argsinfo.target_dir {
+        command.env("CARGO_TARGET_DIR", target_dir);
     }
+    jobserver::configure(&mut command);
+    command
 }
 
 
-/// Build artifact types
+/// A crate to process
 #[derive(Debug)]
-pub enum Target {
-    Bin(String),
-    Dylib(String),
+struct Crate {
+    /// Directory holding `Cargo.toml`
+    dir: PathBuf,
+    /// Artifacts are installed into `priv/crates/<name>/`
+    name: String,
+    /// Base name for installed artifacts, when it differs from the target name
+    artifact_name: Option<String>,
+    /// Elixir module the installed dylib is named after, from `[elixir-modules]`
+    elixir_module: Option<String>,
+    /// What the crate is built for when not what `--target` says
+    target: Option<TargetOverride>,
 }
 
-impl AsRef<String> for Target {
-    fn as_ref(&self) -> &String {
-        match *self {
-            Target::Bin(ref s) => s,
-            Target::Dylib(ref s) => s,
-        }
+/// `target` in a crate's `[package.metadata.erlangapp]`: host tools such as code generators
+/// and test fixtures are built for the host even when cross-compiling, or a crate is built
+/// for a triple of its own
+#[derive(Debug, Clone, PartialEq)]
+enum TargetOverride {
+    Host,
+    Triple(String),
+}
+
+impl TargetOverride {
+    /// The override in the `Cargo.toml` in `dir`, if any; a manifest cargo can't read fails
+    /// later, in cargo
+    fn read(dir: &Path) -> Option<TargetOverride> {
+        let manifest = workspace::read_manifest_toml(dir).ok()?;
+        let target = manifest.get("package")?.get("metadata")?.get("erlangapp")?.get("target")?.as_str()?;
+        Some(match target {
+            "host" => TargetOverride::Host,
+            triple => TargetOverride::Triple(triple.to_string()),
+        })
     }
 }
 
-impl Target {
-    /// Create target from cargo manifest fragment
-    fn from_json(obj: &json::Value) -> Option<Target> {
-        let name = otry!(obj.find("name")
-                    .and_then(|s| s.as_string())
-                    .map(|s| s.to_string()));
-        let kinds: Vec<&str> = otry!(obj.find("kind")
-            .and_then(|s| s.as_array())
-            .map(|arr| arr.iter()
-                .filter_map( |s| s.as_string())
-                .collect()));
-
-        if kinds.contains(&"bin") {
-            Some(Target::Bin(name))
-        } else if kinds.contains(&"dylib") || kinds.contains(&"cdylib"){
-            Some(Target::Dylib(name))
-        } else {
-            None
-        }
+impl Crate {
+    fn from_dir(dir: PathBuf) -> Crate {
+        let name = dir.file_name().map(|n| n.to_string_lossy().into_owned()).unwrap_or_default();
+        let target = TargetOverride::read(&dir);
+        Crate { dir, name, artifact_name: None, elixir_module: None, target }
     }
 }
 
-/// Read manifest for given crate and enumerate targets
-fn enumerate_targets(crate_dir: &Path) -> Result<Vec<Target>, MsgError> {
-    let output = try!(process::Command::new("cargo").arg("read-manifest")
-                          .current_dir(crate_dir)
-                          .output()
-                          .map_err(|err| MsgIo("Cannot read crate manifest",err)));
+/// Crates to process: the one named by `--manifest-path`, or every crate in `crates/`
+/// plus the external crates configured in `erlangapp.toml`
+fn crates(argsinfo: &ArgsInfo, appdir: &Path) -> Result<Vec<Crate>, MsgError> {
+    if let Some(ref manifest_path) = argsinfo.options.manifest_path {
+        let crate_dir = appdir.join(manifest_path).parent().map(Path::to_path_buf)
+            .filter(|dir| dir.join("Cargo.toml").is_file())
+            .ok_or_else(|| MsgDetail("no Cargo.toml at manifest path", manifest_path.to_string_lossy().into_owned())
+                .category(Failure::Enumeration))?;
+        let mut krate = Crate::from_dir(crate_dir);
+        krate.elixir_module = argsinfo.config.elixir_modules.get(&krate.name).cloned();
+        return Ok(vec![krate]);
+    }
 
-    enumerate_targets_opt(output.stdout.as_slice())
-        .ok_or(Msg("Cannot parse crate manifest"))
+    let mut crates: Vec<Crate> = selected_crate_dirs(appdir, &argsinfo.config)?.into_iter().map(Crate::from_dir).collect();
+    for external in argsinfo.config.external_crates.iter() {
+        let dir = appdir.join(&external.path);
+        if !dir.join("Cargo.toml").is_file() {
+            return Err(MsgDetail("no Cargo.toml for external crate", external.name.clone())
+                .category(Failure::Enumeration));
+        }
+        let target = TargetOverride::read(&dir);
+        crates.push(Crate { dir, name: external.name.clone(), artifact_name: external.artifact.clone(), elixir_module: None, target });
+    }
+    for krate in crates.iter_mut() {
+        krate.elixir_module = argsinfo.config.elixir_modules.get(&krate.name).cloned();
+    }
+    Ok(crates)
 }
-/// Parse "targets" portion of JSON text to extract targets
-fn enumerate_targets_opt(json_slice: &[u8]) -> Option<Vec<Target>> {
-    let value: json::Value = otry!(json::from_slice(json_slice).ok());
-    value.find("targets")
-        .and_then(|v| v.as_array())   // :Option<Vec<Value>>
-        .map(|targets|
-                 targets
-                     .iter()
-                     .filter_map(Target::from_json)  // :Vec<Target>
-                     .collect())
+
+/// Destination `priv/` directory: `--priv-dir`, or the first of `priv-dirs` (by default
+/// the application's own)
+fn priv_dir(argsinfo: &ArgsInfo, appdir: &Path) -> PathBuf {
+    match argsinfo.options.priv_dir {
+        Some(ref dir) => appdir.join(dir),
+        None => appdir.join(&argsinfo.config.priv_dirs[0]),
+    }
 }
 
-/// Test all crates
-fn test_crates(argsinfo: &ArgsInfo, appdir: &Path) -> Result<(), MsgError> {
-    // test each create, short circuit fail
-    for crate_dir in try!(enumerate_crate_dirs(appdir)).iter() {
-        println!("Testing {}", crate_dir.to_string_lossy());
-        try!(cargo_command("test", &argsinfo.cargo_args, crate_dir));
-    };
-    Ok(())
+/// The rest of `priv-dirs`, which receive copies of what is installed into `priv_dir`
+fn copy_priv_dirs(argsinfo: &ArgsInfo, appdir: &Path) -> Vec<PathBuf> {
+    argsinfo.config.priv_dirs[1..].iter().map(|dir| appdir.join(dir)).collect()
 }
 
-/// Clean all crates, remote artifacts in `priv/`
-fn clean_crates(argsinfo: &ArgsInfo, appdir: &Path) -> Result<(), MsgError> {
-    // clean all crate dirs
-    for crate_dir in try!(enumerate_crate_dirs(appdir)).iter() {
-        println!("Cleaning {}", crate_dir.to_string_lossy());
-        try!(cargo_command("clean", &argsinfo.cargo_args, crate_dir));
+/// Application directories to process.  A directory with `crates/` is a single application;
+/// otherwise it is an umbrella whose applications live in the configured umbrella dirs.
+/// `apps` restricts the result to applications with those names.
+fn enumerate_app_dirs(appdir: &Path, config: &Config, apps: &[String]) -> Result<Vec<PathBuf>, MsgError> {
+    let mut app_dirs: Vec<PathBuf> = if appdir.join("crates").is_dir() {
+        vec![appdir.to_path_buf()]
+    } else {
+        config.umbrella_dirs.iter()
+            .filter_map(|dir| appdir.join(dir).read_dir().ok())
+            .flat_map(|entries| entries.filter_map(result::Result::ok))
+            .map(|entry| entry.path())
+            .filter(|path| path.join("crates").is_dir())
+            .collect()
     };
+    app_dirs.sort();
+    select_apps(&mut app_dirs, apps)?;
 
-    // clean priv/crates
-    let output_dir =  appdir.join("priv").join("crates");
-    remove_dir_all_force(output_dir).map_err(|err| MsgIo("can't delete output dir", err))
+    if app_dirs.is_empty() {
+        return Err(not_an_app(appdir));
+    }
+    Ok(app_dirs)
 }
 
-// Remove dir.  The dir being absent is not an error.
-fn remove_dir_all_force<P: AsRef<Path>>(path: P) -> io::Result<()> {
-
-    match fs::metadata(path.as_ref()) {
-        Err(err) => {
-            match err.kind() {
-                io::ErrorKind::NotFound => Ok(()),  // not finding is okay (already cleaned)
-                _ => Err(err),   // permission error on parent dir?
-            }
-        },
-        Ok(m) => {
-            match m.is_dir() {
-                true => fs::remove_dir_all(path),
-                false => Ok(()),
-            }
-        },
+/// Why `appdir` can't be processed: nothing has `crates/`, or it isn't an Erlang
+/// application.  Run from inside an application (from one
+/// of its crates, say), name the application directory instead of complaining about `crates/`.
+fn not_an_app(appdir: &Path) -> MsgError {
+    if scaffold::looks_like_erlang_app(appdir) {
+        return MsgDetail("no crates/ directory here or in umbrella applications",
+                         appdir.display().to_string()).category(Failure::Enumeration);
     }
+    let enclosing = appdir.ancestors().skip(1).find(|dir| dir.join("crates").is_dir() && scaffold::looks_like_erlang_app(dir));
+    match enclosing {
+        Some(dir) => MsgDetail("not an Erlang application directory; run from the application",
+                               dir.display().to_string()),
+        None => MsgDetail("not an Erlang application directory: expected src/<app>.app.src, rebar.config, mix.exs or erlang.mk",
+                          appdir.display().to_string()),
+    }.category(Failure::Enumeration)
 }
 
-fn cargo_command(cmd: &str, args: &[String], dir: &Path) -> Result<(), MsgError> {
-    process::Command::new("cargo")
-        .arg(cmd)
-        .args(args)
-        .current_dir(dir)
-        .status()
-        .map_err(|err| MsgIo("cannot start cargo", err))
-        .and_then(|status| {
-            match status.success() {
-                true => Ok(()),
-                false => Err(Msg("cargo command failed")),
+/// Restrict application directories to those named in `apps`, if any
+fn select_apps(app_dirs: &mut Vec<PathBuf>, apps: &[String]) -> Result<(), MsgError> {
+    if !apps.is_empty() {
+        for app in apps {
+            if !app_dirs.iter().any(|dir| dir.file_name().map(|n| n == app.as_str()).unwrap_or(false)) {
+                return Err(MsgDetail("no such application", app.clone()).category(Failure::Enumeration));
             }
-        })
+        }
+        app_dirs.retain(|dir| apps.iter().any(|app| dir.file_name().map(|n| n == app.as_str()).unwrap_or(false)));
+    }
+    Ok(())
 }
 
+/// Directories never searched by `--all`: build output, dependencies and hidden directories
+static SCAN_SKIPPED_DIRS: &[&str] = &["crates", "target", "_build", "deps", "node_modules"];
+
+/// Every Erlang or Elixir application with a `crates/` directory under `root`, for `--all`.
+/// An application's own subdirectories aren't searched further.
+fn scan_app_dirs(root: &Path, app_dirs: &mut Vec<PathBuf>) -> io::Result<()> {
+    if root.join("crates").is_dir() && scaffold::looks_like_erlang_app(root) {
+        app_dirs.push(root.to_path_buf());
+        return Ok(());
+    }
+    for entry in root.read_dir()? {
+        let entry = entry?;
+        let name = entry.file_name().to_string_lossy().into_owned();
+        if entry.file_type()?.is_dir() && !name.starts_with('.') && !SCAN_SKIPPED_DIRS.contains(&name.as_str()) {
+            scan_app_dirs(&entry.path(), app_dirs)?;
+        }
+    }
+    Ok(())
+}
 
-fn enumerate_crate_dirs(appdir: &Path) -> Result<Vec<PathBuf>, MsgError> {
+/// Directories in `appdir/crates` holding a `Cargo.toml`, in no particular order.  A
+/// directory with a virtual workspace manifest stands for its member packages.
+pub fn enumerate_crate_dirs(appdir: &Path) -> Result<Vec<PathBuf>, MsgError> {
 
     appdir
         .join("crates")              // :PathBuf
         .read_dir()                  // :Result<ReadDir>
         .map_err(|err|
-            MsgIo("Cannot read 'crates' directory", err)
+            MsgIo("Cannot read 'crates' directory", err).category(Failure::Enumeration)
         )
         .map(|dirs|
             dirs.filter_map(result::Result::ok)      // discard Error entries and unwrap
             .filter(is_crate)            // discard non-crate entries
             .map(|x| x.path())           // take whole path
+            .flat_map(|dir| workspace::virtual_members(&dir).unwrap_or_else(|| vec![dir]))
             .collect()
         )
 }
 
+/// The crate directories under `crates/` that `crates.include` and `crates.exclude` leave
+/// to process.  Every included directory must be a crate.
+fn selected_crate_dirs(appdir: &Path, config: &Config) -> Result<Vec<PathBuf>, MsgError> {
+    let crate_dirs = enumerate_crate_dirs(appdir)?;
+    let name = |dir: &Path| dir.file_name().map(|n| n.to_string_lossy().into_owned()).unwrap_or_default();
+    // workspace members are installed by their directory name, which must stay unique
+    for (i, dir) in crate_dirs.iter().enumerate() {
+        if let Some(other) = crate_dirs[i + 1..].iter().find(|other| name(other) == name(dir)) {
+            return Err(MsgDetail("two crates in crates/ share a name",
+                                 format!("{} and {}", dir.display(), other.display())).category(Failure::Enumeration));
+        }
+    }
+    if let Some(ref include) = config.crate_include {
+        if let Some(missing) = include.iter().find(|i| !crate_dirs.iter().any(|dir| name(dir) == **i)) {
+            return Err(MsgDetail("crates.include: no crate in crates/ named", missing.clone()).category(Failure::Enumeration));
+        }
+    }
+    Ok(crate_dirs.into_iter().filter(|dir| config.selects_crate(&name(dir))).collect())
+}
+
 fn is_crate(dirent: &DirEntry) -> bool {
     let mut toml_path = dirent.path();
     toml_path.push("Cargo.toml");
@@ -339,43 +2999,387 @@ fn is_crate(dirent: &DirEntry) -> bool {
         .unwrap_or(false)
 }
 
+/// Subcommands
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CargoCommand {
+    Build,
+    Test,
+    Bench,
+    Clippy,
+    Clean,
+    SmokeTest,
+    PruneArtifacts,
+    CheckOtp,
+    CheckSymbols,
+    Dist,
+    Verify,
+    Status,
+    Gc,
+    Du,
+    Outdated,
+    Policy,
+    Graph,
+    SyncDeps,
+    Codegen,
+    VersionBump,
+    Init,
+    New,
+    AddNif,
+    AddPort,
+    WorkspaceIfy,
+    Rollback,
+    SwitchProfile,
+    Ez,
+}
+
+/// Cargo profile selection.  `DefaultDebug` means none was given, so the rebar3 profile
+/// mapping may pick one.
+#[derive(Debug, Clone, PartialEq)]
+pub enum BuildType { Release, Debug, DefaultDebug, Profile(String) }
+
+/// Target kinds selected by `--only-nifs` and `--only-bins`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum OnlyKind { Nifs, Bins }
+
+impl OnlyKind {
+    fn includes(&self, target: &Target) -> bool {
+        match *self {
+            OnlyKind::Nifs => target.is_nif(),
+            OnlyKind::Bins => matches!(*target, Target::Bin(_)),
+        }
+    }
+}
+
+/// How `test` runs each crate's tests, selected by `--runner`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TestRunner {
+    /// `cargo test`
+    Cargo,
+    /// `cargo nextest run`, when cargo-nextest is installed
+    Nextest,
+}
+
+impl FromStr for TestRunner {
+    type Err = ();
+    fn from_str(s: &str) -> Result<TestRunner, ()> {
+        match s {
+            "cargo" => Ok(TestRunner::Cargo),
+            "nextest" => Ok(TestRunner::Nextest),
+            _ => Err(()),
+        }
+    }
+}
+
+impl BuildType {
+    /// Build type for a cargo profile name
+    fn from_profile(profile: &str) -> BuildType {
+        match profile {
+            "release" => BuildType::Release,
+            "dev" | "debug" => BuildType::Debug,
+            other => BuildType::Profile(other.to_string()),
+        }
+    }
+
+    /// Name of the profile, as recorded with the artifacts and for `--profile-dirs`
+    fn profile_name(&self) -> &str {
+        match *self {
+            BuildType::Release => "release",
+            BuildType::Profile(ref p) => p,
+            _ => "debug",
+        }
+    }
+
+    /// Name of the output directory under `target/`.  Cargo builds the `test` profile into
+    /// `debug/` and `bench` into `release/`.
+    fn dir_name(&self) -> &str {
+        match self.profile_name() {
+            "test" => "debug",
+            "bench" => "release",
+            name => name,
+        }
+    }
+}
+
+/// Options resolved against an application: its configuration, the applications to process
+/// and where they build
 #[derive(Debug)]
-enum CargoCommand { Build, Test, Clean }
-#[derive(Debug)]
-enum BuildType { Release, Debug, DefaultDebug }
-#[derive(Debug)]
-pub struct ArgsInfo {
-    command: CargoCommand,
-    target: Option<String>,
-    build_type: BuildType,
-    cargo_args: Vec<String>,
+struct ArgsInfo {
+    options: Options,
+    config: Config,
+    /// Applications to process, resolved from positional paths, umbrella dirs, `--all` and `--app`
+    app_dirs: Vec<PathBuf>,
+    /// Cargo target directory shared by all crates, if any
+    target_dir: Option<PathBuf>,
+    /// `<os>-<arch>` subdirectory artifacts go into, with per-platform directories
+    platform: Option<String>,
+    /// The build target's cfg values, once `[link]` needs them
+    target_cfg: OnceCell<Vec<(String, Option<String>)>>,
+    /// Erlang for NIF sys crates' build scripts, once located
+    erts: OnceCell<erts::Erts>,
+    /// `cargo metadata` of each crate directory, without and with dependencies, once read
+    metadata: RefCell<BTreeMap<(PathBuf, bool), metadata::Metadata>>,
+    output: Output,
 }
 
 impl ArgsInfo {
-    pub fn from_args(args: &[String]) -> Option<ArgsInfo> {
-        if args.len() < 2 {
-            return None;
+    fn new(options: Options) -> ArgsInfo {
+        let output = Output::new(options.hook_mode, options.quiet_cargo, options.json_messages);
+        ArgsInfo {
+            options,
+            config: Config::default(),
+            app_dirs: Vec::new(),
+            target_dir: None,
+            platform: None,
+            target_cfg: OnceCell::new(),
+            erts: OnceCell::new(),
+            metadata: RefCell::new(BTreeMap::new()),
+            output,
+        }
+    }
+
+    /// Load `erlangapp.toml` and resolve everything that depends on it or on `appdir`
+    fn resolve(&mut self, appdir: &Path) -> Result<(), MsgError> {
+        self.config = Config::load(appdir)?;
+        self.apply_rebar_profile();
+        // `[patch]` goes first, so `--config` on the command line can override it
+        let patches = self.config.patch_config(appdir);
+        self.options.cargo_config.splice(0..0, patches);
+
+        // an explicit --target wins over --windows-toolchain
+        if let (Some(toolchain), None) = (self.options.windows_toolchain, self.options.target.as_ref()) {
+            let host = windows::rust_host().ok_or(Msg("cannot determine the Rust host triple"))?;
+            let triple = toolchain.triple(&host);
+            self.options.cargo_args.insert(0, format!("--target={}", triple));
+            self.options.target = Some(triple);
+        }
+
+        match self.options.command {
+            CargoCommand::Build | CargoCommand::Test | CargoCommand::Bench | CargoCommand::Clippy | CargoCommand::Clean
+            | CargoCommand::SmokeTest | CargoCommand::PruneArtifacts | CargoCommand::Rollback
+            | CargoCommand::SwitchProfile | CargoCommand::CheckOtp | CargoCommand::CheckSymbols | CargoCommand::Dist
+            | CargoCommand::Ez | CargoCommand::Verify | CargoCommand::Status | CargoCommand::Gc
+            | CargoCommand::Du | CargoCommand::Outdated | CargoCommand::Policy | CargoCommand::Graph
+            | CargoCommand::SyncDeps | CargoCommand::Codegen | CargoCommand::VersionBump => (),
+            _ => return Ok(()),
+        }
+        self.apply_conditional()?;
+        self.apply_rustflags();
+        if self.options.both_windows_toolchains && self.options.windows_toolchain.is_some() {
+            // one platform directory per toolchain, which the platform module tells apart
+            let toolchain = self.options.windows_toolchain.map(|t| t.to_string()).unwrap_or_default();
+            self.platform = Some(format!("{}-{}", platform::platform_dir(&target_triple(self)?), toolchain));
+        } else if self.options.platform_dirs || self.config.platform_dirs {
+            self.platform = Some(platform::platform_dir(&target_triple(self)?));
+        }
+        let app_paths = self.take_app_paths(appdir);
+        if self.options.manifest_path.is_some() {
+            self.app_dirs = vec![appdir.to_path_buf()];
+        } else if self.options.scan_all {
+            let roots = if app_paths.is_empty() { vec![appdir.to_path_buf()] } else { app_paths };
+            for root in roots.iter() {
+                scan_app_dirs(root, &mut self.app_dirs)
+                    .map_err(|err| MsgIo("cannot search for applications", err).category(Failure::Enumeration))?;
+            }
+            self.app_dirs.sort();
+            self.app_dirs.dedup();
+            select_apps(&mut self.app_dirs, &self.options.apps)?;
+            if self.app_dirs.is_empty() {
+                return Err(Msg("--all: no applications with a crates/ directory found").category(Failure::Enumeration));
+            }
+        } else if app_paths.is_empty() {
+            self.app_dirs = enumerate_app_dirs(appdir, &self.config, &self.options.apps)?;
+        } else {
+            for path in app_paths.iter() {
+                let app_dirs = enumerate_app_dirs(path, &self.config, &self.options.apps)?;
+                self.app_dirs.extend(app_dirs);
+            }
         }
 
-        let build_type =
-        if find_option(args, "--release") { BuildType::Release }
-            else if find_option(args, "--debug") { BuildType::Debug }
-            else { BuildType::DefaultDebug };
+        // a crates/ directory alone isn't enough: don't build into something else's priv/
+        if self.options.manifest_path.is_none() {
+            if let Some(dir) = self.app_dirs.iter().find(|dir| !scaffold::looks_like_erlang_app(dir)) {
+                return Err(not_an_app(dir));
+            }
+        }
 
-        Some(ArgsInfo {
-            command: otry!(parse_cmd_name(args[1].as_str())),
-            target: find_option_value(&args[2..], "--target").map(Into::into),
-            build_type: build_type,
-            cargo_args: args[2..].into_iter().cloned().collect(),
-        })
+        // share one target directory when several applications are built
+        self.target_dir = match self.config.env.var("CARGO_TARGET_DIR") {
+            Some(dir) => Some(appdir.join(dir)),
+            None if self.app_dirs.len() > 1 => Some(appdir.join("target")),
+            None => None,
+        };
+        if self.options.verbose() {
+            self.output.status("Cargo environment:");
+            for line in self.config.env.describe() {
+                self.output.status(&format!("    {}", line));
+            }
+        }
+        Ok(())
+    }
+
+    /// How long to wait for the application lock: `--lock-timeout`, else `lock-timeout` in
+    /// `erlangapp.toml`
+    fn lock_timeout(&self) -> Duration {
+        self.options.lock_timeout.or(self.config.lock_timeout).unwrap_or(lock::DEFAULT_TIMEOUT)
+    }
+
+    /// Artifact retention policy: `--keep-artifacts`, else `keep-artifacts` in `erlangapp.toml`
+    fn keep_artifacts(&self) -> Option<usize> {
+        self.options.keep_artifacts.or(self.config.keep_artifacts)
+    }
+
+    /// `--backup`, or `backup` in `erlangapp.toml`
+    fn backup(&self) -> bool {
+        self.options.backup || self.config.backup
+    }
+
+    /// `--profile-dirs`, or `profile-dirs` in `erlangapp.toml`
+    fn profile_dirs(&self) -> bool {
+        self.options.profile_dirs || self.config.profile_dirs
+    }
+
+    /// `--deny-warnings`, or `deny-warnings` in `erlangapp.toml`
+    fn deny_warnings(&self) -> bool {
+        self.options.deny_warnings || self.config.deny_warnings
+    }
+
+    /// Add the `[lints]` levels and `-D warnings` to rustc's flags in every cargo invocation:
+    /// to the flags variable cargo will see, or without one to `build.rustflags`.  That is set
+    /// through `CARGO_BUILD_RUSTFLAGS`, which keeps the flags of cargo's config files and,
+    /// unlike `--config`, reaches the cargo run by `cargo clippy`.
+    fn apply_rustflags(&mut self) {
+        let mut flags = self.config.lint_flags();
+        if self.deny_warnings() {
+            flags.push("-Dwarnings".to_string());
+        }
+        if flags.is_empty() {
+            return;
+        }
+        let env = &mut self.config.env;
+        if let Some(encoded) = env.var("CARGO_ENCODED_RUSTFLAGS") {
+            let all: Vec<&str> = encoded.split('\x1f').filter(|f| !f.is_empty()).chain(flags.iter().map(|f| f.as_str())).collect();
+            env.set.insert("CARGO_ENCODED_RUSTFLAGS".to_string(), all.join("\x1f"));
+        } else {
+            let name = if env.var("RUSTFLAGS").is_some() { "RUSTFLAGS" } else { "CARGO_BUILD_RUSTFLAGS" };
+            let rustflags = env.var(name).unwrap_or_default();
+            env.set.insert(name.to_string(), format!("{} {}", rustflags, flags.join(" ")).trim_start().to_string());
+        }
+    }
+
+    /// Remove positional arguments naming applications (before any `--`, and not the value
+    /// of a cargo option): directories with `crates/` or that look like an Erlang
+    /// application, or with `--all` any directory to search
+    fn take_app_paths(&mut self, appdir: &Path) -> Vec<PathBuf> {
+        let end = self.options.cargo_args.iter().position(|x| x == "--").unwrap_or(self.options.cargo_args.len());
+        let rest = self.options.cargo_args.split_off(end);
+        let scan_all = self.options.scan_all;
+        let is_app_path = |dir: &Path| dir.is_dir()
+            && (scan_all || dir.join("crates").is_dir() || scaffold::looks_like_erlang_app(dir));
+        let mut paths = Vec::new();
+        let mut args = Vec::new();
+        let mut option_value = false;
+        for arg in self.options.cargo_args.drain(..) {
+            let positional = !option_value && !arg.starts_with('-');
+            option_value = suggest::CARGO_VALUE_OPTIONS.contains(&arg.as_str());
+            match positional && is_app_path(&appdir.join(&arg)) {
+                true => paths.push(appdir.join(arg)),
+                false => args.push(arg),
+            }
+        }
+        args.extend(rest);
+        self.options.cargo_args = args;
+        paths
+    }
+
+    /// Apply the `[target.'cfg(...)']` sections of `erlangapp.toml` that match the build target
+    fn apply_conditional(&mut self) -> Result<(), MsgError> {
+        if self.config.conditional.is_empty() {
+            return Ok(());
+        }
+        let target_cfg = link::target_cfg(self.options.target.as_deref())
+            .ok_or(Msg("cannot read the target's cfg values from rustc --print cfg").category(Failure::Compile))?;
+        let matching: Vec<config::Conditional> = self.config.conditional.iter()
+            .filter(|c| c.cfg.matches(&target_cfg))
+            .cloned()
+            .collect();
+        let _ = self.target_cfg.set(target_cfg);
+        let mut features = Vec::new();
+        for conditional in matching {
+            self.config.env.set.extend(conditional.env);
+            features.extend(conditional.features);
+            if let Some(platform_dirs) = conditional.platform_dirs {
+                self.config.platform_dirs = platform_dirs;
+            }
+            if let Some(versioned_artifacts) = conditional.versioned_artifacts {
+                self.config.versioned_artifacts = versioned_artifacts;
+            }
+        }
+        let compiles = matches!(self.options.command, CargoCommand::Build | CargoCommand::Test | CargoCommand::Bench
+                                | CargoCommand::Clippy | CargoCommand::SmokeTest | CargoCommand::Dist
+                                | CargoCommand::Ez);
+        if !features.is_empty() && compiles {
+            self.options.cargo_args.insert(0, format!("--features={}", features.join(",")));
+        }
+        Ok(())
+    }
+
+    /// Without an explicit cargo profile, select one from the rebar3 profile mapping
+    fn apply_rebar_profile(&mut self) {
+        if let BuildType::DefaultDebug = self.options.build_type {
+            let profile = match self.options.rebar_profile {
+                Some(ref rebar_profile) => self.config.cargo_profile_for_rebar(rebar_profile),
+                None => None,
+            };
+            if let Some(profile) = profile {
+                self.options.build_type = BuildType::from_profile(profile);
+                match self.options.build_type {
+                    BuildType::Release => self.options.cargo_args.insert(0, "--release".to_string()),
+                    BuildType::Profile(ref p) => self.options.cargo_args.insert(0, format!("--profile={}", p)),
+                    _ => (),
+                }
+            }
+        }
     }
 }
 
+/// Subcommand names, for suggestions
+const COMMAND_NAMES: &[&str] = &[
+    "build", "test", "bench", "clippy", "clean", "smoke-test", "prune-artifacts", "check-otp", "check-symbols", "dist",
+    "verify", "status", "gc", "du", "outdated", "policy", "graph", "sync-deps", "codegen", "version-bump",
+    "init", "new", "add-nif", "add-port", "workspace-ify", "rollback",
+    "switch-profile", "ez",
+];
+
 fn parse_cmd_name(arg: &str) -> Option<CargoCommand> {
     match arg {
         "build" => Some(CargoCommand::Build),
         "test" => Some(CargoCommand::Test),
+        "bench" => Some(CargoCommand::Bench),
+        "clippy" => Some(CargoCommand::Clippy),
         "clean" => Some(CargoCommand::Clean),
+        "smoke-test" => Some(CargoCommand::SmokeTest),
+        "prune-artifacts" => Some(CargoCommand::PruneArtifacts),
+        "check-otp" => Some(CargoCommand::CheckOtp),
+        "check-symbols" => Some(CargoCommand::CheckSymbols),
+        "dist" => Some(CargoCommand::Dist),
+        "verify" => Some(CargoCommand::Verify),
+        "status" => Some(CargoCommand::Status),
+        "gc" => Some(CargoCommand::Gc),
+        "du" => Some(CargoCommand::Du),
+        "outdated" => Some(CargoCommand::Outdated),
+        "policy" => Some(CargoCommand::Policy),
+        "graph" => Some(CargoCommand::Graph),
+        "sync-deps" => Some(CargoCommand::SyncDeps),
+        "codegen" => Some(CargoCommand::Codegen),
+        "version-bump" => Some(CargoCommand::VersionBump),
+        "init" => Some(CargoCommand::Init),
+        "new" => Some(CargoCommand::New),
+        "add-nif" => Some(CargoCommand::AddNif),
+        "add-port" => Some(CargoCommand::AddPort),
+        "workspace-ify" => Some(CargoCommand::WorkspaceIfy),
+        "rollback" => Some(CargoCommand::Rollback),
+        "switch-profile" => Some(CargoCommand::SwitchProfile),
+        "ez" => Some(CargoCommand::Ez),
         _ => None,
     }
 }
@@ -384,20 +3388,47 @@ fn find_option(args: &[String], key: &str) -> bool {
     args.iter().any(|x| **x == *key)
 }
 
+/// Remove a tool option ("key=value" or "key value") appearing before any `--` from args,
+/// returning its value
+fn take_option_value(args: &mut Vec<String>, key: &str) -> Option<String> {
+    let end = args.iter().position(|x| x == "--").unwrap_or(args.len());
+    let prefix = format!("{}=", key);
+    let i = args[..end].iter().position(|x| x == key || x.starts_with(&prefix))?;
+    if args[i] == key {
+        if i + 1 >= end {
+            args.remove(i);
+            return None;
+        }
+        args.remove(i);
+        Some(args.remove(i))
+    } else {
+        Some(args.remove(i)[prefix.len()..].to_string())
+    }
+}
+
+/// Remove a tool flag appearing before any `--` from args, returning whether it was present
+fn take_flag(args: &mut Vec<String>, key: &str) -> bool {
+    let end = args.iter().position(|x| x == "--").unwrap_or(args.len());
+    match args[..end].iter().position(|x| x == key) {
+        Some(i) => { args.remove(i); true },
+        None => false,
+    }
+}
+
 /// Search args for "key=value", "key= value", "key =value", or "key = value"
 pub fn find_option_value(args: &[String], key: &str) -> Option<String> {
     let mut i = args.iter();
     loop {
-        let arg0 = otry!(i.next());
+        let arg0 = i.next()?;
         if arg0.starts_with(key) {
             // check 'key=value'
             match arg0.split('=').nth(1) { // try to get "value"
-                Some("") => return i.next().map(Clone::clone), // "key= value"
+                Some("") => return i.next().cloned(), // "key= value"
                 Some(x) => return Some(x.to_string()), // "key=value"
                 None => {
                     if **arg0 == *key { // "key =.."
-                        let arg1 = otry!(i.next());
-                        if **arg1 == *"=" { return i.next().map(Clone::clone) } // "key = value"
+                        let arg1 = i.next()?;
+                        if **arg1 == *"=" { return i.next().cloned() } // "key = value"
                         if arg1.starts_with('=') {
                             return arg1.split('=').nth(1).map(From::from) // "key =value"
                         }
@@ -414,10 +3445,192 @@ mod tests {
     use super::*;
 
     fn find_option_value_wrapper(args: &[&str], key: &str) -> Option<String> {
-        let argsv: Vec<String> = args.into_iter().cloned().map(From::from).collect();
+        let argsv: Vec<String> = args.iter().cloned().map(From::from).collect();
         find_option_value(&argsv, key)
     }
 
+    #[test]
+    fn test_check_passed_options() {
+        assert!(COMMAND_NAMES.iter().all(|name| parse_cmd_name(name).is_some()));
+        let options = |args: &[&str]| args.iter().fold(Options::new(CargoCommand::Build), |options, arg| options.cargo_arg(arg));
+        assert!(check_passed_options(&options(&["--release", "--features=x", "-v", "--", "--realease"])).is_ok());
+        assert!(check_passed_options(&options(&["--some-cargo-option"])).is_ok());
+        assert!(check_passed_options(&options(&["--fix", "--allow-dirty", "--allow-staged", "--no-deps"])).is_ok());
+        let err = check_passed_options(&options(&["--realease", "-v"])).unwrap_err();
+        assert_eq!("unknown option (--realease, did you mean --release? Passed on to cargo: --realease -v)", err.to_string());
+    }
+
+    #[test]
+    fn test_take_app_paths() {
+        let appdir = std::env::temp_dir().join(format!("erlangapp-app-paths-{}", process::id()));
+        for dir in ["myapp/crates", "target", "src"].iter() {
+            fs::create_dir_all(appdir.join(dir)).unwrap();
+        }
+        let take = |command: CargoCommand, args: &[&str]| {
+            let options = args.iter().fold(Options::new(command), |options, arg| options.cargo_arg(arg));
+            let mut argsinfo = ArgsInfo::new(options);
+            let paths = argsinfo.take_app_paths(&appdir);
+            (paths, argsinfo.options.cargo_args)
+        };
+        let strings = |args: &[&str]| args.iter().map(|arg| arg.to_string()).collect::<Vec<_>>();
+        assert_eq!((vec![appdir.join("myapp")], strings(&["--release"])), take(CargoCommand::Build, &["myapp", "--release"]));
+        assert_eq!((vec![], strings(&["--target-dir", "target"])), take(CargoCommand::Build, &["--target-dir", "target"]));
+        assert_eq!((vec![], strings(&["target"])), take(CargoCommand::Build, &["target"]));
+        assert_eq!((vec![], strings(&["--", "src"])), take(CargoCommand::Test, &["--", "src"]));
+        fs::remove_dir_all(appdir).unwrap();
+    }
+
+    #[test]
+    fn test_build_type_dir_name() {
+        let dir_name = |profile: &str| BuildType::from_profile(profile).dir_name().to_string();
+        assert_eq!("debug", dir_name("dev"));
+        assert_eq!("debug", dir_name("test"));
+        assert_eq!("release", dir_name("bench"));
+        assert_eq!("release-lto", dir_name("release-lto"));
+        assert_eq!("bench", BuildType::from_profile("bench").profile_name());
+    }
+
+    #[test]
+    fn test_is_profile_name() {
+        assert!(is_profile_name("release") && is_profile_name("release-lto") && is_profile_name("my_prof"));
+        assert!(!is_profile_name("") && !is_profile_name("..") && !is_profile_name("../x") && !is_profile_name("a/b"));
+    }
+
+    #[test]
+    fn test_versioned_filename() {
+        assert_eq!("libmynif-1.4.2.so", versioned_filename("libmynif.so", "1.4.2"));
+        assert_eq!("mynif-0.1.0-rc.1.dll", versioned_filename("mynif.dll", "0.1.0-rc.1"));
+    }
+
+    #[test]
+    fn test_target_from_metadata() {
+        let target = |kind: &[&str]| metadata::Target {
+            name: "x".into(),
+            kind: kind.iter().map(|k| k.to_string()).collect(),
+            required_features: Vec::new(),
+        };
+        assert_eq!(vec![Target::Cdylib("x".into()), Target::Staticlib("x".into())],
+                   Target::from_metadata(&target(&["cdylib", "staticlib"]), false));
+        assert_eq!(vec![Target::Driver("x".into())], Target::from_metadata(&target(&["cdylib"]), true));
+        assert_eq!(vec![Target::Example("x".into())], Target::from_metadata(&target(&["example"]), false));
+        assert!(Target::from_metadata(&target(&["lib"]), false).is_empty());
+    }
+
+    #[test]
+    fn test_crate_cargo_args() {
+        let mut argsinfo = ArgsInfo::new(Options::new(CargoCommand::Build));
+        argsinfo.options.cargo_args = ["--target=aarch64-unknown-linux-gnu", "--release", "--", "--target", "x"].iter().map(|s| s.to_string()).collect();
+        argsinfo.options.target = Some("aarch64-unknown-linux-gnu".to_string());
+        let mut krate = Crate::from_dir(PathBuf::from("crates/gen"));
+        assert_eq!(argsinfo.options.cargo_args, crate_cargo_args(&argsinfo, &krate));
+        krate.target = Some(TargetOverride::Host);
+        assert_eq!(None, crate_target(&argsinfo, &krate));
+        assert_eq!(vec!["--release", "--", "--target", "x"], crate_cargo_args(&argsinfo, &krate));
+        assert!(is_host_tool(&argsinfo, &krate));
+        assert_eq!(Path::new("app/priv/host-tools/gen"), crate_priv_dir(&argsinfo, Path::new("app"), &krate));
+        krate.target = Some(TargetOverride::Triple("wasm32-wasip1".to_string()));
+        assert_eq!(vec!["--target=wasm32-wasip1", "--release", "--", "--target", "x"], crate_cargo_args(&argsinfo, &krate));
+    }
+
+    #[test]
+    fn test_orphaned_crate_dirs() {
+        let appdir = std::env::temp_dir().join(format!("erlangapp-orphans-{}", process::id()));
+        fs::create_dir_all(appdir.join("crates/mynif/src")).unwrap();
+        fs::write(appdir.join("crates/mynif/Cargo.toml"), "[package]\nname = \"mynif\"\n").unwrap();
+        fs::create_dir_all(appdir.join("crates/skipped/src")).unwrap();
+        fs::write(appdir.join("crates/skipped/Cargo.toml"), "[package]\nname = \"skipped\"\n").unwrap();
+        fs::write(appdir.join(config::CONFIG_FILE), "[crates]\nexclude = [\"skipped\"]\n").unwrap();
+        for dir in ["priv/crates/mynif", "priv/crates/oldnif", "priv/crates/skipped", "priv/host-tools/gen/x86_64-linux"].iter() {
+            fs::create_dir_all(appdir.join(dir)).unwrap();
+            fs::write(appdir.join(dir).join(verify::MANIFEST_FILE), "{}").unwrap();
+        }
+        fs::create_dir_all(appdir.join("priv/crates/handmade")).unwrap();
+        let mut argsinfo = ArgsInfo::new(Options::new(CargoCommand::Build));
+        argsinfo.config = Config::load(&appdir).unwrap();
+        assert_eq!(vec![appdir.join("priv/crates/oldnif"), appdir.join("priv/host-tools/gen")],
+                   orphaned_crate_dirs(&argsinfo, &appdir).unwrap());
+        fs::remove_dir_all(appdir).unwrap();
+    }
+
+    #[test]
+    fn test_manifest_uninstallable() {
+        let manifest = |targets: Vec<Target>, kinds: &[&str]| Manifest {
+            version: "0.1.0".into(),
+            targets,
+            skipped: Vec::new(),
+            enabled: Vec::new(),
+            kinds: kinds.iter().map(|k| k.to_string()).collect(),
+            features: BTreeSet::new(),
+        };
+        assert_eq!(Some(&["proc-macro".to_string()][..]), manifest(Vec::new(), &["proc-macro"]).uninstallable(false));
+        let staticlib = manifest(vec![Target::Staticlib("x".into())], &["staticlib"]);
+        assert!(staticlib.uninstallable(false).is_some() && staticlib.uninstallable(true).is_none());
+        assert!(manifest(vec![Target::Bin("x".into())], &["lib", "bin"]).uninstallable(false).is_none());
+        assert!(manifest(Vec::new(), &[]).uninstallable(false).is_none());
+    }
+
+    #[test]
+    fn test_elixir_filename() {
+        assert_eq!("Elixir.MyApp.Native.so", elixir_filename("MyApp.Native", Naming::Unix));
+        assert_eq!("Elixir.MyApp.Native.so", elixir_filename("Elixir.MyApp.Native", Naming::Apple));
+        assert_eq!("Elixir.MyApp.Native.dll", elixir_filename("MyApp.Native", Naming::of(Some("x86_64-pc-windows-msvc"))));
+    }
+
+    #[test]
+    fn test_target_filenames_for() {
+        let nif = Target::Cdylib("mynif".into());
+        assert_eq!(("libmynif.so".to_string(), "libmynif.dylib".to_string()), target_filenames_for(&nif, "aarch64-apple-darwin"));
+        assert_eq!(("libmynif.so".to_string(), "libmynif.dylib".to_string()), target_filenames_for(&nif, "x86_64-apple-darwin"));
+        assert_eq!(("libmynif.so".to_string(), "libmynif.so".to_string()), target_filenames_for(&nif, "aarch64-unknown-linux-gnu"));
+        assert_eq!(("mynif.dll".to_string(), "mynif.dll".to_string()), target_filenames_for(&nif, "aarch64-pc-windows-msvc"));
+        assert_eq!(("mydrv.so".to_string(), "libmydrv.dylib".to_string()),
+                   target_filenames_for(&Target::Driver("mydrv".into()), "aarch64-apple-darwin"));
+        assert_eq!(("myport".to_string(), "myport".to_string()), target_filenames_for(&Target::Bin("myport".into()), "aarch64-apple-darwin"));
+        assert_eq!(("libemb.a".to_string(), "libemb.a".to_string()),
+                   target_filenames_for(&Target::Staticlib("emb".into()), "aarch64-apple-darwin"));
+        assert_eq!(("mynif.wasm".to_string(), "mynif.wasm".to_string()), target_filenames_for(&nif, "wasm32-unknown-unknown"));
+        assert_eq!(("myport.wasm".to_string(), "myport.wasm".to_string()), target_filenames_for(&Target::Bin("myport".into()), "wasm32-wasip1"));
+    }
+
+    #[test]
+    fn test_native_static_libs() {
+        assert_eq!(None, native_static_libs("   Compiling foo v0.1.0\n"));
+        assert_eq!(Some("-lgcc_s -lc".to_string()),
+                   native_static_libs("note: Link against the following native artifacts\nnote: native-static-libs: -lgcc_s -lc\n"));
+        assert_eq!(Some("-lutil".to_string()),
+                   native_static_libs("{\"rendered\":\"note: native-static-libs: -lutil\\n\"}"));
+    }
+
+    #[test]
+    fn test_warning_counts() {
+        let text = "\x1b[1m\x1b[33mwarning\x1b[0m\x1b[1m: `mynif` (lib) generated 2 warnings (run `cargo fix`)\n\
+                    warning: `helper` (lib) generated 1 warning\n    Finished `dev` profile\n";
+        assert_eq!(3, warning_counts(text).values().sum::<usize>());
+        let json = "{\"reason\":\"compiler-message\",\"package_id\":\"mynif 0.1.0\",\"target\":{\"name\":\"mynif\"},\"message\":{\"level\":\"warning\"}}\n\
+                    {\"reason\":\"compiler-message\",\"package_id\":\"mynif 0.1.0\",\"target\":{\"name\":\"mynif\"},\"message\":{\"level\":\"error\"}}\n";
+        assert_eq!(vec![("mynif 0.1.0 mynif".to_string(), 1)], warning_counts(json).into_iter().collect::<Vec<_>>());
+        assert!(warning_counts("   Compiling foo v0.1.0\n").is_empty());
+    }
+
+    #[test]
+    fn test_merge_rustc_args() {
+        let strings = |args: &[&str]| args.iter().map(|s| s.to_string()).collect::<Vec<String>>();
+        assert_eq!(strings(&["--release"]), merge_rustc_args(&strings(&["--release"]), &[], &[]));
+        assert_eq!(strings(&["--release", "--", "--codegen", "link-args=x", "-v", "-C", "opt-level=1"]),
+                   merge_rustc_args(&strings(&["--release", "--", "-v"]), &strings(&["--codegen", "link-args=x"]),
+                                    &strings(&["-C", "opt-level=1"])));
+    }
+
+    #[test]
+    fn test_take_option_value() {
+        let mut args: Vec<String> = vec!["--a=1".into(), "--b".into(), "2".into(), "--".into(), "--c=3".into()];
+        assert_eq!(Some("1".to_string()), take_option_value(&mut args, "--a"));
+        assert_eq!(Some("2".to_string()), take_option_value(&mut args, "--b"));
+        assert_eq!(None, take_option_value(&mut args, "--c"));
+        assert!(!take_flag(&mut args, "--c=3"));
+        assert_eq!(vec!["--".to_string(), "--c=3".to_string()], args);
+    }
+
     #[test]
     fn test_find_option_value() {
         assert_eq!(None, find_option_value_wrapper(&[], "key"));
@@ -433,4 +3646,35 @@ mod tests {
         assert_eq!(None, find_option_value_wrapper(&["key"], "key"));
         assert_eq!(None, find_option_value_wrapper(&["key="], "key"));
     }
+
+    #[test]
+    fn test_command_failure() {
+        let mut command = process::Command::new("cargo");
+        command.args(["rustc", "--lib", "--", "--codegen", "link-args=-flat_namespace -undefined suppress"]);
+        assert_eq!("cargo rustc --lib -- --codegen 'link-args=-flat_namespace -undefined suppress'", command_line(&command));
+
+        let output = "{\"reason\":\"compiler-message\"}\n\x1b[1m\x1b[31merror\x1b[0m: oops\n\nline a\nline b\n";
+        assert_eq!(vec!["... (1 earlier lines)", "line a", "line b"], output_excerpt(output, 2));
+        assert_eq!(vec!["error: oops", "line a", "line b"], output_excerpt(output, 20));
+    }
+    #[cfg(unix)]
+    #[test]
+    fn test_exit_status() {
+        let mut command = process::Command::new("sh");
+        command.args(["-c", "exit 101"]);
+        let status = command.status().unwrap();
+        let failure = CommandFailure::new(&command, "error: could not compile `mynif`".to_string()).exited(status);
+        assert!(format!("{}", failure).contains("exit status: 101"));
+        let err = MsgCommand("cargo command failed", failure).test_category();
+        assert_eq!(Some(101), err.exit_status().and_then(|status| status.code()));
+        assert_eq!(Failure::Compile as i32, err.exit_code());
+        let err = MsgCommand("cargo command failed", CommandFailure::new(&command, "test result: FAILED".to_string()).exited(status));
+        assert_eq!(Failure::Test as i32, err.test_category().exit_code());
+
+        let mut command = process::Command::new("sh");
+        command.args(["-c", "kill -9 $$"]);
+        let status = command.status().unwrap();
+        let err = MsgCommand("cargo command failed", CommandFailure::new(&command, String::new()).exited(status)).test_category();
+        assert_eq!(137, err.exit_code());
+    }
 }
@@ -0,0 +1,191 @@
+//! Extra rustc arguments per target kind and platform, from `[link]` in `erlangapp.toml`.
+//!
+//! Kind keys directly under `[link]` apply to every build; those under a `cfg(...)` key
+//! apply when the build target matches, as in cargo's `[target.'cfg(...)']`.  The target's
+//! cfg values come from `rustc --print cfg`, so cross builds match their target, not the
+//! host.  Built-in defaults (the macOS NIF link arguments) come first, unless `defaults =
+//! false`.
+
+use std::env;
+use std::process;
+
+/// Which targets an entry applies to
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Kind {
+    /// `nif`: dylib and cdylib targets loaded as NIFs
+    Nif,
+    /// `driver`: port driver libraries
+    Driver,
+    /// `bin`: port programs
+    Bin,
+    Staticlib,
+}
+
+impl Kind {
+    pub fn from_key(key: &str) -> Option<Kind> {
+        match key {
+            "nif" => Some(Kind::Nif),
+            "driver" => Some(Kind::Driver),
+            "bin" => Some(Kind::Bin),
+            "staticlib" => Some(Kind::Staticlib),
+            _ => None,
+        }
+    }
+}
+
+/// Arguments for one kind of target, on targets matching `cfg` if present
+#[derive(Debug, Clone, PartialEq)]
+pub struct Entry {
+    pub cfg: Option<Cfg>,
+    pub kind: Kind,
+    pub args: Vec<String>,
+}
+
+/// Entries applied before any from `erlangapp.toml`.  Without them the macOS linker fails on
+/// NIF API calls, which are resolved when the VM loads the library.  Only those symbols are
+/// left for lookup at load time, and the two-level namespace that current toolchains and
+/// Apple Silicon expect is kept.
+pub fn defaults() -> Vec<Entry> {
+    let macos = Cfg::parse("cfg(target_os = \"macos\")");
+    [Kind::Nif, Kind::Driver].iter()
+        .map(|kind| Entry {
+            cfg: macos.clone(),
+            kind: *kind,
+            args: ["-C", "link-arg=-undefined", "-C", "link-arg=dynamic_lookup"].iter().map(|s| s.to_string()).collect(),
+        })
+        .collect()
+}
+
+/// Static C runtime for port programs on Windows (`--static-crt`), so target machines need
+/// no VC++ redistributable
+pub fn static_crt() -> Entry {
+    Entry {
+        cfg: Cfg::parse("cfg(windows)"),
+        kind: Kind::Bin,
+        args: vec!["-C".to_string(), "target-feature=+crt-static".to_string()],
+    }
+}
+
+/// Arguments of the entries for `kind` matching the target, described by its cfg values
+pub fn args(entries: &[Entry], kind: Kind, target_cfg: &[(String, Option<String>)]) -> Vec<String> {
+    entries.iter()
+        .filter(|e| e.kind == kind && e.cfg.as_ref().map(|cfg| cfg.matches(target_cfg)).unwrap_or(true))
+        .flat_map(|e| e.args.iter().cloned())
+        .collect()
+}
+
+/// The target's cfg values, from `rustc --print cfg`
+pub fn target_cfg(triple: Option<&str>) -> Option<Vec<(String, Option<String>)>> {
+    let mut cmd = process::Command::new(env::var_os("RUSTC").unwrap_or_else(|| "rustc".into()));
+    cmd.args(["--print", "cfg"]);
+    if let Some(triple) = triple {
+        cmd.args(["--target", triple]);
+    }
+    let output = cmd.output().ok().filter(|output| output.status.success())?;
+    Some(parse_cfg_values(&String::from_utf8_lossy(&output.stdout)))
+}
+
+/// `name` and `name="value"` lines
+fn parse_cfg_values(text: &str) -> Vec<(String, Option<String>)> {
+    text.lines()
+        .filter(|line| !line.trim().is_empty())
+        .map(|line| match line.split_once('=') {
+            Some((name, value)) => (name.trim().to_string(), Some(value.trim().trim_matches('"').to_string())),
+            None => (line.trim().to_string(), None),
+        })
+        .collect()
+}
+
+/// A `cfg(...)` predicate
+#[derive(Debug, Clone, PartialEq)]
+pub enum Cfg {
+    Name(String),
+    Value(String, String),
+    All(Vec<Cfg>),
+    Any(Vec<Cfg>),
+    Not(Box<Cfg>),
+}
+
+impl Cfg {
+    /// Parse `cfg(<predicate>)`
+    pub fn parse(text: &str) -> Option<Cfg> {
+        let inner = text.trim().strip_prefix("cfg(")?.strip_suffix(')')?;
+        let (cfg, rest) = predicate(inner.trim_start())?;
+        match rest.trim().is_empty() {
+            true => Some(cfg),
+            false => None,
+        }
+    }
+
+    pub fn matches(&self, target_cfg: &[(String, Option<String>)]) -> bool {
+        match *self {
+            Cfg::Name(ref name) => target_cfg.iter().any(|(n, v)| n == name && v.is_none()),
+            Cfg::Value(ref name, ref value) => target_cfg.iter().any(|(n, v)| n == name && v.as_ref() == Some(value)),
+            Cfg::All(ref cfgs) => cfgs.iter().all(|cfg| cfg.matches(target_cfg)),
+            Cfg::Any(ref cfgs) => cfgs.iter().any(|cfg| cfg.matches(target_cfg)),
+            Cfg::Not(ref cfg) => !cfg.matches(target_cfg),
+        }
+    }
+}
+
+/// One predicate at the start of `text`, and the text after it
+fn predicate(text: &str) -> Option<(Cfg, &str)> {
+    let end = text.find(|c: char| !(c.is_alphanumeric() || c == '_')).unwrap_or(text.len());
+    let (name, rest) = (&text[..end], text[end..].trim_start());
+    if name.is_empty() {
+        return None;
+    }
+    if let Some(rest) = rest.strip_prefix('(') {
+        let mut cfgs = Vec::new();
+        let mut rest = rest.trim_start();
+        while !rest.starts_with(')') {
+            let (cfg, after) = predicate(rest)?;
+            cfgs.push(cfg);
+            rest = after.trim_start();
+            rest = rest.strip_prefix(',').unwrap_or(rest).trim_start();
+        }
+        let rest = &rest[1..];
+        return match name {
+            "all" => Some((Cfg::All(cfgs), rest)),
+            "any" => Some((Cfg::Any(cfgs), rest)),
+            "not" if cfgs.len() == 1 => Some((Cfg::Not(Box::new(cfgs.remove(0))), rest)),
+            _ => None,
+        };
+    }
+    if let Some(rest) = rest.strip_prefix('=') {
+        let value = rest.trim_start().strip_prefix('"')?;
+        let close = value.find('"')?;
+        return Some((Cfg::Value(name.to_string(), value[..close].to_string()), &value[close + 1..]));
+    }
+    Some((Cfg::Name(name.to_string()), rest))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cfg() {
+        let linux = parse_cfg_values("unix\ntarget_os=\"linux\"\ntarget_env=\"gnu\"\ntarget_arch=\"x86_64\"\n");
+        let macos = parse_cfg_values("unix\ntarget_os=\"macos\"\ntarget_arch=\"aarch64\"\ntarget_vendor=\"apple\"\n");
+        let matches = |cfg: &str, target: &[(String, Option<String>)]| Cfg::parse(cfg).unwrap().matches(target);
+        assert!(matches("cfg(unix)", &linux));
+        assert!(!matches("cfg(windows)", &linux));
+        assert!(matches("cfg(target_os = \"linux\")", &linux));
+        assert!(matches("cfg(all(unix, not(target_os = \"macos\")))", &linux));
+        assert!(!matches("cfg(all(unix, not(target_os = \"macos\")))", &macos));
+        assert!(matches("cfg(any(target_env = \"musl\", target_arch = \"aarch64\"))", &macos));
+        assert_eq!(None, Cfg::parse("cfg(target_os = \"linux\""));
+        assert_eq!(None, Cfg::parse("cfg(not(unix, windows))"));
+        assert_eq!(None, Cfg::parse("target_os = \"linux\""));
+
+        let defaults = defaults();
+        assert_eq!(vec!["-C", "link-arg=-undefined", "-C", "link-arg=dynamic_lookup"], args(&defaults, Kind::Nif, &macos));
+        assert_eq!(4, args(&defaults, Kind::Driver, &macos).len());
+        assert!(args(&defaults, Kind::Nif, &linux).is_empty());
+        assert!(args(&defaults, Kind::Bin, &macos).is_empty());
+        let windows = parse_cfg_values("windows\ntarget_os=\"windows\"\ntarget_env=\"msvc\"\n");
+        assert_eq!(vec!["-C", "target-feature=+crt-static"], args(&[static_crt()], Kind::Bin, &windows));
+        assert!(args(&[static_crt()], Kind::Bin, &linux).is_empty());
+    }
+}
@@ -0,0 +1,105 @@
+//! Per-application lock, so concurrent invocations (rebar3 compiling apps in parallel, an
+//! editor hook, CI) don't interleave installs and cleans in the same `priv/crates`.
+//!
+//! The lock is an advisory lock on `_build/cargo-erlangapp.lock`, released when the process
+//! exits however it exits.  The file itself is left in place: deleting it would let a
+//! waiting process lock a file no one else can see.  It holds the owner's pid, for messages.
+
+use std::fs::{self, File, OpenOptions, TryLockError};
+use std::io::{self, Read, Seek, Write};
+use std::path::{Path, PathBuf};
+use std::process;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use interrupt;
+
+pub const LOCK_FILE: &str = "cargo-erlangapp.lock";
+
+/// How long to wait for another invocation when none is configured
+pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(300);
+
+const POLL_INTERVAL: Duration = Duration::from_millis(200);
+
+/// Why the lock was not taken
+#[derive(Debug)]
+pub enum LockError {
+    /// Still held by another invocation (pid, if known) after the timeout
+    TimedOut(Option<u32>),
+    Interrupted,
+    Io(io::Error),
+}
+
+/// A held application lock, released when dropped
+#[derive(Debug)]
+pub struct AppLock(File);
+
+impl Drop for AppLock {
+    fn drop(&mut self) {
+        let _ = self.0.unlock();
+    }
+}
+
+/// The lock file of the application in `appdir`
+pub fn lock_path(appdir: &Path) -> PathBuf {
+    appdir.join("_build").join(LOCK_FILE)
+}
+
+/// Lock the application in `appdir`, waiting up to `timeout` for another invocation to
+/// finish.  `waiting` is called once, with the holder's pid if known, before waiting.
+pub fn acquire<F>(appdir: &Path, timeout: Duration, waiting: F) -> Result<AppLock, LockError>
+    where F: FnOnce(Option<u32>)
+{
+    let path = lock_path(appdir);
+    fs::create_dir_all(path.parent().unwrap()).map_err(LockError::Io)?;
+    let mut file = OpenOptions::new().read(true).write(true).create(true).truncate(false).open(&path)
+        .map_err(LockError::Io)?;
+    let started = Instant::now();
+    let mut waiting = Some(waiting);
+    loop {
+        match file.try_lock() {
+            Ok(()) => break,
+            Err(TryLockError::WouldBlock) => (),
+            Err(TryLockError::Error(err)) => return Err(LockError::Io(err)),
+        }
+        let holder = holder(&mut file);
+        if started.elapsed() >= timeout {
+            return Err(LockError::TimedOut(holder));
+        }
+        if let Some(waiting) = waiting.take() {
+            waiting(holder);
+        }
+        if interrupt::interrupted() {
+            return Err(LockError::Interrupted);
+        }
+        thread::sleep(POLL_INTERVAL.min(timeout.saturating_sub(started.elapsed())));
+    }
+    // best effort: the pid only improves messages
+    let _ = file.set_len(0)
+        .and_then(|_| file.rewind())
+        .and_then(|_| writeln!(file, "{}", process::id()));
+    Ok(AppLock(file))
+}
+
+/// Pid recorded by the lock's holder
+fn holder(file: &mut File) -> Option<u32> {
+    let mut text = String::new();
+    file.rewind().and_then(|_| file.read_to_string(&mut text)).ok()?;
+    parse_pid(&text)
+}
+
+fn parse_pid(text: &str) -> Option<u32> {
+    text.trim().parse().ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_pid() {
+        assert_eq!(Some(4242), parse_pid("4242\n"));
+        assert_eq!(None, parse_pid(""));
+        assert_eq!(Path::new("app/_build/cargo-erlangapp.lock"), lock_path(Path::new("app")));
+    }
+}
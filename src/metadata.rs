@@ -0,0 +1,211 @@
+//! Package and target enumeration through `cargo metadata`.
+//!
+//! Only the fields we use are deserialized; anything else in cargo's output is ignored, so
+//! newer cargo versions keep working.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process;
+
+use json;
+use output;
+
+use CommandFailure;
+use Failure;
+use MsgError;
+use MsgError::*;
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct Metadata {
+    pub packages: Vec<Package>,
+    #[serde(default)]
+    pub workspace_root: PathBuf,
+    /// Only present without `--no-deps`
+    #[serde(default)]
+    pub resolve: Option<Resolve>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct Package {
+    pub name: String,
+    pub id: String,
+    #[serde(default)]
+    pub version: String,
+    #[serde(default)]
+    pub manifest_path: PathBuf,
+    #[serde(default)]
+    pub targets: Vec<Target>,
+    #[serde(default)]
+    pub features: BTreeMap<String, Vec<String>>,
+    #[serde(default)]
+    pub dependencies: Vec<Dependency>,
+    /// SPDX license expression
+    #[serde(default)]
+    pub license: Option<String>,
+    /// Registry or git source; `None` for path dependencies and workspace members
+    #[serde(default)]
+    pub source: Option<String>,
+    /// Minimum supported Rust version
+    #[serde(default)]
+    pub rust_version: Option<String>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct Dependency {
+    pub name: String,
+    /// Version requirement, e.g. `^0.36`
+    #[serde(default)]
+    pub req: String,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct Target {
+    pub name: String,
+    pub kind: Vec<String>,
+    #[serde(default, rename = "required-features")]
+    pub required_features: Vec<String>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct Resolve {
+    pub nodes: Vec<Node>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct Node {
+    pub id: String,
+    #[serde(default)]
+    pub features: Vec<String>,
+    #[serde(default)]
+    pub deps: Vec<NodeDep>,
+}
+
+/// A resolved dependency of a node
+#[derive(Debug, Clone, Deserialize)]
+pub struct NodeDep {
+    /// Package id
+    pub pkg: String,
+    /// Empty with cargo before 1.41
+    #[serde(default)]
+    pub dep_kinds: Vec<DepKind>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct DepKind {
+    /// `None` for normal dependencies, else `dev` or `build`
+    #[serde(default)]
+    pub kind: Option<String>,
+}
+
+impl Metadata {
+    pub fn from_slice(json_slice: &[u8]) -> Option<Metadata> {
+        json::from_slice(json_slice).ok()
+    }
+}
+
+/// Run `cargo metadata` in `crate_dir`, with `cargo` carrying the run's environment and
+/// global options.  With `deps`, the dependency graph is resolved too.
+pub fn read(mut command: process::Command, crate_dir: &Path, deps: bool) -> Result<Metadata, MsgError> {
+    command.args(["metadata", "--format-version", "1"]).current_dir(crate_dir);
+    if !deps {
+        command.arg("--no-deps");
+    }
+    let output = output::output(&mut command)
+        .map_err(|err| MsgIo("Cannot read crate metadata", err).category(Failure::Enumeration))?;
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr).into_owned();
+        return Err(MsgCommand("Cannot read crate metadata", CommandFailure::new(&command, stderr).exited(output.status))
+                   .category(Failure::Enumeration));
+    }
+    Metadata::from_slice(&output.stdout)
+        .ok_or_else(|| {
+            let stdout = String::from_utf8_lossy(&output.stdout).into_owned();
+            MsgCommand("Cannot parse crate metadata", CommandFailure::new(&command, stdout)).category(Failure::Enumeration)
+        })
+}
+
+/// The package whose manifest is in `crate_dir`.  A workspace member's metadata lists the
+/// whole workspace.
+pub fn package(metadata: Metadata, crate_dir: &Path) -> Option<Package> {
+    let manifest = fs::canonicalize(crate_dir.join("Cargo.toml")).ok()?;
+    metadata.packages.into_iter()
+        .find(|p| fs::canonicalize(&p.manifest_path).map(|path| path == manifest).unwrap_or(false))
+}
+
+impl Package {
+    /// Features enabled by the `--features`, `--all-features` and `--no-default-features`
+    /// options in `cargo_args`, following feature-enables-feature entries.  Optional
+    /// dependencies named on the command line count as enabled features too.
+    pub fn enabled_features(&self, cargo_args: &[String]) -> BTreeSet<String> {
+        if cargo_args.iter().any(|arg| arg == "--all-features") {
+            return self.features.keys().cloned().collect();
+        }
+        let mut pending: Vec<String> = Vec::new();
+        if !cargo_args.iter().any(|arg| arg == "--no-default-features") {
+            pending.push("default".to_string());
+        }
+        let mut args = cargo_args.iter();
+        while let Some(arg) = args.next() {
+            let list = match arg.as_str() {
+                "--features" | "-F" => args.next().map(String::as_str),
+                _ => arg.strip_prefix("--features="),
+            };
+            if let Some(list) = list {
+                pending.extend(list.split([',', ' ']).filter(|f| !f.is_empty()).map(String::from));
+            }
+        }
+
+        let mut enabled = BTreeSet::new();
+        while let Some(feature) = pending.pop() {
+            if feature.contains('/') || feature.starts_with("dep:") || !enabled.insert(feature.clone()) {
+                continue;
+            }
+            if let Some(implied) = self.features.get(&feature) {
+                pending.extend(implied.iter().cloned());
+            }
+        }
+        if !self.features.contains_key("default") {
+            enabled.remove("default");
+        }
+        enabled
+    }
+}
+
+impl Target {
+    /// Required features of this target missing from `enabled`
+    pub fn missing_features(&self, enabled: &BTreeSet<String>) -> Vec<String> {
+        self.required_features.iter().filter(|f| !enabled.contains(*f)).cloned().collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_enabled_features() {
+        let metadata = Metadata::from_slice(br#"{
+            "packages": [{
+                "name": "mynif", "id": "mynif 0.1.0", "version": "0.1.0",
+                "manifest_path": "/app/crates/mynif/Cargo.toml",
+                "targets": [
+                    {"name": "mynif", "kind": ["cdylib"], "crate_types": ["cdylib"]},
+                    {"name": "tool", "kind": ["bin"], "required-features": ["cli"]}
+                ],
+                "features": {"default": ["fast"], "fast": [], "cli": ["dep:clap", "fast"], "full": ["cli"]}
+            }],
+            "workspace_members": ["mynif 0.1.0"]
+        }"#).unwrap();
+        let package = &metadata.packages[0];
+        let args = |a: &[&str]| a.iter().map(|s| s.to_string()).collect::<Vec<_>>();
+        let features = |a: &[&str]| package.enabled_features(&args(a)).into_iter().collect::<Vec<_>>();
+        assert_eq!(vec!["default", "fast"], features(&[]));
+        assert_eq!(Vec::<String>::new(), features(&["--no-default-features"]));
+        assert_eq!(vec!["cli", "fast", "full"], features(&["--no-default-features", "--features", "full"]));
+        assert_eq!(vec!["cli", "default", "fast", "full"], features(&["--all-features"]));
+        let tool = &package.targets[1];
+        assert_eq!(vec!["cli"], tool.missing_features(&package.enabled_features(&args(&[]))));
+        assert!(tool.missing_features(&package.enabled_features(&args(&["--features=cli"]))).is_empty());
+    }
+}
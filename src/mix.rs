@@ -0,0 +1,106 @@
+//! Mix compiler manifest, written by `build --mix` for a Mix compiler task running the tool.
+//!
+//! The manifest is `_build/<env>/lib/<app>/.mix/compile.cargo_erlangapp`, an Erlang term
+//! (`term_to_binary`) like the manifests of Mix's own compilers:
+//! `{1, [{Crate, Outputs, Inputs}]}`, with the crate name and paths as binaries.  Outputs
+//! are what the build installed for the crate, inputs what its dependency file lists.  The
+//! compiler task is stale when an output is older than an input, and `clean` removes the
+//! outputs.
+
+use std::env;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use reload;
+
+pub const MANIFEST_FILE: &str = "compile.cargo_erlangapp";
+
+const MANIFEST_VSN: u8 = 1;
+
+/// What one crate's build produced, and from what
+#[derive(Debug, PartialEq)]
+pub struct Entry {
+    pub krate: String,
+    pub outputs: Vec<PathBuf>,
+    pub inputs: Vec<PathBuf>,
+}
+
+/// Where Mix keeps the application's manifests: `Mix.Project.manifest_path/0`, from
+/// `MIX_BUILD_PATH`, or `MIX_BUILD_ROOT` (default `_build`), `MIX_TARGET` and `MIX_ENV`
+pub fn manifest_path(appdir: &Path) -> PathBuf {
+    let build_path = env::var_os("MIX_BUILD_PATH").map(PathBuf::from).unwrap_or_else(|| {
+        let root = env::var_os("MIX_BUILD_ROOT").map(PathBuf::from).unwrap_or_else(|| appdir.join("_build"));
+        let env = env::var("MIX_ENV").unwrap_or_else(|_| "dev".to_string());
+        match env::var("MIX_TARGET") {
+            Ok(ref target) if target != "host" => root.join(format!("{}_{}", target, env)),
+            _ => root.join(env),
+        }
+    });
+    build_path.join("lib").join(app_name(appdir)).join(".mix").join(MANIFEST_FILE)
+}
+
+/// `app: :name` in `mix.exs`, else the application name from `src/*.app.src` or the
+/// directory
+fn app_name(appdir: &Path) -> String {
+    fs::read_to_string(appdir.join("mix.exs")).ok()
+        .and_then(|text| {
+            let rest = &text[text.find("app:")? + "app:".len()..];
+            let name: String = rest.trim_start().strip_prefix(':')?.chars()
+                .take_while(|c| c.is_alphanumeric() || *c == '_')
+                .collect();
+            Some(name).filter(|name| !name.is_empty())
+        })
+        .unwrap_or_else(|| reload::app_name(appdir))
+}
+
+pub fn write_manifest(path: &Path, entries: &[Entry]) -> io::Result<()> {
+    fs::create_dir_all(path.parent().unwrap())?;
+    fs::write(path, encode(entries))
+}
+
+/// `term_to_binary({1, [{Crate, Outputs, Inputs}]})`
+fn encode(entries: &[Entry]) -> Vec<u8> {
+    let mut out = vec![131, 104, 2, 97, MANIFEST_VSN];
+    list(&mut out, entries, |out, entry| {
+        out.extend_from_slice(&[104, 3]);
+        binary(out, entry.krate.as_bytes());
+        list(out, &entry.outputs, |out, path| binary(out, path.to_string_lossy().as_bytes()));
+        list(out, &entry.inputs, |out, path| binary(out, path.to_string_lossy().as_bytes()));
+    });
+    out
+}
+
+fn list<T, F: Fn(&mut Vec<u8>, &T)>(out: &mut Vec<u8>, items: &[T], item: F) {
+    if !items.is_empty() {
+        out.push(108);
+        out.extend_from_slice(&(items.len() as u32).to_be_bytes());
+        for i in items.iter() {
+            item(out, i);
+        }
+    }
+    out.push(106);
+}
+
+fn binary(out: &mut Vec<u8>, bytes: &[u8]) {
+    out.push(109);
+    out.extend_from_slice(&(bytes.len() as u32).to_be_bytes());
+    out.extend_from_slice(bytes);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_encode() {
+        // term_to_binary({1, []})
+        assert_eq!(vec![131, 104, 2, 97, 1, 106], encode(&[]));
+        // term_to_binary({1, [{<<"n">>, [<<"o">>], []}]})
+        let entry = Entry { krate: "n".into(), outputs: vec![PathBuf::from("o")], inputs: Vec::new() };
+        assert_eq!(vec![131, 104, 2, 97, 1, 108, 0, 0, 0, 1,
+                        104, 3, 109, 0, 0, 0, 1, b'n', 108, 0, 0, 0, 1, 109, 0, 0, 0, 1, b'o', 106, 106,
+                        106],
+                   encode(&[entry]));
+    }
+}
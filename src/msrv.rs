@@ -0,0 +1,72 @@
+//! Crates' minimum supported Rust versions (`rust-version` in `Cargo.toml`) against the
+//! active toolchain.
+//!
+//! Cargo refuses a crate whose `rust-version` is newer than the toolchain, but only once it
+//! reaches that crate, and an older cargo fails somewhere in rustc instead.  Checking every
+//! crate first gives one report, and the toolchain that satisfies them all.
+
+use std::fmt::{self, Display};
+use std::path::Path;
+use std::process;
+
+use env_policy;
+
+/// `major.minor.patch`; a missing patch (or minor) is 0, as cargo reads `rust-version`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct RustVersion(u64, u64, u64);
+
+impl RustVersion {
+    /// Parse `1.70`, `1.70.1`, or a toolchain's `1.96.0-nightly`, whose pre-release tag is
+    /// ignored
+    pub fn parse(text: &str) -> Option<RustVersion> {
+        let text = text.trim();
+        let text = text.split_once('-').map(|(v, _)| v).unwrap_or(text);
+        let mut parts = text.split('.').map(|p| p.parse::<u64>());
+        let major = parts.next()?.ok()?;
+        let minor = parts.next().unwrap_or(Ok(0)).ok()?;
+        let patch = parts.next().unwrap_or(Ok(0)).ok()?;
+        match parts.next() {
+            None => Some(RustVersion(major, minor, patch)),
+            Some(_) => None,
+        }
+    }
+}
+
+impl Display for RustVersion {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}.{}.{}", self.0, self.1, self.2)
+    }
+}
+
+/// Version of the rustc cargo would use in `dir`, where a `rust-toolchain.toml` may select
+/// the toolchain, with cargo's environment
+pub fn toolchain_version(dir: &Path, env: &env_policy::Policy) -> Option<RustVersion> {
+    let mut command = process::Command::new(env.var("RUSTC").unwrap_or_else(|| "rustc".to_string()));
+    command.arg("-vV").current_dir(dir);
+    env.apply(&mut command);
+    let output = command.output().ok().filter(|output| output.status.success())?;
+    parse_release(&String::from_utf8_lossy(&output.stdout))
+}
+
+/// The `release:` line of `rustc -vV`
+fn parse_release(text: &str) -> Option<RustVersion> {
+    text.lines().find_map(|line| line.strip_prefix("release: ")).and_then(RustVersion::parse)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rust_version() {
+        assert_eq!(Some(RustVersion(1, 70, 0)), RustVersion::parse("1.70"));
+        assert_eq!(Some(RustVersion(1, 70, 1)), RustVersion::parse("1.70.1"));
+        assert_eq!(Some(RustVersion(1, 96, 0)), RustVersion::parse("1.96.0-nightly"));
+        assert_eq!(None, RustVersion::parse("1.x"));
+        assert!(RustVersion::parse("1.70").unwrap() < RustVersion::parse("1.70.1").unwrap());
+        assert!(RustVersion::parse("1.9").unwrap() < RustVersion::parse("1.10").unwrap());
+        assert_eq!("1.70.0", RustVersion(1, 70, 0).to_string());
+        assert_eq!(Some(RustVersion(1, 95, 0)),
+                   parse_release("rustc 1.95.0 (abc 2026-03-21)\nbinary: rustc\nrelease: 1.95.0\nLLVM version: 20.1.0\n"));
+    }
+}
@@ -0,0 +1,279 @@
+//! Generated `include/<crate>_native.hrl`: Erlang defines from a crate's annotated Rust
+//! constants, so error codes and limits decided natively are visible to Erlang code.
+//!
+//! A constant is exported by a `// erlangapp:define` comment on a line before it, optionally
+//! naming the macro (`// erlangapp:define MAX_BATCH`).  Integer, float, string, char and
+//! bool literals are supported; strings become binaries and bools atoms.
+
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use MsgError;
+use MsgError::*;
+
+/// First line of the Erlang files we generate, so they can be replaced or removed, and
+/// are never read back by `codegen`
+pub const GENERATED_MARKER: &str = "%% Generated by cargo-erlangapp";
+
+const ANNOTATION: &str = "erlangapp:define";
+
+/// An exported constant
+#[derive(Debug, PartialEq)]
+pub struct NativeDefine {
+    pub name: String,
+    /// The value as Erlang source
+    pub value: String,
+}
+
+/// Where a crate's defines go
+pub fn header_path(appdir: &Path, crate_name: &str) -> PathBuf {
+    appdir.join("include").join(format!("{}_native.hrl", crate_name.replace('-', "_")))
+}
+
+/// The annotated constants in a crate's `src/`, and where annotations couldn't be applied
+/// (as `file:line`)
+pub fn scan(crate_dir: &Path) -> io::Result<(Vec<NativeDefine>, Vec<String>)> {
+    let (mut defines, mut skipped) = (Vec::new(), Vec::new());
+    for file in rust_sources(crate_dir)?.iter() {
+        let shown = file.strip_prefix(crate_dir).unwrap_or(file).to_string_lossy().replace('\\', "/");
+        scan_source(&fs::read_to_string(file)?, &shown, &mut defines, &mut skipped);
+    }
+    Ok((defines, skipped))
+}
+
+/// The `.rs` files under a crate's `src/`, in path order
+pub fn rust_sources(crate_dir: &Path) -> io::Result<Vec<PathBuf>> {
+    let mut files = Vec::new();
+    collect_sources(&crate_dir.join("src"), &mut files)?;
+    files.sort();
+    Ok(files)
+}
+
+fn collect_sources(dir: &Path, files: &mut Vec<PathBuf>) -> io::Result<()> {
+    if !dir.is_dir() {
+        return Ok(());
+    }
+    for entry in dir.read_dir()? {
+        let path = entry?.path();
+        if path.is_dir() {
+            collect_sources(&path, files)?;
+        } else if path.extension().map(|ext| ext == "rs").unwrap_or(false) {
+            files.push(path);
+        }
+    }
+    Ok(())
+}
+
+fn scan_source(text: &str, file: &str, defines: &mut Vec<NativeDefine>, skipped: &mut Vec<String>) {
+    // (line number, macro name override) of an annotation awaiting its constant
+    let mut pending: Option<(usize, Option<String>)> = None;
+    for (i, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if let Some(rest) = line.strip_prefix("//").map(str::trim).and_then(|l| l.strip_prefix(ANNOTATION)) {
+            let name = rest.trim();
+            pending = Some((i + 1, Some(name.to_string()).filter(|n| !n.is_empty())));
+            continue;
+        }
+        let (annotated_at, name) = match pending {
+            Some(_) if line.is_empty() || line.starts_with("//") || line.starts_with("#[") => continue,
+            Some(ref p) => p.clone(),
+            None => continue,
+        };
+        pending = None;
+        match const_item(line) {
+            Some((rust_name, value)) => match erlang_value(value) {
+                Some(value) => defines.push(NativeDefine { name: name.unwrap_or_else(|| rust_name.to_string()), value }),
+                None => skipped.push(format!("{}:{}", file, i + 1)),
+            },
+            None => skipped.push(format!("{}:{}", file, annotated_at)),
+        }
+    }
+    if let Some((annotated_at, _)) = pending {
+        skipped.push(format!("{}:{}", file, annotated_at));
+    }
+}
+
+/// Name and value of a one-line `[pub] const NAME: Type = value;`
+fn const_item(line: &str) -> Option<(&str, &str)> {
+    let rest = &line[line.find("const ")? + "const ".len()..];
+    let (name, rest) = rest.split_once(':')?;
+    let (_, value) = rest.split_once('=')?;
+    let name = name.trim();
+    if name.is_empty() || !name.chars().all(|c| c.is_alphanumeric() || c == '_') {
+        return None;
+    }
+    Some((name, value.trim().strip_suffix(';')?.trim()))
+}
+
+static INT_SUFFIXES: &[&str] = &["i8", "i16", "i32", "i64", "i128", "isize", "u8", "u16", "u32", "u64", "u128", "usize"];
+
+/// Erlang source for a Rust literal
+fn erlang_value(literal: &str) -> Option<String> {
+    match literal {
+        "true" | "false" => return Some(literal.to_string()),
+        _ => (),
+    }
+    if let Some(s) = literal.strip_prefix('"').and_then(|s| s.strip_suffix('"')) {
+        return Some(format!("<<\"{}\"/utf8>>", erlang_escape(&rust_unescape(s)?)));
+    }
+    if let Some(c) = literal.strip_prefix('\'').and_then(|s| s.strip_suffix('\'')) {
+        let c = rust_unescape(c)?;
+        let mut chars = c.chars();
+        return match (chars.next(), chars.next()) {
+            (Some(c), None) if c.is_ascii_graphic() && c != '\\' => Some(format!("${}", c)),
+            (Some(c), None) => Some((c as u32).to_string()),
+            _ => None,
+        };
+    }
+    let (negative, digits) = match literal.strip_prefix('-') {
+        Some(rest) => (true, rest.trim_start()),
+        None => (false, literal),
+    };
+    let digits = digits.replace('_', "");
+    let radix = match digits.get(..2) {
+        Some("0x") => 16,
+        Some("0o") => 8,
+        Some("0b") => 2,
+        _ => 10,
+    };
+    if radix != 10 {
+        let digits = INT_SUFFIXES.iter().find_map(|s| digits[2..].strip_suffix(s)).unwrap_or(&digits[2..]);
+        let n = i128::from_str_radix(digits, radix).ok()?;
+        return Some(if negative { -n } else { n }.to_string());
+    }
+    if let Some(digits) = INT_SUFFIXES.iter().find_map(|s| digits.strip_suffix(s)).or(Some(digits.as_str()))
+        .filter(|d| !d.is_empty() && d.chars().all(|c| c.is_ascii_digit())) {
+        let n: i128 = digits.parse().ok()?;
+        return Some(if negative { -n } else { n }.to_string());
+    }
+    let float = digits.strip_suffix("f64").or_else(|| digits.strip_suffix("f32")).unwrap_or(&digits);
+    let f: f64 = float.parse().ok().filter(|f: &f64| f.is_finite())?;
+    Some(erlang_float(if negative { -f } else { f }))
+}
+
+/// Erlang floats need digits on both sides of the point: `1.0e-7`, not `1e-7`
+fn erlang_float(f: f64) -> String {
+    let s = format!("{:?}", f);
+    match (s.contains('.'), s.find('e')) {
+        (false, Some(e)) => format!("{}.0{}", &s[..e], &s[e..]),
+        (false, None) => format!("{}.0", s),
+        _ => s,
+    }
+}
+
+fn rust_unescape(s: &str) -> Option<String> {
+    let mut out = String::new();
+    let mut chars = s.chars();
+    while let Some(c) = chars.next() {
+        out.push(match c {
+            '\\' => match chars.next()? {
+                'n' => '\n',
+                't' => '\t',
+                'r' => '\r',
+                '0' => '\0',
+                c @ ('\\' | '"' | '\'') => c,
+                _ => return None,
+            },
+            c => c,
+        });
+    }
+    Some(out)
+}
+
+fn erlang_escape(s: &str) -> String {
+    let mut out = String::new();
+    for c in s.chars() {
+        match c {
+            '\n' => out.push_str("\\n"),
+            '\t' => out.push_str("\\t"),
+            '\r' => out.push_str("\\r"),
+            '\0' => out.push_str("\\000"),
+            '"' | '\\' => {
+                out.push('\\');
+                out.push(c);
+            },
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+/// Write the header for a crate's defines, leaving it untouched if unchanged.  Without
+/// defines, a header we generated earlier is removed.  Returns whether anything changed.
+pub fn generate(path: &Path, crate_name: &str, defines: &[NativeDefine]) -> Result<bool, MsgError> {
+    let old = fs::read_to_string(path).ok();
+    if defines.is_empty() {
+        return match old {
+            Some(ref old) if old.starts_with(GENERATED_MARKER) => fs::remove_file(path)
+                .map(|_| true)
+                .map_err(|err| MsgIo("cannot remove native header", err)),
+            _ => Ok(false),
+        };
+    }
+    let text = render(crate_name, defines);
+    if old.as_ref() == Some(&text) {
+        return Ok(false);
+    }
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir).map_err(|err| MsgIo("cannot create include directory", err))?;
+    }
+    fs::write(path, text).map_err(|err| MsgIo("cannot write native header", err))?;
+    Ok(true)
+}
+
+fn render(crate_name: &str, defines: &[NativeDefine]) -> String {
+    let guard = format!("{}_NATIVE_HRL", crate_name.replace('-', "_").to_uppercase());
+    let mut text = format!("{} from constants in crate {}; do not edit.\n-ifndef({}).\n-define({}, true).\n\n",
+                           GENERATED_MARKER, crate_name, guard, guard);
+    for define in defines.iter() {
+        text.push_str(&format!("-define({}, {}).\n", define.name, define.value));
+    }
+    text.push_str("\n-endif.\n");
+    text
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_scan_source() {
+        let (mut defines, mut skipped) = (Vec::new(), Vec::new());
+        scan_source(r#"
+// erlangapp:define
+pub const MAX_BATCH: usize = 1_024;
+
+// erlangapp:define ERR_TIMEOUT
+/// Returned when the device doesn't answer
+#[allow(dead_code)]
+pub(crate) const TIMEOUT: i32 = -0x10;
+// erlangapp:define
+const RATIO: f64 = 1e-7;
+// erlangapp:define
+pub const NAME: &str = "dev\"ice";
+// erlangapp:define
+pub const SEP: char = ',';
+// erlangapp:define
+pub const STRICT: bool = true;
+// erlangapp:define
+pub const DERIVED: u32 = MAX_BATCH as u32 * 2;
+const UNMARKED: u32 = 1;
+// erlangapp:define
+"#, "src/lib.rs", &mut defines, &mut skipped);
+        let value = |name: &str| defines.iter().find(|d| d.name == name).map(|d| d.value.as_str());
+        assert_eq!(Some("1024"), value("MAX_BATCH"));
+        assert_eq!(Some("-16"), value("ERR_TIMEOUT"));
+        assert_eq!(Some("1.0e-7"), value("RATIO"));
+        assert_eq!(Some("<<\"dev\\\"ice\"/utf8>>"), value("NAME"));
+        assert_eq!(Some("$,"), value("SEP"));
+        assert_eq!(Some("true"), value("STRICT"));
+        assert_eq!(None, value("UNMARKED"));
+        assert_eq!(vec!["src/lib.rs:18", "src/lib.rs:20"], skipped);
+
+        let text = render("my-nif", &defines[..1]);
+        assert_eq!("%% Generated by cargo-erlangapp from constants in crate my-nif; do not edit.\n\
+                    -ifndef(MY_NIF_NATIVE_HRL).\n-define(MY_NIF_NATIVE_HRL, true).\n\n\
+                    -define(MAX_BATCH, 1024).\n\n-endif.\n", text);
+    }
+}
@@ -0,0 +1,283 @@
+//! Generated `src/<module>.erl`: the Erlang side of a rustler NIF library, with a stub and
+//! a `-spec` for each `#[rustler::nif]` function.
+//!
+//! Specs are derived from the Rust signatures as rustler encodes and decodes them; types
+//! we can't see through (structs with derived codecs, for instance) become `term()`.  A
+//! module that wasn't generated by us is never overwritten.
+
+use std::fs;
+use std::path::Path;
+
+use native_hrl::{self, GENERATED_MARKER};
+use MsgError;
+use MsgError::*;
+
+/// A `#[rustler::nif]` function
+#[derive(Debug, PartialEq)]
+pub struct NifFn {
+    /// Erlang name: the function name, or `name = "..."` from the attribute
+    pub name: String,
+    /// Argument names and Erlang types, without the `Env`
+    pub args: Vec<(String, String)>,
+    pub ret: String,
+}
+
+/// The NIF functions defined in a crate's `src/`
+pub fn scan(crate_dir: &Path) -> Result<Vec<NifFn>, MsgError> {
+    let mut fns = Vec::new();
+    let files = native_hrl::rust_sources(crate_dir).map_err(|err| MsgIo("cannot read crate sources", err))?;
+    for file in files.iter() {
+        let text = fs::read_to_string(file).map_err(|err| MsgIo("cannot read crate sources", err))?;
+        fns.extend(scan_source(&text));
+    }
+    Ok(fns)
+}
+
+fn scan_source(text: &str) -> Vec<NifFn> {
+    let mut fns = Vec::new();
+    let mut rest = text;
+    while let Some(at) = rest.find("#[") {
+        rest = &rest[at + 2..];
+        let attribute = rest.trim_start();
+        let attribute = attribute.strip_prefix("rustler::").unwrap_or(attribute);
+        if !attribute.starts_with("nif]") && !attribute.starts_with("nif(") {
+            continue;
+        }
+        let end = match rest.find(']') {
+            Some(end) => end,
+            None => break,
+        };
+        let rename = rest[..end].split("name").nth(1)
+            .and_then(|s| s.trim_start().strip_prefix('='))
+            .and_then(|s| s.trim_start().strip_prefix('"'))
+            .and_then(|s| s.split('"').next())
+            .map(String::from);
+        rest = &rest[end + 1..];
+        if let Some(nif) = signature(rest, rename) {
+            fns.push(nif);
+        }
+    }
+    fns
+}
+
+/// Parse `[pub] fn name<...>(args) -> Ret` at the start of `text`
+fn signature(text: &str, rename: Option<String>) -> Option<NifFn> {
+    let text = &text[text.find("fn ")? + 3..];
+    let name_end = text.find(|c: char| !c.is_alphanumeric() && c != '_')?;
+    let name = text[..name_end].to_string();
+    let open = text.find('(')?;
+    let mut depth = 0;
+    let close = open + text[open..].char_indices().find(|&(_, c)| {
+        match c {
+            '(' | '<' | '[' => depth += 1,
+            ')' | '>' | ']' => depth -= 1,
+            _ => (),
+        }
+        depth == 0
+    })?.0;
+    let after = text[close + 1..].trim_start();
+    let ret = match after.strip_prefix("->") {
+        Some(ret) => {
+            let ret = &ret[..ret.find(['{', ';']).unwrap_or(ret.len())];
+            spec_type(ret.split(" where ").next().unwrap_or(ret))
+        },
+        None => "ok".to_string(),
+    };
+    let args = split_top_level(&text[open + 1..close]).into_iter()
+        .filter_map(|arg| {
+            let (arg_name, ty) = arg.split_once(':')?;
+            match base_name(ty) == "Env" {
+                true => None,
+                false => Some((variable(arg_name.trim().trim_start_matches("mut ")), spec_type(ty))),
+            }
+        })
+        .collect();
+    Some(NifFn { name: rename.unwrap_or(name), args, ret })
+}
+
+/// Split at commas outside brackets, dropping empty parts
+fn split_top_level(text: &str) -> Vec<&str> {
+    let mut parts = Vec::new();
+    let (mut depth, mut start) = (0i32, 0);
+    for (i, c) in text.char_indices() {
+        match c {
+            '(' | '<' | '[' => depth += 1,
+            ')' | '>' | ']' => depth -= 1,
+            ',' if depth == 0 => {
+                parts.push(text[start..i].trim());
+                start = i + 1;
+            },
+            _ => (),
+        }
+    }
+    parts.push(text[start..].trim());
+    parts.retain(|p| !p.is_empty());
+    parts
+}
+
+/// The last path segment of a type, without references, lifetimes or generics
+fn base_name(ty: &str) -> &str {
+    let ty = strip_reference(ty);
+    let ty = &ty[..ty.find('<').unwrap_or(ty.len())];
+    ty.rsplit("::").next().unwrap_or(ty).trim()
+}
+
+fn strip_reference(ty: &str) -> &str {
+    let mut ty = ty.trim();
+    if let Some(rest) = ty.strip_prefix('&') {
+        ty = rest.trim_start();
+        if ty.starts_with('\'') {
+            ty = ty.split_once(char::is_whitespace).map(|(_, t)| t).unwrap_or("").trim_start();
+        }
+        ty = ty.strip_prefix("mut ").unwrap_or(ty).trim_start();
+    }
+    ty
+}
+
+/// Erlang type for a Rust type, as rustler encodes it
+fn spec_type(ty: &str) -> String {
+    let ty = strip_reference(ty);
+    if let Some(inner) = ty.strip_prefix('(').and_then(|t| t.strip_suffix(')')) {
+        let elements = split_top_level(inner);
+        return match elements.is_empty() {
+            true => "ok".to_string(),
+            false => format!("{{{}}}", elements.iter().map(|t| spec_type(t)).collect::<Vec<_>>().join(", ")),
+        };
+    }
+    if let Some(inner) = ty.strip_prefix('[').and_then(|t| t.strip_suffix(']')) {
+        return format!("[{}]", spec_type(inner.split(';').next().unwrap_or(inner)));
+    }
+    let params: Vec<String> = match (ty.find('<'), ty.rfind('>')) {
+        (Some(open), Some(close)) if open < close => split_top_level(&ty[open + 1..close]).into_iter()
+            .filter(|p| !p.starts_with('\''))
+            .map(spec_type)
+            .collect(),
+        _ => Vec::new(),
+    };
+    let param = |i: usize| params.get(i).cloned().unwrap_or_else(|| "term()".to_string());
+    match base_name(ty) {
+        "i8" | "i16" | "i32" | "i64" | "i128" | "isize" => "integer()".to_string(),
+        "u8" | "u16" | "u32" | "u64" | "u128" | "usize" => "non_neg_integer()".to_string(),
+        "f32" | "f64" => "float()".to_string(),
+        "bool" => "boolean()".to_string(),
+        "char" => "char()".to_string(),
+        "str" | "String" | "Binary" | "OwnedBinary" | "NewBinary" => "binary()".to_string(),
+        "Atom" => "atom()".to_string(),
+        "LocalPid" => "pid()".to_string(),
+        "ResourceArc" => "reference()".to_string(),
+        "Vec" | "ListIterator" => format!("[{}]", param(0)),
+        "HashMap" | "BTreeMap" => format!("#{{{} => {}}}", param(0), param(1)),
+        "Option" => format!("{} | nil", param(0)),
+        "NifResult" => param(0),
+        "Result" => format!("{{ok, {}}} | {{error, {}}}", param(0), param(1)),
+        _ => "term()".to_string(),
+    }
+}
+
+/// Erlang variable for an unused argument: `_` and the name in upper camel case
+fn variable(name: &str) -> String {
+    let name = name.trim_start_matches('_');
+    let camel: String = name.split('_').filter(|w| !w.is_empty())
+        .map(|word| {
+            let mut chars = word.chars();
+            chars.next().map(|c| c.to_uppercase().chain(chars).collect::<String>()).unwrap_or_default()
+        })
+        .collect();
+    format!("_{}", camel)
+}
+
+/// True if `src/<module>.erl` exists and we didn't generate it
+pub fn is_hand_written(appdir: &Path, module: &str) -> bool {
+    fs::read_to_string(appdir.join("src").join(format!("{}.erl", module)))
+        .map(|text| !text.starts_with(GENERATED_MARKER))
+        .unwrap_or(false)
+}
+
+/// Write `src/<module>.erl`, leaving it untouched if unchanged so it isn't recompiled.
+/// `load_path` is the Erlang expression for `erlang:load_nif/2`'s path.  Returns whether
+/// it was written.
+pub fn generate(appdir: &Path, module: &str, crate_name: &str, load_path: &str, fns: &[NifFn]) -> Result<bool, MsgError> {
+    let path = appdir.join("src").join(format!("{}.erl", module));
+    let text = render(module, crate_name, load_path, fns);
+    if fs::read_to_string(&path).map(|old| old == text).unwrap_or(false) {
+        return Ok(false);
+    }
+    fs::create_dir_all(appdir.join("src"))
+        .and_then(|_| fs::write(&path, text))
+        .map_err(|err| MsgIo("cannot write NIF stub module", err))?;
+    Ok(true)
+}
+
+fn render(module: &str, crate_name: &str, load_path: &str, fns: &[NifFn]) -> String {
+    let exports: Vec<String> = fns.iter().map(|f| format!("{}/{}", f.name, f.args.len())).collect();
+    let mut text = format!("\
+{marker} from crate {krate}; do not edit.
+-module({module}).
+-export([{exports}]).
+-nifs([{exports}]).
+-on_load(init/0).
+
+init() ->
+    erlang:load_nif({load_path}, 0).
+", marker = GENERATED_MARKER, krate = crate_name, module = module, exports = exports.join(", "), load_path = load_path);
+    for f in fns.iter() {
+        let types: Vec<&str> = f.args.iter().map(|(_, ty)| ty.as_str()).collect();
+        let vars: Vec<&str> = f.args.iter().map(|(var, _)| var.as_str()).collect();
+        text.push_str(&format!("
+-spec {name}({types}) -> {ret}.
+{name}({vars}) ->
+    erlang:nif_error(nif_not_loaded).
+", name = f.name, types = types.join(", "), ret = f.ret, vars = vars.join(", ")));
+    }
+    text
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_scan_source() {
+        let fns = scan_source(r#"
+use rustler::{Env, Term, NifResult};
+
+#[rustler::nif]
+fn add(a: i64, b: i64) -> i64 {
+    a + b
+}
+
+#[rustler::nif(schedule = "DirtyCpu", name = "sum_list")]
+pub fn sum<'a>(env: Env<'a>, items: Vec<f64>, scale: Option<u32>) -> NifResult<(f64, usize)> {
+    unimplemented!()
+}
+
+#[nif]
+fn lookup(table: ResourceArc<Table>, key: &str, opts: HashMap<Atom, Term<'_>>) -> Result<String, Atom> {
+    unimplemented!()
+}
+
+#[rustler::nif]
+fn reset(_state_ref: ResourceArc<State>) {}
+
+#[derive(Debug)]
+struct NotANif;
+"#);
+        assert_eq!(vec![
+            NifFn { name: "add".into(), args: vec![("_A".into(), "integer()".into()), ("_B".into(), "integer()".into())],
+                    ret: "integer()".into() },
+            NifFn { name: "sum_list".into(),
+                    args: vec![("_Items".into(), "[float()]".into()), ("_Scale".into(), "non_neg_integer() | nil".into())],
+                    ret: "{float(), non_neg_integer()}".into() },
+            NifFn { name: "lookup".into(),
+                    args: vec![("_Table".into(), "reference()".into()), ("_Key".into(), "binary()".into()),
+                               ("_Opts".into(), "#{atom() => term()}".into())],
+                    ret: "{ok, binary()} | {error, atom()}".into() },
+            NifFn { name: "reset".into(), args: vec![("_StateRef".into(), "reference()".into())], ret: "ok".into() },
+        ], fns);
+
+        let text = render("mynif", "mynif", "\"priv/mynif\"", &fns[..1]);
+        assert!(text.starts_with("%% Generated by cargo-erlangapp from crate mynif; do not edit.\n-module(mynif).\n"));
+        assert!(text.contains("-export([add/2]).\n-nifs([add/2]).\n"));
+        assert!(text.contains("\n-spec add(integer(), integer()) -> integer().\nadd(_A, _B) ->\n    erlang:nif_error(nif_not_loaded).\n"));
+    }
+}
@@ -0,0 +1,720 @@
+//! Options for a run, parsed from the command line or built programmatically.
+
+use std::env;
+use std::path::PathBuf;
+use std::time::Duration;
+
+use dist::Compression;
+use gc::{self, Policy};
+use graph::Format;
+use windows::Toolchain;
+use {find_option, find_option_value, parse_cmd_name, take_flag, take_option_value};
+use {BuildType, CargoCommand, OnlyKind, Target, TestRunner};
+
+/// Everything that controls a run.  `Options::from_args` parses a command line into it;
+/// programs can start from `Options::new` and chain the builder methods instead.
+///
+/// ```no_run
+/// use cargo_erlangapp::{Options, CargoCommand, OnlyKind};
+/// use std::path::Path;
+///
+/// let options = Options::new(CargoCommand::Build).release().only(OnlyKind::Nifs);
+/// cargo_erlangapp::run(options, Path::new("my_app")).unwrap();
+/// ```
+#[derive(Debug, Clone)]
+pub struct Options {
+    pub command: CargoCommand,
+    /// `--target`: target triple to build for, else the host
+    pub target: Option<String>,
+    /// Cargo profile, from `--release`, `--debug` or `--profile`
+    pub build_type: BuildType,
+    /// `--rebar-profile` or `REBAR_PROFILE`: selects the cargo profile through `erlangapp.toml`
+    pub rebar_profile: Option<String>,
+    /// `--app`: only process these umbrella applications
+    pub apps: Vec<String>,
+    /// `--all`: search the directory tree for applications instead of using umbrella dirs
+    pub scan_all: bool,
+    /// `--manifest-path`: build just this crate, bypassing `crates/` discovery
+    pub manifest_path: Option<PathBuf>,
+    /// `--priv-dir`: install into this `priv/` instead of the application's
+    pub priv_dir: Option<PathBuf>,
+    /// `--versioned-artifacts`: suffix installed dylibs with the crate version (also set by
+    /// `versioned-artifacts` in `erlangapp.toml`)
+    pub versioned_artifacts: bool,
+    /// `--platform-dirs`: install into a `<os>-<arch>` subdirectory per platform (also set by
+    /// `platform-dirs` in `erlangapp.toml`)
+    pub platform_dirs: bool,
+    /// `--profile-dirs`: also keep each build in a subdirectory per profile, for
+    /// `switch-profile` (also set by `profile-dirs` in `erlangapp.toml`)
+    pub profile_dirs: bool,
+    /// `--rebar-lib-dirs`: also install into rebar3's `_build/<profile>/lib/<app>/priv` (also
+    /// set by `rebar-lib-dirs` in `erlangapp.toml`)
+    pub rebar_lib_dirs: bool,
+    /// `--strip`: strip debug information and local symbols from installed artifacts (also
+    /// set by `strip` in `erlangapp.toml`)
+    pub strip: bool,
+    /// `--strict-targets`: fail on crates with no installable targets (also set by
+    /// `strict-targets` in `erlangapp.toml`)
+    pub strict_targets: bool,
+    /// `--deny-warnings`: make compiler warnings errors in every crate (also set by
+    /// `deny-warnings` in `erlangapp.toml`)
+    pub deny_warnings: bool,
+    /// `--prune`: build and prune-artifacts remove the `priv/` directories of crates that no longer exist
+    pub prune: bool,
+    /// `--backup`: keep the artifact a build replaces for `rollback` (also set by `backup`
+    /// in `erlangapp.toml`)
+    pub backup: bool,
+    /// `--keep-artifacts`: generations of each artifact to retain
+    pub keep_artifacts: Option<usize>,
+    /// `--lock-timeout`: how long to wait for another invocation on the same application
+    pub lock_timeout: Option<Duration>,
+    /// `--only-nifs` or `--only-bins`: build just one kind of target
+    pub only: Option<OnlyKind>,
+    /// `--bin <name>`, repeatable: build just these bin targets (and the lib, with `--lib`)
+    pub bins: Vec<String>,
+    /// `--lib`: build just each crate's lib target (and named bins, with `--bin`)
+    pub lib: bool,
+    /// `--rustc-args`: extra rustc arguments for every target built, after the tool's own
+    pub rustc_args: Vec<String>,
+    /// `--static-crt`: link port programs with a static C runtime on Windows (also set per
+    /// crate by `static-crt.crates` in `erlangapp.toml`)
+    pub static_crt: bool,
+    /// `check-otp` argument: OTP installation path, release or NIF API version
+    pub otp: Option<String>,
+    /// `version-bump` argument: patch, minor, major or a version
+    pub bump: Option<String>,
+    /// `switch-profile` argument: the profile whose kept build to install
+    pub switch_to: Option<String>,
+    /// `--sync-app`: `version-bump` the application version too, and give every crate the result
+    pub sync_app: bool,
+    /// `--dry-run`: `sync-deps` shows the changes without making them
+    pub dry_run: bool,
+    /// `--windows-toolchain`: build for the Windows target triple of this toolchain
+    pub windows_toolchain: Option<Toolchain>,
+    /// `--windows-toolchain=both`: build for the MSVC, then the GNU Windows target, into
+    /// `<os>-<arch>-<toolchain>` platform subdirectories
+    pub both_windows_toolchains: bool,
+    /// `--compression`: dist archive compression, overriding `erlangapp.toml`
+    pub compression: Option<Compression>,
+    /// `--runner` for `test`
+    pub runner: TestRunner,
+    /// `--junit` for `test`: where to write a JUnit XML report, relative to the application
+    pub junit: Option<PathBuf>,
+    /// `--max-age` and `--max-size` for `gc`
+    pub gc_policy: Policy,
+    /// `--format` for `graph`
+    pub graph_format: Format,
+    /// `--external[=<n>]` for `graph`: show external dependencies pulling in at least this
+    /// many packages
+    pub graph_external: Option<usize>,
+    /// `--hook-mode`: print nothing unless something fails
+    pub hook_mode: bool,
+    /// `--quiet-cargo`: capture cargo's output, printing it only if something fails
+    pub quiet_cargo: bool,
+    /// `--mix`: write a Mix compiler manifest of what `build` installed
+    pub mix: bool,
+    /// `--message-format=json`: pass cargo's JSON messages through on stdout
+    pub json_messages: bool,
+    /// `--config`, repeatable: cargo configuration overrides for every cargo invocation,
+    /// e.g. `profile.release.lto="thin"`
+    pub cargo_config: Vec<String>,
+    /// Arguments passed through to cargo, including the profile and target options
+    pub cargo_args: Vec<String>,
+}
+
+/// Options the tool reads itself, whatever it passes on to cargo
+pub const TOOL_OPTIONS: &[&str] = &[
+    "--all", "--app", "--backup", "--bin", "--compression", "--config", "--debug", "--deny-warnings",
+    "--dry-run", "--external", "--format", "--hook-mode", "--junit", "--keep-artifacts", "--lib",
+    "--lock-timeout", "--manifest-path", "--max-age", "--max-size", "--message-format", "--mix", "--nif",
+    "--only-bins", "--only-nifs", "--platform-dirs", "--priv-dir", "--profile-dirs", "--prune",
+    "--quiet-cargo", "--rebar-lib-dirs", "--rebar-profile", "--runner", "--rustc-args", "--static-crt",
+    "--strict-targets", "--strip", "--sync-app", "--template", "--versioned-artifacts", "--windows-toolchain",
+];
+
+impl Default for Options {
+    fn default() -> Options {
+        Options::new(CargoCommand::Build)
+    }
+}
+
+impl Options {
+    pub fn new(command: CargoCommand) -> Options {
+        Options {
+            command,
+            target: None,
+            build_type: BuildType::DefaultDebug,
+            rebar_profile: None,
+            apps: Vec::new(),
+            scan_all: false,
+            manifest_path: None,
+            priv_dir: None,
+            versioned_artifacts: false,
+            platform_dirs: false,
+            profile_dirs: false,
+            rebar_lib_dirs: false,
+            strip: false,
+            strict_targets: false,
+            deny_warnings: false,
+            prune: false,
+            backup: false,
+            keep_artifacts: None,
+            lock_timeout: None,
+            only: None,
+            bins: Vec::new(),
+            lib: false,
+            rustc_args: Vec::new(),
+            static_crt: false,
+            otp: None,
+            bump: None,
+            switch_to: None,
+            sync_app: false,
+            dry_run: false,
+            windows_toolchain: None,
+            both_windows_toolchains: false,
+            compression: None,
+            runner: TestRunner::Cargo,
+            junit: None,
+            gc_policy: Policy::default(),
+            graph_format: Format::Dot,
+            graph_external: None,
+            hook_mode: false,
+            quiet_cargo: false,
+            mix: false,
+            json_messages: false,
+            cargo_config: Vec::new(),
+            cargo_args: Vec::new(),
+        }
+    }
+
+    /// Parse a command line: `args[0]` is the program, `args[1]` the command.  `None` if it
+    /// isn't valid.
+    pub fn from_args(args: &[String]) -> Option<Options> {
+        if args.len() < 2 {
+            return None;
+        }
+
+        let mut cargo_args = args[2..].to_vec();
+        let hook_mode = take_flag(&mut cargo_args, "--hook-mode");
+        let quiet_cargo = take_flag(&mut cargo_args, "--quiet-cargo");
+        let mix = take_flag(&mut cargo_args, "--mix");
+        let apps = take_option_value(&mut cargo_args, "--app")
+            .map(|apps| apps.split(',').map(String::from).collect())
+            .unwrap_or_default();
+        let scan_all = take_flag(&mut cargo_args, "--all");
+        let message_format = take_option_value(&mut cargo_args, "--message-format");
+        let json_messages = message_format.as_ref().map(|f| f == "json").unwrap_or(false);
+        if let (Some(format), false) = (message_format, json_messages) {
+            cargo_args.insert(0, format!("--message-format={}", format)); // not ours, leave it for cargo
+        }
+        let manifest_path = take_option_value(&mut cargo_args, "--manifest-path").map(PathBuf::from);
+        let priv_dir = take_option_value(&mut cargo_args, "--priv-dir").map(PathBuf::from);
+        let versioned_artifacts = take_flag(&mut cargo_args, "--versioned-artifacts");
+        let platform_dirs = take_flag(&mut cargo_args, "--platform-dirs");
+        let profile_dirs = take_flag(&mut cargo_args, "--profile-dirs");
+        let rebar_lib_dirs = take_flag(&mut cargo_args, "--rebar-lib-dirs");
+        let static_crt = take_flag(&mut cargo_args, "--static-crt");
+        let strip = take_flag(&mut cargo_args, "--strip");
+        let strict_targets = take_flag(&mut cargo_args, "--strict-targets");
+        let deny_warnings = take_flag(&mut cargo_args, "--deny-warnings");
+        let prune = take_flag(&mut cargo_args, "--prune");
+        let backup = take_flag(&mut cargo_args, "--backup");
+        let keep_artifacts = match take_option_value(&mut cargo_args, "--keep-artifacts") {
+            Some(n) => Some(n.parse().ok()?),
+            None => None,
+        };
+        let lock_timeout = match take_option_value(&mut cargo_args, "--lock-timeout") {
+            Some(secs) => Some(Duration::from_secs(secs.parse().ok()?)),
+            None => None,
+        };
+        let only = match (take_flag(&mut cargo_args, "--only-nifs"), take_flag(&mut cargo_args, "--only-bins")) {
+            (true, true) => return None,
+            (true, false) => Some(OnlyKind::Nifs),
+            (false, true) => Some(OnlyKind::Bins),
+            (false, false) => None,
+        };
+        let rebar_profile = take_option_value(&mut cargo_args, "--rebar-profile")
+            .or_else(|| env::var("REBAR_PROFILE").ok());
+        let (windows_toolchain, both_windows_toolchains) = match take_option_value(&mut cargo_args, "--windows-toolchain") {
+            Some(ref toolchain) if toolchain == "both" => (None, true),
+            Some(toolchain) => (Some(toolchain.parse().ok()?), false),
+            None => (None, false),
+        };
+        let compression = match take_option_value(&mut cargo_args, "--compression") {
+            Some(compression) => Some(compression.parse().ok()?),
+            None => None,
+        };
+        let gc_policy = gc::Policy {
+            max_age: match take_option_value(&mut cargo_args, "--max-age") {
+                Some(age) => Some(gc::parse_age(&age)?),
+                None => None,
+            },
+            max_size: match take_option_value(&mut cargo_args, "--max-size") {
+                Some(size) => Some(gc::parse_size(&size)?),
+                None => None,
+            },
+        };
+        let sync_app = take_flag(&mut cargo_args, "--sync-app");
+        let mut cargo_config = Vec::new();
+        while let Some(value) = take_option_value(&mut cargo_args, "--config") {
+            cargo_config.push(value);
+        }
+        let command = parse_cmd_name(args[1].as_str())?;
+        let mut positional = || cargo_args.iter().position(|arg| !arg.starts_with('-')).map(|i| cargo_args.remove(i));
+        let otp = match command {
+            CargoCommand::CheckOtp => positional(),
+            _ => None,
+        };
+        let bump = match command {
+            CargoCommand::VersionBump => positional(),
+            _ => None,
+        };
+        let switch_to = match command {
+            CargoCommand::SwitchProfile => positional(),
+            _ => None,
+        };
+        let dry_run = command == CargoCommand::SyncDeps && take_flag(&mut cargo_args, "--dry-run");
+        let runner = match command {
+            CargoCommand::Test => match take_option_value(&mut cargo_args, "--runner") {
+                Some(runner) => runner.parse().ok()?,
+                None => TestRunner::Cargo,
+            },
+            _ => TestRunner::Cargo,
+        };
+        let junit = match command {
+            CargoCommand::Test => take_option_value(&mut cargo_args, "--junit").map(PathBuf::from),
+            _ => None,
+        };
+        let (mut graph_format, mut graph_external) = (Format::Dot, None);
+        if let CargoCommand::Graph = command {
+            if let Some(format) = take_option_value(&mut cargo_args, "--format") {
+                graph_format = format.parse().ok()?;
+            }
+            graph_external = match take_flag(&mut cargo_args, "--external") {
+                true => Some(1),
+                false => match take_option_value(&mut cargo_args, "--external") {
+                    Some(min) => Some(min.parse().ok()?),
+                    None => None,
+                },
+            };
+        }
+        // cargo's own target selection, applied across the crates; other commands pass it on
+        let (mut bins, mut lib, mut rustc_args) = (Vec::new(), false, Vec::new());
+        if let CargoCommand::Build | CargoCommand::SmokeTest | CargoCommand::Dist = command {
+            while let Some(bin) = take_option_value(&mut cargo_args, "--bin") {
+                bins.push(bin);
+            }
+            lib = take_flag(&mut cargo_args, "--lib");
+            while let Some(args) = take_option_value(&mut cargo_args, "--rustc-args") {
+                rustc_args.extend(args.split_whitespace().map(String::from));
+            }
+        }
+
+        let build_type =
+        if find_option(args, "--release") { BuildType::Release }
+            else if find_option(args, "--debug") { BuildType::Debug }
+            else if let Some(profile) = find_option_value(&args[2..], "--profile") { BuildType::from_profile(&profile) }
+            else { BuildType::DefaultDebug };
+
+        Some(Options {
+            command,
+            target: find_option_value(&args[2..], "--target"),
+            build_type,
+            rebar_profile,
+            apps,
+            scan_all,
+            manifest_path,
+            priv_dir,
+            versioned_artifacts,
+            platform_dirs,
+            profile_dirs,
+            rebar_lib_dirs,
+            strip,
+            strict_targets,
+            deny_warnings,
+            prune,
+            backup,
+            keep_artifacts,
+            lock_timeout,
+            only,
+            bins,
+            lib,
+            rustc_args,
+            static_crt,
+            otp,
+            bump,
+            switch_to,
+            sync_app,
+            dry_run,
+            windows_toolchain,
+            both_windows_toolchains,
+            compression,
+            runner,
+            junit,
+            gc_policy,
+            graph_format,
+            graph_external,
+            hook_mode,
+            quiet_cargo,
+            mix,
+            json_messages,
+            cargo_config,
+            cargo_args,
+        })
+    }
+
+    /// Build with the `release` profile
+    pub fn release(mut self) -> Options {
+        self.build_type = BuildType::Release;
+        self.cargo_args.insert(0, "--release".to_string());
+        self
+    }
+
+    /// Build with a named cargo profile
+    pub fn profile(mut self, profile: &str) -> Options {
+        self.build_type = BuildType::from_profile(profile);
+        self.cargo_args.insert(0, format!("--profile={}", profile));
+        self
+    }
+
+    /// Build for a target triple
+    pub fn target(mut self, triple: &str) -> Options {
+        self.target = Some(triple.to_string());
+        self.cargo_args.insert(0, format!("--target={}", triple));
+        self
+    }
+
+    pub fn apps(mut self, apps: &[&str]) -> Options {
+        self.apps = apps.iter().map(|app| app.to_string()).collect();
+        self
+    }
+
+    /// Process every application found under the application directory
+    pub fn all(mut self) -> Options {
+        self.scan_all = true;
+        self
+    }
+
+    pub fn manifest_path<P: Into<PathBuf>>(mut self, path: P) -> Options {
+        self.manifest_path = Some(path.into());
+        self
+    }
+
+    pub fn priv_dir<P: Into<PathBuf>>(mut self, dir: P) -> Options {
+        self.priv_dir = Some(dir.into());
+        self
+    }
+
+    pub fn versioned_artifacts(mut self, versioned: bool) -> Options {
+        self.versioned_artifacts = versioned;
+        self
+    }
+
+    pub fn platform_dirs(mut self, platform_dirs: bool) -> Options {
+        self.platform_dirs = platform_dirs;
+        self
+    }
+
+    pub fn profile_dirs(mut self, profile_dirs: bool) -> Options {
+        self.profile_dirs = profile_dirs;
+        self
+    }
+
+    pub fn rebar_lib_dirs(mut self, rebar_lib_dirs: bool) -> Options {
+        self.rebar_lib_dirs = rebar_lib_dirs;
+        self
+    }
+
+    pub fn strip(mut self, strip: bool) -> Options {
+        self.strip = strip;
+        self
+    }
+
+    pub fn strict_targets(mut self, strict_targets: bool) -> Options {
+        self.strict_targets = strict_targets;
+        self
+    }
+
+    pub fn deny_warnings(mut self, deny_warnings: bool) -> Options {
+        self.deny_warnings = deny_warnings;
+        self
+    }
+
+    pub fn keep_artifacts(mut self, keep: usize) -> Options {
+        self.keep_artifacts = Some(keep);
+        self
+    }
+
+    pub fn lock_timeout(mut self, timeout: Duration) -> Options {
+        self.lock_timeout = Some(timeout);
+        self
+    }
+
+    pub fn runner(mut self, runner: TestRunner) -> Options {
+        self.runner = runner;
+        self
+    }
+
+    /// Write `test`'s JUnit XML report to `path`, relative to the application
+    pub fn junit<P: Into<PathBuf>>(mut self, path: P) -> Options {
+        self.junit = Some(path.into());
+        self
+    }
+
+    pub fn graph_format(mut self, format: Format) -> Options {
+        self.graph_format = format;
+        self
+    }
+
+    /// Show `graph`'s external dependencies that pull in at least `min` packages
+    pub fn graph_external(mut self, min: usize) -> Options {
+        self.graph_external = Some(min);
+        self
+    }
+
+    pub fn only(mut self, kind: OnlyKind) -> Options {
+        self.only = Some(kind);
+        self
+    }
+
+    /// Build this bin target; may be given several times
+    pub fn bin(mut self, name: &str) -> Options {
+        self.bins.push(name.to_string());
+        self
+    }
+
+    /// Build the lib targets
+    pub fn lib(mut self) -> Options {
+        self.lib = true;
+        self
+    }
+
+    /// Pass an argument to rustc for every target built
+    pub fn rustc_arg(mut self, arg: &str) -> Options {
+        self.rustc_args.push(arg.to_string());
+        self
+    }
+
+    pub fn static_crt(mut self, static_crt: bool) -> Options {
+        self.static_crt = static_crt;
+        self
+    }
+
+    /// True if cargo's `-v` or `--verbose` is among the cargo arguments
+    pub fn verbose(&self) -> bool {
+        let end = self.cargo_args.iter().position(|arg| arg == "--").unwrap_or(self.cargo_args.len());
+        self.cargo_args[..end].iter().any(|arg| arg == "--verbose" || (arg.starts_with("-v") && arg[1..].chars().all(|c| c == 'v')))
+    }
+
+    /// True if `--only-*`, `--bin` and `--lib` leave `target` to be built
+    pub fn selects(&self, target: &Target) -> bool {
+        let named = match *target {
+            _ if self.bins.is_empty() && !self.lib => true,
+            Target::Bin(ref name) => self.bins.contains(name),
+            ref target => self.lib && target.is_lib(),
+        };
+        named && self.only.map(|only| only.includes(target)).unwrap_or(true)
+    }
+
+    /// True if some targets may be left out of the build
+    pub fn filters_targets(&self) -> bool {
+        self.only.is_some() || !self.bins.is_empty() || self.lib
+    }
+
+    pub fn hook_mode(mut self, hook_mode: bool) -> Options {
+        self.hook_mode = hook_mode;
+        self
+    }
+
+    pub fn quiet_cargo(mut self, quiet_cargo: bool) -> Options {
+        self.quiet_cargo = quiet_cargo;
+        self
+    }
+
+    pub fn mix(mut self, mix: bool) -> Options {
+        self.mix = mix;
+        self
+    }
+
+    pub fn json_messages(mut self, json: bool) -> Options {
+        self.json_messages = json;
+        self
+    }
+
+    /// Override a cargo configuration value in every cargo invocation; may be given several times
+    pub fn cargo_config(mut self, value: &str) -> Options {
+        self.cargo_config.push(value.to_string());
+        self
+    }
+
+    /// Pass an argument through to cargo
+    pub fn cargo_arg(mut self, arg: &str) -> Options {
+        self.cargo_args.push(arg.to_string());
+        self
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Options {
+        let args: Vec<String> = ["cargo-erlangapp"].iter().chain(args).map(|s| s.to_string()).collect();
+        Options::from_args(&args).unwrap()
+    }
+
+    #[test]
+    fn test_from_args() {
+        let args: Vec<String> = ["cargo-erlangapp", "build", "--release", "--only-nifs", "--keep-artifacts=2", "--", "-v"]
+            .iter().map(|s| s.to_string()).collect();
+        let options = Options::from_args(&args).unwrap();
+        assert_eq!(CargoCommand::Build, options.command);
+        assert_eq!(BuildType::Release, options.build_type);
+        assert_eq!(Some(OnlyKind::Nifs), options.only);
+        assert_eq!(Some(2), options.keep_artifacts);
+        assert_eq!(vec!["--release", "--", "-v"], options.cargo_args);
+
+        let built = Options::new(CargoCommand::Build).release().only(OnlyKind::Nifs).keep_artifacts(2);
+        assert_eq!(options.build_type, built.build_type);
+        assert_eq!(vec!["--release"], built.cargo_args);
+        assert!(Options::from_args(&args[..1]).is_none());
+    }
+
+    #[test]
+    fn test_lock_timeout() {
+        assert_eq!(Some(Duration::from_secs(30)), parse(&["clean", "--lock-timeout=30"]).lock_timeout);
+    }
+
+    #[test]
+    fn test_profile_dirs() {
+        assert!(parse(&["build", "--profile-dirs"]).profile_dirs);
+        assert_eq!(Some("release".to_string()), parse(&["switch-profile", "release"]).switch_to);
+    }
+
+    #[test]
+    fn test_platform_dirs() {
+        let options = parse(&["build", "--platform-dirs"]);
+        assert!(options.platform_dirs && options.cargo_args.is_empty());
+    }
+
+    #[test]
+    fn test_static_crt() {
+        let options = parse(&["build", "--static-crt"]);
+        assert!(options.static_crt && options.cargo_args.is_empty());
+    }
+
+    #[test]
+    fn test_rebar_lib_dirs() {
+        let options = parse(&["build", "--rebar-lib-dirs"]);
+        assert!(options.rebar_lib_dirs && options.cargo_args.is_empty());
+    }
+
+    #[test]
+    fn test_strip() {
+        let options = parse(&["build", "--strip"]);
+        assert!(options.strip && options.cargo_args.is_empty());
+    }
+
+    #[test]
+    fn test_strict_targets() {
+        let options = parse(&["build", "--strict-targets"]);
+        assert!(options.strict_targets && options.cargo_args.is_empty());
+    }
+
+    #[test]
+    fn test_deny_warnings() {
+        let options = parse(&["build", "--deny-warnings"]);
+        assert!(options.deny_warnings && options.cargo_args.is_empty());
+    }
+
+    #[test]
+    fn test_prune() {
+        let options = parse(&["build", "--prune"]);
+        assert!(options.prune && options.cargo_args.is_empty());
+    }
+
+    #[test]
+    fn test_backup() {
+        let options = parse(&["build", "--backup"]);
+        assert!(options.backup && options.cargo_args.is_empty());
+    }
+
+    #[test]
+    fn test_quiet_cargo() {
+        let options = parse(&["build", "--quiet-cargo"]);
+        assert!(options.quiet_cargo && options.cargo_args.is_empty());
+    }
+
+    #[test]
+    fn test_mix() {
+        let options = parse(&["build", "--mix"]);
+        assert!(options.mix && options.cargo_args.is_empty());
+    }
+
+    #[test]
+    fn test_runner() {
+        let options = parse(&["test", "--runner", "nextest", "--retries", "2"]);
+        assert_eq!(TestRunner::Nextest, options.runner);
+        assert_eq!(None, options.junit);
+        assert_eq!(vec!["--retries", "2"], options.cargo_args);
+        assert_eq!(Some(PathBuf::from("_build/junit.xml")), parse(&["test", "--junit=_build/junit.xml"]).junit);
+    }
+
+    #[test]
+    fn test_graph() {
+        let options = parse(&["graph", "--format=mermaid", "--external=5"]);
+        assert_eq!((Format::Mermaid, Some(5)), (options.graph_format, options.graph_external));
+        assert_eq!(Some(1), parse(&["graph", "--external"]).graph_external);
+    }
+
+    #[test]
+    fn test_dry_run() {
+        assert!(parse(&["sync-deps", "--dry-run"]).dry_run);
+    }
+
+    #[test]
+    fn test_windows_toolchain() {
+        let options = parse(&["build", "--windows-toolchain=both"]);
+        assert!(options.both_windows_toolchains && options.windows_toolchain.is_none());
+    }
+
+    #[test]
+    fn test_selects() {
+        let options = parse(&["build", "--bin", "helloexe", "--bin=other", "--lib", "-v"]);
+        assert_eq!(vec!["helloexe", "other"], options.bins);
+        assert!(options.lib);
+        assert_eq!(vec!["-v"], options.cargo_args);
+        assert!(options.selects(&Target::Bin("other".into())));
+        assert!(options.selects(&Target::Cdylib("mynif".into())));
+        assert!(!options.selects(&Target::Bin("unnamed".into())));
+        assert!(!options.only(OnlyKind::Bins).selects(&Target::Cdylib("mynif".into())));
+        assert_eq!(vec!["--lib"], parse(&["test", "--lib"]).cargo_args);
+    }
+
+    #[test]
+    fn test_verbose() {
+        assert!(parse(&["build", "-v"]).verbose());
+        assert!(parse(&["build", "-vv"]).verbose());
+        assert!(!Options::new(CargoCommand::Build).cargo_arg("--").cargo_arg("-v").verbose());
+    }
+
+    #[test]
+    fn test_rustc_args() {
+        assert_eq!(vec!["-C", "target-cpu=native"], parse(&["build", "--rustc-args", "-C target-cpu=native"]).rustc_args);
+    }
+
+    #[test]
+    fn test_cargo_config() {
+        let options = parse(&["clean", "--config", "profile.release.lto=\"thin\"", "--config=net.offline=true", "-v"]);
+        assert_eq!(vec!["profile.release.lto=\"thin\"", "net.offline=true"], options.cargo_config);
+        assert_eq!(vec!["-v"], options.cargo_args);
+    }
+
+    #[test]
+    fn test_scan_all() {
+        let options = parse(&["test", "--all", "--app=a,b"]);
+        assert!(options.scan_all);
+        assert_eq!(vec!["a", "b"], options.apps);
+        assert!(options.cargo_args.is_empty());
+    }
+}
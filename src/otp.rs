@@ -0,0 +1,161 @@
+//! NIF API versions: what each crate was compiled against, and what an OTP release provides.
+//!
+//! A NIF built for API version `M.m` loads into a runtime with the same major version and
+//! a minor version of at least `m`.
+
+use std::fmt::{self, Display};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use metadata::Metadata;
+
+use MsgError;
+use MsgError::*;
+
+/// File recording the NIF API version, next to a crate's installed artifacts
+pub const NIF_VERSION_FILE: &str = "nif-version";
+
+/// NIF API version of each OTP major release, as found in its `erl_nif.h`
+static OTP_NIF_VERSIONS: &[(u32, NifVersion)] = &[
+    (19, NifVersion(2, 10)),
+    (20, NifVersion(2, 12)),
+    (21, NifVersion(2, 14)),
+    (22, NifVersion(2, 15)),
+    (23, NifVersion(2, 15)),
+    (24, NifVersion(2, 16)),
+    (25, NifVersion(2, 16)),
+    (26, NifVersion(2, 17)),
+    (27, NifVersion(2, 17)),
+    (28, NifVersion(2, 17)),
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, PartialOrd)]
+pub struct NifVersion(pub u32, pub u32);
+
+impl NifVersion {
+    /// True if a NIF built against `self` loads into a runtime providing `runtime`
+    pub fn loads_into(&self, runtime: &NifVersion) -> bool {
+        self.0 == runtime.0 && self.1 <= runtime.1
+    }
+}
+
+impl Display for NifVersion {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}.{}", self.0, self.1)
+    }
+}
+
+impl FromStr for NifVersion {
+    type Err = ();
+    fn from_str(s: &str) -> Result<NifVersion, ()> {
+        let mut parts = s.trim().splitn(2, '.').map(|p| p.parse::<u32>());
+        match (parts.next(), parts.next()) {
+            (Some(Ok(major)), Some(Ok(minor))) => Ok(NifVersion(major, minor)),
+            _ => Err(()),
+        }
+    }
+}
+
+/// Highest `nif_version_*` feature enabled on rustler or rustler_sys in a resolved graph: the
+/// NIF API version a crate is compiled against
+pub fn nif_version_from_metadata(metadata: &Metadata) -> Option<NifVersion> {
+    let ids: Vec<&str> = metadata.packages.iter()
+        .filter(|p| p.name == "rustler" || p.name == "rustler_sys")
+        .map(|p| p.id.as_str())
+        .collect();
+    metadata.resolve.as_ref()?.nodes.iter()
+        .filter(|node| ids.contains(&node.id.as_str()))
+        .flat_map(|node| node.features.iter())
+        .filter_map(|feature| feature.strip_prefix("nif_version_")?.replacen('_', ".", 1).parse().ok())
+        .fold(None, |max: Option<NifVersion>, v| match max {
+            Some(m) if m >= v => Some(m),
+            _ => Some(v),
+        })
+}
+
+/// NIF API version of an OTP major release, if known
+pub fn release_nif_version(release: u32) -> Option<NifVersion> {
+    OTP_NIF_VERSIONS.iter().find(|&&(otp, _)| otp == release).map(|&(_, version)| version)
+}
+
+/// NIF API version provided by an OTP installation (path), an OTP release (`26`, `26.2.1`)
+/// or given directly (`2.17`)
+pub fn runtime_nif_version(spec: &str) -> Result<NifVersion, MsgError> {
+    let path = Path::new(spec);
+    if path.exists() {
+        let header = find_erl_nif_h(path)
+            .ok_or_else(|| MsgDetail("no erl_nif.h found", spec.to_string()))?;
+        let text = fs::read_to_string(&header).map_err(|err| MsgIo("cannot read erl_nif.h", err))?;
+        return header_nif_version(&text)
+            .ok_or_else(|| MsgDetail("no NIF version in erl_nif.h", header.to_string_lossy().into_owned()));
+    }
+    if let Ok(version) = spec.parse::<NifVersion>() {
+        if version.0 < 10 {
+            return Ok(version);
+        }
+    }
+    spec.split('.').next().and_then(|major| major.parse::<u32>().ok())
+        .and_then(release_nif_version)
+        .ok_or_else(|| MsgDetail("unknown OTP release or NIF version", spec.to_string()))
+}
+
+/// `erl_nif.h` in an OTP installation or source tree, or the path itself if it is the header
+fn find_erl_nif_h(path: &Path) -> Option<PathBuf> {
+    if path.is_file() {
+        return Some(path.to_path_buf());
+    }
+    let mut candidates = vec![path.join("usr/include/erl_nif.h"), path.join("erts/emulator/beam/erl_nif.h")];
+    for base in [path.to_path_buf(), path.join("lib/erlang")].iter() {
+        if let Ok(entries) = base.read_dir() {
+            candidates.extend(entries.filter_map(Result::ok)
+                .filter(|e| e.file_name().to_string_lossy().starts_with("erts-"))
+                .map(|e| e.path().join("include/erl_nif.h")));
+        }
+        candidates.push(base.join("usr/include/erl_nif.h"));
+    }
+    candidates.into_iter().find(|p| p.is_file())
+}
+
+fn header_nif_version(text: &str) -> Option<NifVersion> {
+    let define = |name: &str| text.lines()
+        .filter_map(|line| line.trim().strip_prefix("#define")?.trim().strip_prefix(name))
+        .find_map(|rest| rest.trim().parse::<u32>().ok());
+    Some(NifVersion(define("ERL_NIF_MAJOR_VERSION")?, define("ERL_NIF_MINOR_VERSION")?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_runtime_nif_version() {
+        assert_eq!(NifVersion(2, 17), runtime_nif_version("26").unwrap());
+        assert_eq!(NifVersion(2, 16), runtime_nif_version("25.3.2").unwrap());
+        assert_eq!(NifVersion(2, 15), runtime_nif_version("2.15").unwrap());
+        assert!(runtime_nif_version("12").is_err());
+        assert!(NifVersion(2, 15).loads_into(&NifVersion(2, 17)));
+        assert!(!NifVersion(2, 17).loads_into(&NifVersion(2, 16)));
+    }
+
+    #[test]
+    fn test_header_nif_version() {
+        let text = "#define ERL_NIF_MAJOR_VERSION 2\n#define ERL_NIF_MINOR_VERSION 17\n";
+        assert_eq!(Some(NifVersion(2, 17)), header_nif_version(text));
+        assert_eq!(None, header_nif_version("#define ERL_NIF_MAJOR_VERSION 2\n"));
+    }
+
+    #[test]
+    fn test_nif_version_from_metadata() {
+        let metadata = br#"{
+            "packages": [{"name": "rustler", "id": "rustler 0.36.2"}, {"name": "mynif", "id": "mynif 0.1.0"}],
+            "resolve": {"nodes": [
+                {"id": "rustler 0.36.2", "features": ["default", "nif_version_2_14", "nif_version_2_15"]},
+                {"id": "mynif 0.1.0", "features": ["nif_version_2_17"]}
+            ]}
+        }"#;
+        assert_eq!(Some(NifVersion(2, 15)), nif_version_from_metadata(&Metadata::from_slice(metadata).unwrap()));
+        let empty = Metadata::from_slice(br#"{"packages": [], "resolve": {"nodes": []}}"#).unwrap();
+        assert_eq!(None, nif_version_from_metadata(&empty));
+    }
+}
@@ -0,0 +1,73 @@
+//! Dependencies with newer versions, from `cargo update --dry-run --verbose`.
+//!
+//! Cargo reports each package it would update within the semver requirement (`Updating
+//! serde v1.0.100 -> v1.0.210`) and, with `--verbose`, those held back by it (`Unchanged
+//! rustler v0.36.2 (available: v0.38.0)`).  The registry is queried, but the lock file is
+//! left alone.
+
+/// A locked package with a newer version
+#[derive(Debug, PartialEq)]
+pub struct Outdated {
+    pub name: String,
+    /// Version in the lock file
+    pub locked: String,
+    /// Newest version the requirement allows, if newer than `locked`
+    pub compatible: Option<String>,
+    /// Newest version outside the requirement
+    pub latest: Option<String>,
+}
+
+/// Parse the output of `cargo update --dry-run --verbose`
+pub fn parse(text: &str) -> Vec<Outdated> {
+    text.lines().filter_map(parse_line).collect()
+}
+
+fn parse_line(line: &str) -> Option<Outdated> {
+    let (line, latest) = match line.trim_end().strip_suffix(')').and_then(|l| l.rsplit_once(" (available: ")) {
+        Some((line, available)) => (line, Some(version(available))),
+        None => (line, None),
+    };
+    let words: Vec<&str> = line.split_whitespace().collect();
+    match words[..] {
+        ["Updating", name, from, "->", to] => Some(Outdated {
+            name: name.to_string(),
+            locked: version(from),
+            compatible: Some(version(to)),
+            latest,
+        }),
+        ["Unchanged", name, locked] | ["Adding", name, locked] if latest.is_some() => Some(Outdated {
+            name: name.to_string(),
+            locked: version(locked),
+            compatible: None,
+            latest,
+        }),
+        _ => None,
+    }
+}
+
+fn version(v: &str) -> String {
+    v.trim_start_matches('v').to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        let text = "    Updating crates.io index
+     Locking 2 packages to latest compatible versions
+    Updating serde v1.0.100 -> v1.0.210
+    Updating rand v0.7.3 -> v0.7.4 (available: v0.8.5)
+   Unchanged rustler v0.36.2 (available: v0.38.0)
+note: to see how you depend on a package, run `cargo tree --invert <dep>@<ver>`
+warning: not updating lockfile due to dry run
+";
+        assert_eq!(vec![
+            Outdated { name: "serde".into(), locked: "1.0.100".into(), compatible: Some("1.0.210".into()), latest: None },
+            Outdated { name: "rand".into(), locked: "0.7.3".into(), compatible: Some("0.7.4".into()), latest: Some("0.8.5".into()) },
+            Outdated { name: "rustler".into(), locked: "0.36.2".into(), compatible: None, latest: Some("0.38.0".into()) },
+        ], parse(text));
+        assert!(parse("   Unchanged libc v0.2.150\n").is_empty());
+    }
+}
@@ -0,0 +1,337 @@
+//! Progress output and subprocess output handling.
+
+use std::cell::RefCell;
+use std::env;
+use std::io::{self, BufRead, BufReader, IsTerminal};
+use std::iter;
+use std::process::{self, Stdio};
+use std::thread;
+
+use interrupt;
+use json;
+use progress::{self, Progress};
+
+/// Where progress messages and cargo's output go.
+///
+/// Normally messages are printed and subprocesses inherit stdout/stderr.  In hook mode
+/// everything is captured instead, and only dumped if the command fails.  With quiet cargo
+/// only the subprocesses' output is captured, and messages are still printed.
+///
+/// With JSON message format, stdout carries only cargo's JSON messages (each tagged with
+/// an `erlangapp_crate` field); progress and anything else goes to stderr.
+///
+/// On a terminal, builds show a live status line per crate.  Cargo's output is then
+/// captured as with quiet cargo, and its warnings printed above the status lines.
+#[derive(Debug, Default)]
+pub struct Output {
+    hook_mode: bool,
+    quiet_cargo: bool,
+    json: bool,
+    /// A live display can be drawn
+    live: bool,
+    progress: RefCell<Option<Progress>>,
+    captured: RefCell<String>,
+}
+
+impl Output {
+    pub fn new(hook_mode: bool, quiet_cargo: bool, json: bool) -> Output {
+        let live = !hook_mode && !json && progress::available(io::stdout().is_terminal(), io::stderr().is_terminal());
+        Output { hook_mode, quiet_cargo, json, live, progress: RefCell::new(None), captured: RefCell::new(String::new()) }
+    }
+
+    /// Show a status line for each crate about to be built, if on a terminal
+    pub fn begin_crates(&self, crates: &[String]) {
+        if self.live {
+            *self.progress.borrow_mut() = Some(Progress::start(crates));
+        }
+    }
+
+    pub fn crate_state(&self, crate_name: &str, state: progress::State) {
+        if let Some(ref progress) = *self.progress.borrow() {
+            progress.set(crate_name, state);
+        }
+    }
+
+    /// Leave the crates' final status lines
+    pub fn end_crates(&self) {
+        if let Some(progress) = self.progress.borrow_mut().take() {
+            progress.finish();
+        }
+    }
+
+    /// True if subprocess output is captured rather than passed through
+    fn captures(&self) -> bool {
+        self.hook_mode || self.quiet_cargo || self.progress.borrow().is_some()
+    }
+
+    /// With quiet cargo or the live display only the latest subprocess's output is kept,
+    /// that of the one that failed; hook mode keeps everything
+    fn start_capture(&self) {
+        if !self.hook_mode {
+            self.captured.borrow_mut().clear();
+        }
+    }
+
+    /// True if cargo should be asked for `--message-format=json`
+    pub fn json(&self) -> bool {
+        self.json
+    }
+
+    /// Report progress
+    pub fn status(&self, msg: &str) {
+        if self.hook_mode {
+            let mut captured = self.captured.borrow_mut();
+            captured.push_str(msg);
+            captured.push('\n');
+        } else if let Some(ref progress) = *self.progress.borrow() {
+            progress.print(msg);
+        } else if self.json {
+            eprintln!("{}", msg);
+        } else {
+            println!("{}", msg);
+        }
+    }
+
+    /// Report a step that the live display, when shown, already shows
+    pub fn step(&self, msg: &str) {
+        if self.progress.borrow().is_none() {
+            self.status(msg);
+        }
+    }
+
+    /// Run a subprocess for `crate_name` to completion, capturing its output in hook mode
+    /// and with quiet cargo.
+    ///
+    /// The subprocess's stderr is passed through and also returned (in JSON mode, after its
+    /// stdout messages), for parsing and for error messages.  Since stderr is then a pipe,
+    /// cargo is asked to keep its colors when ours is a terminal.
+    pub fn run(&self, cmd: &mut process::Command, crate_name: &str)
+        -> io::Result<(process::ExitStatus, String)>
+    {
+        self.start_capture();
+        if !self.captures() && io::stderr().is_terminal() && env::var_os("CARGO_TERM_COLOR").is_none() {
+            cmd.env("CARGO_TERM_COLOR", "always");
+        }
+        if self.json {
+            self.run_json(cmd, crate_name)
+        } else if self.captures() {
+            let output = output(cmd)?;
+            let stderr = String::from_utf8_lossy(&output.stderr).into_owned();
+            let mut captured = self.captured.borrow_mut();
+            captured.push_str(&String::from_utf8_lossy(&output.stdout));
+            captured.push_str(&stderr);
+            if let (Some(ref progress), true) = (&*self.progress.borrow(), output.status.success()) {
+                let messages = cargo_messages(&stderr);
+                if !messages.is_empty() {
+                    progress.print(&messages);
+                }
+            }
+            Ok((output.status, stderr))
+        } else {
+            let mut child = cmd.stderr(Stdio::piped()).spawn()?;
+            let _registered = interrupt::Child::register(&child);
+            let mut text = String::new();
+            for line in lossy_lines(BufReader::new(child.stderr.take().unwrap())) {
+                eprintln!("{}", line);
+                text.push_str(&line);
+                text.push('\n');
+            }
+            Ok((child.wait()?, text))
+        }
+    }
+
+    /// Like `run`, but for subprocesses whose stdout we need too (benchmark results): stdout
+    /// is passed through the same way as stderr, and returned before it.
+    pub fn run_with_stdout(&self, cmd: &mut process::Command, crate_name: &str)
+        -> io::Result<(process::ExitStatus, String)>
+    {
+        if self.json {
+            return self.run(cmd, crate_name);
+        }
+        self.start_capture();
+        if self.captures() {
+            let output = output(cmd)?;
+            let text = format!("{}{}", String::from_utf8_lossy(&output.stdout), String::from_utf8_lossy(&output.stderr));
+            self.captured.borrow_mut().push_str(&text);
+            return Ok((output.status, text));
+        }
+        if io::stderr().is_terminal() && env::var_os("CARGO_TERM_COLOR").is_none() {
+            cmd.env("CARGO_TERM_COLOR", "always");
+        }
+        let mut child = cmd.stdout(Stdio::piped()).stderr(Stdio::piped()).spawn()?;
+        let _registered = interrupt::Child::register(&child);
+        let stderr_reader = child.stderr.take().map(|stderr| thread::spawn(move || {
+            let mut text = String::new();
+            for line in lossy_lines(BufReader::new(stderr)) {
+                eprintln!("{}", line);
+                text.push_str(&line);
+                text.push('\n');
+            }
+            text
+        }));
+        let mut text = String::new();
+        for line in lossy_lines(BufReader::new(child.stdout.take().unwrap())) {
+            println!("{}", line);
+            text.push_str(&line);
+            text.push('\n');
+        }
+        let status = child.wait()?;
+        if let Some(reader) = stderr_reader {
+            text.push_str(&reader.join().unwrap_or_default());
+        }
+        Ok((status, text))
+    }
+
+    /// Stream cargo's stdout, tagging JSON messages with the crate name.  Returns the messages
+    /// followed by stderr.
+    fn run_json(&self, cmd: &mut process::Command, crate_name: &str) -> io::Result<(process::ExitStatus, String)> {
+        let mut child = cmd.stdout(Stdio::piped()).stderr(Stdio::piped()).spawn()?;
+        let _registered = interrupt::Child::register(&child);
+        let stdout = child.stdout.take().unwrap();
+
+        // drain stderr concurrently so a full pipe can't stall cargo
+        let captures = self.captures();
+        let stderr_reader = child.stderr.take().map(|stderr| thread::spawn(move || {
+            let mut text = String::new();
+            for line in lossy_lines(BufReader::new(stderr)) {
+                if !captures {
+                    eprintln!("{}", line);
+                }
+                text.push_str(&line);
+                text.push('\n');
+            }
+            text
+        }));
+
+        let mut text = String::new();
+        for line in lossy_lines(BufReader::new(stdout)) {
+            text.push_str(&line);
+            text.push('\n');
+            let tagged = match json::from_str::<json::Value>(&line) {
+                Ok(json::Value::Object(mut obj)) => {
+                    obj.insert("erlangapp_crate".to_string(), json::Value::String(crate_name.to_string()));
+                    json::to_string(&json::Value::Object(obj)).ok()
+                },
+                _ => None,
+            };
+            // quiet cargo still passes the JSON messages on; they are what was asked for
+            match tagged {
+                Some(msg) if !self.hook_mode => println!("{}", msg),
+                None if !self.captures() => eprintln!("{}", line),
+                msg => {
+                    let mut captured = self.captured.borrow_mut();
+                    captured.push_str(msg.as_ref().unwrap_or(&line));
+                    captured.push('\n');
+                },
+            }
+        }
+        let status = child.wait()?;
+        if let Some(reader) = stderr_reader {
+            let stderr = reader.join().unwrap_or_default();
+            if self.captures() {
+                self.captured.borrow_mut().push_str(&stderr);
+            }
+            text.push_str(&stderr);
+        }
+        Ok((status, text))
+    }
+
+    /// Write out everything captured so far.  Called when a command fails.
+    pub fn dump_captured(&self) {
+        let captured = self.captured.borrow();
+        if !captured.is_empty() {
+            eprint!("{}", captured);
+        }
+    }
+}
+
+/// Cargo's output without its progress lines (`Compiling ...`, `Finished ...`): the
+/// warnings and notes worth showing after a successful build
+fn cargo_messages(stderr: &str) -> String {
+    const PROGRESS: &[&str] = &["Compiling", "Finished", "Fresh", "Checking", "Blocking", "Locking", "Updating",
+                                "Downloading", "Downloaded", "Adding", "Running", "Building"];
+    stderr.lines()
+        .filter(|line| !PROGRESS.iter().any(|word| line.trim_start().starts_with(word)))
+        .map(|line| format!("{}\n", line))
+        .collect()
+}
+
+/// Lines of a subprocess's output, invalid UTF-8 replaced.  Reading on past a bad line,
+/// rather than stopping, keeps the pipe drained so the child can't block on it.
+fn lossy_lines<R: BufRead>(mut reader: R) -> impl Iterator<Item = String> {
+    iter::from_fn(move || {
+        let mut line = Vec::new();
+        match reader.read_until(b'\n', &mut line) {
+            Ok(0) | Err(_) => None,
+            Ok(_) => {
+                if line.ends_with(b"\n") {
+                    line.pop();
+                    if line.ends_with(b"\r") {
+                        line.pop();
+                    }
+                }
+                Some(String::from_utf8_lossy(&line).into_owned())
+            },
+        }
+    })
+}
+
+/// `Command::output`, with interrupts forwarded to the child
+pub fn output(cmd: &mut process::Command) -> io::Result<process::Output> {
+    let child = cmd.stdin(Stdio::null()).stdout(Stdio::piped()).stderr(Stdio::piped()).spawn()?;
+    let _registered = interrupt::Child::register(&child);
+    child.wait_with_output()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cargo_messages() {
+        let stderr = "   Compiling mynif v0.1.0\nwarning: unused variable: `x`\n --> src/lib.rs:3:9\n    Finished `dev` profile\n";
+        assert_eq!("warning: unused variable: `x`\n --> src/lib.rs:3:9\n", cargo_messages(stderr));
+        assert_eq!("", cargo_messages("    Blocking waiting for file lock\n     Running `rustc`\n"));
+        let lines: Vec<String> = lossy_lines(&b"a\xffb\r\nlast"[..]).collect();
+        assert_eq!(vec!["a\u{fffd}b".to_string(), "last".to_string()], lines);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_run() {
+        let sh = |script: &str| {
+            let mut cmd = process::Command::new("sh");
+            cmd.args(["-c", script]);
+            cmd
+        };
+
+        // hook mode keeps everything, messages included, for a failure to dump
+        let hook = Output::new(true, false, false);
+        hook.status("Building mynif");
+        let (status, stderr) = hook.run(&mut sh("echo out; echo err >&2; exit 3"), "mynif").unwrap();
+        assert_eq!(Some(3), status.code());
+        assert_eq!("err\n", stderr);
+        hook.run(&mut sh("echo again >&2"), "mynif").unwrap();
+        assert_eq!("Building mynif\nout\nerr\nagain\n", *hook.captured.borrow());
+
+        // JSON messages are tagged with the crate; in hook mode they are captured too
+        let json_hook = Output::new(true, false, true);
+        let (_, text) = json_hook.run(&mut sh("echo '{\"reason\":\"build-finished\"}'; echo note >&2"), "mynif").unwrap();
+        assert_eq!("{\"reason\":\"build-finished\"}\nnote\n", text);
+        assert!(json_hook.captured.borrow().contains("\"erlangapp_crate\":\"mynif\""));
+
+        // quiet cargo keeps only the latest subprocess's output
+        let quiet = Output::new(false, true, false);
+        quiet.run(&mut sh("echo first >&2"), "mynif").unwrap();
+        quiet.run(&mut sh("echo second >&2"), "mynif").unwrap();
+        assert_eq!("second\n", *quiet.captured.borrow());
+
+        // passed through: invalid UTF-8 doesn't cut the output short
+        let through = Output { hook_mode: false, quiet_cargo: false, json: false, live: false, ..Output::default() };
+        let (status, stderr) = through.run(&mut sh("printf 'a\\377b\\nafter\\n' >&2"), "mynif").unwrap();
+        assert!(status.success());
+        assert_eq!("a\u{fffd}b\nafter\n", stderr);
+        let (_, text) = through.run_with_stdout(&mut sh("printf 'x\\377\\n'; echo y; echo z >&2"), "mynif").unwrap();
+        assert_eq!("x\u{fffd}\ny\nz\n", text);
+    }
+}
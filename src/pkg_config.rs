@@ -0,0 +1,86 @@
+//! pkg-config for cross builds, from `[target.<triple>.pkg-config]` in `erlangapp.toml`.
+//!
+//! Build scripts using the `pkg-config` crate find C libraries through the host's `.pc`
+//! files unless told otherwise, and a cross build then fails late with an obscure link
+//! error.  The sysroot and search path configured here reach those build scripts through
+//! the target-scoped variables the `pkg-config` crate reads, so build dependencies compiled
+//! for the host still see the host's libraries.  The `.pc` files of the listed packages are
+//! looked for before anything is compiled.
+
+use std::env;
+use std::path::PathBuf;
+
+/// Searched under the sysroot when no `path` is configured
+const DEFAULT_PATH: &[&str] = &["usr/lib/pkgconfig", "usr/share/pkgconfig"];
+
+#[derive(Debug, Default, PartialEq)]
+pub struct Settings {
+    /// `sysroot`: prefixed to the paths in the `.pc` files found
+    pub sysroot: Option<PathBuf>,
+    /// `path`: directories holding the target's `.pc` files, relative ones under the sysroot
+    pub path: Vec<PathBuf>,
+    /// `packages`: `.pc` files that must exist for the target
+    pub packages: Vec<String>,
+}
+
+impl Settings {
+    /// Directories searched for `.pc` files; the host's own are not
+    pub fn search_path(&self) -> Vec<PathBuf> {
+        let path = match self.path.is_empty() {
+            true => DEFAULT_PATH.iter().map(PathBuf::from).collect(),
+            false => self.path.clone(),
+        };
+        match self.sysroot {
+            Some(ref sysroot) => path.iter().map(|dir| sysroot.join(dir)).collect(),
+            None => path,
+        }
+    }
+
+    /// Variables for cargo building for `triple`, scoped to that triple
+    pub fn env(&self, triple: &str) -> Vec<(String, String)> {
+        let scoped = |name: &str| format!("{}_{}", name, triple.replace(['-', '.'], "_"));
+        let mut vars = vec![(scoped("PKG_CONFIG_ALLOW_CROSS"), "1".to_string())];
+        if let Some(ref sysroot) = self.sysroot {
+            vars.push((scoped("PKG_CONFIG_SYSROOT_DIR"), sysroot.to_string_lossy().into_owned()));
+        }
+        if let Ok(libdir) = env::join_paths(self.search_path()) {
+            vars.push((scoped("PKG_CONFIG_LIBDIR"), libdir.to_string_lossy().into_owned()));
+        }
+        vars
+    }
+
+    /// The listed packages without a `.pc` file in the search path
+    pub fn missing(&self) -> Vec<String> {
+        let search_path = self.search_path();
+        self.packages.iter()
+            .filter(|package| !search_path.iter().any(|dir| dir.join(format!("{}.pc", package)).is_file()))
+            .cloned()
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    #[test]
+    fn test_settings() {
+        let sysroot = env::temp_dir().join(format!("erlangapp-pkg-config-{}", std::process::id()));
+        fs::create_dir_all(sysroot.join("usr/lib/pkgconfig")).unwrap();
+        fs::write(sysroot.join("usr/lib/pkgconfig/zlib.pc"), "").unwrap();
+        let settings = Settings {
+            sysroot: Some(sysroot.clone()),
+            path: Vec::new(),
+            packages: vec!["zlib".to_string(), "openssl".to_string()],
+        };
+        assert_eq!(vec!["openssl"], settings.missing());
+        let env = settings.env("aarch64-unknown-linux-gnu");
+        assert_eq!(("PKG_CONFIG_ALLOW_CROSS_aarch64_unknown_linux_gnu".to_string(), "1".to_string()), env[0]);
+        assert_eq!(("PKG_CONFIG_SYSROOT_DIR_aarch64_unknown_linux_gnu".to_string(), sysroot.to_string_lossy().into_owned()), env[1]);
+        assert_eq!("PKG_CONFIG_LIBDIR_aarch64_unknown_linux_gnu", env[2].0);
+        let absolute = Settings { path: vec![PathBuf::from("/opt/pc")], ..Settings::default() };
+        assert_eq!(vec![PathBuf::from("/opt/pc")], absolute.search_path());
+        fs::remove_dir_all(sysroot).unwrap();
+    }
+}
@@ -0,0 +1,155 @@
+//! Per-platform `priv` layout: artifacts under `priv/crates/<crate>/<os>-<arch>/`, and a
+//! generated `src/<app>_nif_platform.erl` that picks the running VM's directory.
+//!
+//! Building once per target into the same `priv/` gives one release that loads the right
+//! library on every node of a mixed fleet.  Platforms are named the same way from a Rust
+//! target triple here and from `os:type()` and `erlang:system_info(system_architecture)`
+//! in the generated module.  C library variants (gnu and musl) share a directory, except
+//! with `--windows-toolchain=both`, which installs into `windows-<arch>-msvc` and
+//! `windows-<arch>-gnu`; the generated module prefers the directory of the VM's toolchain.
+
+use std::fs;
+use std::path::Path;
+
+use reload;
+use MsgError;
+use MsgError::*;
+
+/// `<os>-<arch>` directory name for a Rust target triple, e.g. `linux-x86_64`
+pub fn platform_dir(triple: &str) -> String {
+    format!("{}-{}", os(triple), arch(triple.split('-').next().unwrap_or(triple)))
+}
+
+/// OS as the generated module names it from `os:type()`
+fn os(triple: &str) -> &str {
+    let parts: Vec<&str> = triple.split('-').collect();
+    let has = |name: &str| parts.iter().any(|p| p.starts_with(name));
+    if has("windows") {
+        "windows"
+    } else if has("darwin") {
+        "macos"
+    } else if has("linux") {
+        "linux"
+    } else if has("solaris") || has("illumos") {
+        "sunos"
+    } else {
+        ["freebsd", "netbsd", "openbsd", "dragonfly"].iter().find(|os| has(os)).copied().unwrap_or("unknown")
+    }
+}
+
+/// Architecture, normalized as in the generated module's `normalize_arch/1`
+fn arch(arch: &str) -> String {
+    let arch = arch.to_ascii_lowercase();
+    match arch.as_str() {
+        "amd64" => "x86_64".to_string(),
+        "arm64" => "aarch64".to_string(),
+        "x86" | "i386" | "i486" | "i586" | "i686" => "x86".to_string(),
+        a if a.starts_with("armv") => "arm".to_string(),
+        a if a.starts_with("riscv64") => "riscv64".to_string(),
+        _ => arch,
+    }
+}
+
+/// Write `src/<app>_nif_platform.erl`, leaving it untouched if unchanged so it isn't
+/// recompiled.  Returns whether it was written.
+pub fn generate(appdir: &Path) -> Result<bool, MsgError> {
+    let app = reload::app_name(appdir);
+    let path = appdir.join("src").join(format!("{}_nif_platform.erl", app));
+    let text = render(&app);
+    if fs::read_to_string(&path).map(|old| old == text).unwrap_or(false) {
+        return Ok(false);
+    }
+    fs::create_dir_all(appdir.join("src"))
+        .and_then(|_| fs::write(&path, text))
+        .map(|_| true)
+        .map_err(|err| MsgIo("cannot write NIF platform module", err))
+}
+
+fn render(app: &str) -> String {
+    format!("\
+%% Generated by cargo-erlangapp; do not edit.
+%%
+%% Artifacts are installed per platform, in priv/crates/<crate>/<os>-<arch>/.  Load a NIF
+%% for the running VM with:
+%%     init() -> erlang:load_nif({app}_nif_platform:path(\"mynif\", \"libmynif\"), 0).
+-module({app}_nif_platform).
+-export([platform/0, crate_dir/1, path/2]).
+
+%% \"<os>-<arch>\" of the running VM, e.g. \"linux-x86_64\"
+platform() ->
+    os() ++ \"-\" ++ arch().
+
+%% Directory holding a crate's artifacts for the running VM.  Windows builds for both
+%% toolchains are in \"<os>-<arch>-msvc\" and \"<os>-<arch>-gnu\".
+crate_dir(Crate) ->
+    Dir = filename:join([priv_dir(), \"crates\", Crate, platform()]),
+    case os:type() of
+        {{win32, _}} ->
+            Suffixed = Dir ++ \"-\" ++ toolchain(),
+            case filelib:is_dir(Suffixed) of
+                true -> Suffixed;
+                false -> Dir
+            end;
+        _ ->
+            Dir
+    end.
+
+%% Path of one of a crate's artifacts for the running VM
+path(Crate, Artifact) ->
+    filename:join(crate_dir(Crate), Artifact).
+
+priv_dir() ->
+    case code:priv_dir({app}) of
+        {{error, bad_name}} ->
+            filename:join(filename:dirname(filename:dirname(code:which(?MODULE))), \"priv\");
+        Dir ->
+            Dir
+    end.
+
+os() ->
+    case os:type() of
+        {{win32, _}} -> \"windows\";
+        {{unix, darwin}} -> \"macos\";
+        {{unix, Os}} -> atom_to_list(Os)
+    end.
+
+arch() ->
+    Arch = case os:type() of
+        {{win32, _}} -> os:getenv(\"PROCESSOR_ARCHITECTURE\", \"x86\");
+        _ -> hd(string:split(erlang:system_info(system_architecture), \"-\"))
+    end,
+    normalize_arch(string:lowercase(Arch)).
+
+%% C toolchain the VM was built with: MinGW builds name it in their system architecture
+toolchain() ->
+    case string:find(erlang:system_info(system_architecture), \"mingw\") of
+        nomatch -> \"msvc\";
+        _ -> \"gnu\"
+    end.
+
+normalize_arch(\"amd64\") -> \"x86_64\";
+normalize_arch(\"arm64\") -> \"aarch64\";
+normalize_arch(\"i\" ++ [_, $8, $6]) -> \"x86\";
+normalize_arch(\"armv\" ++ _) -> \"arm\";
+normalize_arch(\"riscv64\" ++ _) -> \"riscv64\";
+normalize_arch(Arch) -> Arch.
+", app = app)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_platform_dir() {
+        assert_eq!("linux-x86_64", platform_dir("x86_64-unknown-linux-gnu"));
+        assert_eq!("linux-x86_64", platform_dir("x86_64-unknown-linux-musl"));
+        assert_eq!("macos-aarch64", platform_dir("aarch64-apple-darwin"));
+        assert_eq!("windows-x86_64", platform_dir("x86_64-pc-windows-msvc"));
+        assert_eq!("windows-x86", platform_dir("i686-pc-windows-gnu"));
+        assert_eq!("linux-arm", platform_dir("armv7-unknown-linux-gnueabihf"));
+        assert_eq!("freebsd-x86_64", platform_dir("x86_64-unknown-freebsd"));
+        assert_eq!("sunos-x86_64", platform_dir("x86_64-unknown-illumos"));
+        assert!(render("myapp").contains("-module(myapp_nif_platform)."));
+    }
+}
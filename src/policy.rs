@@ -0,0 +1,157 @@
+//! Dependency policy from `[policy]` in `erlangapp.toml`: allowed licenses, banned crates
+//! and allowed sources, checked against each crate's whole dependency graph.
+//!
+//! Native code shipped inside a release is subject to the same compliance rules as the
+//! Erlang code around it, so `build` refuses to compile a crate whose graph breaks the
+//! policy.  Path dependencies are the project's own code and are not checked for license
+//! or source.
+
+use metadata::{Metadata, Package};
+
+/// Registry sources that `crates.io` in `sources` stands for
+const CRATES_IO: &[&str] = &["registry+https://github.com/rust-lang/crates.io-index", "sparse+https://index.crates.io/"];
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Policy {
+    /// `policy.licenses`: SPDX identifiers a dependency's license expression must be
+    /// satisfiable with; any license when absent
+    pub licenses: Option<Vec<String>>,
+    /// `policy.banned`: crates that must not appear in a graph
+    pub banned: Vec<String>,
+    /// `policy.sources`: `crates.io` or URL prefixes of registries and git repositories
+    /// dependencies may come from; any source when absent
+    pub sources: Option<Vec<String>>,
+}
+
+/// A package breaking the policy
+#[derive(Debug, PartialEq)]
+pub struct Violation {
+    /// `name v1.2.3`
+    pub package: String,
+    pub problem: String,
+}
+
+/// Packages in `metadata`, a crate's resolved graph, breaking `policy`
+pub fn check(policy: &Policy, metadata: &Metadata) -> Vec<Violation> {
+    let mut violations = Vec::new();
+    for package in metadata.packages.iter() {
+        let mut violation = |problem: String| violations.push(Violation {
+            package: format!("{} v{}", package.name, package.version),
+            problem,
+        });
+        if policy.banned.contains(&package.name) {
+            violation("banned".to_string());
+        }
+        let source = match package.source {
+            Some(ref source) => source,
+            None => continue,  // a path dependency
+        };
+        if let Some(ref sources) = policy.sources {
+            if !sources.iter().any(|allowed| source_allowed(source, allowed)) {
+                violation(format!("source not allowed: {}", source));
+            }
+        }
+        if let Some(ref licenses) = policy.licenses {
+            if let Some(problem) = license_problem(package, licenses) {
+                violation(problem);
+            }
+        }
+    }
+    violations
+}
+
+fn license_problem(package: &Package, allowed: &[String]) -> Option<String> {
+    match package.license {
+        None => Some("no license expression".to_string()),
+        Some(ref license) => match license_allowed(license, allowed) {
+            Some(true) => None,
+            Some(false) => Some(format!("license not allowed: {}", license)),
+            None => Some(format!("cannot parse license: {}", license)),
+        },
+    }
+}
+
+/// Whether an SPDX expression can be satisfied with `allowed` licenses: one side of each
+/// `OR` and both sides of each `AND`.  An exception (`WITH`) only adds permissions, so the
+/// license it modifies decides.  The legacy `/` separator means `OR`.  `None` if it can't be
+/// parsed.
+pub fn license_allowed(expression: &str, allowed: &[String]) -> Option<bool> {
+    let spaced = expression.replace('(', " ( ").replace(')', " ) ").replace('/', " OR ");
+    let tokens: Vec<&str> = spaced.split_whitespace().collect();
+    let (result, rest) = or_expression(&tokens, allowed)?;
+    match rest.is_empty() {
+        true => Some(result),
+        false => None,
+    }
+}
+
+fn or_expression<'a>(tokens: &'a [&'a str], allowed: &[String]) -> Option<(bool, &'a [&'a str])> {
+    let (mut result, mut rest) = and_expression(tokens, allowed)?;
+    while let Some((&"OR", after)) = rest.split_first() {
+        let (right, after) = and_expression(after, allowed)?;
+        result |= right;
+        rest = after;
+    }
+    Some((result, rest))
+}
+
+fn and_expression<'a>(tokens: &'a [&'a str], allowed: &[String]) -> Option<(bool, &'a [&'a str])> {
+    let (mut result, mut rest) = license(tokens, allowed)?;
+    while let Some((&"AND", after)) = rest.split_first() {
+        let (right, after) = license(after, allowed)?;
+        result &= right;
+        rest = after;
+    }
+    Some((result, rest))
+}
+
+fn license<'a>(tokens: &'a [&'a str], allowed: &[String]) -> Option<(bool, &'a [&'a str])> {
+    match tokens.split_first()? {
+        (&"(", rest) => {
+            let (result, rest) = or_expression(rest, allowed)?;
+            match rest.split_first() {
+                Some((&")", rest)) => Some((result, rest)),
+                _ => None,
+            }
+        },
+        (&")", _) | (&"OR", _) | (&"AND", _) | (&"WITH", _) => None,
+        (id, rest) => {
+            let rest = match rest.split_first() {
+                Some((&"WITH", rest)) => rest.split_first()?.1,
+                _ => rest,
+            };
+            Some((allowed.iter().any(|a| a == id), rest))
+        },
+    }
+}
+
+/// `crates.io`, or a URL prefix of the source without its `registry+`, `sparse+` or `git+`
+fn source_allowed(source: &str, allowed: &str) -> bool {
+    if allowed == "crates.io" {
+        return CRATES_IO.contains(&source);
+    }
+    let url = source.split_once('+').map(|(_, url)| url).unwrap_or(source);
+    url.starts_with(allowed) || source.starts_with(allowed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_license_allowed() {
+        let allowed: Vec<String> = ["MIT", "Apache-2.0"].iter().map(|s| s.to_string()).collect();
+        assert_eq!(Some(true), license_allowed("MIT OR Apache-2.0", &allowed));
+        assert_eq!(Some(true), license_allowed("MIT/Apache-2.0", &allowed));
+        assert_eq!(Some(true), license_allowed("(MIT OR GPL-3.0) AND Apache-2.0 WITH LLVM-exception", &allowed));
+        assert_eq!(Some(false), license_allowed("MIT AND GPL-3.0", &allowed));
+        assert_eq!(Some(false), license_allowed("GPL-3.0-only", &allowed));
+        assert_eq!(None, license_allowed("MIT OR", &allowed));
+        assert_eq!(None, license_allowed("(MIT", &allowed));
+
+        assert!(source_allowed("registry+https://github.com/rust-lang/crates.io-index", "crates.io"));
+        assert!(source_allowed("sparse+https://index.crates.io/", "crates.io"));
+        assert!(source_allowed("git+https://github.com/myorg/nif-utils?rev=1a2b#1a2b3c", "https://github.com/myorg/"));
+        assert!(!source_allowed("git+https://github.com/other/nif-utils#1a2b3c", "https://github.com/myorg/"));
+    }
+}
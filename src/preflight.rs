@@ -0,0 +1,50 @@
+//! Checks before cross-compiling that the target's standard library is installed for the
+//! toolchain and that its configured linker exists, so a build of many crates stops at once
+//! with what to install rather than on the last crate.
+
+use std::path::{Path, PathBuf};
+use std::process;
+
+use env_policy;
+use erts;
+
+/// Sysroot of the rustc cargo would use in `dir`, where a `rust-toolchain.toml` may select
+/// the toolchain
+pub fn sysroot(dir: &Path, env: &env_policy::Policy) -> Option<PathBuf> {
+    let mut command = process::Command::new(env.var("RUSTC").unwrap_or_else(|| "rustc".to_string()));
+    command.args(["--print", "sysroot"]).current_dir(dir);
+    env.apply(&mut command);
+    let output = command.output().ok().filter(|output| output.status.success())?;
+    Some(PathBuf::from(String::from_utf8_lossy(&output.stdout).trim()))
+}
+
+/// True if the standard library for `triple` is installed in `sysroot`
+pub fn target_installed(sysroot: &Path, triple: &str) -> bool {
+    sysroot.join("lib").join("rustlib").join(triple).join("lib").is_dir()
+}
+
+/// True if the linker `program`, a path or a name looked up on `PATH`, exists
+pub fn linker_exists(program: &str) -> bool {
+    match Path::new(program).components().count() > 1 {
+        true => Path::new(program).is_file(),
+        false => erts::find_on_path(program).is_some() || erts::find_on_path(&format!("{}.exe", program)).is_some(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use std::fs;
+
+    #[test]
+    fn test_target_installed() {
+        let sysroot = env::temp_dir().join(format!("erlangapp-sysroot-{}", process::id()));
+        fs::create_dir_all(sysroot.join("lib/rustlib/x86_64-unknown-linux-gnu/lib")).unwrap();
+        assert!(target_installed(&sysroot, "x86_64-unknown-linux-gnu"));
+        assert!(!target_installed(&sysroot, "aarch64-unknown-linux-gnu"));
+        fs::remove_dir_all(sysroot).unwrap();
+        assert!(!linker_exists("/nonexistent/aarch64-linux-gnu-gcc"));
+        assert!(!linker_exists("no-such-linker-for-erlangapp"));
+    }
+}
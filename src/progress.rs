@@ -0,0 +1,185 @@
+//! Live progress display: one status line per crate, redrawn in place while building.
+//!
+//! Only used when stdout and stderr are terminals.  Everything printed during a build goes
+//! through the display, which clears its lines, prints the message and draws them again
+//! below it, so the status block stays at the bottom of the terminal.  A ticker thread
+//! keeps the elapsed times running.
+
+use std::env;
+use std::io::{self, Write};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+const TICK: Duration = Duration::from_millis(100);
+const SPINNER: &[char] = &['|', '/', '-', '\\'];
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum State {
+    Queued,
+    Building,
+    Copying,
+    Done,
+    Failed,
+}
+
+impl State {
+    fn label(&self) -> &'static str {
+        match *self {
+            State::Queued => "queued",
+            State::Building => "building",
+            State::Copying => "copying",
+            State::Done => "done",
+            State::Failed => "failed",
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Line {
+    name: String,
+    state: State,
+    started: Option<Instant>,
+    elapsed: Duration,
+}
+
+#[derive(Debug)]
+struct Block {
+    lines: Vec<Line>,
+    /// Lines currently drawn on the terminal
+    drawn: usize,
+    ticks: usize,
+    running: bool,
+}
+
+/// The status block of a build in progress
+#[derive(Debug)]
+pub struct Progress {
+    block: Arc<Mutex<Block>>,
+    ticker: Option<thread::JoinHandle<()>>,
+}
+
+/// True if a live display can be drawn
+pub fn available(stdout_terminal: bool, stderr_terminal: bool) -> bool {
+    stdout_terminal && stderr_terminal && env::var("TERM").map(|term| term != "dumb").unwrap_or(cfg!(windows))
+}
+
+impl Progress {
+    /// Show `crates`, all queued
+    pub fn start(crates: &[String]) -> Progress {
+        let block = Arc::new(Mutex::new(Block {
+            lines: crates.iter().map(|name| Line { name: name.clone(), state: State::Queued, started: None, elapsed: Duration::default() }).collect(),
+            drawn: 0,
+            ticks: 0,
+            running: true,
+        }));
+        block.lock().unwrap().redraw();
+        let ticking = Arc::clone(&block);
+        let ticker = thread::spawn(move || loop {
+            thread::sleep(TICK);
+            let mut block = ticking.lock().unwrap();
+            if !block.running {
+                break;
+            }
+            block.ticks += 1;
+            block.redraw();
+        });
+        Progress { block, ticker: Some(ticker) }
+    }
+
+    pub fn set(&self, name: &str, state: State) {
+        let mut block = self.block.lock().unwrap();
+        if let Some(line) = block.lines.iter_mut().find(|line| line.name == name) {
+            match state {
+                State::Building if line.started.is_none() => line.started = Some(Instant::now()),
+                State::Done | State::Failed => line.elapsed = line.started.map(|s| s.elapsed()).unwrap_or_default(),
+                _ => {},
+            }
+            line.state = state;
+        }
+        block.redraw();
+    }
+
+    /// Print `text` above the status block
+    pub fn print(&self, text: &str) {
+        let mut block = self.block.lock().unwrap();
+        block.clear();
+        println!("{}", text.trim_end_matches('\n'));
+        block.redraw();
+    }
+
+    /// Stop updating and leave the final state on the terminal
+    pub fn finish(mut self) {
+        self.stop();
+    }
+
+    fn stop(&mut self) {
+        if let Some(ticker) = self.ticker.take() {
+            let mut block = self.block.lock().unwrap();
+            block.running = false;
+            block.redraw();
+            block.drawn = 0;
+            drop(block);
+            let _ = ticker.join();
+        }
+    }
+}
+
+impl Drop for Progress {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
+
+impl Block {
+    /// Move back over the drawn lines and erase them
+    fn clear(&mut self) {
+        if self.drawn > 0 {
+            print!("\x1b[{}A\r\x1b[J", self.drawn);
+            self.drawn = 0;
+        }
+    }
+
+    fn redraw(&mut self) {
+        self.clear();
+        let width = self.lines.iter().map(|line| line.name.chars().count()).max().unwrap_or(0);
+        let columns = env::var("COLUMNS").ok().and_then(|c| c.parse().ok()).unwrap_or(80);
+        let spinner = SPINNER[self.ticks % SPINNER.len()];
+        let mut out = String::new();
+        for line in self.lines.iter() {
+            let text = render(line, width, spinner);
+            out.push_str(&text.chars().take(columns - 1).collect::<String>());
+            out.push('\n');
+        }
+        print!("{}", out);
+        let _ = io::stdout().flush();
+        self.drawn = self.lines.len();
+    }
+}
+
+/// `  / mynif   building  1.2s`
+fn render(line: &Line, width: usize, spinner: char) -> String {
+    let (mark, elapsed) = match line.state {
+        State::Queued => (' ', None),
+        State::Building | State::Copying => (spinner, line.started.map(|s| s.elapsed())),
+        State::Done => ('\u{2713}', Some(line.elapsed)),
+        State::Failed => ('\u{2717}', Some(line.elapsed)),
+    };
+    let text = format!("  {} {:width$}  {:8}  {}", mark, line.name, line.state.label(),
+                       elapsed.map(|e| format!("{:.1}s", e.as_secs_f64())).unwrap_or_default(), width = width);
+    text.trim_end().to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render() {
+        let line = |state, elapsed| Line { name: "mynif".into(), state, started: None, elapsed };
+        assert_eq!("    mynif      queued", render(&line(State::Queued, Duration::default()), 9, '|'));
+        assert_eq!("  \u{2713} mynif      done      1.5s", render(&line(State::Done, Duration::from_millis(1500)), 9, '|'));
+        assert_eq!("  \u{2717} mynif  failed    0.0s", render(&line(State::Failed, Duration::default()), 5, '|'));
+        assert_eq!("  / mynif  copying", render(&line(State::Copying, Duration::default()), 5, '/'));
+    }
+}
@@ -0,0 +1,222 @@
+//! Build provenance: `priv/crates/<crate>/PROVENANCE.json` records how each installed
+//! artifact was produced, so one found in the wild can be traced back by its SHA-256.
+//!
+//! Every artifact entry stands alone: the rustc and cargo versions, profile, target triple,
+//! cargo arguments and features, `--config` overrides and `RUSTFLAGS`, the git commit of
+//! the crate's sources and whether they had uncommitted changes, and the build time
+//! (`SOURCE_DATE_EPOCH`, for reproducible builds, else the clock).  A build that filters
+//! targets keeps the entries of the targets it didn't rebuild.
+//!
+//! Each installed artifact also gets a small `<artifact>.BUILD_INFO` next to it, saying
+//! which variant it is (crate version, target triple, profile, features) for whoever inspects
+//! a deployed release without the crate directory at hand.
+
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use json;
+
+use MsgError;
+use MsgError::*;
+
+pub const PROVENANCE_FILE: &str = "PROVENANCE.json";
+
+/// Appended to an installed artifact's file name for its build info
+pub const BUILD_INFO_SUFFIX: &str = ".BUILD_INFO";
+
+/// What is the same for every artifact of a crate's build
+#[derive(Debug)]
+pub struct Build {
+    /// `rustc -V`, e.g. `rustc 1.95.0 (4a4ef493e 2026-03-21)`
+    pub rustc: Option<String>,
+    /// `cargo -V`
+    pub cargo: Option<String>,
+    pub profile: String,
+    pub triple: String,
+    /// `--config` overrides
+    pub cargo_config: Vec<String>,
+    /// `RUSTFLAGS` as cargo sees it
+    pub rustflags: Option<String>,
+    pub git: Option<Git>,
+    /// RFC 3339, UTC
+    pub built_at: String,
+}
+
+/// The commit a crate's sources were checked out at
+#[derive(Debug, PartialEq)]
+pub struct Git {
+    pub commit: String,
+    /// Uncommitted changes under the crate directory
+    pub dirty: bool,
+}
+
+/// One installed artifact and how it was built
+#[derive(Debug)]
+pub struct Artifact {
+    pub target: String,
+    /// Relative to the application, with `/` separators
+    pub path: String,
+    pub sha256: String,
+    /// Arguments to `cargo rustc`
+    pub cargo_args: Vec<String>,
+    /// Features enabled for the target beyond the cargo arguments' own
+    pub features: Vec<String>,
+}
+
+impl Artifact {
+    fn to_json(&self, build: &Build) -> json::Value {
+        let string = |s: &str| json::Value::String(s.to_string());
+        let strings = |v: &[String]| json::Value::Array(v.iter().map(|s| string(s)).collect());
+        let optional = |v: &Option<String>| v.as_ref().map(|s| string(s)).unwrap_or(json::Value::Null);
+        let mut obj = json::Map::new();
+        obj.insert("target".to_string(), string(&self.target));
+        obj.insert("path".to_string(), string(&self.path));
+        obj.insert("sha256".to_string(), string(&self.sha256));
+        obj.insert("rustc".to_string(), optional(&build.rustc));
+        obj.insert("cargo".to_string(), optional(&build.cargo));
+        obj.insert("profile".to_string(), string(&build.profile));
+        obj.insert("triple".to_string(), string(&build.triple));
+        obj.insert("cargo_args".to_string(), strings(&self.cargo_args));
+        obj.insert("features".to_string(), strings(&self.features));
+        obj.insert("cargo_config".to_string(), strings(&build.cargo_config));
+        obj.insert("rustflags".to_string(), optional(&build.rustflags));
+        obj.insert("git_commit".to_string(), optional(&build.git.as_ref().map(|git| git.commit.clone())));
+        obj.insert("git_dirty".to_string(), build.git.as_ref().map(|git| json::Value::Bool(git.dirty)).unwrap_or(json::Value::Null));
+        obj.insert("built_at".to_string(), string(&build.built_at));
+        json::Value::Object(obj)
+    }
+}
+
+/// Write the provenance of `artifacts` into `dir`.  With `keep_others`, entries of other
+/// targets from an earlier build are kept.
+pub fn write(dir: &Path, build: &Build, artifacts: &[Artifact], keep_others: bool) -> Result<(), MsgError> {
+    let path = dir.join(PROVENANCE_FILE);
+    let mut entries: Vec<json::Value> = Vec::new();
+    if keep_others {
+        let previous = fs::read_to_string(&path).ok()
+            .and_then(|text| json::from_str::<json::Value>(&text).ok())
+            .and_then(|value| value.get("artifacts").and_then(|a| a.as_array()).cloned())
+            .unwrap_or_default();
+        entries.extend(previous.into_iter().filter(|entry| {
+            let target = entry.get("target").and_then(|t| t.as_str());
+            !artifacts.iter().any(|artifact| Some(artifact.target.as_str()) == target)
+        }));
+    }
+    entries.extend(artifacts.iter().map(|artifact| artifact.to_json(build)));
+    let mut obj = json::Map::new();
+    obj.insert("artifacts".to_string(), json::Value::Array(entries));
+    let text = json::to_string_pretty(&json::Value::Object(obj))
+        .map_err(|err| MsgDetail("cannot serialize provenance", err.to_string()))?;
+    fs::write(&path, text + "\n").map_err(|err| MsgIo("cannot write provenance", err))
+}
+
+/// The variant an installed artifact is
+#[derive(Debug)]
+pub struct BuildInfo {
+    pub crate_name: String,
+    pub version: String,
+    pub target: String,
+    pub triple: String,
+    pub profile: String,
+    /// Every feature the target was built with
+    pub features: Vec<String>,
+}
+
+impl BuildInfo {
+    fn to_json(&self) -> json::Value {
+        let string = |s: &str| json::Value::String(s.to_string());
+        let mut obj = json::Map::new();
+        obj.insert("crate".to_string(), string(&self.crate_name));
+        obj.insert("version".to_string(), string(&self.version));
+        obj.insert("target".to_string(), string(&self.target));
+        obj.insert("triple".to_string(), string(&self.triple));
+        obj.insert("profile".to_string(), string(&self.profile));
+        obj.insert("features".to_string(), json::Value::Array(self.features.iter().map(|f| string(f)).collect()));
+        json::Value::Object(obj)
+    }
+}
+
+/// Where the build info of the artifact at `artifact` goes
+pub fn build_info_path(artifact: &Path) -> PathBuf {
+    let mut name = artifact.as_os_str().to_owned();
+    name.push(BUILD_INFO_SUFFIX);
+    PathBuf::from(name)
+}
+
+/// Write the build info of the artifact at `artifact` next to it
+pub fn write_build_info(artifact: &Path, info: &BuildInfo) -> Result<(), MsgError> {
+    let text = json::to_string_pretty(&info.to_json())
+        .map_err(|err| MsgDetail("cannot serialize build info", err.to_string()))?;
+    fs::write(build_info_path(artifact), text + "\n").map_err(|err| MsgIo("cannot write build info", err))
+}
+
+/// First line of `<tool> -V`
+pub fn tool_version(mut command: process::Command) -> Option<String> {
+    let output = command.arg("-V").output().ok().filter(|output| output.status.success())?;
+    String::from_utf8_lossy(&output.stdout).lines().next().map(|line| line.trim().to_string())
+}
+
+/// The commit checked out in the repository holding `dir`, if any
+pub fn git(dir: &Path) -> Option<Git> {
+    let run = |args: &[&str]| process::Command::new("git").args(args).current_dir(dir).output().ok()
+        .filter(|output| output.status.success())
+        .map(|output| String::from_utf8_lossy(&output.stdout).trim().to_string());
+    Some(Git {
+        commit: run(&["rev-parse", "HEAD"])?,
+        dirty: !run(&["status", "--porcelain", "--", "."])?.is_empty(),
+    })
+}
+
+/// `SOURCE_DATE_EPOCH`, else now, as RFC 3339
+pub fn timestamp() -> String {
+    let secs = env::var("SOURCE_DATE_EPOCH").ok().and_then(|s| s.parse().ok())
+        .unwrap_or_else(|| SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0));
+    rfc3339(secs)
+}
+
+fn rfc3339(secs: u64) -> String {
+    let (days, time) = (secs / 86400, secs % 86400);
+    // civil date from days since 1970-01-01, after Howard Hinnant's `civil_from_days`
+    let z = days as i64 + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z - era * 146097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+    format!("{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z", year, month, day, time / 3600, time % 3600 / 60, time % 60)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rfc3339() {
+        assert_eq!("1970-01-01T00:00:00Z", rfc3339(0));
+        assert_eq!("2000-02-29T12:34:56Z", rfc3339(951827696));
+        assert_eq!("2026-10-15T00:00:00Z", rfc3339(1792022400));
+    }
+
+    #[test]
+    fn test_build_info() {
+        let info = BuildInfo {
+            crate_name: "mynif".into(),
+            version: "0.1.0".into(),
+            target: "mynif (cdylib)".into(),
+            triple: "x86_64-unknown-linux-gnu".into(),
+            profile: "release".into(),
+            features: vec!["default".into(), "nif_version_2_16".into()],
+        };
+        assert_eq!(PathBuf::from("priv/crates/mynif/libmynif.so.BUILD_INFO"), build_info_path(Path::new("priv/crates/mynif/libmynif.so")));
+        assert_eq!("{\n  \"crate\": \"mynif\",\n  \"features\": [\n    \"default\",\n    \"nif_version_2_16\"\n  ],\n  \
+                    \"profile\": \"release\",\n  \"target\": \"mynif (cdylib)\",\n  \
+                    \"triple\": \"x86_64-unknown-linux-gnu\",\n  \"version\": \"0.1.0\"\n}",
+                   json::to_string_pretty(&info.to_json()).unwrap());
+    }
+}
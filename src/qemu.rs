@@ -0,0 +1,68 @@
+//! qemu-user as cargo's runner for cross targets, from `runner` in `[target.<triple>]` of
+//! `erlangapp.toml`, so `test` and `bench` can run a foreign architecture's binaries on the
+//! build machine.
+//!
+//! `runner = "qemu"` picks the emulator for the triple's architecture, with the target's
+//! pkg-config sysroot, if configured, as its library prefix (`-L`); any other value is the
+//! runner command itself.
+
+use std::path::Path;
+
+/// qemu-user emulator names by the first component of a target triple
+const EMULATORS: &[(&str, &str)] = &[
+    ("aarch64", "aarch64"),
+    ("aarch64_be", "aarch64_be"),
+    ("arm", "arm"),
+    ("armv5te", "arm"),
+    ("armv7", "arm"),
+    ("thumbv7neon", "arm"),
+    ("i586", "i386"),
+    ("i686", "i386"),
+    ("loongarch64", "loongarch64"),
+    ("mips", "mips"),
+    ("mipsel", "mipsel"),
+    ("mips64", "mips64"),
+    ("mips64el", "mips64el"),
+    ("powerpc", "ppc"),
+    ("powerpc64", "ppc64"),
+    ("powerpc64le", "ppc64le"),
+    ("riscv32gc", "riscv32"),
+    ("riscv64gc", "riscv64"),
+    ("s390x", "s390x"),
+    ("sparc64", "sparc64"),
+    ("x86_64", "x86_64"),
+];
+
+/// The qemu-user emulator running `triple`'s binaries
+pub fn emulator(triple: &str) -> Option<String> {
+    let arch = triple.split('-').next()?;
+    EMULATORS.iter().find(|&&(a, _)| a == arch).map(|&(_, qemu)| format!("qemu-{}", qemu))
+}
+
+/// The runner command for `triple` from a configured `runner`; `None` for `"qemu"` on an
+/// architecture qemu-user doesn't emulate
+pub fn runner(configured: &str, triple: &str, sysroot: Option<&Path>) -> Option<String> {
+    if configured != "qemu" {
+        return Some(configured.to_string());
+    }
+    let emulator = emulator(triple)?;
+    Some(match sysroot {
+        Some(sysroot) => format!("{} -L {}", emulator, sysroot.display()),
+        None => emulator,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_runner() {
+        assert_eq!(Some("qemu-aarch64 -L /sysroots/aarch64".to_string()),
+                   runner("qemu", "aarch64-unknown-linux-gnu", Some(Path::new("/sysroots/aarch64"))));
+        assert_eq!(Some("qemu-arm".to_string()), runner("qemu", "armv7-unknown-linux-gnueabihf", None));
+        assert_eq!(Some("qemu-ppc64le".to_string()), runner("qemu", "powerpc64le-unknown-linux-gnu", None));
+        assert_eq!(None, runner("qemu", "wasm32-wasip1", None));
+        assert_eq!(Some("wasmtime".to_string()), runner("wasmtime", "wasm32-wasip1", None));
+    }
+}
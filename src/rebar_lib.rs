@@ -0,0 +1,62 @@
+//! rebar3's copies of the application's `priv/` in `_build/<profile>/lib/<app>/priv`.
+//!
+//! rebar3 copies `priv/` into its build directory when it compiles the application, so a NIF
+//! rebuilt outside of that isn't seen by `rebar3 shell` until the next compile.  With
+//! `rebar-lib-dirs`, `build` also installs fresh artifacts into each copy that exists.  A
+//! `priv` that rebar3 symlinked to the application's own needs nothing.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// `_build/<profile>/lib/<app>/priv` directories in `appdir` and the directories above it,
+/// where an umbrella's `_build` is
+pub fn priv_dirs(appdir: &Path, app: &str) -> Vec<PathBuf> {
+    let appdir = appdir.canonicalize().unwrap_or_else(|_| appdir.to_path_buf());
+    let mut dirs = Vec::new();
+    for root in appdir.ancestors() {
+        let profiles = match fs::read_dir(root.join("_build")) {
+            Ok(entries) => entries,
+            Err(_) => continue,
+        };
+        let mut found: Vec<PathBuf> = profiles.filter_map(Result::ok)
+            .map(|profile| profile.path().join("lib").join(app).join("priv"))
+            .filter(|priv_dir| priv_dir.is_dir())
+            .collect();
+        found.sort();
+        dirs.extend(found);
+    }
+    dirs
+}
+
+/// `_build/<profile>/lib/<app>/ebin`, where rebar3 compiles the application, in `appdir` or
+/// the nearest directory above it that has one
+pub fn ebin_dir(appdir: &Path, app: &str, profile: &str) -> Option<PathBuf> {
+    let appdir = appdir.canonicalize().unwrap_or_else(|_| appdir.to_path_buf());
+    appdir.ancestors()
+        .map(|root| root.join("_build").join(profile).join("lib").join(app).join("ebin"))
+        .find(|ebin| ebin.is_dir())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use std::process;
+
+    #[test]
+    fn test_priv_dirs() {
+        let root = env::temp_dir().join(format!("cargo-erlangapp-rebar-lib-{}", process::id()));
+        let appdir = root.join("apps").join("my_app");
+        for dir in ["_build/default/lib/my_app/priv", "_build/test/lib/my_app", "_build/test/lib/other/priv",
+                    "apps/my_app/_build/prod/lib/my_app/priv"].iter() {
+            fs::create_dir_all(root.join(dir)).unwrap();
+        }
+        let root = root.canonicalize().unwrap();
+        assert_eq!(vec![root.join("apps/my_app/_build/prod/lib/my_app/priv"), root.join("_build/default/lib/my_app/priv")],
+                   priv_dirs(&appdir, "my_app"));
+        assert_eq!(None, ebin_dir(&appdir, "my_app", "default"));
+        fs::create_dir_all(root.join("_build/default/lib/my_app/ebin")).unwrap();
+        assert_eq!(Some(root.join("_build/default/lib/my_app/ebin")), ebin_dir(&appdir, "my_app", "default"));
+        fs::remove_dir_all(&root).unwrap();
+    }
+}
@@ -0,0 +1,126 @@
+//! Generated `src/<app>_nif_reload.erl`: NIF paths, loading and upgrades for the application's crates.
+
+use std::fs;
+use std::path::Path;
+
+use MsgError;
+use MsgError::*;
+
+/// A NIF library as installed in `priv/crates/<crate_name>/`
+#[derive(Debug)]
+pub struct Nif {
+    pub crate_name: String,
+    /// Erlang module named in `rustler::init!`, if it could be found
+    pub module: Option<String>,
+    /// Installed file name without extension, e.g. `libmynif`, or `libmynif-1.4.2` when
+    /// artifacts are versioned
+    pub library: String,
+    pub version: String,
+}
+
+/// Application name: the `src/<app>.app.src` stem, else the directory name
+pub fn app_name(appdir: &Path) -> String {
+    appdir.join("src").read_dir().ok()
+        .and_then(|entries| entries
+            .filter_map(Result::ok)
+            .filter_map(|e| e.file_name().to_string_lossy().strip_suffix(".app.src").map(String::from))
+            .next())
+        .or_else(|| appdir.file_name().map(|n| n.to_string_lossy().into_owned()))
+        .unwrap_or_default()
+}
+
+/// Find the module name in a rustler NIF's `init!("module", ...)`
+pub fn nif_module(crate_dir: &Path) -> Option<String> {
+    let src = fs::read_to_string(crate_dir.join("src").join("lib.rs")).ok()?;
+    let rest = &src[src.find("init!(")? + "init!(".len()..];
+    let rest = rest.trim_start().strip_prefix('"')?;
+    Some(rest[..rest.find('"')?].to_string())
+}
+
+/// Write `src/<app>_nif_reload.erl`, leaving it untouched if unchanged so it isn't recompiled.
+/// With `platform_dirs`, libraries are looked up through `<app>_nif_platform`.
+pub fn generate(appdir: &Path, nifs: &[Nif], platform_dirs: bool) -> Result<(), MsgError> {
+    let app = app_name(appdir);
+    let path = appdir.join("src").join(format!("{}_nif_reload.erl", app));
+    let text = render(&app, nifs, platform_dirs);
+    if fs::read_to_string(&path).map(|old| old == text).unwrap_or(false) {
+        return Ok(());
+    }
+    fs::create_dir_all(appdir.join("src"))
+        .and_then(|_| fs::write(&path, text))
+        .map_err(|err| MsgIo("cannot write NIF reload module", err))
+}
+
+fn render(app: &str, nifs: &[Nif], platform_dirs: bool) -> String {
+    let crates: Vec<String> = nifs.iter()
+        .map(|nif| format!("     {{\"{}\", {}, \"{}\", \"{}\"}}", nif.crate_name,
+                           nif.module.as_ref().map(|m| format!("'{}'", m)).unwrap_or_else(|| "undefined".to_string()),
+                           nif.library, nif.version))
+        .collect();
+    let crate_dir = match platform_dirs {
+        true => format!("{}_nif_platform:crate_dir(Crate)", app),
+        false => "filename:join([priv_dir(), \"crates\", Crate])".to_string(),
+    };
+    format!("\
+%% Generated by cargo-erlangapp; do not edit.
+%%
+%% Call path/1 from a NIF module's on_load function:
+%%     init() -> erlang:load_nif({app}_nif_reload:path(\"mynif\"), 0).
+-module({app}_nif_reload).
+-export([crates/0, priv_dir/0, path/1, load/0, upgrade/1, versions/0]).
+
+%% {{Crate, Module, Library, Version}} for each NIF crate
+crates() ->
+    [
+{crates}
+    ].
+
+priv_dir() ->
+    case code:priv_dir({app}) of
+        {{error, bad_name}} ->
+            filename:join(filename:dirname(filename:dirname(code:which(?MODULE))), \"priv\");
+        Dir ->
+            Dir
+    end.
+
+%% Library path for erlang:load_nif/2: exactly the artifact of this build, never a stale
+%% library left beside it
+path(Crate) ->
+    {{Crate, _Module, Library, Version}} = lists:keyfind(Crate, 1, crates()),
+    persistent_term:put({{?MODULE, Crate}}, Version),
+    filename:join({crate_dir}, Library).
+
+%% Load every NIF module
+load() ->
+    [{{Module, code:ensure_loaded(Module)}} || {{_, Module, _, _}} <- crates(), Module =/= undefined].
+
+%% Load new code for a NIF module; its on_load function loads the new library
+upgrade(Module) ->
+    code:soft_purge(Module),
+    code:load_file(Module).
+
+%% Version of each NIF library loaded through path/1
+versions() ->
+    [{{Crate, persistent_term:get({{?MODULE, Crate}}, not_loaded)}} || {{Crate, _, _, _}} <- crates()].
+", app = app, crates = crates.join(",\n"), crate_dir = crate_dir)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render() {
+        let nifs = [
+            Nif { crate_name: "a".into(), module: Some("a_nif".into()), library: "liba".into(), version: "1.0.0".into() },
+            Nif { crate_name: "b".into(), module: None, library: "libb".into(), version: "0.2.0".into() },
+        ];
+        let text = render("myapp", &nifs, false);
+        assert!(text.contains("-module(myapp_nif_reload)."));
+        assert!(text.contains("    [\n     {\"a\", 'a_nif', \"liba\", \"1.0.0\"},\n     {\"b\", undefined, \"libb\", \"0.2.0\"}\n    ]."));
+        assert!(text.contains("code:priv_dir(myapp)"));
+        assert!(!text.contains("wildcard"));
+        assert!(text.contains("filename:join(filename:join([priv_dir(), \"crates\", Crate]), Library)."));
+        assert!(render("myapp", &nifs, true).contains("filename:join(myapp_nif_platform:crate_dir(Crate), Library)."));
+    }
+}
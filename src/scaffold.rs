@@ -0,0 +1,452 @@
+//! `init`, `new`, `add-nif` and `add-port` commands: scaffolding for crates in an Erlang application.
+
+use std::env;
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::Path;
+use std::process;
+
+use config::CONFIG_FILE;
+use MsgError;
+use MsgError::*;
+
+static STARTER_CONFIG: &str = "\
+# cargo-erlangapp configuration
+#
+# Settings in this file apply to every crate under crates/.
+
+# Generations of each installed artifact to keep (see prune-artifacts).
+#keep-artifacts = 3
+
+# Keep the artifact each build replaces as <name>.prev; rollback swaps it back in.
+#backup = true
+
+# Install dylibs as lib<name>-<version>.so, so a stale library is never loaded.
+#versioned-artifacts = true
+
+# Install into priv/crates/<crate>/<os>-<arch>/, building once per --target, and generate
+# src/<app>_nif_platform.erl to pick the running VM's directory.
+#platform-dirs = true
+
+# Also keep each build in priv/crates/<crate>/<profile>/, so switch-profile can flip between
+# debug and release builds without rebuilding.
+#profile-dirs = true
+
+# Directories to install artifacts into: the first is built into, the others get copies.
+#priv-dirs = [\"priv\", \"rel/overlay/priv\"]
+
+# Also install into rebar3's _build/<profile>/lib/<app>/priv, so `rebar3 shell` sees rebuilt NIFs.
+#rebar-lib-dirs = true
+
+# Strip debug information and local symbols from installed artifacts, keeping the NIF and
+# driver entry points and the symbols matching these patterns.
+#strip = true
+#strip-keep = [\"my_debug_*\"]
+
+# Fail on crates with nothing to install (only lib, rlib or proc-macro targets) instead of
+# skipping them.
+#strict-targets = true
+
+# Make compiler warnings errors in every crate.  Otherwise builds end with a count of each
+# crate's warnings.
+#deny-warnings = true
+
+# Lint levels for every crate, as in a Cargo.toml [lints] table, passed to rustc and to
+# clippy (cargo-erlangapp clippy).
+#[lints.rust]
+#unsafe_op_in_unsafe_fn = \"deny\"
+#[lints.clippy]
+#unwrap_used = \"deny\"
+
+# When cross-compiling, crates with target = \"host\" in [package.metadata.erlangapp] are
+# built for the host and installed into priv/host-tools/<crate>; skip leaves them out.
+#host-tools = \"skip\"
+
+# Seconds to wait for another cargo-erlangapp working on this application (0: fail at once).
+#lock-timeout = 300
+
+# Warn when a crate's version leaves the application's (vsn in src/*.app.src or mix.exs):
+# any (no check), major, minor or exact.
+#version-policy = \"minor\"
+
+# Generate src/<app>_nif_reload.erl (NIF paths, loading and upgrades) when building.
+#nif-reload-module = true
+
+# Generate each rustler NIF's Erlang module (src/<module>.erl) with -spec attributes
+# derived from the Rust signatures.  Hand-written modules are never replaced.
+#nif-stubs = true
+
+# Erlang used by the build scripts of rustler_sys / erlang_nif-sys (default: $ERL, then PATH).
+#erl = \"/usr/local/lib/erlang/bin/erl\"
+
+# Oldest OTP release supported: NIFs built against a newer NIF API fail the build.
+#min-otp = \"26\"
+
+# Build bin targets with their required-features enabled instead of skipping them.
+#enable-required-features = true
+
+# Cargo profile used for each rebar3 profile (REBAR_PROFILE or --rebar-profile).
+# `prod` maps to `release` by default.
+#[rebar-profiles]
+#prod = \"release\"
+#test = \"debug\"
+
+# Directories under crates/ to process: only those in include, if given, and none in exclude.
+#[crates]
+#include = [\"my_nif\"]
+#exclude = [\"scratch\"]
+
+# Crates outside this application, built and installed into priv/crates/<name>.
+#[external-crates]
+#shared = { path = \"../shared/crates/shared\", artifact = \"shared_nif\" }
+
+# The complete set of targets to build and install, per crate; anything else is ignored.
+#[targets]
+#my_nif = [\"my_nif\"]
+
+# Install a crate's dylib as Elixir.<Module>.so, for :erlang.load_nif in Elixir projects.
+#[elixir-modules]
+#my_nif = \"MyApp.Native\"
+
+# Crates whose dynamic library is a port driver (installed as <name>.so for erl_ddll).
+#[driver]
+#crates = [\"my_drv\"]
+
+# Crates whose port programs link a static C runtime on Windows (no VC++ redistributable).
+#[static-crt]
+#crates = [\"my_port\"]
+
+# Crates whose staticlib targets are built, installed with a .link-args file.
+#[staticlib]
+#crates = [\"embedded_nif\"]
+#dir = \"c_src/static\"
+
+# Extra rustc arguments per target kind (nif, driver, bin, staticlib), for every build or
+# for build targets matching a cfg(...).  defaults = false drops the built-in macOS NIF
+# link arguments (-undefined dynamic_lookup).
+#[link]
+#defaults = false
+#bin = [\"-C\", \"target-feature=+crt-static\"]
+#[link.'cfg(target_os = \"linux\")']
+#nif = [\"-C\", \"link-arg=-Wl,-z,noexecstack\"]
+
+# Environment of cargo processes.  remove defaults to [\"ERL_LIBS\", \"REBAR_*\", \"MIX_*\"].
+#[env]
+#remove = [\"ERL_LIBS\", \"REBAR_*\", \"MIX_*\", \"CARGO_TARGET_DIR\"]
+#keep = [\"REBAR_CACHE_DIR\"]
+#[env.set]
+#RUSTUP_TOOLCHAIN = \"stable\"
+
+# Variables set for cargo when building for a --target, e.g. the cross toolchain.  linker
+# stands for CARGO_TARGET_<TRIPLE>_LINKER.
+#[target.aarch64-unknown-linux-gnu.env]
+#CC = \"aarch64-linux-gnu-gcc\"
+#AR = \"aarch64-linux-gnu-ar\"
+#linker = \"aarch64-linux-gnu-gcc\"
+
+# Where pkg-config finds the target's C libraries (path defaults to usr/lib/pkgconfig and
+# usr/share/pkgconfig under the sysroot); build fails early if a listed package's .pc is missing.
+#[target.aarch64-unknown-linux-gnu.pkg-config]
+#sysroot = \"/opt/sysroots/aarch64\"
+#path = [\"usr/lib/aarch64-linux-gnu/pkgconfig\"]
+#packages = [\"openssl\", \"zlib\"]
+
+# Runs test and bench binaries for a --target with qemu-user (qemu-<arch> -L <pkg-config
+# sysroot>), or with the given command.
+#[target.aarch64-unknown-linux-gnu]
+#runner = \"qemu\"
+
+# Settings for build targets matching a cfg(...): cargo features, variables, output layout
+# (platform-dirs, versioned-artifacts) and [link] arguments.
+#[target.'cfg(unix)']
+#features = [\"epoll\"]
+#platform-dirs = true
+#[target.'cfg(unix)'.env]
+#CFLAGS = \"-fPIC\"
+#[target.'cfg(windows)'.link]
+#bin = [\"-C\", \"target-feature=+crt-static\"]
+
+# Licenses, banned crates and sources allowed in every crate's dependency graph.
+#[policy]
+#licenses = [\"MIT\", \"Apache-2.0\"]
+#banned = [\"openssl-sys\"]
+#sources = [\"crates.io\"]
+
+# Version requirements every crate depending on these packages must declare.
+#[shared-dependencies]
+#rustler = \"0.36\"
+
+# Dependency overrides applied to every crate, as cargo's [patch]; paths are relative to
+# the application.
+#[patch.crates-io]
+#openssl-src = { git = \"https://github.com/my-org/openssl-src-rs\", branch = \"backport\" }
+
+# Rust constants and structs generated from the -define and -record definitions in include/*.hrl.
+#[codegen]
+#crate = \"my_nif\"
+#module = \"src/erl_defines.rs\"
+#records = \"rustler\"   # or \"term\" for port programs
+";
+
+static GITIGNORE_ENTRIES: &[&str] = &["/crates/*/target/", "/crates/target/", "/priv/crates/"];
+
+/// True if `appdir` has the marks of a rebar3, mix or erlang.mk application
+pub fn looks_like_erlang_app(appdir: &Path) -> bool {
+    if appdir.join("rebar.config").is_file() || appdir.join("mix.exs").is_file()
+        || appdir.join("erlang.mk").is_file() {
+        return true;
+    }
+    appdir.join("src").read_dir()
+        .map(|entries| entries
+            .filter_map(Result::ok)
+            .any(|e| e.file_name().to_string_lossy().ends_with(".app.src")))
+        .unwrap_or(false)
+}
+
+/// Create `crates/`, a starter `erlangapp.toml` and `.gitignore` entries, and optionally
+/// a first NIF crate.  Anything that already exists is left alone.
+pub fn init(appdir: &Path, nif_name: Option<&str>, template: Option<&str>) -> Result<(), MsgError> {
+    if !looks_like_erlang_app(appdir) {
+        return Err(Msg("no rebar.config, mix.exs, erlang.mk or src/*.app.src found; run init from an Erlang application directory"));
+    }
+
+    fs::create_dir_all(appdir.join("crates"))
+        .map_err(|err| MsgIo("cannot create crates/ directory", err))?;
+
+    let config_path = appdir.join(CONFIG_FILE);
+    if !config_path.exists() {
+        fs::write(&config_path, STARTER_CONFIG)
+            .map_err(|err| MsgIo("cannot write erlangapp.toml", err))?;
+        println!("Created {}", CONFIG_FILE);
+    }
+
+    update_gitignore(appdir)?;
+
+    if let Some(name) = nif_name {
+        new_crate(appdir, name, Skeleton::Nif, template)?;
+    }
+    Ok(())
+}
+
+/// Append whichever of our ignore entries are missing from `.gitignore`
+fn update_gitignore(appdir: &Path) -> Result<(), MsgError> {
+    let path = appdir.join(".gitignore");
+    let existing = fs::read_to_string(&path).unwrap_or_default();
+    let missing: Vec<&str> = GITIGNORE_ENTRIES.iter()
+        .filter(|entry| !existing.lines().any(|line| line.trim() == **entry))
+        .cloned()
+        .collect();
+    if missing.is_empty() {
+        return Ok(());
+    }
+
+    let mut text = String::new();
+    if !existing.is_empty() && !existing.ends_with('\n') {
+        text.push('\n');
+    }
+    for entry in missing {
+        text.push_str(entry);
+        text.push('\n');
+    }
+    OpenOptions::new().create(true).append(true).open(&path)
+        .and_then(|mut f| f.write_all(text.as_bytes()))
+        .map_err(|err| MsgIo("cannot update .gitignore", err))?;
+    println!("Updated .gitignore");
+    Ok(())
+}
+
+/// Built-in crate skeletons
+#[derive(Debug, Clone, Copy)]
+pub enum Skeleton {
+    /// Plain library crate, for code shared between other crates
+    Lib,
+    /// rustler NIF library
+    Nif,
+    /// Port program speaking `{packet, 4}` on stdin/stdout
+    Port,
+}
+
+/// Create `crates/<name>` from a template (local directory or git URL) or a built-in skeleton.
+///
+/// Template files have `{{crate_name}}` and `{{app_name}}` substituted.
+pub fn new_crate(appdir: &Path, name: &str, skeleton: Skeleton, template: Option<&str>) -> Result<(), MsgError> {
+    if !is_package_name(name) {
+        return Err(MsgDetail("not a valid crate name (letters, digits, `-` and `_`, not starting with a digit)",
+                             name.to_string()));
+    }
+    let crate_dir = appdir.join("crates").join(name);
+    if crate_dir.exists() {
+        return Err(MsgDetail("crate directory already exists", crate_dir.to_string_lossy().into_owned()));
+    }
+
+    let created = match template {
+        Some(template) => {
+            let app_name = appdir.file_name().map(|s| s.to_string_lossy().into_owned()).unwrap_or_default();
+            let substitutions = [("{{crate_name}}", name), ("{{app_name}}", app_name.as_str())];
+            if is_git_url(template) {
+                let checkout = env::temp_dir().join(format!("cargo-erlangapp-template-{}", process::id()));
+                let result = git_clone(template, &checkout)
+                    .and_then(|_| copy_template(&checkout, &crate_dir, &substitutions));
+                let _ = fs::remove_dir_all(&checkout);
+                result
+            } else {
+                copy_template(Path::new(template), &crate_dir, &substitutions)
+            }
+        },
+        None => write_skeleton(&crate_dir, name, skeleton),
+    };
+    // don't leave a half-written crate for the next build to trip over
+    if created.is_err() {
+        let _ = fs::remove_dir_all(&crate_dir);
+    }
+    created?;
+    println!("Created crates/{}", name);
+    Ok(())
+}
+
+/// A name cargo accepts for a package, which is also safe as a directory name under `crates/`
+fn is_package_name(name: &str) -> bool {
+    !name.is_empty() && !name.starts_with(|c: char| c.is_ascii_digit())
+        && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+}
+
+fn is_git_url(template: &str) -> bool {
+    ["http://", "https://", "ssh://", "git://", "git@"].iter().any(|p| template.starts_with(p))
+        || template.ends_with(".git")
+}
+
+fn git_clone(url: &str, dest: &Path) -> Result<(), MsgError> {
+    let status = process::Command::new("git")
+        .args(["clone", "--quiet", "--depth", "1", url])
+        .arg(dest)
+        .status()
+        .map_err(|err| MsgIo("cannot start git", err))?;
+    match status.success() {
+        true => Ok(()),
+        false => Err(MsgDetail("cannot clone template", url.to_string())),
+    }
+}
+
+/// Recursively copy a template directory, skipping `.git` and `target`, substituting
+/// placeholders in file names and UTF-8 file contents.
+fn copy_template(from: &Path, to: &Path, substitutions: &[(&str, &str)]) -> Result<(), MsgError> {
+    let entries = from.read_dir()
+        .map_err(|err| MsgIo("cannot read template directory", err))?;
+    fs::create_dir_all(to)
+        .map_err(|err| MsgIo("cannot create crate directory", err))?;
+
+    for entry in entries {
+        let entry = entry.map_err(|err| MsgIo("cannot read template directory", err))?;
+        let file_name = entry.file_name().to_string_lossy().into_owned();
+        if file_name == ".git" || file_name == "target" {
+            continue;
+        }
+        let dest = to.join(substitute(&file_name, substitutions));
+        let file_type = entry.file_type().map_err(|err| MsgIo("cannot read template directory", err))?;
+        if file_type.is_dir() {
+            copy_template(&entry.path(), &dest, substitutions)?;
+        } else {
+            let bytes = fs::read(entry.path()).map_err(|err| MsgIo("cannot read template file", err))?;
+            let bytes = match String::from_utf8(bytes) {
+                Ok(text) => substitute(&text, substitutions).into_bytes(),
+                Err(err) => err.into_bytes(),
+            };
+            fs::write(&dest, bytes).map_err(|err| MsgIo("cannot write crate file", err))?;
+        }
+    }
+    Ok(())
+}
+
+fn substitute(text: &str, substitutions: &[(&str, &str)]) -> String {
+    substitutions.iter().fold(text.to_string(), |acc, &(key, value)| acc.replace(key, value))
+}
+
+fn write_skeleton(crate_dir: &Path, name: &str, skeleton: Skeleton) -> Result<(), MsgError> {
+    let (lib_section, dependencies, src_file, src) = match skeleton {
+        Skeleton::Lib => ("", "", "lib.rs", LIB_SRC.to_string()),
+        Skeleton::Nif => ("\n[lib]\ncrate-type = [\"cdylib\"]\n", "rustler = \"0.36\"\n", "lib.rs",
+                          NIF_SRC.replace("{{crate_name}}", name)),
+        Skeleton::Port => ("", "", "main.rs", PORT_SRC.to_string()),
+    };
+    let manifest = format!("\
+[package]
+name = \"{}\"
+version = \"0.1.0\"
+edition = \"2021\"
+{}
+[dependencies]
+{}", name, lib_section, dependencies);
+
+    fs::create_dir_all(crate_dir.join("src"))
+        .map_err(|err| MsgIo("cannot create crate directory", err))?;
+    fs::write(crate_dir.join("Cargo.toml"), manifest)
+        .and_then(|_| fs::write(crate_dir.join("src").join(src_file), src))
+        .map_err(|err| MsgIo("cannot write crate skeleton", err))
+}
+
+static LIB_SRC: &str = "\
+pub fn add(a: i64, b: i64) -> i64 {
+    a + b
+}
+";
+
+static NIF_SRC: &str = "\
+#[rustler::nif]
+fn add(a: i64, b: i64) -> i64 {
+    a + b
+}
+
+rustler::init!(\"{{crate_name}}\");
+";
+
+static PORT_SRC: &str = "\
+use std::io::{self, Read, Write};
+
+/// Echo every `{packet, 4}` framed message back to the Erlang port owner.
+fn main() -> io::Result<()> {
+    let stdin = io::stdin();
+    let stdout = io::stdout();
+    let mut input = stdin.lock();
+    let mut output = stdout.lock();
+    loop {
+        let mut len = [0u8; 4];
+        if input.read_exact(&mut len).is_err() {
+            return Ok(()); // port closed
+        }
+        let mut msg = vec![0u8; u32::from_be_bytes(len) as usize];
+        input.read_exact(&mut msg)?;
+        output.write_all(&len)?;
+        output.write_all(&msg)?;
+        output.flush()?;
+    }
+}
+";
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_is_git_url() {
+        assert!(is_git_url("https://github.com/org/template"));
+        assert!(is_git_url("git@github.com:org/template.git"));
+        assert!(is_git_url("../templates/nif.git"));
+        assert!(!is_git_url("../templates/nif"));
+    }
+
+    #[test]
+    fn test_new_crate_name() {
+        assert!(is_package_name("my_nif-2"));
+        for name in ["", "../tpl", "a/b", "..", "2fast", "a b"].iter() {
+            assert!(!is_package_name(name), "{}", name);
+        }
+
+        let appdir = env::temp_dir().join(format!("cargo-erlangapp-new-crate-{}", process::id()));
+        fs::create_dir_all(appdir.join("crates")).unwrap();
+        assert!(new_crate(&appdir, "../tpl", Skeleton::Lib, None).is_err());
+        assert!(new_crate(&appdir, "mynif", Skeleton::Lib, Some("/nonexistent/template")).is_err());
+        let left = appdir.join("crates").read_dir().unwrap().count();
+        fs::remove_dir_all(&appdir).unwrap();
+        assert_eq!(0, left);
+    }
+}
@@ -0,0 +1,187 @@
+//! `[shared-dependencies]`: one version requirement per key dependency (rustler, serde,
+//! openssl) for every crate, like a workspace's `workspace.dependencies` without the
+//! workspace.
+//!
+//! Requirements are compared as cargo reads them, so `0.36` and `^0.36` agree while `0.35`
+//! and `~0.36` drift.  Crates not depending on a listed package aren't affected.  `sync-deps`
+//! rewrites drifting requirements in the manifests as text, so comments and layout survive;
+//! dependencies inherited from a workspace are left to the workspace.
+
+use std::collections::BTreeMap;
+
+use metadata::Package;
+
+/// A dependency declared with another requirement than the shared one
+#[derive(Debug, PartialEq)]
+pub struct Drift {
+    pub dependency: String,
+    pub declared: String,
+    pub expected: String,
+}
+
+/// The dependencies of `package` that drift from `shared`, each reported once
+pub fn check(shared: &BTreeMap<String, String>, package: &Package) -> Vec<Drift> {
+    let mut drifts: Vec<Drift> = Vec::new();
+    for dep in package.dependencies.iter() {
+        let expected = match shared.get(&dep.name) {
+            Some(expected) => expected,
+            None => continue,
+        };
+        if !same_requirement(&dep.req, expected) && !drifts.iter().any(|d| d.dependency == dep.name && d.declared == dep.req) {
+            drifts.push(Drift { dependency: dep.name.clone(), declared: dep.req.clone(), expected: expected.clone() });
+        }
+    }
+    drifts
+}
+
+/// The lines of a diff from the declared requirements to the shared ones
+pub fn diff(drifts: &[Drift]) -> Vec<String> {
+    drifts.iter()
+        .flat_map(|d| vec![format!("-{} = \"{}\"", d.dependency, d.declared), format!("+{} = \"{}\"", d.dependency, d.expected)])
+        .collect()
+}
+
+/// `text`, a manifest, with the requirements of the dependencies in `requirements` replaced,
+/// in every dependency section.  `dep = "..."`, `dep = { version = "..." }`,
+/// `dep.version = "..."`, renamed dependencies (`package = "dep"`) and `[dependencies.dep]`
+/// sections are recognized.
+pub fn set_requirements(text: &str, requirements: &BTreeMap<String, String>) -> String {
+    let mut section = String::new();
+    let mut out = String::with_capacity(text.len());
+    for line in text.split_inclusive('\n') {
+        let trimmed = line.trim();
+        if trimmed.starts_with('[') {
+            section = trimmed.trim_matches(|c| c == '[' || c == ']').replace([' ', '"', '\''], "");
+            out.push_str(line);
+            continue;
+        }
+        let (key, value) = match line.split_once('=') {
+            Some((key, value)) if !trimmed.starts_with('#') => (key.trim().trim_matches('"'), value.trim_start()),
+            _ => {
+                out.push_str(line);
+                continue;
+            },
+        };
+        let compact = value.replace(' ', "");
+        let dependency = match section.rsplit_once(".dependencies.").or_else(|| section.split_once("dependencies.")) {
+            // [dependencies.dep]: its version key
+            Some((_, dep)) if key == "version" && !dep.contains('.') => Some(dep.to_string()),
+            Some(_) => None,
+            None if section.ends_with("dependencies") => {
+                let renamed = requirements.keys().find(|dep| compact.contains(&format!("package=\"{}\"", dep)));
+                match renamed {
+                    Some(dep) if value.starts_with('{') => Some(dep.clone()),
+                    _ => Some(key.strip_suffix(".version").unwrap_or(key).trim_matches('"').to_string()),
+                }
+            },
+            None => None,
+        };
+        let start = match value.starts_with('{') {
+            true => version_value(line),
+            false => line.find('=').map(|eq| eq + 1),
+        };
+        match (dependency.and_then(|dep| requirements.get(&dep)), start) {
+            (Some(req), Some(start)) if value.starts_with('{') || value.starts_with('"') => out.push_str(&replace_quoted(line, start, req)),
+            _ => out.push_str(line),
+        }
+    }
+    out
+}
+
+/// Where the value of `version` starts in an inline table on `line`
+fn version_value(line: &str) -> Option<usize> {
+    let mut from = line.find('{')?;
+    while let Some(i) = line[from..].find("version") {
+        let at = from + i + "version".len();
+        let before = line[..from + i].trim_end();
+        if (before.ends_with('{') || before.ends_with(',')) && line[at..].trim_start().starts_with('=') {
+            return Some(at + line[at..].find('=')? + 1);
+        }
+        from = at;
+    }
+    None
+}
+
+/// `line` with the first quoted string from `start` on replaced by `value`
+fn replace_quoted(line: &str, start: usize, value: &str) -> String {
+    let open = match line[start..].find('"') {
+        Some(open) => start + open,
+        None => return line.to_string(),
+    };
+    match line[open + 1..].find('"') {
+        Some(close) => format!("{}\"{}\"{}", &line[..open], value, &line[open + 1 + close + 1..]),
+        None => line.to_string(),
+    }
+}
+
+/// The lines `after` changed from `before`, as a diff body; the lines correspond one to one
+pub fn line_diff(before: &str, after: &str) -> Vec<String> {
+    before.lines().zip(after.lines())
+        .filter(|(a, b)| a != b)
+        .flat_map(|(a, b)| vec![format!("-{}", a), format!("+{}", b)])
+        .collect()
+}
+
+fn same_requirement(a: &str, b: &str) -> bool {
+    normalize(a) == normalize(b)
+}
+
+/// Comparators of a requirement, a bare version meaning `^`
+fn normalize(req: &str) -> Vec<String> {
+    req.split(',')
+        .map(|c| c.split_whitespace().collect::<String>())
+        .map(|c| match c.starts_with(|ch: char| ch.is_ascii_digit()) {
+            true => format!("^{}", c),
+            false => c,
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use metadata::Metadata;
+
+    #[test]
+    fn test_check() {
+        let metadata = Metadata::from_slice(br#"{
+            "packages": [{"name": "mynif", "id": "mynif", "dependencies": [
+                {"name": "rustler", "req": "^0.35"},
+                {"name": "serde", "req": "^1.0"},
+                {"name": "libc", "req": "^0.2"},
+                {"name": "openssl", "req": ">= 0.10.60, < 0.11"}
+            ]}]
+        }"#).unwrap();
+        let shared: BTreeMap<String, String> = [("rustler", "0.36"), ("serde", "1.0"), ("openssl", ">=0.10.60,<0.11"), ("tokio", "1")]
+            .iter().map(|&(k, v)| (k.to_string(), v.to_string())).collect();
+        let drifts = check(&shared, &metadata.packages[0]);
+        assert_eq!(vec![Drift { dependency: "rustler".into(), declared: "^0.35".into(), expected: "0.36".into() }], drifts);
+        assert_eq!(vec!["-rustler = \"^0.35\"", "+rustler = \"0.36\""], diff(&drifts));
+        assert!(!same_requirement("~0.36", "0.36"));
+    }
+
+    #[test]
+    fn test_set_requirements() {
+        let requirements: BTreeMap<String, String> = [("rustler", "0.36"), ("serde", "1.0.200"), ("libc", "0.2.150"), ("openssl", "0.10.66")]
+            .iter().map(|&(k, v)| (k.to_string(), v.to_string())).collect();
+        let manifest = "[package]\nname = \"mynif\"\nversion = \"0.1.0\"\n\n\
+                        [dependencies]\nrustler = \"0.35\"   # NIF bindings\n\
+                        serde = { version = \"1.0\", features = [\"derive\"] }\n\
+                        ssl = { package = \"openssl\", version = \"0.10\" }\n\
+                        tokio = { version = \"1\", features = [\"rt\"] }\n\n\
+                        [target.'cfg(unix)'.dependencies]\nlibc.version = \"0.2\"\n\n\
+                        [dev-dependencies.serde]\nversion = \"1.0.100\"\n";
+        let synced = set_requirements(manifest, &requirements);
+        assert_eq!("[package]\nname = \"mynif\"\nversion = \"0.1.0\"\n\n\
+                    [dependencies]\nrustler = \"0.36\"   # NIF bindings\n\
+                    serde = { version = \"1.0.200\", features = [\"derive\"] }\n\
+                    ssl = { package = \"openssl\", version = \"0.10.66\" }\n\
+                    tokio = { version = \"1\", features = [\"rt\"] }\n\n\
+                    [target.'cfg(unix)'.dependencies]\nlibc.version = \"0.2.150\"\n\n\
+                    [dev-dependencies.serde]\nversion = \"1.0.200\"\n", synced);
+        assert_eq!(vec!["-rustler = \"0.35\"   # NIF bindings", "+rustler = \"0.36\"   # NIF bindings"],
+                   line_diff(manifest, &synced)[..2].to_vec());
+        let inherited = "[dependencies]\nrustler = { workspace = true }\nserde.workspace = true\n";
+        assert_eq!(inherited, set_requirements(inherited, &requirements));
+    }
+}
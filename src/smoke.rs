@@ -0,0 +1,135 @@
+//! `smoke-test`: load each built NIF into a throwaway Erlang VM, and optionally exchange a
+//! probe message with each port program.
+
+use std::env;
+use std::fs;
+use std::io::{Read, Write};
+use std::path::Path;
+use std::process::{self, Stdio};
+use std::sync::mpsc;
+use std::thread;
+
+use config::PortProbe;
+
+use MsgError;
+use MsgError::*;
+
+/// Attempt `erlang:load_nif/2` on `library` (path without extension) from a probe module
+/// named `module`.  Returns the load error, if any.
+pub fn probe(module: &str, library: &Path) -> Result<Result<(), String>, MsgError> {
+    let dir = env::temp_dir().join(format!("cargo-erlangapp-smoke-{}", process::id()));
+    let source = dir.join(format!("{}.erl", module));
+    let result = fs::create_dir_all(&dir)
+        .and_then(|_| fs::write(&source, probe_source(module)))
+        .map_err(|err| MsgIo("cannot write probe module", err))
+        .and_then(|_| run_probe(&source, library));
+    let _ = fs::remove_dir_all(&dir);
+    result
+}
+
+fn run_probe(source: &Path, library: &Path) -> Result<Result<(), String>, MsgError> {
+    let source = erlang_string(&source.to_string_lossy());
+    let eval = format!("\
+{{ok, M, Bin}} = compile:file({src}, [binary, return_errors]), \
+{{module, M}} = code:load_binary(M, {src}, Bin), \
+io:format(\"~p~n\", [M:probe({lib})]), \
+halt().", src = source, lib = erlang_string(&library.to_string_lossy()));
+    let erl = env::var("ERL").unwrap_or_else(|_| "erl".to_string());
+    let output = process::Command::new(erl)
+        .args(["-noshell", "-eval", &eval])
+        .output()
+        .map_err(|err| MsgIo("cannot start erl", err))?;
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    match output.status.success() {
+        true => Ok(classify(&stdout)),
+        false => Ok(Err(format!("{}{}", stdout, String::from_utf8_lossy(&output.stderr)).trim().to_string())),
+    }
+}
+
+fn probe_source(module: &str) -> String {
+    format!("-module('{}').\n-export([probe/1]).\nprobe(Path) -> erlang:load_nif(Path, 0).\n", module)
+}
+
+/// Interpret the probe's printed result.  The probe module has none of the NIF's functions,
+/// so errors raised after the library was opened and its ABI version accepted count as success.
+fn classify(output: &str) -> Result<(), String> {
+    let result = output.trim().lines().last().unwrap_or("").trim();
+    if result == "ok" || result.contains("Function not found") || result.contains("does not match calling module") {
+        Ok(())
+    } else {
+        Err(result.to_string())
+    }
+}
+
+/// Spawn a port program, send the probe with `{packet, N}` framing and check its response.
+/// Returns the handshake error, if any.
+pub fn probe_port(program: &Path, probe: &PortProbe) -> Result<Result<(), String>, MsgError> {
+    let mut child = process::Command::new(program)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .map_err(|err| MsgIo("cannot start port program", err))?;
+    let mut stdin = child.stdin.take().unwrap();
+    let mut stdout = child.stdout.take().unwrap();
+
+    // read the response on a thread so a silent program can be timed out
+    let (tx, rx) = mpsc::channel();
+    let packet = probe.packet;
+    thread::spawn(move || {
+        let mut header = vec![0u8; packet];
+        let response = stdout.read_exact(&mut header).and_then(|_| {
+            let len = header.iter().fold(0usize, |len, b| len << 8 | *b as usize);
+            let mut body = vec![0u8; len];
+            stdout.read_exact(&mut body).map(|_| body)
+        });
+        let _ = tx.send(response);
+    });
+
+    let sent = stdin.write_all(&frame(packet, probe.probe.as_bytes())).and_then(|_| stdin.flush());
+    let result = match (sent, rx.recv_timeout(probe.timeout)) {
+        (Err(err), _) => Err(format!("cannot send probe ({})", err)),
+        (_, Err(_)) => Err(format!("no response within {} ms", probe.timeout.as_millis())),
+        (_, Ok(Err(err))) => Err(format!("cannot read response ({})", err)),
+        (_, Ok(Ok(body))) => match probe.expect {
+            Some(ref expect) if expect.as_bytes() != body.as_slice() =>
+                Err(format!("unexpected response {:?}", String::from_utf8_lossy(&body))),
+            _ => Ok(()),
+        },
+    };
+    drop(stdin);
+    let _ = child.kill();
+    let _ = child.wait();
+    Ok(result)
+}
+
+/// Prefix a message with its big-endian length in `packet` bytes
+fn frame(packet: usize, msg: &[u8]) -> Vec<u8> {
+    let len = (msg.len() as u32).to_be_bytes();
+    let mut framed = len[4 - packet..].to_vec();
+    framed.extend_from_slice(msg);
+    framed
+}
+
+fn erlang_string(s: &str) -> String {
+    format!("\"{}\"", s.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_frame() {
+        assert_eq!(b"\x00\x00\x00\x04ping".to_vec(), frame(4, b"ping"));
+        assert_eq!(b"\x00\x02hi".to_vec(), frame(2, b"hi"));
+        assert_eq!(b"\x00".to_vec(), frame(1, b""));
+    }
+
+    #[test]
+    fn test_classify() {
+        assert_eq!(Ok(()), classify("ok\n"));
+        assert_eq!(Ok(()), classify("{error,{bad_lib,\"Function not found emb_nif:add/2\"}}\n"));
+        assert_eq!(Err("{error,{load_failed,\"undefined symbol: foo\"}}".to_string()),
+                   classify("{error,{load_failed,\"undefined symbol: foo\"}}\n"));
+    }
+}
@@ -0,0 +1,104 @@
+//! Stripping installed artifacts of debug information and local symbols.
+//!
+//! A plain `strip` removes the symbol table a NIF or driver is loaded through on some
+//! platforms.  This keeps every global symbol, so `nif_init` and `driver_init` stay, and
+//! the symbols matching the `strip-keep` patterns (`*` matches anything); on macOS, where
+//! `strip` can't single out local symbols, keep patterns leave all of them.  The stripped copy
+//! is checked for the entry point and the kept symbols before it is installed.  `strip` (or
+//! `$STRIP`) does the work; MSVC builds keep their debug information in a separate PDB and
+//! are installed as built.
+
+use std::env;
+use std::fs;
+use std::path::Path;
+use std::process;
+
+use symbols;
+use CommandFailure;
+use MsgError;
+use MsgError::*;
+
+/// Write a stripped copy of the artifact `src` for `triple` to `dst`.  `entry` is the
+/// symbol the artifact is loaded through.
+pub fn strip(src: &Path, dst: &Path, triple: &str, entry: Option<&str>, keep: &[String]) -> Result<(), MsgError> {
+    if triple.contains("msvc") {
+        return fs::copy(src, dst).map(|_| ()).map_err(|err| MsgIo("cannot copy artifact", err));
+    }
+    let apple = triple.contains("apple");
+    let before = symbols::defined(src, apple)?;
+    let required = required(&before, entry, keep);
+
+    let mut command = process::Command::new(env::var_os("STRIP").unwrap_or_else(|| "strip".into()));
+    command.args(arguments(apple, entry, keep)).arg("-o").arg(dst).arg(src);
+    let output = command.output().map_err(|err| MsgIo("cannot run strip", err))?;
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr).into_owned();
+        return Err(MsgCommand("strip failed", CommandFailure::new(&command, stderr).exited(output.status)));
+    }
+
+    let after = symbols::defined(dst, apple)?;
+    let missing: Vec<String> = required.into_iter()
+        .filter(|name| !after.iter().any(|symbol| symbol.name == *name))
+        .collect();
+    match missing.is_empty() {
+        true => Ok(()),
+        false => Err(MsgDetail("strip removed symbols that must be kept", format!("{}: {}", src.display(), missing.join(", ")))),
+    }
+}
+
+/// Debug information and local symbols go, except those to keep
+fn arguments(apple: bool, entry: Option<&str>, keep: &[String]) -> Vec<String> {
+    if apple {
+        // -x only removes what isn't global, but Apple's strip can't spare local symbols
+        return match keep.is_empty() {
+            true => vec!["-S".to_string(), "-x".to_string()],
+            false => vec!["-S".to_string()],
+        };
+    }
+    let mut args = vec!["--strip-debug".to_string(), "--discard-all".to_string(), "--wildcard".to_string()];
+    args.extend(entry.into_iter().map(String::from).chain(keep.iter().cloned()).map(|name| format!("--keep-symbol={}", name)));
+    args
+}
+
+/// Symbols of `symbols` that must survive stripping: the entry point and those matching
+/// `keep`
+fn required(symbols: &[symbols::Symbol], entry: Option<&str>, keep: &[String]) -> Vec<String> {
+    symbols.iter()
+        .filter(|symbol| Some(symbol.name.as_str()) == entry || keep.iter().any(|pattern| matches(pattern, &symbol.name)))
+        .map(|symbol| symbol.name.clone())
+        .collect()
+}
+
+/// Glob match with `*` as the only wildcard
+pub fn matches(pattern: &str, name: &str) -> bool {
+    match pattern.find('*') {
+        None => pattern == name,
+        Some(i) => {
+            let (prefix, rest) = (&pattern[..i], &pattern[i + 1..]);
+            match name.strip_prefix(prefix) {
+                Some(tail) => tail.char_indices().map(|(j, _)| j).chain(Some(tail.len())).any(|j| matches(rest, &tail[j..])),
+                None => false,
+            }
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_required() {
+        assert!(matches("my_*", "my_helper"));
+        assert!(matches("*_init*", "ssl_init_once"));
+        assert!(!matches("my_*", "your_helper"));
+        assert!(matches("exact", "exact") && !matches("exact", "exactly"));
+
+        let symbols: Vec<symbols::Symbol> = [("nif_init", true), ("helper", false), ("my_table", false), ("exported", true)].iter()
+            .map(|&(name, exported)| symbols::Symbol { name: name.to_string(), exported })
+            .collect();
+        assert_eq!(vec!["nif_init", "my_table"], required(&symbols, Some("nif_init"), &["my_*".to_string()]));
+        assert_eq!(vec!["--strip-debug", "--discard-all", "--wildcard", "--keep-symbol=nif_init", "--keep-symbol=my_*"],
+                   arguments(false, Some("nif_init"), &["my_*".to_string()]));
+    }
+}
@@ -0,0 +1,65 @@
+//! "Did you mean" suggestions for mistyped subcommands and options.
+//!
+//! Options the tool doesn't know are passed on to cargo, so a typo such as `--realease`
+//! would reach cargo, or a NIF's build, as something else.  Such an option is reported
+//! with the nearest known one instead, when it is close enough to be a typo.
+
+/// Cargo options commonly passed through, which are not typos of the tool's own
+pub const CARGO_OPTIONS: &[&str] = &[
+    "--all-features", "--all-targets", "--allow-dirty", "--allow-no-vcs", "--allow-staged", "--artifact-dir",
+    "--bench", "--benches", "--bins", "--broken-code", "--build-plan", "--color", "--crate-type", "--doc",
+    "--example", "--examples", "--exclude", "--features", "--fix", "--frozen", "--future-incompat-report",
+    "--help", "--ignore-rust-version", "--jobs", "--keep-going", "--locked", "--lockfile-path",
+    "--no-capture", "--no-default-features", "--no-deps", "--no-fail-fast", "--no-run", "--offline",
+    "--package", "--partition", "--print", "--profile", "--quiet", "--release", "--retries", "--run-ignored",
+    "--target", "--target-dir", "--test", "--test-threads", "--tests", "--timings", "--unit-graph",
+    "--verbose", "--workspace",
+];
+
+/// Cargo options that take their value as the next argument when not given as `--opt=value`
+pub const CARGO_VALUE_OPTIONS: &[&str] = &[
+    "--artifact-dir", "--bench", "--color", "--config", "--crate-type", "--example", "--exclude", "--features",
+    "--jobs", "--lockfile-path", "--manifest-path", "--message-format", "--package", "--print", "--profile",
+    "--target", "--target-dir", "--test", "-F", "-Z", "-j", "-p",
+];
+
+/// The candidate nearest to `word`, if it is close enough to be what was meant
+pub fn closest<'a>(word: &str, candidates: &[&'a str]) -> Option<&'a str> {
+    candidates.iter()
+        .map(|candidate| (distance(word, candidate), *candidate))
+        .filter(|&(d, _)| d > 0 && d <= 2 && d * 3 <= word.trim_start_matches('-').len())
+        .min_by_key(|&(d, _)| d)
+        .map(|(_, candidate)| candidate)
+}
+
+/// Edit distance counting insertions, deletions, substitutions and swaps of adjacent
+/// characters, so `buidl` is one edit from `build`
+fn distance(a: &str, b: &str) -> usize {
+    let (a, b): (Vec<char>, Vec<char>) = (a.chars().collect(), b.chars().collect());
+    let mut d: Vec<Vec<usize>> = (0..=a.len()).map(|i| (0..=b.len()).map(|j| if i == 0 { j } else { i }).collect()).collect();
+    for i in 1..=a.len() {
+        for j in 1..=b.len() {
+            let cost = if a[i - 1] == b[j - 1] { 0 } else { 1 };
+            d[i][j] = (d[i - 1][j] + 1).min(d[i][j - 1] + 1).min(d[i - 1][j - 1] + cost);
+            if i > 1 && j > 1 && a[i - 1] == b[j - 2] && a[i - 2] == b[j - 1] {
+                d[i][j] = d[i][j].min(d[i - 2][j - 2] + 1);
+            }
+        }
+    }
+    d[a.len()][b.len()]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_closest() {
+        assert_eq!(1, distance("buidl", "build"));
+        assert_eq!(Some("build"), closest("buidl", &["build", "bench", "clean"]));
+        assert_eq!(Some("--release"), closest("--realease", CARGO_OPTIONS));
+        assert_eq!(None, closest("--release", CARGO_OPTIONS));
+        assert_eq!(None, closest("du", &["gc", "dist"]));
+        assert_eq!(None, closest("frobnicate", &["build", "bench", "clean"]));
+    }
+}
@@ -0,0 +1,133 @@
+//! End-of-run summary table.
+
+use std::path::PathBuf;
+use std::time::Duration;
+
+/// Outcome of building or testing one crate
+#[derive(Debug)]
+pub struct CrateReport {
+    pub name: String,
+    /// Targets built, e.g. `helloexe (bin)`
+    pub targets: Vec<String>,
+    /// Total size of installed artifacts
+    pub artifact_bytes: u64,
+    /// Paths artifacts were installed at
+    pub installed: Vec<PathBuf>,
+    pub duration: Duration,
+    pub ok: bool,
+    /// Compiler warnings in the crate and the path dependencies built with it
+    pub warnings: usize,
+}
+
+impl CrateReport {
+    pub fn new(name: &str) -> CrateReport {
+        CrateReport {
+            name: name.to_string(),
+            targets: Vec::new(),
+            artifact_bytes: 0,
+            installed: Vec::new(),
+            duration: Duration::default(),
+            ok: false,
+            warnings: 0,
+        }
+    }
+}
+
+/// Render reports as an aligned table
+pub fn render(reports: &[CrateReport]) -> String {
+    let mut rows: Vec<Vec<String>> = vec![["", "crate", "targets", "size", "time"].map(String::from).to_vec()];
+    rows.extend(reports.iter().map(|r| vec![
+        (if r.ok { "\u{2713}" } else { "\u{2717}" }).to_string(),
+        r.name.clone(),
+        if r.targets.is_empty() { "-".to_string() } else { r.targets.join(", ") },
+        if r.artifact_bytes == 0 { "-".to_string() } else { format_size(r.artifact_bytes) },
+        format!("{:.1}s", r.duration.as_secs_f64()),
+    ]));
+    format!("Summary:\n{}", table(&rows))
+}
+
+/// Render the warning count of each crate that had any, so warnings piling up in a crate
+/// don't go unnoticed
+pub fn render_warnings(reports: &[CrateReport]) -> Option<String> {
+    let rows: Vec<Vec<String>> = reports.iter()
+        .filter(|r| r.warnings > 0)
+        .map(|r| vec![r.name.clone(), format!("{} warning{}", r.warnings, if r.warnings == 1 { "" } else { "s" })])
+        .collect();
+    match rows.is_empty() {
+        true => None,
+        false => Some(format!("Warnings (--deny-warnings makes them errors):\n{}", table(&rows))),
+    }
+}
+
+/// Align rows into indented columns.  Short rows leave their last cells empty.
+pub fn table(rows: &[Vec<String>]) -> String {
+    let mut widths = Vec::new();
+    for row in rows.iter() {
+        widths.resize(widths.len().max(row.len()), 0);
+        for (width, cell) in widths.iter_mut().zip(row.iter()) {
+            *width = (*width).max(cell.chars().count());
+        }
+    }
+
+    let lines: Vec<String> = rows.iter()
+        .map(|row| {
+            let mut line = String::from("  ");
+            for (width, cell) in widths.iter().zip(row.iter()) {
+                line.push_str(cell);
+                line.push_str(&" ".repeat(width - cell.chars().count() + 2));
+            }
+            line.trim_end().to_string()
+        })
+        .collect();
+    lines.join("\n")
+}
+
+/// Coarse age, e.g. `5m ago`
+pub fn format_age(age: Duration) -> String {
+    let secs = age.as_secs();
+    match secs {
+        0..=59 => format!("{}s ago", secs),
+        60..=3599 => format!("{}m ago", secs / 60),
+        3600..=86399 => format!("{}h ago", secs / 3600),
+        _ => format!("{}d ago", secs / 86400),
+    }
+}
+
+pub fn format_size(bytes: u64) -> String {
+    const UNITS: &[&str] = &["B", "KiB", "MiB", "GiB"];
+    let mut size = bytes as f64;
+    let mut unit = 0;
+    while size >= 1024.0 && unit + 1 < UNITS.len() {
+        size /= 1024.0;
+        unit += 1;
+    }
+    match unit {
+        0 => format!("{} B", bytes),
+        _ => format!("{:.1} {}", size, UNITS[unit]),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render() {
+        let mut a = CrateReport::new("helloexe");
+        a.targets.push("helloexe (bin)".to_string());
+        a.artifact_bytes = 3 * 1024 * 1024 / 2;
+        a.duration = Duration::from_millis(1300);
+        a.ok = true;
+        let b = CrateReport::new("nif");
+        assert_eq!("Summary:\n\
+                    \x20    crate     targets         size     time\n\
+                    \x20 \u{2713}  helloexe  helloexe (bin)  1.5 MiB  1.3s\n\
+                    \x20 \u{2717}  nif       -               -        0.0s",
+                   render(&[a, b]));
+        assert_eq!(None, render_warnings(&[CrateReport::new("quiet")]));
+        let mut c = CrateReport::new("noisy");
+        c.warnings = 3;
+        assert_eq!(Some("Warnings (--deny-warnings makes them errors):\n  noisy  3 warnings".to_string()), render_warnings(&[c]));
+        assert_eq!("3h ago", format_age(Duration::from_secs(3 * 3600 + 5)));
+    }
+}
@@ -0,0 +1,137 @@
+//! `check-symbols` command: native symbols defined by more than one installed NIF.
+//!
+//! NIFs that each statically link the same C library (OpenSSL, zlib) carry their own copy
+//! of it.  Exported copies clash when the VM loads both; hidden ones still mean separate
+//! global state, so a library initialized through one NIF is uninitialized in the other.
+//! Symbols come from `nm` (or `$NM`), falling back to the dynamic symbol table for stripped
+//! libraries.  Rust symbols, the NIF entry point and what the toolchain adds to every
+//! library are left out.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::env;
+use std::path::Path;
+use std::process;
+
+use CommandFailure;
+use MsgError;
+use MsgError::*;
+
+/// Added to every library by the C runtime, compiler and linker
+const TOOLCHAIN_SYMBOLS: &[&str] = &[
+    "_DYNAMIC", "_GLOBAL_OFFSET_TABLE_", "_init", "_fini", "_edata", "_end",
+    "deregister_tm_clones", "register_tm_clones", "frame_dummy",
+    "rust_eh_personality", "nif_init",
+];
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Symbol {
+    pub name: String,
+    /// Global, as opposed to hidden or local to the library
+    pub exported: bool,
+}
+
+/// Symbols defined by more than one library
+#[derive(Debug, PartialEq)]
+pub struct Overlap {
+    /// Libraries sharing the symbols
+    pub libraries: Vec<String>,
+    /// Exported by at least two of them
+    pub clashes: Vec<String>,
+    pub duplicates: Vec<String>,
+}
+
+/// The symbols defined in the library at `path` that could be shared with another library
+pub fn read(path: &Path, apple: bool) -> Result<Vec<Symbol>, MsgError> {
+    Ok(relevant(defined(path, apple)?))
+}
+
+/// All symbols defined in the library or program at `path`
+pub fn defined(path: &Path, apple: bool) -> Result<Vec<Symbol>, MsgError> {
+    let nm = env::var_os("NM").unwrap_or_else(|| "nm".into());
+    let mut symbols = Vec::new();
+    for args in [&["--defined-only"][..], &["--defined-only", "-D"][..]].iter() {
+        let mut command = process::Command::new(&nm);
+        command.args(args.iter()).arg(path);
+        let output = command.output().map_err(|err| MsgIo("cannot run nm", err))?;
+        let text = String::from_utf8_lossy(&output.stdout).into_owned();
+        symbols = parse(&text, apple);
+        if !symbols.is_empty() || !text.is_empty() {
+            break;
+        }
+        let stderr = String::from_utf8_lossy(&output.stderr).into_owned();
+        if !output.status.success() && !stderr.contains("no symbols") {
+            return Err(MsgCommand("nm failed", CommandFailure::new(&command, stderr).exited(output.status)));
+        }
+    }
+    Ok(symbols)
+}
+
+/// `nm` output: `<address> <type> <name>`, uppercase types being global.  Mach-O names
+/// lose their leading underscore.
+fn parse(text: &str, apple: bool) -> Vec<Symbol> {
+    let mut symbols: Vec<Symbol> = text.lines()
+        .filter_map(|line| {
+            let mut fields = line.split_whitespace().rev();
+            let name = fields.next()?;
+            let kind = fields.next()?.chars().next()?;
+            let name = if apple { name.strip_prefix('_').unwrap_or(name) } else { name };
+            Some(Symbol { name: name.to_string(), exported: kind.is_ascii_uppercase() })
+        })
+        .collect();
+    symbols.sort_by(|a, b| a.name.cmp(&b.name).then(b.exported.cmp(&a.exported)));
+    symbols.dedup_by(|a, b| a.name == b.name);
+    symbols
+}
+
+fn relevant(symbols: Vec<Symbol>) -> Vec<Symbol> {
+    symbols.into_iter().filter(|symbol| !ignored(&symbol.name)).collect()
+}
+
+fn ignored(name: &str) -> bool {
+    // mangled Rust, reserved for the implementation, compiler-generated locals
+    name.starts_with("_ZN") || name.starts_with("_R") || name.starts_with("__") || name.starts_with("_rust")
+        || name.starts_with("GCC_except_table") || name.contains('.') || name.contains('$')
+        || TOOLCHAIN_SYMBOLS.contains(&name)
+}
+
+/// Symbols defined by more than one of `libraries`, grouped by the libraries sharing them
+pub fn overlaps(libraries: &[(String, Vec<Symbol>)]) -> Vec<Overlap> {
+    let mut definitions: BTreeMap<&str, Vec<(&str, bool)>> = BTreeMap::new();
+    for (library, symbols) in libraries.iter() {
+        for symbol in symbols.iter() {
+            definitions.entry(&symbol.name).or_default().push((library, symbol.exported));
+        }
+    }
+    let mut groups: BTreeMap<BTreeSet<&str>, Overlap> = BTreeMap::new();
+    for (name, defined) in definitions.into_iter().filter(|(_, defined)| defined.len() > 1) {
+        let sharing: BTreeSet<&str> = defined.iter().map(|(library, _)| *library).collect();
+        let overlap = groups.entry(sharing.clone()).or_insert_with(|| Overlap {
+            libraries: sharing.iter().map(|l| l.to_string()).collect(),
+            clashes: Vec::new(),
+            duplicates: Vec::new(),
+        });
+        match defined.iter().filter(|(_, exported)| *exported).count() > 1 {
+            true => overlap.clashes.push(name.to_string()),
+            false => overlap.duplicates.push(name.to_string()),
+        }
+    }
+    groups.into_values().collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_overlaps() {
+        let a = relevant(parse("0000000000001000 T nif_init\n0000000000001100 T deflate\n0000000000001200 t inflate_fast\n\
+                       0000000000001300 t _ZN4core3fmt5write17h0123456789abcdefE\n0000000000001400 b completed.0\n\
+                       0000000000001500 t __udivti3\n0000000000001600 T only_a\n", false));
+        assert_eq!(vec!["deflate", "inflate_fast", "only_a"], a.iter().map(|s| s.name.as_str()).collect::<Vec<_>>());
+        let b = parse("0000000000001100 T _deflate\n0000000000001200 t _inflate_fast\n", true);
+        let c = parse("0000000000001100 t deflate\n", false);
+        assert_eq!(vec![Overlap { libraries: vec!["a".into(), "b".into()], clashes: Vec::new(), duplicates: vec!["inflate_fast".into()] },
+                        Overlap { libraries: vec!["a".into(), "b".into(), "c".into()], clashes: vec!["deflate".into()], duplicates: Vec::new() }],
+                   overlaps(&[("a".into(), a), ("b".into(), b), ("c".into(), c)]));
+    }
+}
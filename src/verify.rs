@@ -0,0 +1,268 @@
+//! Build manifests of installed artifacts, and `verify` and `status` against them.
+//!
+//! Each build writes `priv/crates/<crate>/artifacts.json` recording the profile, a
+//! fingerprint of the crate's sources, the `min-otp` it was checked against and, for every
+//! artifact installed, its path (relative to the application), target triple, size and
+//! SHA-256.
+
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use json;
+use sha2::{Digest, Sha256};
+
+use MsgError;
+use MsgError::*;
+
+pub const MANIFEST_FILE: &str = "artifacts.json";
+
+/// What a build of one crate installed
+#[derive(Debug, PartialEq)]
+pub struct BuildManifest {
+    /// Cargo profile directory name, e.g. `release`
+    pub profile: String,
+    /// Fingerprint of the sources built, see `source_fingerprint`
+    pub fingerprint: String,
+    /// `min-otp` the crate's NIF API version was checked against
+    pub min_otp: Option<u32>,
+    pub records: Vec<Record>,
+}
+
+impl BuildManifest {
+    fn to_json(&self) -> json::Value {
+        let mut obj = json::Map::new();
+        obj.insert("profile".to_string(), json::Value::String(self.profile.clone()));
+        obj.insert("fingerprint".to_string(), json::Value::String(self.fingerprint.clone()));
+        if let Some(min_otp) = self.min_otp {
+            obj.insert("min_otp".to_string(), json::Value::from(min_otp));
+        }
+        obj.insert("artifacts".to_string(), json::Value::Array(self.records.iter().map(Record::to_json).collect()));
+        json::Value::Object(obj)
+    }
+
+    fn from_json(value: &json::Value) -> Option<BuildManifest> {
+        Some(BuildManifest {
+            profile: value.get("profile")?.as_str()?.to_string(),
+            fingerprint: value.get("fingerprint")?.as_str()?.to_string(),
+            min_otp: value.get("min_otp").and_then(json::Value::as_u64).map(|v| v as u32),
+            records: value.get("artifacts")?.as_array()?.iter().map(Record::from_json).collect::<Option<_>>()?,
+        })
+    }
+}
+
+/// One installed artifact
+#[derive(Debug, PartialEq)]
+pub struct Record {
+    pub target: String,
+    /// Path relative to the application directory, with `/` separators
+    pub path: String,
+    pub triple: String,
+    pub size: u64,
+    pub sha256: String,
+}
+
+impl Record {
+    /// Describe an installed artifact
+    pub fn of(appdir: &Path, path: &Path, target: &str, triple: &str) -> Result<Record, MsgError> {
+        let bytes = fs::read(path).map_err(|err| MsgIo("cannot read installed artifact", err))?;
+        let relative = path.strip_prefix(appdir).unwrap_or(path);
+        Ok(Record {
+            target: target.to_string(),
+            path: relative.to_string_lossy().replace('\\', "/"),
+            triple: triple.to_string(),
+            size: bytes.len() as u64,
+            sha256: sha256_hex(&bytes),
+        })
+    }
+
+    fn to_json(&self) -> json::Value {
+        let mut obj = json::Map::new();
+        obj.insert("target".to_string(), json::Value::String(self.target.clone()));
+        obj.insert("path".to_string(), json::Value::String(self.path.clone()));
+        obj.insert("triple".to_string(), json::Value::String(self.triple.clone()));
+        obj.insert("size".to_string(), json::Value::from(self.size));
+        obj.insert("sha256".to_string(), json::Value::String(self.sha256.clone()));
+        json::Value::Object(obj)
+    }
+
+    fn from_json(value: &json::Value) -> Option<Record> {
+        let string = |key: &str| value.get(key).and_then(|v| v.as_str()).map(String::from);
+        Some(Record {
+            target: string("target")?,
+            path: string("path")?,
+            triple: string("triple")?,
+            size: value.get("size")?.as_u64()?,
+            sha256: string("sha256")?,
+        })
+    }
+
+    /// Compare the installed file against this record, returning what has drifted
+    pub fn check(&self, appdir: &Path) -> Result<(), String> {
+        let bytes = fs::read(appdir.join(&self.path)).map_err(|err| format!("missing ({})", err))?;
+        if bytes.len() as u64 != self.size || sha256_hex(&bytes) != self.sha256 {
+            return Err("contents differ from the build".to_string());
+        }
+        match binary_platform(&bytes) {
+            Some((os, arch)) if !triple_matches(&self.triple, os, arch) =>
+                Err(format!("built for {} {}, not {}", os, arch, self.triple)),
+            _ => Ok(()),
+        }
+    }
+}
+
+pub fn write_manifest(dir: &Path, manifest: &BuildManifest) -> Result<(), MsgError> {
+    let text = json::to_string_pretty(&manifest.to_json())
+        .map_err(|err| MsgDetail("cannot serialize build manifest", err.to_string()))?;
+    fs::write(dir.join(MANIFEST_FILE), text + "\n")
+        .map_err(|err| MsgIo("cannot write build manifest", err))
+}
+
+/// Read a crate's build manifest; `None` if it has none
+pub fn read_manifest(dir: &Path) -> Result<Option<BuildManifest>, MsgError> {
+    let path = dir.join(MANIFEST_FILE);
+    let text = match fs::read_to_string(&path) {
+        Ok(text) => text,
+        Err(_) => return Ok(None),
+    };
+    json::from_str::<json::Value>(&text).ok()
+        .and_then(|value| BuildManifest::from_json(&value))
+        .map(Some)
+        .ok_or_else(|| MsgDetail("cannot parse build manifest", path.to_string_lossy().into_owned()))
+}
+
+/// SHA-256 over the paths and contents of a crate's files, skipping `target` and hidden
+/// entries.  Changes whenever a rebuild would produce a different artifact (path
+/// dependencies outside the crate aside).
+pub fn source_fingerprint(crate_dir: &Path) -> io::Result<String> {
+    let mut files = Vec::new();
+    collect_files(crate_dir, &mut files)?;
+    files.sort();
+    let mut hasher = Sha256::new();
+    for file in files.iter() {
+        hasher.update(file.strip_prefix(crate_dir).unwrap_or(file).to_string_lossy().as_bytes());
+        hasher.update([0]);
+        hasher.update(fs::read(file)?);
+    }
+    Ok(hasher.finalize().iter().map(|b| format!("{:02x}", b)).collect())
+}
+
+fn collect_files(dir: &Path, files: &mut Vec<PathBuf>) -> io::Result<()> {
+    for entry in dir.read_dir()? {
+        let entry = entry?;
+        let name = entry.file_name().to_string_lossy().into_owned();
+        if name == "target" || name.starts_with('.') {
+            continue;
+        }
+        if entry.file_type()?.is_dir() {
+            collect_files(&entry.path(), files)?;
+        } else {
+            files.push(entry.path());
+        }
+    }
+    Ok(())
+}
+
+pub fn sha256_hex(bytes: &[u8]) -> String {
+    Sha256::digest(bytes).iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// Object file format and architecture of a binary, for the formats we install
+fn binary_platform(bytes: &[u8]) -> Option<(&'static str, &'static str)> {
+    let u16_le = |i: usize| bytes.get(i..i + 2).map(|b| u16::from_le_bytes([b[0], b[1]]));
+    let u16_be = |i: usize| bytes.get(i..i + 2).map(|b| u16::from_be_bytes([b[0], b[1]]));
+    if bytes.starts_with(b"\x7fELF") {
+        let machine = match bytes.get(5) {
+            Some(&1) => u16_le(18)?,
+            _ => u16_be(18)?,
+        };
+        let arch = match machine {
+            0x3e => "x86_64",
+            0xb7 => "aarch64",
+            0x03 => "x86",
+            0x28 => "arm",
+            0xf3 => "riscv",
+            _ => "other",
+        };
+        return Some(("elf", arch));
+    }
+    if bytes.starts_with(&[0xcf, 0xfa, 0xed, 0xfe]) {
+        let arch = match u16_le(4)? {
+            0x07 => "x86_64",
+            0x0c => "aarch64",
+            _ => "other",
+        };
+        return Some(("mach-o", arch));
+    }
+    if bytes.starts_with(b"\0asm") {
+        return Some(("wasm", "other"));
+    }
+    if bytes.starts_with(b"MZ") {
+        let pe = bytes.get(0x3c..0x40).map(|b| u32::from_le_bytes([b[0], b[1], b[2], b[3]]) as usize)?;
+        let arch = match u16_le(pe + 4)? {
+            0x8664 => "x86_64",
+            0xaa64 => "aarch64",
+            0x14c => "x86",
+            _ => "other",
+        };
+        return Some(("pe", arch));
+    }
+    None
+}
+
+fn triple_matches(triple: &str, os: &str, arch: &str) -> bool {
+    let triple_os = if triple.contains("-windows") {
+        "pe"
+    } else if triple.starts_with("wasm") {
+        "wasm"
+    } else if triple.contains("-apple-") {
+        "mach-o"
+    } else {
+        "elf"
+    };
+    let triple_arch = match triple.split('-').next().unwrap_or("") {
+        "x86_64" => "x86_64",
+        "aarch64" | "arm64" => "aarch64",
+        "i686" | "i586" | "i386" => "x86",
+        a if a.starts_with("arm") || a.starts_with("thumb") => "arm",
+        a if a.starts_with("riscv") => "riscv",
+        _ => "other",
+    };
+    os == triple_os && (arch == triple_arch || arch == "other" || triple_arch == "other")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_binary_platform() {
+        let mut elf = b"\x7fELF\x02\x01\x01".to_vec();
+        elf.resize(20, 0);
+        elf[18] = 0x3e;
+        assert_eq!(Some(("elf", "x86_64")), binary_platform(&elf));
+        assert_eq!(None, binary_platform(b"!<arch>\n"));
+        assert!(triple_matches("x86_64-unknown-linux-gnu", "elf", "x86_64"));
+        assert!(!triple_matches("aarch64-unknown-linux-gnu", "elf", "x86_64"));
+        assert!(!triple_matches("x86_64-pc-windows-msvc", "elf", "x86_64"));
+        assert_eq!(Some(("wasm", "other")), binary_platform(b"\0asm\x01\0\0\0"));
+        assert!(triple_matches("wasm32-wasip1", "wasm", "other"));
+        assert!(!triple_matches("x86_64-unknown-linux-gnu", "wasm", "other"));
+    }
+
+    #[test]
+    fn test_record_json() {
+        let record = || Record {
+            target: "mynif (dylib)".into(),
+            path: "priv/crates/mynif/libmynif.so".into(),
+            triple: "x86_64-unknown-linux-gnu".into(),
+            size: 3,
+            sha256: sha256_hex(b"abc"),
+        };
+        assert_eq!("ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad", record().sha256);
+        assert_eq!(Some(record()), Record::from_json(&record().to_json()));
+        let manifest = |min_otp| BuildManifest { profile: "release".into(), fingerprint: "f".into(), min_otp, records: vec![record()] };
+        assert_eq!(Some(manifest(None)), BuildManifest::from_json(&manifest(None).to_json()));
+        assert_eq!(Some(manifest(Some(26))), BuildManifest::from_json(&manifest(Some(26)).to_json()));
+    }
+}
@@ -0,0 +1,111 @@
+//! Windows toolchain selection: NIFs must be built with the same C runtime family (MSVC or
+//! GNU) as the Erlang that loads them.
+
+use std::env;
+use std::fmt::{self, Display};
+use std::process;
+use std::str::FromStr;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Toolchain {
+    Msvc,
+    Gnu,
+}
+
+impl Toolchain {
+    /// Toolchain of a Windows target triple
+    fn from_triple(triple: &str) -> Option<Toolchain> {
+        if !triple.contains("-windows-") {
+            None
+        } else if triple.ends_with("-msvc") {
+            Some(Toolchain::Msvc)
+        } else if triple.ends_with("-gnu") || triple.ends_with("-gnullvm") {
+            Some(Toolchain::Gnu)
+        } else {
+            None
+        }
+    }
+
+    /// Windows target triple for this toolchain on the architecture of `host`
+    pub fn triple(&self, host: &str) -> String {
+        let arch = host.split('-').next().unwrap_or("x86_64");
+        format!("{}-pc-windows-{}", arch, self)
+    }
+}
+
+impl Display for Toolchain {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Toolchain::Msvc => write!(f, "msvc"),
+            Toolchain::Gnu => write!(f, "gnu"),
+        }
+    }
+}
+
+impl FromStr for Toolchain {
+    type Err = ();
+    fn from_str(s: &str) -> Result<Toolchain, ()> {
+        match s {
+            "msvc" => Ok(Toolchain::Msvc),
+            "gnu" => Ok(Toolchain::Gnu),
+            _ => Err(()),
+        }
+    }
+}
+
+/// Host triple of the active Rust toolchain
+pub fn rust_host() -> Option<String> {
+    let output = process::Command::new("rustc").arg("-vV").output().ok()?;
+    String::from_utf8_lossy(&output.stdout).lines()
+        .find_map(|line| line.strip_prefix("host: ").map(String::from))
+}
+
+/// Toolchain the installed Erlang was built with, from its system architecture
+/// (`win32` for the usual MSVC builds, a `*-mingw32` triple for MinGW builds)
+fn erlang_toolchain() -> Option<Toolchain> {
+    let erl = env::var("ERL").unwrap_or_else(|_| "erl".to_string());
+    let output = process::Command::new(erl)
+        .args(["-noshell", "-eval", "io:format(\"~s\", [erlang:system_info(system_architecture)]), halt()."])
+        .output()
+        .ok()?;
+    architecture_toolchain(&String::from_utf8_lossy(&output.stdout))
+}
+
+fn architecture_toolchain(architecture: &str) -> Option<Toolchain> {
+    let architecture = architecture.trim();
+    if architecture.contains("mingw") {
+        Some(Toolchain::Gnu)
+    } else if architecture == "win32" || architecture.ends_with("-msvc") {
+        Some(Toolchain::Msvc)
+    } else {
+        None
+    }
+}
+
+/// Warning to show when the Rust toolchain in use doesn't match the installed Erlang
+pub fn check(target: Option<&str>) -> Option<String> {
+    let triple = target.map(String::from).or_else(rust_host)?;
+    let rust = Toolchain::from_triple(&triple)?;
+    let erlang = erlang_toolchain()?;
+    match rust == erlang {
+        true => None,
+        false => Some(format!("warning: Erlang is {}-built but Rust targets {}; NIFs may fail to load (use --windows-toolchain={})",
+                              erlang, triple, erlang)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_toolchain() {
+        assert_eq!(Some(Toolchain::Msvc), Toolchain::from_triple("x86_64-pc-windows-msvc"));
+        assert_eq!(Some(Toolchain::Gnu), Toolchain::from_triple("x86_64-pc-windows-gnu"));
+        assert_eq!(None, Toolchain::from_triple("x86_64-unknown-linux-gnu"));
+        assert_eq!("aarch64-pc-windows-msvc", Toolchain::Msvc.triple("aarch64-pc-windows-gnullvm"));
+        assert_eq!(Some(Toolchain::Msvc), architecture_toolchain("win32\n"));
+        assert_eq!(Some(Toolchain::Gnu), architecture_toolchain("x86_64-w64-mingw32"));
+        assert_eq!(None, architecture_toolchain("x86_64-pc-linux-gnu"));
+    }
+}
@@ -0,0 +1,293 @@
+//! `workspace-ify` command: convert the `crates/` directory into a cargo workspace.
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use toml;
+
+use config::Config;
+use strip;
+use {selected_crate_dirs, MsgError};
+use MsgError::*;
+
+/// True if `crates/Cargo.toml` exists and declares a `[workspace]`
+pub fn is_workspace(appdir: &Path) -> bool {
+    read_manifest_toml(&appdir.join("crates"))
+        .map(|m| m.get("workspace").is_some())
+        .unwrap_or(false)
+}
+
+/// True if `dir` holds a virtual manifest: a `[workspace]` without a `[package]`
+pub fn is_virtual(dir: &Path) -> bool {
+    read_manifest_toml(dir)
+        .map(|m| m.get("workspace").is_some() && m.get("package").is_none())
+        .unwrap_or(false)
+}
+
+/// The package directories of the workspace whose virtual manifest is in `dir`, following
+/// `members` (whose path components may contain `*`) less `exclude`, or `None` if `dir`
+/// isn't a virtual manifest
+pub fn virtual_members(dir: &Path) -> Option<Vec<PathBuf>> {
+    if !is_virtual(dir) {
+        return None;
+    }
+    let manifest = read_manifest_toml(dir).ok()?;
+    let list = |key: &str| -> Vec<String> {
+        manifest.get("workspace").and_then(|w| w.get(key)).and_then(|v| v.as_array()).into_iter().flatten()
+            .filter_map(|v| v.as_str().map(String::from))
+            .collect()
+    };
+    let excluded: Vec<PathBuf> = list("exclude").iter().map(|e| dir.join(e)).collect();
+    let mut members = Vec::new();
+    for pattern in list("members") {
+        for member in expand(dir, &pattern) {
+            if member.join("Cargo.toml").is_file() && !excluded.contains(&member) && !members.contains(&member) {
+                members.push(member);
+            }
+        }
+    }
+    members.sort();
+    Some(members)
+}
+
+/// The virtual workspace below `crates/` that the crate in `crate_dir` is a member of
+pub fn virtual_root(appdir: &Path, crate_dir: &Path) -> Option<PathBuf> {
+    let crates_dir = appdir.join("crates");
+    crate_dir.ancestors().skip(1)
+        .take_while(|dir| *dir != crates_dir)
+        .find(|dir| is_virtual(dir))
+        .map(Path::to_path_buf)
+}
+
+/// Paths below `dir` matching `pattern`
+fn expand(dir: &Path, pattern: &str) -> Vec<PathBuf> {
+    let mut paths = vec![dir.to_path_buf()];
+    for component in pattern.split('/').filter(|c| !c.is_empty() && *c != ".") {
+        paths = paths.into_iter()
+            .flat_map(|path| match component.contains('*') {
+                false => vec![path.join(component)],
+                true => path.read_dir().into_iter().flatten().filter_map(Result::ok)
+                    .filter(|entry| strip::matches(component, &entry.file_name().to_string_lossy()))
+                    .map(|entry| entry.path())
+                    .collect(),
+            })
+            .collect();
+    }
+    paths
+}
+
+/// Parse the `Cargo.toml` found in `dir`
+pub fn read_manifest_toml(dir: &Path) -> Result<toml::Value, MsgError> {
+    let text = fs::read_to_string(dir.join("Cargo.toml"))
+        .map_err(|err| MsgIo("cannot read Cargo.toml", err))?;
+    text.parse::<toml::Value>()
+        .map_err(|err| MsgDetail("cannot parse Cargo.toml", err.to_string()))
+}
+
+/// Generate `crates/Cargo.toml` listing every selected crate as a member, hoist `[profile.*]`
+/// sections from the members into it (cargo ignores member profiles in a workspace),
+/// and strip those sections from the member manifests.
+pub fn workspaceify(appdir: &Path) -> Result<(), MsgError> {
+    let crates_dir = appdir.join("crates");
+    let root_manifest = crates_dir.join("Cargo.toml");
+    if root_manifest.exists() {
+        return Err(Msg("crates/Cargo.toml already exists"));
+    }
+
+    let mut crate_dirs = selected_crate_dirs(appdir, &Config::load(appdir)?)?;
+    crate_dirs.sort();
+    if let Some(member) = crate_dirs.iter().find(|dir| dir.parent() != Some(crates_dir.as_path())) {
+        return Err(MsgDetail("crates/ already holds a workspace", member.display().to_string()));
+    }
+
+    let mut members = Vec::new();
+    let mut profiles = toml::value::Table::new();
+    for crate_dir in crate_dirs.iter() {
+        let name = crate_dir.file_name().unwrap().to_string_lossy().into_owned();
+        let manifest = read_manifest_toml(crate_dir)?;
+        if manifest.get("workspace").is_some() {
+            return Err(MsgDetail("crate already declares its own workspace", name));
+        }
+        if let Some(member_profiles) = manifest.get("profile").and_then(|p| p.as_table()) {
+            merge_profiles(&mut profiles, member_profiles, &name)?;
+        }
+        members.push(name);
+    }
+
+    // read everything before writing anything, and write the root manifest last, so a
+    // failure doesn't leave crates/ looking like a workspace that isn't
+    let mut rewrites = Vec::new();
+    for crate_dir in crate_dirs.iter() {
+        let manifest_path = crate_dir.join("Cargo.toml");
+        let text = fs::read_to_string(&manifest_path)
+            .map_err(|err| MsgIo("cannot read Cargo.toml", err))?;
+        let stripped = strip_profile_sections(&text);
+        if stripped != text {
+            rewrites.push((manifest_path, stripped));
+        }
+    }
+    for (manifest_path, stripped) in rewrites {
+        fs::write(&manifest_path, stripped)
+            .map_err(|err| MsgIo("cannot rewrite Cargo.toml", err))?;
+    }
+    fs::write(&root_manifest, workspace_manifest(&members, &profiles))
+        .map_err(|err| MsgIo("cannot write crates/Cargo.toml", err))?;
+
+    println!("Created workspace with {} members in {}", members.len(), root_manifest.to_string_lossy());
+    println!("Per-crate target/ directories are no longer used and may be deleted.");
+    Ok(())
+}
+
+/// Merge one member's `[profile.*]` tables into the shared set, failing on disagreement.
+fn merge_profiles(shared: &mut toml::value::Table, member: &toml::value::Table, crate_name: &str)
+    -> Result<(), MsgError>
+{
+    for (profile_name, settings) in member.iter() {
+        let settings = match settings.as_table() {
+            Some(t) => t,
+            None => continue,
+        };
+        let shared_settings = shared.entry(profile_name.clone())
+            .or_insert_with(|| toml::Value::Table(toml::value::Table::new()))
+            .as_table_mut()
+            .unwrap();
+        for (key, value) in settings.iter() {
+            if let Some(existing) = shared_settings.get(key) {
+                if existing != value {
+                    return Err(MsgDetail("conflicting profile settings between crates",
+                                         format!("profile.{}.{} in {}", profile_name, key, crate_name)));
+                }
+            }
+            shared_settings.insert(key.clone(), value.clone());
+        }
+    }
+    Ok(())
+}
+
+/// Text of the workspace root manifest
+fn workspace_manifest(members: &[String], profiles: &toml::value::Table) -> String {
+    let mut text = String::from("[workspace]\nmembers = [\n");
+    for member in members {
+        text.push_str(&format!("    \"{}\",\n", member));
+    }
+    text.push_str("]\n");
+
+    if !profiles.is_empty() {
+        let mut root = BTreeMap::new();
+        root.insert("profile", profiles);
+        text.push('\n');
+        text.push_str(&toml::to_string(&root).unwrap());
+    }
+    text
+}
+
+/// Remove profiles from manifest text in every form `merge_profiles` reads: `[profile...]`
+/// sections (header through the next header), and top-level `profile.<...> = ...` dotted
+/// keys or a `profile = { ... }` inline table, with any value continued on later lines
+fn strip_profile_sections(text: &str) -> String {
+    let mut out = String::new();
+    let mut skipping = false;
+    let mut top_level = true;
+    let mut open_brackets = 0;
+    for line in text.lines() {
+        let trimmed = line.trim_start();
+        if open_brackets > 0 {
+            open_brackets += bracket_depth(line);
+            continue;
+        }
+        if trimmed.starts_with('[') {
+            let name = trimmed.trim_start_matches('[').split(']').next().unwrap_or("").trim();
+            skipping = name == "profile" || name.starts_with("profile.");
+            top_level = false;
+        } else if top_level && is_profile_key(trimmed) {
+            open_brackets = bracket_depth(line).max(0);
+            continue;
+        }
+        if !skipping {
+            out.push_str(line);
+            out.push('\n');
+        }
+    }
+    out
+}
+
+/// True if the line assigns a `profile` key, dotted or not
+fn is_profile_key(line: &str) -> bool {
+    match line.find('=') {
+        Some(eq) => line[..eq].split('.').next()
+            .map(|first| first.trim().trim_matches(|c| c == '"' || c == '\'') == "profile")
+            .unwrap_or(false),
+        None => false,
+    }
+}
+
+/// Brackets and braces the line opens minus those it closes, outside strings and comments
+fn bracket_depth(line: &str) -> i32 {
+    let mut depth = 0;
+    let mut quote = None;
+    let mut escaped = false;
+    for c in line.chars() {
+        match (quote, c) {
+            _ if escaped => escaped = false,
+            (Some('"'), '\\') => escaped = true,
+            (Some(q), c) if c == q => quote = None,
+            (Some(_), _) => (),
+            (None, '"') | (None, '\'') => quote = Some(c),
+            (None, '#') => break,
+            (None, '[') | (None, '{') => depth += 1,
+            (None, ']') | (None, '}') => depth -= 1,
+            _ => (),
+        }
+    }
+    depth
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use std::process;
+
+    #[test]
+    fn test_strip_profile_sections() {
+        let text = "[package]\nname = \"a\"\n\n[profile.release]\nlto = true\n\n[dependencies]\nfoo = \"1\"\n";
+        assert_eq!("[package]\nname = \"a\"\n\n[dependencies]\nfoo = \"1\"\n", strip_profile_sections(text));
+        assert_eq!("[package]\n", strip_profile_sections("[package]\n[profile.dev]\nopt-level = 1\n"));
+        let other = "[package.metadata.profiles]\nx = 1\n[profile-tools]\ny = 2\n";
+        assert_eq!(other, strip_profile_sections(other));
+        let dotted = "profile.release.opt-level = 3\nprofile = { dev = { debug = [\n  \"line-\\\"]tables\",\n] } }\n\
+                      profiles = 1\n[package]\nname = \"a\"\nprofile.x = 1\n";
+        assert_eq!("profiles = 1\n[package]\nname = \"a\"\nprofile.x = 1\n", strip_profile_sections(dotted));
+    }
+
+    #[test]
+    fn test_virtual_members() {
+        let root = env::temp_dir().join(format!("cargo-erlangapp-virtual-{}", process::id()));
+        let ws = root.join("crates").join("ws");
+        for member in ["a", "b", "c"].iter() {
+            fs::create_dir_all(ws.join("members").join(member)).unwrap();
+            fs::write(ws.join("members").join(member).join("Cargo.toml"), "[package]\n").unwrap();
+        }
+        fs::create_dir_all(ws.join("tools")).unwrap();
+        fs::write(ws.join("Cargo.toml"), "[workspace]\nmembers = [\"members/*\", \"tools\"]\nexclude = [\"members/c\"]\n").unwrap();
+        let members = virtual_members(&ws);
+        let root_of = virtual_root(&root, &ws.join("members").join("a"));
+        let package = virtual_members(&ws.join("members").join("a"));
+        fs::remove_dir_all(&root).unwrap();
+
+        assert_eq!(Some(vec![ws.join("members").join("a"), ws.join("members").join("b")]), members);
+        assert_eq!(Some(ws.clone()), root_of);
+        assert_eq!(None, package);
+    }
+
+    #[test]
+    fn test_workspace_manifest() {
+        let mut profiles = toml::value::Table::new();
+        merge_profiles(&mut profiles, "release = { lto = true }".parse::<toml::Value>().unwrap().as_table().unwrap(), "a").unwrap();
+        merge_profiles(&mut profiles, "release = { lto = true }".parse::<toml::Value>().unwrap().as_table().unwrap(), "b").unwrap();
+        assert!(merge_profiles(&mut profiles, "release = { lto = false }".parse::<toml::Value>().unwrap().as_table().unwrap(), "c").is_err());
+        assert_eq!("[workspace]\nmembers = [\n    \"a\",\n    \"b\",\n]\n\n[profile.release]\nlto = true\n",
+                   workspace_manifest(&["a".to_string(), "b".to_string()], &profiles));
+    }
+}
@@ -2,7 +2,7 @@ extern crate walkdir;
 extern crate itertools;
 extern crate cargo_erlangapp;
 
-use cargo_erlangapp::{Target, target_filenames};
+use cargo_erlangapp::{CargoCommand, OnlyKind, Options, Target, TargetInfo, target_filenames, enumerate_crates};
 use std::ffi::{OsStr};
 use std::{env, fs, io};
 use std::path::{Path};
@@ -10,23 +10,24 @@ use walkdir::WalkDir;
 use itertools::Itertools;
 
 #[cfg(unix)]
-const TEST_DIR: &'static str = "tests/testdir";
+const TEST_DIR: &str = "tests/testdir";
 #[cfg(unix)]
-const APP_DIR: &'static str = "tests/testdir/testapp";
+const APP_DIR: &str = "tests/testdir/testapp";
 #[cfg(unix)]
-const APP_SRC: &'static str = "tests/testapp";
+const APP_SRC: &str = "tests/testapp";
 
 #[cfg(windows)]
-const TEST_DIR: &'static str = "tests\\testdir";
+const TEST_DIR: &str = "tests\\testdir";
 #[cfg(windows)]
-const APP_DIR: &'static str = "tests\\testdir\\testapp";
+const APP_DIR: &str = "tests\\testdir\\testapp";
 #[cfg(windows)]
-const APP_SRC: &'static str = "tests\\testapp";
+const APP_SRC: &str = "tests\\testapp";
 
 
 #[test]
 fn do_test() {
     test_init();
+    check_enumerate();
 
     invoke_with_args(&["cargo-erlangapp", "build" ]);
     check_build();
@@ -54,6 +55,50 @@ fn do_test() {
     test_cleanup();
 }
 
+#[test]
+fn dist_lib_only() {
+    // a crate with both a cdylib and a bin; --lib builds, and so archives, only the cdylib
+    let appdir = env::temp_dir().join(format!("cargo-erlangapp-dist-lib-{}", std::process::id()));
+    let crate_dir = appdir.join("crates").join("both");
+    fs::create_dir_all(crate_dir.join("src")).unwrap();
+    fs::create_dir_all(appdir.join("src")).unwrap();
+    fs::write(appdir.join("src").join("distapp.app.src"), "{application, distapp, [{vsn, \"0.1.0\"}]}.\n").unwrap();
+    fs::write(crate_dir.join("Cargo.toml"), "[package]\nname = \"both\"\nversion = \"0.1.0\"\n\n[lib]\ncrate-type = [\"cdylib\"]\n").unwrap();
+    fs::write(crate_dir.join("src").join("lib.rs"), "#[no_mangle]\npub extern \"C\" fn both() {}\n").unwrap();
+    fs::write(crate_dir.join("src").join("main.rs"), "fn main() {}\n").unwrap();
+
+    let result = cargo_erlangapp::run(Options::new(CargoCommand::Dist).lib().quiet_cargo(true), &appdir);
+    let archives: Vec<String> = fs::read_dir(appdir.join("dist"))
+        .map(|entries| entries
+            .map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned())
+            .filter(|name| name != "manifest.json")
+            .collect())
+        .unwrap_or_default();
+    fs::remove_dir_all(&appdir).unwrap();
+
+    result.unwrap();
+    assert_eq!(1, archives.len(), "{:?}", archives);
+    assert!(archives[0].contains("both") && !archives[0].starts_with("both-"), "{:?}", archives);
+}
+
+#[test]
+fn smoke_test_only_bins() {
+    // the cdylib isn't a NIF, so loading it would fail the smoke test (or find no erl)
+    let appdir = env::temp_dir().join(format!("cargo-erlangapp-smoke-bins-{}", std::process::id()));
+    let crate_dir = appdir.join("crates").join("both");
+    fs::create_dir_all(crate_dir.join("src")).unwrap();
+    fs::create_dir_all(appdir.join("src")).unwrap();
+    fs::write(appdir.join("src").join("smokeapp.app.src"), "{application, smokeapp, [{vsn, \"0.1.0\"}]}.\n").unwrap();
+    fs::write(crate_dir.join("Cargo.toml"), "[package]\nname = \"both\"\nversion = \"0.1.0\"\n\n[lib]\ncrate-type = [\"cdylib\"]\n").unwrap();
+    fs::write(crate_dir.join("src").join("lib.rs"), "#[no_mangle]\npub extern \"C\" fn both() {}\n").unwrap();
+    fs::write(crate_dir.join("src").join("main.rs"), "fn main() {}\n").unwrap();
+
+    let options = Options::new(CargoCommand::SmokeTest).only(OnlyKind::Bins).quiet_cargo(true);
+    let result = cargo_erlangapp::run(options, &appdir);
+    fs::remove_dir_all(&appdir).unwrap();
+    result.unwrap();
+}
+
 fn invoke_with_args(args: &[&str]) {
     let mut appdir = env::current_dir().unwrap();
     appdir.push(TEST_DIR);
@@ -61,6 +106,25 @@ fn invoke_with_args(args: &[&str]) {
     cargo_erlangapp::invoke_with_args_str(args, &appdir)
 }
 
+fn check_enumerate() {
+    let crates = enumerate_crates(Path::new(APP_DIR)).unwrap();
+    let found: Vec<(&str, Vec<&Target>)> = crates.iter()
+        .map(|c| (c.name.as_str(), c.targets.iter().map(|t| &t.target).collect()))
+        .collect();
+    assert_eq!(vec![
+        ("bonjourdylib", vec![&Target::Dylib("bonjourdylib".into())]),
+        ("helloexe", vec![&Target::Bin("helloexe".into())]),
+        ("holalib", vec![]),
+        ("nihaocdylib", vec![&Target::Cdylib("nihaocdylib".into())]),
+    ], found);
+    assert_eq!(TargetInfo {
+        target: Target::Bin("helloexe".into()),
+        crate_name: "helloexe".into(),
+        version: "0.1.0".into(),
+        required_features: vec![],
+    }, crates[1].targets[0]);
+}
+
 fn check_build() {
     check_artifact("nihaocdylib", &Target::Dylib("nihaocdylib".into())).unwrap();
     check_artifact("bonjourdylib", &Target::Dylib("bonjourdylib".into())).unwrap();
@@ -104,15 +168,15 @@ fn copy_all<P: AsRef<Path>, Q: AsRef<Path>>(from: P, to: Q) -> io::Result<()> {
     // calculate how many path elements to chop off entry when forming to path
     let chop_cnt = from.as_ref().components().count() - 1;
     for entry in WalkDir::new(from).follow_links(false) {
-        let entry = try!(entry);
+        let entry = entry?;
         let filetype = entry.file_type();
         let compi = entry.path().components().dropping(chop_cnt);
         let to_path = to.as_ref().join(compi.as_path());
         //let to_path = to.as_ref().join(entry.path());
         if filetype.is_dir() {
-            try!(fs::create_dir_all(to_path));
+            fs::create_dir_all(to_path)?;
         } else if filetype.is_file() {
-            try!(fs::copy(entry.path(), to_path));
+            fs::copy(entry.path(), to_path)?;
         }
     }
     Ok(())